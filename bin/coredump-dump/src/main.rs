@@ -1,16 +1,23 @@
+use std::env;
 use std::io;
 use std::io::Read;
-use std::sync::Arc;
 
 type BoxError = Box<dyn std::error::Error>;
 
 fn main() -> Result<(), BoxError> {
+    let history = env::args().skip(1).any(|arg| arg == "--history");
+
     let mut input = Vec::new();
     io::stdin().read_to_end(&mut input)?;
 
     let coredump_wasm = wasm_parser::parse(&input)
         .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
-    let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(Arc::new(coredump_wasm));
+    let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(coredump_wasm);
+
+    if history {
+        return print_history(&coredump_wasm);
+    }
+
     let coredump = coredump_wasm.get_coredump()?;
 
     let mut out = String::new();
@@ -19,3 +26,19 @@ fn main() -> Result<(), BoxError> {
     println!("{}", out);
     Ok(())
 }
+
+/// Print the calls recorded by the flight recorder, the oldest first.
+fn print_history(coredump_wasm: &core_wasm_ast::traverse::WasmModule) -> Result<(), BoxError> {
+    let content = coredump_wasm
+        .get_custom_section(wasm_coredump_rewriter::FLIGHT_RECORDER_SECTION)
+        .ok_or("no flight_recorder section in the coredump")?;
+
+    let events = wasm_coredump_rewriter::read_flight_recorder(&content)?;
+    let mut out = String::new();
+    wasm_coredump_rewriter::write_history(&mut out, &events, |funcidx| {
+        format!("<func {}>", funcidx)
+    })?;
+    print!("{}", out);
+
+    Ok(())
+}
@@ -1,8 +1,6 @@
 use clap::Parser;
 use std::fs::File;
 use std::io::prelude::*;
-use std::sync::Arc;
-use std::sync::Mutex;
 use uuid::Uuid;
 
 type BoxError = Box<dyn std::error::Error>;
@@ -25,14 +23,60 @@ fn main() -> Result<(), BoxError> {
             .expect("Error while reading file");
     }
 
-    let input = wasm_parser::parse(&input_bytes)
+    let input = wasm_parser::parse_binary(&input_bytes)
         .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
 
-    let debug_wasm = core_wasm_ast::traverse::WasmModule::new(Arc::new(core_wasm_ast::Module {
-        sections: Arc::new(Mutex::new(vec![])),
-    }));
+    let (output, debug_wasms) = match input {
+        core_wasm_ast::Binary::Module(module) => {
+            let (module, debug_wasm) = split(module)?;
+            (
+                wasm_printer::wasm::print(&module).unwrap(),
+                vec![debug_wasm],
+            )
+        }
+        core_wasm_ast::Binary::Component(mut component) => {
+            // Each core module gets its own build id and debug module
+            let mut debug_wasms = vec![];
+            for module in component.core_modules_mut() {
+                let (stripped, debug_wasm) = split(std::mem::take(module))?;
+                *module = stripped;
+                debug_wasms.push(debug_wasm);
+            }
+            (
+                wasm_printer::wasm::print_component(&component).unwrap(),
+                debug_wasms,
+            )
+        }
+    };
+
+    // override input with stripped down version
+    {
+        let mut file = File::create(&args.source)?;
+        file.write_all(&output)?;
+    }
+
+    // write debug wasm
+    for (build_id, debug_wasm) in debug_wasms {
+        let filename = format!("debug-{}.wasm", build_id);
+        let mut file = File::create(&filename)?;
+
+        let bytes = wasm_printer::wasm::print(&debug_wasm).unwrap();
+        file.write_all(&bytes)?;
+
+        println!("Wrote debugging infos {}", filename);
+    }
+
+    Ok(())
+}
 
-    for section in input.sections.lock().unwrap().iter() {
+/// Move the debugging infos of the module to a separate module. The stripped
+/// module is tagged with a new build id, which names the debug module.
+fn split(
+    input: core_wasm_ast::Module,
+) -> Result<(core_wasm_ast::Module, (Uuid, core_wasm_ast::Module)), BoxError> {
+    let mut debug_wasm = core_wasm_ast::traverse::WasmModule::new(core_wasm_ast::Module::default());
+
+    for section in input.sections.iter() {
         match &section.value {
             core_wasm_ast::Section::Type((_, _))
             | core_wasm_ast::Section::Func((_, _))
@@ -49,7 +93,7 @@ fn main() -> Result<(), BoxError> {
         }
     }
 
-    let wasm = core_wasm_ast::traverse::WasmModule::new(Arc::new(input));
+    let mut wasm = core_wasm_ast::traverse::WasmModule::new(input);
     let mut custom_sections_to_remove = vec![];
 
     for custom_section in wasm.get_custom_sections() {
@@ -66,6 +110,12 @@ fn main() -> Result<(), BoxError> {
 
                 custom_sections_to_remove.push("name".to_owned());
             }
+            core_wasm_ast::CustomSection::CodeOffsets(map) => {
+                debug_wasm
+                    .add_custom_section(core_wasm_ast::CustomSection::CodeOffsets(map.clone()));
+
+                custom_sections_to_remove.push("code_offsets".to_owned());
+            }
 
             _ => {}
         }
@@ -81,23 +131,5 @@ fn main() -> Result<(), BoxError> {
 
     // FIXME add debug id to correlate debugging symbols with binary
 
-    // override input with stripped down version
-    {
-        let bytes = wasm_printer::wasm::print(&wasm.into_inner()).unwrap();
-        let mut file = File::create(&args.source)?;
-        file.write_all(&bytes)?;
-    }
-
-    // write debug wasm
-    {
-        let filename = format!("debug-{}.wasm", build_id);
-        let mut file = File::create(&filename)?;
-
-        let bytes = wasm_printer::wasm::print(&debug_wasm.into_inner()).unwrap();
-        file.write_all(&bytes)?;
-
-        println!("Wrote debugging infos {}", filename);
-    }
-
-    Ok(())
+    Ok((wasm.into_inner(), (build_id, debug_wasm.into_inner())))
 }
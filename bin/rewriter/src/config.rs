@@ -0,0 +1,76 @@
+//! Configuration file of the command line, in TOML.
+//!
+//! Keys are the long command line flags, for instance:
+//!
+//! ```toml
+//! coredump-placement = "grow"
+//! exclude-funcs = ["core::*", "alloc::*"]
+//! ```
+//!
+//! Flags given on the command line take precedence over the file, including
+//! boolean flags: `--debug=false` turns off `debug = true`.
+
+use crate::Placement;
+use serde::Deserialize;
+use std::fs;
+
+type BoxError = Box<dyn std::error::Error>;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Config {
+    pub(crate) check_memory_operations: Option<bool>,
+    pub(crate) debug: Option<bool>,
+    pub(crate) instance_id: Option<u32>,
+    pub(crate) instance_id_import: Option<String>,
+    pub(crate) instance_id_setter: Option<bool>,
+    pub(crate) coredump_placement: Option<Placement>,
+    pub(crate) coredump_offset: Option<u32>,
+    pub(crate) coredump_size: Option<u32>,
+    pub(crate) wasi_coredump_name: Option<String>,
+    pub(crate) wasi_dirfd: Option<u32>,
+    pub(crate) include_funcs: Vec<String>,
+    pub(crate) include_funcs_regex: Vec<String>,
+    pub(crate) exclude_funcs: Vec<String>,
+    pub(crate) exclude_funcs_regex: Vec<String>,
+    pub(crate) max_locals: Option<u32>,
+    pub(crate) frames_only: Option<bool>,
+    pub(crate) frame_locals: Option<u32>,
+    pub(crate) max_call_depth: Option<u32>,
+    pub(crate) shadow_stack_size: Option<u32>,
+    pub(crate) shadow_stack_offset: Option<u32>,
+    pub(crate) coredump_snapshot: Option<bool>,
+    pub(crate) snapshot_funcs: Vec<String>,
+    pub(crate) flight_recorder_size: Option<u32>,
+    pub(crate) flight_recorder_offset: Option<u32>,
+    pub(crate) flight_recorder_exits: Option<bool>,
+    pub(crate) thread_frames_size: Option<u32>,
+    pub(crate) record_all_globals: Option<bool>,
+    pub(crate) record_globals: Vec<String>,
+    pub(crate) offset_map_section: Option<bool>,
+    pub(crate) offset_map: Option<String>,
+    pub(crate) rewrite_dwarf: Option<bool>,
+    pub(crate) capture_panics: Option<bool>,
+    pub(crate) capture_exceptions: Option<bool>,
+}
+
+impl Config {
+    pub(crate) fn load(path: &str) -> Result<Self, BoxError> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+        Self::parse(&contents).map_err(|err| format!("failed to parse {}: {}", path, err).into())
+    }
+
+    fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+}
+
+/// Value of a boolean flag, the command line wins over the file. Flags are
+/// off unless set.
+pub(crate) fn flag(arg: Option<bool>, config: Option<bool>) -> bool {
+    arg.or(config).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,45 @@
+use super::{flag, Config};
+use crate::{Args, Placement};
+use clap::Parser;
+
+#[test]
+fn test_parse() {
+    let config = Config::parse(
+        r#"
+coredump-placement = "grow"
+exclude-funcs = ["core::*", "alloc::*"]
+debug = true
+capture-panics = false
+"#,
+    )
+    .unwrap();
+    assert!(matches!(config.coredump_placement, Some(Placement::Grow)));
+    assert_eq!(config.exclude_funcs, ["core::*", "alloc::*"]);
+    assert_eq!(config.debug, Some(true));
+    assert_eq!(config.capture_panics, Some(false));
+    assert_eq!(config.frames_only, None);
+
+    assert!(Config::parse("unknown-flag = true").is_err());
+}
+
+#[test]
+fn test_command_line_wins() {
+    let config = Config::parse("debug = true\nframes-only = true").unwrap();
+
+    let args = Args::try_parse_from(["rewriter", "--debug=false", "input.wasm"]).unwrap();
+    assert!(!flag(args.debug, config.debug));
+    assert!(flag(args.frames_only, config.frames_only));
+    assert_eq!(args.input.as_deref(), Some("input.wasm"));
+
+    // A flag without value is set, it doesn't take the next argument
+    let args = Args::try_parse_from(["rewriter", "--coredump-snapshot", "input.wasm"]).unwrap();
+    assert!(flag(args.coredump_snapshot, None));
+    assert_eq!(args.input.as_deref(), Some("input.wasm"));
+
+    // Flags are off by default
+    let args = Args::try_parse_from(["rewriter"]).unwrap();
+    assert!(!flag(
+        args.capture_exceptions,
+        Config::default().capture_exceptions
+    ));
+}
@@ -0,0 +1,78 @@
+//! Custom sections appended to the coredump once the runtime wrote it.
+//!
+//! The coredump spans until the end of the memory it's written to, pages are
+//! grown past it to hold the section. The section's size covers the grown pages, so its
+//! content is padded with zeros.
+
+use core_wasm_ast as ast;
+
+const PAGE_SIZE: u32 = 64 * 1024;
+
+// Section id and size.
+const SECTION_ID_SIZE: u32 = 1 + 5;
+
+/// LEB128 encoding of `value` using the maximum number of bytes, so that it
+/// can be written before the value is known.
+fn padded_leb128(mut value: u32) -> [u8; 5] {
+    let mut bytes = [0; 5];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value & 0x7f) as u8;
+        if i < 4 {
+            *byte |= 0x80;
+        }
+        value >>= 7;
+    }
+    bytes
+}
+
+/// Grow the memory `memidx` and write the header of the `name` custom
+/// section, for `content_size` bytes of content. The start of the content is
+/// then in `ptr_local`. Returns from the function if the memory can't be
+/// grown.
+pub(crate) fn make_append(
+    name: &str,
+    content_size: u32,
+    ptr_local: u32,
+    memidx: u32,
+) -> Vec<ast::Instr> {
+    let header_size = SECTION_ID_SIZE + 1 + name.len() as u32;
+    let pages = (header_size + content_size).div_ceil(PAGE_SIZE);
+
+    let mut body = vec![
+        ast::Instr::memory_size(memidx),
+        ast::Instr::i32_const(16),
+        ast::Instr::i32_shl,
+        ast::Instr::local_set(ptr_local),
+        ast::Instr::i32_const(pages as i64),
+        ast::Instr::memory_grow(memidx),
+        ast::Instr::i32_const(-1),
+        ast::Instr::i32_eq,
+    ];
+    let if_body = vec![
+        ast::Value::new(ast::Instr::Return),
+        ast::Value::new(ast::Instr::end),
+    ];
+    body.push(ast::Instr::If(
+        ast::BlockType::Empty,
+        ast::Value::new(if_body),
+    ));
+
+    let mut header = vec![0x0];
+    header.extend(padded_leb128(pages * PAGE_SIZE - SECTION_ID_SIZE));
+    header.push(name.len() as u8);
+    header.extend(name.bytes());
+    for (i, byte) in header.into_iter().enumerate() {
+        body.push(ast::Instr::local_get(ptr_local));
+        body.push(ast::Instr::i32_const(byte as i64));
+        body.push(ast::Instr::i32_store8(ast::MemArg::with_memidx(
+            0, i as u32, memidx,
+        )));
+    }
+
+    body.push(ast::Instr::local_get(ptr_local));
+    body.push(ast::Instr::i32_const(header_size as i64));
+    body.push(ast::Instr::i32_add);
+    body.push(ast::Instr::local_set(ptr_local));
+
+    body
+}
@@ -0,0 +1,401 @@
+//! Update the DWARF code addresses after the instrumentation.
+//!
+//! DWARF code addresses are offsets in the content of the code section. The
+//! addresses of the original instructions are moved to where the printer
+//! writes them; addresses inside instructions that were replaced stay
+//! relative to the previous original instruction.
+
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::WasmModule;
+use gimli::read::Reader;
+use gimli::write;
+use gimli::{EndianRcSlice, LittleEndian};
+use std::ops::Range;
+use std::rc::Rc;
+
+type BoxError = Box<dyn std::error::Error>;
+
+type DwarfReader = EndianRcSlice<LittleEndian>;
+
+// Sections referring to .debug_info offsets, which change once the DWARF is
+// written again.
+const STALE_SECTIONS: [&str; 4] = [
+    ".debug_aranges",
+    ".debug_pubnames",
+    ".debug_pubtypes",
+    ".debug_names",
+];
+
+#[derive(Debug)]
+struct FuncAddresses {
+    start: u64,
+    locals_start: u64,
+    instrs_start: u64,
+    end: u64,
+}
+
+#[derive(Debug)]
+struct Func {
+    original: FuncAddresses,
+    rewritten: FuncAddresses,
+    /// (original, rewritten) offsets of the original instructions, relative
+    /// to the start of the instructions. Sorted.
+    instrs: Vec<(u64, u64)>,
+}
+
+/// Original functions, sorted by address. The original functions stay first
+/// in the code section, and in the same order.
+struct AddressMap {
+    funcs: Vec<Func>,
+}
+
+impl AddressMap {
+    fn new(
+        module: &WasmModule,
+        code_offsets: &ast::CodeOffsetMap,
+        original_funcs: &Range<u32>,
+    ) -> Self {
+        let positions = wasm_printer::wasm::func_positions(module.inner());
+
+        let mut funcs = vec![];
+
+        for section in &module.inner().sections {
+            let ast::Section::Code((section_size, content)) = &section.value else {
+                continue;
+            };
+            let content_start = section_size.end_offset as u64;
+
+            let codes = content.value.iter().take(original_funcs.len());
+            for (i, (code, position)) in codes.zip(&positions).enumerate() {
+                // Instruction offsets are shifted by the size of the function's
+                // size.
+                let size_len = code.size.end_offset - code.size.start_offset;
+                let locals_start = code.size.end_offset as u64 - content_start;
+
+                let original = FuncAddresses {
+                    start: code.size.start_offset as u64 - content_start,
+                    locals_start,
+                    instrs_start: (code.body.start_offset + size_len) as u64 - content_start,
+                    end: locals_start + code.size.value as u64,
+                };
+                let rewritten = FuncAddresses {
+                    start: position.start as u64,
+                    locals_start: (position.start + size_len) as u64,
+                    instrs_start: position.instrs_start as u64,
+                    end: position.end as u64,
+                };
+
+                let funcidx = original_funcs.start + i as u32;
+                let mut instrs: Vec<(u64, u64)> = code_offsets
+                    .funcs
+                    .get(&funcidx)
+                    .map(|entries| {
+                        entries
+                            .iter()
+                            .map(|(rewritten, original)| (*original as u64, *rewritten as u64))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                instrs.sort();
+
+                funcs.push(Func {
+                    original,
+                    rewritten,
+                    instrs,
+                });
+            }
+        }
+
+        Self { funcs }
+    }
+
+    fn convert(&self, addr: u64) -> u64 {
+        let idx = self
+            .funcs
+            .partition_point(|func| func.original.start <= addr);
+        if idx == 0 {
+            return addr;
+        }
+
+        let func = &self.funcs[idx - 1];
+        let (original, rewritten) = (&func.original, &func.rewritten);
+
+        // Outside of the code, for instance tombstone addresses
+        if addr > original.end {
+            return addr;
+        }
+        // The end of the last function
+        if addr == original.end {
+            return rewritten.end;
+        }
+
+        if addr == original.start {
+            return rewritten.start;
+        }
+        if addr < original.instrs_start {
+            let offset = addr.saturating_sub(original.locals_start);
+            return (rewritten.locals_start + offset).min(rewritten.instrs_start);
+        }
+
+        let offset = addr - original.instrs_start;
+        let instrs_len = rewritten.end - rewritten.instrs_start;
+
+        let idx = func.instrs.partition_point(|(o, _)| *o <= offset);
+        let (prev_original, prev_rewritten) = match idx {
+            0 => (0, 0),
+            idx => func.instrs[idx - 1],
+        };
+        let next_rewritten = func.instrs.get(idx).map(|(_, r)| *r).unwrap_or(instrs_len);
+
+        let rewritten_offset = (prev_rewritten + (offset - prev_original)).min(next_rewritten);
+        rewritten.instrs_start + rewritten_offset
+    }
+}
+
+/// Update the code addresses of the DWARF custom sections, if any, to match
+/// the printed module. Must run once the code won't change anymore.
+pub(crate) fn rewrite_dwarf(
+    module: &mut WasmModule,
+    code_offsets: &ast::CodeOffsetMap,
+    original_funcs: &Range<u32>,
+) -> Result<(), BoxError> {
+    if module.get_custom_section(".debug_info").is_none() {
+        return Ok(());
+    }
+
+    let map = AddressMap::new(module, code_offsets, original_funcs);
+
+    let dwarf = gimli::read::Dwarf::load(|id| -> Result<DwarfReader, gimli::Error> {
+        let bytes = module.get_custom_section(id.name()).unwrap_or_default();
+        Ok(EndianRcSlice::new(Rc::from(bytes), LittleEndian))
+    })?;
+
+    let convert_address = |addr| Some(write::Address::Constant(map.convert(addr)));
+    let mut converted = write::Dwarf::from(&dwarf, &convert_address)?;
+
+    // The conversion keeps the line programs' addresses and the functions'
+    // length as is.
+    let mut units = dwarf.units();
+    let mut i = 0;
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let unit_id = converted.units.id(i);
+        i += 1;
+
+        let write_unit = converted.units.get_mut(unit_id);
+
+        if let Some(program) = unit.line_program.clone() {
+            write_unit.line_program = convert_line_program(
+                program,
+                &dwarf,
+                &mut converted.line_strings,
+                &mut converted.strings,
+                &map,
+            )?;
+        }
+
+        let mut tree = unit.entries_tree(None)?;
+        let root_id = write_unit.root();
+        convert_high_pc(&dwarf, &unit, tree.root()?, write_unit, root_id, &map)?;
+    }
+
+    let mut sections = write::Sections::new(write::EndianVec::new(LittleEndian));
+    converted.write(&mut sections)?;
+
+    sections.for_each(|id, data| -> Result<(), BoxError> {
+        module.remove_custom_section(id.name());
+        if !data.slice().is_empty() {
+            let section =
+                ast::CustomSection::Unknown(id.name().to_owned(), data.slice().to_owned());
+            module.add_custom_section(section);
+        }
+        Ok(())
+    })?;
+
+    for name in STALE_SECTIONS {
+        module.remove_custom_section(name);
+    }
+
+    Ok(())
+}
+
+/// Same as gimli's conversion, but the rows' addresses are converted as well.
+fn convert_line_program(
+    from_program: gimli::IncompleteLineProgram<DwarfReader>,
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    line_strings: &mut write::LineStringTable,
+    strings: &mut write::StringTable,
+    map: &AddressMap,
+) -> Result<write::LineProgram, BoxError> {
+    let mut dirs = vec![];
+    let mut files = vec![];
+    let comp_file;
+
+    let mut program = {
+        let header = from_program.header();
+        let encoding = header.encoding();
+
+        let comp_dir = match header.directory(0) {
+            Some(dir) => convert_line_string(dir, dwarf, line_strings, strings)?,
+            None => write::LineString::new(&[][..], encoding, line_strings),
+        };
+        let (comp_name, comp_file_info) = match header.file(0) {
+            Some(file) => (
+                convert_line_string(file.path_name(), dwarf, line_strings, strings)?,
+                Some(write::FileInfo {
+                    timestamp: file.timestamp(),
+                    size: file.size(),
+                    md5: *file.md5(),
+                }),
+            ),
+            None => (
+                write::LineString::new(&[][..], encoding, line_strings),
+                None,
+            ),
+        };
+
+        comp_file = (comp_name.clone(), comp_file_info);
+        let mut program = write::LineProgram::new(
+            encoding,
+            header.line_encoding(),
+            comp_dir,
+            comp_name,
+            comp_file_info,
+        );
+
+        // For version <= 4, the first directory is implicit and a file index
+        // of 0 is invalid. Otherwise the first file isn't added, but can be
+        // referenced.
+        let file_skip = if header.version() <= 4 {
+            dirs.push(program.default_directory());
+            0
+        } else {
+            1
+        };
+        files.push(None);
+
+        for dir in header.include_directories() {
+            let dir = convert_line_string(dir.clone(), dwarf, line_strings, strings)?;
+            dirs.push(program.add_directory(dir));
+        }
+
+        program.file_has_timestamp = header.file_has_timestamp();
+        program.file_has_size = header.file_has_size();
+        program.file_has_md5 = header.file_has_md5();
+        for file in header.file_names().iter().skip(file_skip) {
+            let name = convert_line_string(file.path_name(), dwarf, line_strings, strings)?;
+            let dir = *dirs
+                .get(file.directory_index() as usize)
+                .ok_or("invalid directory index")?;
+            let info = write::FileInfo {
+                timestamp: file.timestamp(),
+                size: file.size(),
+                md5: *file.md5(),
+            };
+            files.push(Some(program.add_file(name, dir, Some(info))));
+        }
+
+        program
+    };
+
+    let mut rows = from_program.rows();
+    let mut sequence_start = 0;
+    while let Some((_, row)) = rows.next_row()? {
+        let address = map.convert(row.address());
+
+        if !program.in_sequence() {
+            program.begin_sequence(Some(write::Address::Constant(address)));
+            sequence_start = address;
+        }
+
+        if row.end_sequence() {
+            program.end_sequence(address - sequence_start);
+            continue;
+        }
+
+        let file = match files.get(row.file_index() as usize) {
+            Some(Some(file)) => *file,
+            // The primary source file, for version >= 5. gimli doesn't
+            // expose its FileId, add it again.
+            Some(None) if program.version() >= 5 => {
+                let (name, info) = comp_file.clone();
+                let file = program.add_file(name, program.default_directory(), info);
+                files[0] = Some(file);
+                file
+            }
+            _ => return Err("invalid file index".into()),
+        };
+
+        let out = program.row();
+        out.address_offset = address - sequence_start;
+        out.op_index = row.op_index();
+        out.file = file;
+        out.line = row.line().map(|line| line.get()).unwrap_or(0);
+        out.column = match row.column() {
+            gimli::ColumnType::LeftEdge => 0,
+            gimli::ColumnType::Column(column) => column.get(),
+        };
+        out.discriminator = row.discriminator();
+        out.is_statement = row.is_stmt();
+        out.basic_block = row.basic_block();
+        out.prologue_end = row.prologue_end();
+        out.epilogue_begin = row.epilogue_begin();
+        out.isa = row.isa();
+        program.generate_row();
+    }
+
+    Ok(program)
+}
+
+fn convert_line_string(
+    value: gimli::AttributeValue<DwarfReader>,
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    line_strings: &mut write::LineStringTable,
+    strings: &mut write::StringTable,
+) -> Result<write::LineString, BoxError> {
+    Ok(match value {
+        gimli::AttributeValue::String(s) => write::LineString::String(s.to_slice()?.to_vec()),
+        gimli::AttributeValue::DebugStrRef(offset) => {
+            let s = dwarf.debug_str.get_str(offset)?;
+            write::LineString::StringRef(strings.add(s.to_slice()?))
+        }
+        gimli::AttributeValue::DebugLineStrRef(offset) => {
+            let s = dwarf.debug_line_str.get_str(offset)?;
+            write::LineString::LineStringRef(line_strings.add(s.to_slice()?))
+        }
+        _ => return Err("unsupported line string form".into()),
+    })
+}
+
+/// A DW_AT_high_pc can be the length of the entry's code, which changed.
+fn convert_high_pc(
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    unit: &gimli::Unit<DwarfReader>,
+    node: gimli::EntriesTreeNode<DwarfReader>,
+    write_unit: &mut write::Unit,
+    id: write::UnitEntryId,
+    map: &AddressMap,
+) -> Result<(), BoxError> {
+    let entry = node.entry();
+
+    let low_pc = entry.attr_value(gimli::DW_AT_low_pc)?;
+    let high_pc = entry.attr_value(gimli::DW_AT_high_pc)?;
+    if let (Some(low_pc), Some(high_pc)) = (low_pc, high_pc) {
+        let low_pc = dwarf.attr_address(unit, low_pc)?;
+        if let (Some(low_pc), Some(len)) = (low_pc, high_pc.udata_value()) {
+            let len = map.convert(low_pc + len) - map.convert(low_pc);
+            write_unit
+                .get_mut(id)
+                .set(gimli::DW_AT_high_pc, write::AttributeValue::Udata(len));
+        }
+    }
+
+    let write_children: Vec<write::UnitEntryId> = write_unit.get(id).children().cloned().collect();
+    let mut children = node.children();
+    for child_id in write_children {
+        let child = children.next()?.ok_or("DWARF entries mismatch")?;
+        convert_high_pc(dwarf, unit, child, write_unit, child_id, map)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+//! Uncaught exceptions.
+//!
+//! With the exception handling proposal, an exception that isn't caught
+//! unwinds the stack up to the host without reaching the instructions the
+//! rewriter instruments. Exported functions and the start function run their
+//! body in a block catching all exceptions, using the same form of
+//! instructions as the module. The handler records the frame of the
+//! function, writes the coredump and traps.
+//!
+//! The engine already unwound the frames of the functions the exception went
+//! through, they aren't in the coredump.
+
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::WasmModule;
+
+/// Block type of the function's results.
+pub(crate) fn block_type(module: &mut WasmModule, t: &ast::Type) -> ast::BlockType {
+    match t.results.as_slice() {
+        [] => ast::BlockType::Empty,
+        [valtype] => ast::BlockType::ValueType(valtype.clone()),
+        results => {
+            let t = ast::Type {
+                params: vec![],
+                results: results.to_vec(),
+            };
+            ast::BlockType::Typeidx(module.add_type(&t))
+        }
+    }
+}
+
+/// Handler of the exceptions escaping an exported function, `frame` records
+/// the frame of the function.
+pub(crate) fn make_handler(
+    reset: Option<u32>,
+    frame: Vec<ast::Instr>,
+    write_coredump: u32,
+) -> Vec<ast::Instr> {
+    let mut handler = vec![];
+    if let Some(reset) = reset {
+        handler.push(ast::Instr::call(reset));
+    }
+    handler.extend(frame);
+    handler.push(ast::Instr::call(write_coredump));
+    handler.push(ast::Instr::unreachable);
+    handler
+}
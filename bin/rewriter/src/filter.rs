@@ -0,0 +1,87 @@
+//! Selection of the functions to instrument, by their name in the name
+//! section.
+
+use regex::Regex;
+
+type BoxError = Box<dyn std::error::Error>;
+
+/// Functions excluded by the filter don't record a frame; they only
+/// propagate the unwinding to their caller. Functions without a name are
+/// instrumented unless include patterns are given.
+#[derive(Debug, Default, Clone)]
+pub struct FuncFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    /// Don't record the locals of functions with more locals (including
+    /// params) than this.
+    pub max_locals: Option<u32>,
+}
+
+impl FuncFilter {
+    pub fn include_glob(&mut self, pattern: &str) -> Result<(), BoxError> {
+        self.include.push(glob_to_regex(pattern)?);
+        Ok(())
+    }
+
+    pub fn include_regex(&mut self, pattern: &str) -> Result<(), BoxError> {
+        self.include.push(Regex::new(pattern)?);
+        Ok(())
+    }
+
+    pub fn exclude_glob(&mut self, pattern: &str) -> Result<(), BoxError> {
+        self.exclude.push(glob_to_regex(pattern)?);
+        Ok(())
+    }
+
+    pub fn exclude_regex(&mut self, pattern: &str) -> Result<(), BoxError> {
+        self.exclude.push(Regex::new(pattern)?);
+        Ok(())
+    }
+
+    pub(crate) fn is_included(&self, name: Option<&str>) -> bool {
+        let Some(name) = name else {
+            return self.include.is_empty();
+        };
+
+        let included = self.include.is_empty() || self.include.iter().any(|re| re.is_match(name));
+        included && !self.exclude.iter().any(|re| re.is_match(name))
+    }
+
+    /// Settings of the filter, recorded in the rewritten module. Globs are
+    /// recorded as their regex.
+    pub(crate) fn describe(&self) -> Vec<(&'static str, String)> {
+        let mut out = vec![];
+        for re in &self.include {
+            out.push(("include-funcs-regex", re.as_str().to_owned()));
+        }
+        for re in &self.exclude {
+            out.push(("exclude-funcs-regex", re.as_str().to_owned()));
+        }
+        if let Some(max_locals) = self.max_locals {
+            out.push(("max-locals", max_locals.to_string()));
+        }
+        out
+    }
+
+    pub(crate) fn records_locals(&self, local_count: u32) -> bool {
+        match self.max_locals {
+            Some(max) => local_count <= max,
+            None => true,
+        }
+    }
+}
+
+/// `*` matches any sequence of characters and `?` a single character.
+pub(crate) fn glob_to_regex(pattern: &str) -> Result<Regex, BoxError> {
+    let mut re = "^".to_owned();
+    for c in pattern.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+
+    Ok(Regex::new(&re)?)
+}
@@ -0,0 +1,195 @@
+//! Flight recorder of the recent calls, in a reserved memory region.
+//!
+//! Each function records an event when it's entered, and optionally when
+//! its calls return, in a ring buffer. The region is structured as follows:
+//!
+//! | next: u32 | event: u32 * capacity |
+//!
+//! `next` is the slot of the next event. An event is `funcidx + 1` when
+//! entering the function and `0x8000_0000 | (funcidx + 1)` when returning
+//! to it, empty slots are zero.
+//!
+//! Once the coredump is written, a `flight_recorder` custom section is
+//! appended to it with the following structure:
+//!
+//! | capacity: u32 | next: u32 | event: u32 * capacity |
+
+use crate::coredump_section;
+use core_wasm_ast as ast;
+use std::fmt::Write;
+
+type BoxError = Box<dyn std::error::Error>;
+
+pub const FLIGHT_RECORDER_SECTION: &str = "flight_recorder";
+
+const HEADER_SIZE: u32 = 4;
+const EVENT_SIZE: u32 = 4;
+const RETURN_BIT: u32 = 0x8000_0000;
+
+/// Memory region of the flight recorder, starting at `offset` and spanning
+/// `size` bytes. With `exits`, returning from a call is also recorded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlightRecorder {
+    pub offset: u32,
+    pub size: u32,
+    pub exits: bool,
+}
+
+impl FlightRecorder {
+    pub(crate) fn capacity(&self) -> u32 {
+        self.size.saturating_sub(HEADER_SIZE) / EVENT_SIZE
+    }
+
+    pub(crate) fn check(&self) -> Result<(), BoxError> {
+        if self.capacity() == 0 {
+            return Err(format!(
+                "flight recorder must be at least {} bytes",
+                HEADER_SIZE + EVENT_SIZE
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Call recorded by the flight recorder, by funcidx.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallEvent {
+    /// The function was entered.
+    Entry(u32),
+    /// A call returned to the function.
+    Return(u32),
+}
+
+fn make_record(recorder: &FlightRecorder, event: u32) -> Vec<ast::Instr> {
+    let offset = recorder.offset as i32 as i64;
+
+    vec![
+        // events[next] = event
+        ast::Instr::i32_const(offset),
+        ast::Instr::i32_load(ast::MemArg::new(2, 0)),
+        ast::Instr::i32_const(EVENT_SIZE as i64),
+        ast::Instr::i32_mul,
+        ast::Instr::i32_const(event as i32 as i64),
+        ast::Instr::i32_store(ast::MemArg::new(2, recorder.offset + HEADER_SIZE)),
+        // next = (next + 1) % capacity
+        ast::Instr::i32_const(offset),
+        ast::Instr::i32_const(offset),
+        ast::Instr::i32_load(ast::MemArg::new(2, 0)),
+        ast::Instr::i32_const(1),
+        ast::Instr::i32_add,
+        ast::Instr::i32_const(recorder.capacity() as i64),
+        ast::Instr::i32_rem_u,
+        ast::Instr::i32_store(ast::MemArg::new(2, 0)),
+    ]
+}
+
+/// Record the entry in the function.
+pub(crate) fn make_entry(recorder: &FlightRecorder, funcidx: u32) -> Vec<ast::Instr> {
+    make_record(recorder, funcidx + 1)
+}
+
+/// Record the return of a call to the function.
+pub(crate) fn make_return(recorder: &FlightRecorder, funcidx: u32) -> Vec<ast::Instr> {
+    make_record(recorder, RETURN_BIT | (funcidx + 1))
+}
+
+/// Build a function that writes the coredump, using `write_coredump`, and
+/// appends the `flight_recorder` section. The coredump is in the memory
+/// `coredump_memory`, the recorder in the memory of the program.
+pub(crate) fn make_write_flight_recorder(
+    recorder: &FlightRecorder,
+    write_coredump: u32,
+    coredump_memory: u32,
+) -> ast::Code {
+    // locals
+    let ptr = 0;
+
+    let capacity = recorder.capacity();
+    let region_size = HEADER_SIZE + capacity * EVENT_SIZE;
+
+    let mut body = vec![ast::Instr::call(write_coredump)];
+    body.extend(coredump_section::make_append(
+        FLIGHT_RECORDER_SECTION,
+        4 + region_size,
+        ptr,
+        coredump_memory,
+    ));
+
+    body.extend([
+        ast::Instr::local_get(ptr),
+        ast::Instr::i32_const(capacity as i64),
+        ast::Instr::i32_store(ast::MemArg::with_memidx(2, 0, coredump_memory)),
+        // Copy next and the events
+        ast::Instr::local_get(ptr),
+        ast::Instr::i32_const(4),
+        ast::Instr::i32_add,
+        ast::Instr::i32_const(recorder.offset as i32 as i64),
+        ast::Instr::i32_const(region_size as i64),
+        ast::Instr::memory_copy(coredump_memory, 0),
+        ast::Instr::end,
+    ]);
+
+    ast::Code {
+        locals: vec![ast::CodeLocal {
+            count: 1,
+            value_type: ast::ValueType::NumType(ast::NumType::I32),
+        }],
+        size: ast::Value::new(0), // printer calculates based on the body
+        body: ast::Value::new(body.into_iter().map(ast::Value::new).collect()),
+    }
+}
+
+/// Parse the content of the `flight_recorder` section of a coredump. The
+/// events are returned the oldest first.
+pub fn read_flight_recorder(content: &[u8]) -> Result<Vec<CallEvent>, BoxError> {
+    let read_u32 = |offset: u32| -> Result<u32, BoxError> {
+        let offset = offset as usize;
+        let bytes = content
+            .get(offset..offset + 4)
+            .ok_or("truncated flight_recorder section")?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let capacity = read_u32(0)?;
+    let next = read_u32(4)?;
+    if capacity == 0 || next >= capacity {
+        return Err("invalid flight_recorder section".into());
+    }
+
+    let mut events = vec![];
+    for i in 0..capacity {
+        let slot = (next + i) % capacity;
+        let event = read_u32(8 + slot * EVENT_SIZE)?;
+        if event == 0 {
+            continue;
+        }
+
+        let funcidx = (event & !RETURN_BIT)
+            .checked_sub(1)
+            .ok_or("invalid flight_recorder event")?;
+        if event & RETURN_BIT != 0 {
+            events.push(CallEvent::Return(funcidx));
+        } else {
+            events.push(CallEvent::Entry(funcidx));
+        }
+    }
+
+    Ok(events)
+}
+
+/// Write the events, the oldest first, one per line. The functions are named
+/// by `func_name`.
+pub fn write_history<W: Write>(
+    out: &mut W,
+    events: &[CallEvent],
+    func_name: impl Fn(u32) -> String,
+) -> std::fmt::Result {
+    for (i, event) in events.iter().enumerate() {
+        match event {
+            CallEvent::Entry(funcidx) => writeln!(out, "#{}\t-> {}", i, func_name(*funcidx))?,
+            CallEvent::Return(funcidx) => writeln!(out, "#{}\t<- {}", i, func_name(*funcidx))?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,162 @@
+//! Globals recorded in the coredump.
+//!
+//! The values of the selected globals are written by `write_coredump` in the
+//! global section of the coredump, as constant globals in the order of the
+//! module. A name section names them after the globals of the module, so that
+//! a subset can be looked up by name, for instance `__stack_pointer` for the
+//! debugger to find the variables on the stack.
+
+use crate::filter;
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::WasmModule;
+use log::warn;
+
+type BoxError = Box<dyn std::error::Error>;
+
+// Subsection of the name section naming the globals.
+const GLOBAL_NAMES_SUBSECTION: u8 = 7;
+
+/// Globals whose value is recorded in the coredump.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum RecordedGlobals {
+    #[default]
+    None,
+    /// All the globals of the module, imported ones included.
+    All,
+    /// Globals whose name in the name section matches one of the glob
+    /// patterns.
+    Matching(Vec<String>),
+}
+
+/// Global recorded in the coredump.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedGlobal {
+    pub(crate) globalidx: u32,
+    pub(crate) valtype: ast::NumType,
+    pub(crate) name: Option<String>,
+}
+
+impl RecordedGlobal {
+    /// Size of its entry in the global section: type, mutability, constant
+    /// instruction with a padded value and `end`.
+    pub(crate) fn entry_size(&self) -> u32 {
+        let value_size = match self.valtype {
+            ast::NumType::I32 => 5,
+            ast::NumType::I64 => 10,
+            ast::NumType::F32 => 4,
+            ast::NumType::F64 => 8,
+        };
+        3 + value_size + 1
+    }
+}
+
+/// Select the globals to record, among the globals currently in the module.
+pub(crate) fn select(
+    module: &WasmModule,
+    recorded: &RecordedGlobals,
+) -> Result<Vec<RecordedGlobal>, BoxError> {
+    let patterns = match recorded {
+        RecordedGlobals::None => return Ok(vec![]),
+        RecordedGlobals::All => None,
+        RecordedGlobals::Matching(patterns) => Some(
+            patterns
+                .iter()
+                .map(|pattern| filter::glob_to_regex(pattern))
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+    };
+
+    let imported = module
+        .imports()
+        .iter()
+        .filter_map(|import| match &import.import_type {
+            ast::ImportType::Global(global_type) => Some(global_type),
+            _ => None,
+        });
+    let defined = module.globals().iter().map(|global| &global.global_type);
+
+    let mut out = vec![];
+    for (globalidx, global_type) in imported.chain(defined).enumerate() {
+        let globalidx = globalidx as u32;
+        let name = module.get_global_name(globalidx);
+        if let Some(patterns) = &patterns {
+            let Some(name) = &name else {
+                continue;
+            };
+            if !patterns.iter().any(|re| re.is_match(name)) {
+                continue;
+            }
+        }
+
+        // References and vectors can't be written as constant globals.
+        let ast::ValueType::NumType(valtype) = &global_type.valtype else {
+            continue;
+        };
+        out.push(RecordedGlobal {
+            globalidx,
+            valtype: valtype.clone(),
+            name,
+        });
+    }
+
+    if patterns.is_some() && out.is_empty() {
+        warn!("no global matches the patterns, check that the module has a name section");
+    }
+    Ok(out)
+}
+
+fn write_unsigned_leb128(buffer: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer.push(byte);
+            break;
+        }
+        buffer.push(byte | 0x80);
+    }
+}
+
+/// Header of the global section, with its size and the number of globals.
+pub(crate) fn global_section_header(globals: &[RecordedGlobal]) -> Vec<u8> {
+    let mut count = vec![];
+    write_unsigned_leb128(&mut count, globals.len() as u32);
+    let size = count.len() as u32 + globals.iter().map(|g| g.entry_size()).sum::<u32>();
+
+    let mut header = vec![6];
+    write_unsigned_leb128(&mut header, size);
+    header.extend(count);
+    header
+}
+
+/// Name section naming the globals of the coredump, after the globals of the
+/// module.
+pub(crate) fn name_section(globals: &[RecordedGlobal]) -> Vec<u8> {
+    let named = globals
+        .iter()
+        .enumerate()
+        .filter_map(|(i, global)| Some((i as u32, global.name.as_ref()?)))
+        .collect::<Vec<_>>();
+    if named.is_empty() {
+        return vec![];
+    }
+
+    let mut names = vec![];
+    write_unsigned_leb128(&mut names, named.len() as u32);
+    for (globalidx, name) in named {
+        write_unsigned_leb128(&mut names, globalidx);
+        write_unsigned_leb128(&mut names, name.len() as u32);
+        names.extend(name.bytes());
+    }
+
+    let mut content = vec![4];
+    content.extend(b"name");
+    content.push(GLOBAL_NAMES_SUBSECTION);
+    write_unsigned_leb128(&mut content, names.len() as u32);
+    content.extend(names);
+
+    let mut section = vec![0];
+    write_unsigned_leb128(&mut section, content.len() as u32);
+    section.extend(content);
+    section
+}
@@ -0,0 +1,43 @@
+//! Instance index recorded in the stack frames.
+//!
+//! By default the index given by [crate::RewriteOptions::instance_id] is
+//! baked into every frame, which requires knowing the order in which the
+//! instances are created. When a module is instantiated several times the
+//! index can instead be given at runtime, by an imported global or by
+//! calling an exported setter after instantiation.
+
+use core_wasm_ast as ast;
+
+/// Export setting the instance index, called with the index.
+pub(crate) const SETTER_EXPORT: &str = "coredump_set_instance_id";
+
+/// Where the instance index comes from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum InstanceIdSource {
+    /// [crate::RewriteOptions::instance_id], baked into the frames.
+    #[default]
+    Constant,
+    /// Immutable i32 global imported from `module`.`name`, given at
+    /// instantiation.
+    Import { module: String, name: String },
+    /// Mutable global set by calling the exported `coredump_set_instance_id`
+    /// function. It holds [crate::RewriteOptions::instance_id] until then.
+    Setter,
+}
+
+/// Instance index of the frames, as written by the instrumentation.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum InstanceId {
+    Constant(u32),
+    Global(u32),
+}
+
+impl InstanceId {
+    /// Instruction pushing the instance index.
+    pub(crate) fn instr(self) -> ast::Instr {
+        match self {
+            InstanceId::Constant(instance_id) => ast::Instr::i32_const(instance_id as i64),
+            InstanceId::Global(globalidx) => ast::Instr::global_get(globalidx),
+        }
+    }
+}
@@ -1,4 +1,34 @@
+mod coredump_section;
+mod dwarf;
+mod exceptions;
+mod filter;
+mod flight_recorder;
+mod globals;
+mod instance;
+mod marker;
+mod options;
+mod panic;
 mod rewriter;
 mod runtime;
+mod shadow_stack;
+mod snapshot;
+#[cfg(test)]
+mod test;
+mod threads;
+mod wasi;
 
-pub use rewriter::rewrite;
+pub use filter::FuncFilter;
+pub use flight_recorder::{
+    read_flight_recorder, write_history, CallEvent, FlightRecorder, FLIGHT_RECORDER_SECTION,
+};
+pub use globals::RecordedGlobals;
+pub use instance::InstanceIdSource;
+pub use options::{RewriteOptions, RewriteReport};
+pub use panic::{read_crash_metadata, PanicRecord, CRASH_METADATA_SECTION};
+pub use rewriter::{
+    rewrite, rewrite_bytes, rewrite_component, CoredumpPlacement, FrameLocals,
+    COREDUMP_MEMORY_EXPORT,
+};
+pub use shadow_stack::ShadowStack;
+pub use threads::ThreadFrames;
+pub use wasi::WasiCoredumpFile;
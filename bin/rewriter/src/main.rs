@@ -1,31 +1,285 @@
 use clap::Parser;
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::WasmModule;
 use log::info;
+use std::fs;
 use std::io::stdin;
 use std::io::stdout;
 use std::io::Read;
 use std::io::Write;
-use std::sync::Arc;
 use std::time::Instant;
+use wasm_coredump_rewriter::{
+    CoredumpPlacement, FlightRecorder, FrameLocals, FuncFilter, InstanceIdSource, RecordedGlobals,
+    RewriteOptions, RewriteReport, ShadowStack, ThreadFrames, WasiCoredumpFile,
+};
 
-mod rewriter;
-mod runtime;
+mod config;
+
+use config::flag;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Input Wasm module, defaults to stdin.
+    input: Option<String>,
+
+    #[arg(short, long)]
+    /// Output Wasm module, defaults to stdout.
+    output: Option<String>,
+
     #[arg(long)]
+    /// Read options from a TOML file, keys are the long flags. Flags given
+    /// on the command line take precedence, `--<FLAG>=false` turns off a
+    /// flag set in the file.
+    config: Option<String>,
+
+    #[arg(long)]
+    /// Print a summary of the instrumentation to stderr.
+    stats: bool,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
     /// Wraps each memory operation.
     /// This will likely reduce significantly your program's performance.
-    check_memory_operations: bool,
+    check_memory_operations: Option<bool>,
 
-    #[arg(long)]
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
     /// Enable debugging, mostly useful for developing this tooling.
-    debug: bool,
+    debug: Option<bool>,
 
-    #[arg(long, default_value_t = 0)]
-    /// Specify the instance index to use in stack frames.
+    #[arg(long)]
+    /// Specify the instance index to use in stack frames, defaults to 0.
     /// MUST match the order in which instances are instantiated at runtime.
-    instance_id: u32,
+    /// For a component, index of its first core module, the following ones
+    /// are numbered in order.
+    instance_id: Option<u32>,
+
+    #[arg(long, value_name = "MODULE.NAME")]
+    /// Read the instance index from an imported i32 global instead of
+    /// --instance-id, for modules instantiated several times.
+    instance_id_import: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Export a `coredump_set_instance_id` function setting the instance
+    /// index after instantiation, it's --instance-id until then.
+    instance_id_setter: Option<bool>,
+
+    #[arg(long, value_enum)]
+    /// Where to write the coredump in memory, defaults to `reserved`.
+    /// `reserved` uses the range given by --coredump-offset and
+    /// --coredump-size, `grow` grows the memory and writes past the heap,
+    /// `memory` writes in a memory added to the module.
+    coredump_placement: Option<Placement>,
+
+    #[arg(long)]
+    /// Start of the reserved memory range, defaults to 0.
+    coredump_offset: Option<u32>,
+
+    #[arg(long)]
+    /// Size of the reserved memory range, defaults to the rest of the memory.
+    coredump_size: Option<u32>,
+
+    #[arg(long)]
+    /// Write the coredump to <NAME>.coredump.wasm using WASI, in the
+    /// preopened directory given by --wasi-dirfd.
+    wasi_coredump_name: Option<String>,
+
+    #[arg(long)]
+    /// File descriptor of the WASI preopened directory, defaults to 3.
+    wasi_dirfd: Option<u32>,
+
+    #[arg(long)]
+    /// Only instrument functions whose name matches the glob pattern.
+    /// Can be repeated.
+    include_funcs: Vec<String>,
+
+    #[arg(long)]
+    /// Only instrument functions whose name matches the regex.
+    /// Can be repeated.
+    include_funcs_regex: Vec<String>,
+
+    #[arg(long)]
+    /// Don't instrument functions whose name matches the glob pattern.
+    /// Can be repeated.
+    exclude_funcs: Vec<String>,
+
+    #[arg(long)]
+    /// Don't instrument functions whose name matches the regex.
+    /// Can be repeated.
+    exclude_funcs_regex: Vec<String>,
+
+    #[arg(long)]
+    /// Don't record the locals of functions with more locals than this.
+    max_locals: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Only record the funcidx and code offset of the frames, without their
+    /// params and locals. Reduces the size of the rewritten module.
+    frames_only: Option<bool>,
+
+    #[arg(long)]
+    /// Only record the first params and locals of the frames, up to this
+    /// number. The frame base is usually in the first locals.
+    frame_locals: Option<u32>,
+
+    #[arg(long)]
+    /// Unwind the stack and write the coredump when the number of nested
+    /// calls reaches this limit, to capture stack overflows.
+    max_call_depth: Option<u32>,
+
+    #[arg(long)]
+    /// Maintain a shadow call stack in the reserved memory range of this
+    /// size, to read the frames after any trap.
+    shadow_stack_size: Option<u32>,
+
+    #[arg(long)]
+    /// Start of the shadow stack memory range, defaults to 0.
+    shadow_stack_offset: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Export a `coredump_snapshot` function writing a coredump of the
+    /// current call stack without stopping the program. Needs a shadow
+    /// stack.
+    coredump_snapshot: Option<bool>,
+
+    #[arg(long)]
+    /// Take a snapshot when entering the functions whose name matches the
+    /// glob pattern. Can be repeated.
+    snapshot_funcs: Vec<String>,
+
+    #[arg(long)]
+    /// Record the recent function calls in the reserved memory range of
+    /// this size and add them to the coredump.
+    flight_recorder_size: Option<u32>,
+
+    #[arg(long)]
+    /// Start of the flight recorder memory range, defaults to 0.
+    flight_recorder_offset: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Also record when calls return in the flight recorder.
+    flight_recorder_exits: Option<bool>,
+
+    #[arg(long)]
+    /// Give each thread a frame buffer of this size in the reserved
+    /// coredump range, for modules with a shared memory.
+    thread_frames_size: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Record the value of all the globals in the coredump.
+    record_all_globals: Option<bool>,
+
+    #[arg(long)]
+    /// Record the value of the globals whose name matches the glob pattern
+    /// in the coredump, for instance `__stack_pointer`. Can be repeated.
+    record_globals: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Add a code_offsets custom section mapping the code offsets of the
+    /// rewritten module to the original ones.
+    offset_map_section: Option<bool>,
+
+    #[arg(long)]
+    /// Write the code offset map to a separate Wasm module.
+    offset_map: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Update the code addresses in the DWARF custom sections to match the
+    /// rewritten module.
+    rewrite_dwarf: Option<bool>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Save the arguments of the Rust panic entry points in the coredump.
+    capture_panics: Option<bool>,
+
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    /// Write the coredump when an exception escapes an exported function,
+    /// for modules using exception handling.
+    capture_exceptions: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Placement {
+    Reserved,
+    Grow,
+    Memory,
 }
 
 type BoxError = Box<dyn std::error::Error>;
@@ -35,33 +289,299 @@ fn main() -> Result<(), BoxError> {
 
     env_logger::init();
 
+    let config = match &args.config {
+        Some(path) => config::Config::load(path)?,
+        None => config::Config::default(),
+    };
+
     let mut input = Vec::new();
-    stdin().read_to_end(&mut input)?;
+    match &args.input {
+        Some(path) => {
+            input = fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))?
+        }
+        None => {
+            stdin().read_to_end(&mut input)?;
+        }
+    }
 
     let now = Instant::now();
-    let module = Arc::new(
-        wasm_parser::parse(&input)
-            .map_err(|err| format!("failed to parse Wasm module: {}", err))?,
-    );
+    let binary = wasm_parser::parse_binary(&input)
+        .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
     let elapsed = now.elapsed();
     info!("decode: {:.2?}", elapsed);
 
+    let placement = match args
+        .coredump_placement
+        .or(config.coredump_placement)
+        .unwrap_or(Placement::Reserved)
+    {
+        Placement::Reserved => CoredumpPlacement::Reserved {
+            offset: args
+                .coredump_offset
+                .or(config.coredump_offset)
+                .unwrap_or_default(),
+            size: args
+                .coredump_size
+                .or(config.coredump_size)
+                .unwrap_or(u32::MAX),
+        },
+        Placement::Grow => CoredumpPlacement::Grow,
+        Placement::Memory => CoredumpPlacement::Memory,
+    };
+
+    let wasi_file = args
+        .wasi_coredump_name
+        .or(config.wasi_coredump_name)
+        .map(|name| WasiCoredumpFile {
+            name,
+            dirfd: args.wasi_dirfd.or(config.wasi_dirfd).unwrap_or(3),
+        });
+
+    // Patterns of the configuration file and the command line are combined
+    let mut filter = FuncFilter::default();
+    for pattern in config.include_funcs.iter().chain(&args.include_funcs) {
+        filter.include_glob(pattern)?;
+    }
+    for pattern in config
+        .include_funcs_regex
+        .iter()
+        .chain(&args.include_funcs_regex)
+    {
+        filter.include_regex(pattern)?;
+    }
+    for pattern in config.exclude_funcs.iter().chain(&args.exclude_funcs) {
+        filter.exclude_glob(pattern)?;
+    }
+    for pattern in config
+        .exclude_funcs_regex
+        .iter()
+        .chain(&args.exclude_funcs_regex)
+    {
+        filter.exclude_regex(pattern)?;
+    }
+    filter.max_locals = args.max_locals.or(config.max_locals);
+
+    let frame_locals = match (
+        flag(args.frames_only, config.frames_only),
+        args.frame_locals.or(config.frame_locals),
+    ) {
+        (true, Some(_)) => {
+            return Err("--frames-only and --frame-locals can't be combined".into());
+        }
+        (true, None) => FrameLocals::None,
+        (false, Some(limit)) => FrameLocals::First(limit),
+        (false, None) => FrameLocals::All,
+    };
+
+    let shadow_stack = args
+        .shadow_stack_size
+        .or(config.shadow_stack_size)
+        .map(|size| ShadowStack {
+            offset: args
+                .shadow_stack_offset
+                .or(config.shadow_stack_offset)
+                .unwrap_or_default(),
+            size,
+        });
+
+    let instance_id_import = args.instance_id_import.or(config.instance_id_import);
+    let instance_id_setter = flag(args.instance_id_setter, config.instance_id_setter);
+    let instance_id_source = match (instance_id_import, instance_id_setter) {
+        (Some(_), true) => {
+            return Err("--instance-id-import and --instance-id-setter can't be combined".into());
+        }
+        (Some(import), false) => {
+            let (module, name) = import
+                .split_once('.')
+                .ok_or_else(|| format!("expected MODULE.NAME, got {}", import))?;
+            InstanceIdSource::Import {
+                module: module.to_owned(),
+                name: name.to_owned(),
+            }
+        }
+        (None, true) => InstanceIdSource::Setter,
+        (None, false) => InstanceIdSource::Constant,
+    };
+
+    let record_globals = config
+        .record_globals
+        .into_iter()
+        .chain(args.record_globals)
+        .collect::<Vec<_>>();
+    let record_globals = if flag(args.record_all_globals, config.record_all_globals) {
+        if !record_globals.is_empty() {
+            return Err("--record-all-globals and --record-globals can't be combined".into());
+        }
+        RecordedGlobals::All
+    } else if !record_globals.is_empty() {
+        RecordedGlobals::Matching(record_globals)
+    } else {
+        RecordedGlobals::None
+    };
+
+    let flight_recorder = args
+        .flight_recorder_size
+        .or(config.flight_recorder_size)
+        .map(|size| FlightRecorder {
+            offset: args
+                .flight_recorder_offset
+                .or(config.flight_recorder_offset)
+                .unwrap_or_default(),
+            size,
+            exits: flag(args.flight_recorder_exits, config.flight_recorder_exits),
+        });
+
+    let offset_map_section = flag(args.offset_map_section, config.offset_map_section);
+    let offset_map = args.offset_map.or(config.offset_map);
+
+    let options = RewriteOptions::new()
+        .check_memory_operations(flag(
+            args.check_memory_operations,
+            config.check_memory_operations,
+        ))
+        .debug(flag(args.debug, config.debug))
+        .instance_id(args.instance_id.or(config.instance_id).unwrap_or_default())
+        .instance_id_source(instance_id_source)
+        .placement(placement)
+        .wasi_file(wasi_file)
+        .filter(filter)
+        .offset_map(offset_map_section || offset_map.is_some())
+        .rewrite_dwarf(flag(args.rewrite_dwarf, config.rewrite_dwarf))
+        .max_call_depth(args.max_call_depth.or(config.max_call_depth))
+        .shadow_stack(shadow_stack)
+        .capture_panics(flag(args.capture_panics, config.capture_panics))
+        .capture_exceptions(flag(args.capture_exceptions, config.capture_exceptions))
+        .snapshot(flag(args.coredump_snapshot, config.coredump_snapshot))
+        .snapshot_funcs(
+            config
+                .snapshot_funcs
+                .into_iter()
+                .chain(args.snapshot_funcs)
+                .collect(),
+        )
+        .flight_recorder(flight_recorder)
+        .thread_frames(
+            args.thread_frames_size
+                .or(config.thread_frames_size)
+                .map(|size| ThreadFrames { size }),
+        )
+        .record_globals(record_globals)
+        .frame_locals(frame_locals);
+
+    let output = match binary {
+        ast::Binary::Module(module) => {
+            let (output, mut report) =
+                rewrite_module(module, &options, offset_map.as_deref(), offset_map_section)?;
+            report.size_delta = Some(output.len() as i64 - input.len() as i64);
+            if args.stats {
+                print_stats(&report);
+            }
+            output
+        }
+        ast::Binary::Component(mut component) => {
+            if offset_map.is_some() {
+                return Err("the offset map of a component can only be written as a section, use --offset-map-section".into());
+            }
+
+            let now = Instant::now();
+            let reports = wasm_coredump_rewriter::rewrite_component(&mut component, &options)?;
+            let elapsed = now.elapsed();
+            info!("transform: {:.2?}", elapsed);
+
+            let now = Instant::now();
+            let output = wasm_printer::wasm::print_component(&component)
+                .map_err(|err| format!("failed to print component: {}", err))?;
+            let elapsed = now.elapsed();
+            info!("print: {:.2?}", elapsed);
+
+            if args.stats {
+                for (i, report) in reports.iter().enumerate() {
+                    eprintln!("core module {}:", i);
+                    print_stats(report);
+                }
+                eprintln!(
+                    "size delta: {:+} bytes",
+                    output.len() as i64 - input.len() as i64
+                );
+            }
+            output
+        }
+    };
+
+    match &args.output {
+        Some(path) => {
+            fs::write(path, output).map_err(|err| format!("failed to write {}: {}", path, err))?
+        }
+        None => stdout().write_all(&output)?,
+    }
+    Ok(())
+}
+
+/// Rewrite a core module and write its offset map to `offset_map`, if given.
+/// The code_offsets section is only kept with `offset_map_section`.
+fn rewrite_module(
+    module: ast::Module,
+    options: &RewriteOptions,
+    offset_map: Option<&str>,
+    offset_map_section: bool,
+) -> Result<(Vec<u8>, RewriteReport), BoxError> {
+    let mut module = WasmModule::new(module);
+
     let now = Instant::now();
-    rewriter::rewrite(
-        Arc::clone(&module),
-        args.check_memory_operations,
-        args.debug,
-        args.instance_id,
-    )?;
+    let report = wasm_coredump_rewriter::rewrite(&mut module, options)?;
     let elapsed = now.elapsed();
     info!("transform: {:.2?}", elapsed);
 
+    // The offset map of an already rewritten module was written by the
+    // previous run
+    if let Some(filename) = offset_map.filter(|_| !report.already_rewritten) {
+        let map = module
+            .get_code_offset_map()
+            .ok_or("code offset map not found")?;
+        let section = ast::CustomSection::CodeOffsets(map.clone());
+
+        let mut sidecar = vec![];
+        wasm_printer::wasm::write_header(&mut sidecar)
+            .map_err(|err| format!("failed to write header: {}", err))?;
+        let section = ast::Section::Custom((ast::Value::new(0), section));
+        wasm_printer::wasm::write_section(&mut sidecar, &section)
+            .map_err(|err| format!("failed to write offset map: {}", err))?;
+        fs::write(filename, sidecar)?;
+
+        if !offset_map_section {
+            module.remove_custom_section("code_offsets");
+        }
+    }
+
     let now = Instant::now();
-    input = wasm_printer::wasm::print(&module)
+    let output = wasm_printer::wasm::print(module.inner())
         .map_err(|err| format!("failed to print Wasm module: {}", err))?;
     let elapsed = now.elapsed();
     info!("print: {:.2?}", elapsed);
 
-    stdout().write_all(&input)?;
-    Ok(())
+    Ok((output, report))
+}
+
+fn print_stats(report: &RewriteReport) {
+    if report.already_rewritten {
+        eprintln!("already rewritten with the same options");
+    }
+    eprintln!("instrumented sites: {}", report.instrumented_sites());
+    eprintln!("  unreachable: {}", report.unreachable_sites);
+    eprintln!("  calls: {}", report.call_sites);
+    eprintln!("  memory operations: {}", report.memory_check_sites);
+    eprintln!("added functions: {}", report.added_funcs.len());
+    for (funcidx, name) in &report.added_funcs {
+        eprintln!("  {}: {}", funcidx, name);
+    }
+    eprintln!("skipped functions: {}", report.skipped_funcs.len());
+    eprintln!("functions without locals: {}", report.no_locals_funcs.len());
+    eprintln!("snapshot functions: {}", report.snapshot_funcs.len());
+    eprintln!("panic entry points: {}", report.panic_entry_points.len());
+    for (funcidx, name) in &report.panic_entry_points {
+        eprintln!("  {}: {}", funcidx, name);
+    }
+    if let Some(size_delta) = report.size_delta {
+        eprintln!("size delta: {:+} bytes", size_delta);
+    }
 }
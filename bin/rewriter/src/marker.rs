@@ -0,0 +1,71 @@
+//! Custom section marking a rewritten module.
+//!
+//! The section records the version of the rewriter and the options it was
+//! given, as `key=value` lines:
+//!
+//! ```text
+//! version=0.2.2
+//! check-memory-operations=false
+//! coredump-placement=grow
+//! ...
+//! ```
+
+use crate::options::RewriteOptions;
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::WasmModule;
+
+pub(crate) const SECTION_NAME: &str = "coredump_rewriter";
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+type BoxError = Box<dyn std::error::Error>;
+
+pub(crate) fn make_section(options: &RewriteOptions) -> ast::CustomSection {
+    let mut content = format!("version={}\n", VERSION);
+    for (key, value) in options.describe() {
+        content.push_str(&format!("{}={}\n", key, value));
+    }
+
+    ast::CustomSection::Unknown(SECTION_NAME.to_owned(), content.into_bytes())
+}
+
+/// Check if the module was already rewritten. Rewriting it again with the
+/// same options is a no-op; with other options, or by a version of the
+/// rewriter that didn't add the marker, the original module has to be
+/// rewritten instead.
+pub(crate) fn is_rewritten(
+    module: &WasmModule,
+    options: &RewriteOptions,
+) -> Result<bool, BoxError> {
+    let Some(bytes) = module.get_custom_section(SECTION_NAME) else {
+        if module.get_export_func("get_coredump_ptr").is_ok() {
+            return Err("module was already rewritten, rewrite the original module instead".into());
+        }
+        return Ok(false);
+    };
+
+    let content = String::from_utf8(bytes)
+        .map_err(|err| format!("invalid {} section: {}", SECTION_NAME, err))?;
+    let mut previous = vec![];
+    let mut version = None;
+    for line in content.lines() {
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("invalid {} section: {}", SECTION_NAME, line))?;
+        if key == "version" {
+            version = Some(value);
+        } else {
+            previous.push((key, value.to_owned()));
+        }
+    }
+
+    if previous != options.describe() {
+        return Err(format!(
+            "module was already rewritten by version {} with other options, rewrite the original module instead",
+            version.unwrap_or("unknown")
+        )
+        .into());
+    }
+
+    Ok(true)
+}
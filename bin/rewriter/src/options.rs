@@ -0,0 +1,299 @@
+//! Options and report of a rewrite.
+
+use crate::filter::FuncFilter;
+use crate::flight_recorder::FlightRecorder;
+use crate::globals::RecordedGlobals;
+use crate::instance::InstanceIdSource;
+use crate::rewriter::{CoredumpPlacement, FrameLocals};
+use crate::shadow_stack::ShadowStack;
+use crate::threads::ThreadFrames;
+use crate::wasi::WasiCoredumpFile;
+
+/// Options of [crate::rewrite].
+///
+/// # Examples
+///
+/// ```
+/// let options = wasm_coredump_rewriter::RewriteOptions::new()
+///     .instance_id(1)
+///     .placement(wasm_coredump_rewriter::CoredumpPlacement::Grow);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RewriteOptions {
+    pub(crate) check_memory_operations: bool,
+    pub(crate) debug: bool,
+    pub(crate) instance_id: u32,
+    pub(crate) instance_id_source: InstanceIdSource,
+    pub(crate) placement: CoredumpPlacement,
+    pub(crate) wasi_file: Option<WasiCoredumpFile>,
+    pub(crate) filter: FuncFilter,
+    pub(crate) offset_map: bool,
+    pub(crate) rewrite_dwarf: bool,
+    pub(crate) max_call_depth: Option<u32>,
+    pub(crate) shadow_stack: Option<ShadowStack>,
+    pub(crate) capture_panics: bool,
+    pub(crate) capture_exceptions: bool,
+    pub(crate) snapshot: bool,
+    pub(crate) snapshot_funcs: Vec<String>,
+    pub(crate) flight_recorder: Option<FlightRecorder>,
+    pub(crate) thread_frames: Option<ThreadFrames>,
+    pub(crate) record_globals: RecordedGlobals,
+    pub(crate) frame_locals: FrameLocals,
+}
+
+impl RewriteOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        RewriteOptions::default()
+    }
+
+    /// Wrap each memory operation with a bounds check.
+    /// This will likely reduce significantly the program's performance.
+    pub fn check_memory_operations(mut self, check_memory_operations: bool) -> Self {
+        self.check_memory_operations = check_memory_operations;
+        self
+    }
+
+    /// Export the runtime internals, mostly useful for developing this
+    /// tooling.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Instance index to use in stack frames. Must match the order in which
+    /// instances are instantiated at runtime, see
+    /// [RewriteOptions::instance_id_source] to give it at runtime instead.
+    /// For a component, index of its first core module, see
+    /// [crate::rewrite_component].
+    pub fn instance_id(mut self, instance_id: u32) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    /// Where the instance index comes from, by default
+    /// [RewriteOptions::instance_id] is baked into the frames.
+    pub fn instance_id_source(mut self, instance_id_source: InstanceIdSource) -> Self {
+        self.instance_id_source = instance_id_source;
+        self
+    }
+
+    /// Where to write the coredump in memory.
+    pub fn placement(mut self, placement: CoredumpPlacement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Write the coredump to a file using WASI.
+    pub fn wasi_file(mut self, wasi_file: Option<WasiCoredumpFile>) -> Self {
+        self.wasi_file = wasi_file;
+        self
+    }
+
+    /// Select the functions to instrument.
+    pub fn filter(mut self, filter: FuncFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Add a code_offsets custom section mapping the code offsets of the
+    /// rewritten module to the original ones.
+    pub fn offset_map(mut self, offset_map: bool) -> Self {
+        self.offset_map = offset_map;
+        self
+    }
+
+    /// Update the code addresses in the DWARF custom sections, otherwise
+    /// they keep describing the original module.
+    pub fn rewrite_dwarf(mut self, rewrite_dwarf: bool) -> Self {
+        self.rewrite_dwarf = rewrite_dwarf;
+        self
+    }
+
+    /// Unwind the stack and write the coredump when the number of nested
+    /// calls reaches the limit, before the engine runs out of stack.
+    pub fn max_call_depth(mut self, max_call_depth: Option<u32>) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Maintain a shadow call stack in a reserved memory region, to read the
+    /// frames after traps the rewriter can't intercept.
+    pub fn shadow_stack(mut self, shadow_stack: Option<ShadowStack>) -> Self {
+        self.shadow_stack = shadow_stack;
+        self
+    }
+
+    /// Save the arguments of the Rust panic entry points and add them to
+    /// the coredump.
+    pub fn capture_panics(mut self, capture_panics: bool) -> Self {
+        self.capture_panics = capture_panics;
+        self
+    }
+
+    /// Write the coredump when an exception escapes an exported function,
+    /// for modules using exception handling.
+    pub fn capture_exceptions(mut self, capture_exceptions: bool) -> Self {
+        self.capture_exceptions = capture_exceptions;
+        self
+    }
+
+    /// Export a `coredump_snapshot` function writing a coredump of the
+    /// current call stack without stopping the program. Needs a shadow
+    /// stack.
+    pub fn snapshot(mut self, snapshot: bool) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Take a snapshot when entering the functions whose name matches one of
+    /// the glob patterns. Implies [RewriteOptions::snapshot].
+    pub fn snapshot_funcs(mut self, snapshot_funcs: Vec<String>) -> Self {
+        self.snapshot_funcs = snapshot_funcs;
+        self
+    }
+
+    /// Record the recent function calls in a reserved memory region and add
+    /// them to the coredump.
+    pub fn flight_recorder(mut self, flight_recorder: Option<FlightRecorder>) -> Self {
+        self.flight_recorder = flight_recorder;
+        self
+    }
+
+    /// Give each thread its own frame buffer in the reserved coredump range,
+    /// for modules with a shared memory.
+    pub fn thread_frames(mut self, thread_frames: Option<ThreadFrames>) -> Self {
+        self.thread_frames = thread_frames;
+        self
+    }
+
+    /// Record the value of globals in the coredump, for instance
+    /// `__stack_pointer` to find the variables on the stack.
+    pub fn record_globals(mut self, record_globals: RecordedGlobals) -> Self {
+        self.record_globals = record_globals;
+        self
+    }
+
+    /// Params and locals recorded in each frame, all of them by default.
+    /// Recording fewer of them reduces the size of the rewritten module.
+    pub fn frame_locals(mut self, frame_locals: FrameLocals) -> Self {
+        self.frame_locals = frame_locals;
+        self
+    }
+
+    pub(crate) fn snapshot_enabled(&self) -> bool {
+        self.snapshot || !self.snapshot_funcs.is_empty()
+    }
+
+    /// Options affecting the instrumentation, recorded in the rewritten
+    /// module.
+    pub(crate) fn describe(&self) -> Vec<(&'static str, String)> {
+        let mut out = vec![
+            (
+                "check-memory-operations",
+                self.check_memory_operations.to_string(),
+            ),
+            ("debug", self.debug.to_string()),
+            ("instance-id", self.instance_id.to_string()),
+        ];
+
+        match &self.instance_id_source {
+            InstanceIdSource::Constant => {}
+            InstanceIdSource::Import { module, name } => {
+                out.push(("instance-id-import", format!("{}.{}", module, name)));
+            }
+            InstanceIdSource::Setter => out.push(("instance-id-setter", "true".to_owned())),
+        }
+
+        match self.placement {
+            CoredumpPlacement::Reserved { offset, size } => {
+                out.push(("coredump-placement", "reserved".to_owned()));
+                out.push(("coredump-offset", offset.to_string()));
+                out.push(("coredump-size", size.to_string()));
+            }
+            CoredumpPlacement::Grow => out.push(("coredump-placement", "grow".to_owned())),
+            CoredumpPlacement::Memory => out.push(("coredump-placement", "memory".to_owned())),
+        }
+
+        if let Some(file) = &self.wasi_file {
+            out.push(("wasi-coredump-name", file.name.clone()));
+            out.push(("wasi-dirfd", file.dirfd.to_string()));
+        }
+
+        out.extend(self.filter.describe());
+        out.push(("offset-map", self.offset_map.to_string()));
+        out.push(("rewrite-dwarf", self.rewrite_dwarf.to_string()));
+        if let Some(max_call_depth) = self.max_call_depth {
+            out.push(("max-call-depth", max_call_depth.to_string()));
+        }
+        if let Some(stack) = &self.shadow_stack {
+            out.push(("shadow-stack-offset", stack.offset.to_string()));
+            out.push(("shadow-stack-size", stack.size.to_string()));
+        }
+        out.push(("capture-panics", self.capture_panics.to_string()));
+        if !self.capture_exceptions {
+            out.push(("capture-exceptions", "false".to_owned()));
+        }
+        out.push(("coredump-snapshot", self.snapshot_enabled().to_string()));
+        for pattern in &self.snapshot_funcs {
+            out.push(("snapshot-funcs", pattern.clone()));
+        }
+        if let Some(recorder) = &self.flight_recorder {
+            out.push(("flight-recorder-offset", recorder.offset.to_string()));
+            out.push(("flight-recorder-size", recorder.size.to_string()));
+            out.push(("flight-recorder-exits", recorder.exits.to_string()));
+        }
+        if let Some(threads) = &self.thread_frames {
+            out.push(("thread-frames-size", threads.size.to_string()));
+        }
+        match self.frame_locals {
+            FrameLocals::All => {}
+            FrameLocals::None => out.push(("frames-only", "true".to_owned())),
+            FrameLocals::First(limit) => out.push(("frame-locals", limit.to_string())),
+        }
+        match &self.record_globals {
+            RecordedGlobals::None => {}
+            RecordedGlobals::All => out.push(("record-all-globals", "true".to_owned())),
+            RecordedGlobals::Matching(patterns) => {
+                for pattern in patterns {
+                    out.push(("record-globals", pattern.clone()));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Summary of a rewrite.
+#[derive(Debug, Default, Clone)]
+pub struct RewriteReport {
+    /// `unreachable` instructions replaced by the unwinding code.
+    pub unreachable_sites: usize,
+    /// Calls followed by an unwinding check.
+    pub call_sites: usize,
+    /// Memory operations wrapped with a bounds check.
+    pub memory_check_sites: usize,
+    /// Functions added by the rewriter, by funcidx and name.
+    pub added_funcs: Vec<(u32, String)>,
+    /// Functions excluded by the filter, they don't record a frame.
+    pub skipped_funcs: Vec<u32>,
+    /// Functions recording a frame without their locals.
+    pub no_locals_funcs: Vec<u32>,
+    /// Panic entry points whose arguments are saved, by funcidx and name.
+    pub panic_entry_points: Vec<(u32, String)>,
+    /// Functions taking a snapshot when they are entered.
+    pub snapshot_funcs: Vec<u32>,
+    /// Size difference in bytes between the rewritten module and the input.
+    /// Only known once the module is printed, see [crate::rewrite_bytes].
+    pub size_delta: Option<i64>,
+    /// The module was already rewritten with the same options and was left
+    /// untouched.
+    pub already_rewritten: bool,
+}
+
+impl RewriteReport {
+    /// Total number of instrumented sites.
+    pub fn instrumented_sites(&self) -> usize {
+        self.unreachable_sites + self.call_sites + self.memory_check_sites
+    }
+}
@@ -0,0 +1,268 @@
+//! Capture of Rust panics.
+//!
+//! The standard panic entry points are found by name; when they are called
+//! their arguments, pointers to the message and location of the panic, are
+//! saved in globals. Once the coredump is written, a `crash_metadata`
+//! custom section is appended to it with the following structure:
+//!
+//! | count: u32 | (funcidx: u32, arg_count: u32, arg: u32*)* |
+//!
+//! With an entry for each entry point that was called. Values are little
+//! endian.
+//!
+//! Interpreting the arguments is left to the debugger, the memory they point
+//! to is in the coredump.
+
+use crate::coredump_section;
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::WasmModule;
+use log::{debug, warn};
+use rustc_demangle::demangle;
+
+type BoxError = Box<dyn std::error::Error>;
+
+pub const CRASH_METADATA_SECTION: &str = "crash_metadata";
+
+/// Functions whose arguments are saved, by their demangled path without the
+/// hash. A function also matches if its path ends with the name, since
+/// internal symbols of std are mangled differently depending on the Rust
+/// version.
+const ENTRY_POINTS: &[&str] = &[
+    "core::panicking::panic_fmt",
+    "std::panicking::begin_panic_handler",
+    "rust_begin_unwind",
+    "std::panicking::rust_panic_with_hook",
+    "rust_panic",
+    "__rust_start_panic",
+];
+
+/// Panic entry point found in the module.
+#[derive(Debug, Clone)]
+pub(crate) struct EntryPoint {
+    pub(crate) funcidx: u32,
+    pub(crate) name: String,
+    /// Set once the function is called.
+    entered_global: u32,
+    /// Arguments of the latest call.
+    arg_globals: Vec<u32>,
+}
+
+/// Arguments of a panic entry point, read from the `crash_metadata` section
+/// of a coredump.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PanicRecord {
+    pub funcidx: u32,
+    pub args: Vec<u32>,
+}
+
+fn is_entry_point(name: &str) -> bool {
+    let name = format!("{:#}", demangle(name));
+    ENTRY_POINTS
+        .iter()
+        .any(|path| name == *path || name.ends_with(&format!("::{}", path)))
+}
+
+fn add_i32_global(module: &mut WasmModule, name: &str) -> u32 {
+    let expr = ast::Value::new(vec![
+        ast::Value::new(ast::Instr::i32_const(0)),
+        ast::Value::new(ast::Instr::end),
+    ]);
+    let global = ast::Global {
+        global_type: ast::GlobalType {
+            valtype: ast::ValueType::NumType(ast::NumType::I32),
+            mutable: true,
+        },
+        expr,
+    };
+    let globalidx = module.add_global(&global).unwrap();
+    module.add_global_name(globalidx, name);
+    globalidx
+}
+
+/// Find the panic entry points of the module and add the globals holding
+/// their arguments.
+pub(crate) fn add_entry_points(module: &mut WasmModule) -> Vec<EntryPoint> {
+    let mut entry_points = vec![];
+    for funcidx in module.defined_funcs() {
+        let Some(name) = module.get_func_name(funcidx) else {
+            continue;
+        };
+        if !is_entry_point(&name) {
+            continue;
+        }
+
+        let params = module.get_func_type(funcidx).params.clone();
+        if params
+            .iter()
+            .any(|param| *param != ast::ValueType::NumType(ast::NumType::I32))
+        {
+            warn!("panic entry point {} has non-i32 arguments, ignoring", name);
+            continue;
+        }
+
+        let entered_global = add_i32_global(module, &format!("panic/{}/entered", funcidx));
+        let arg_globals = (0..params.len())
+            .map(|i| add_i32_global(module, &format!("panic/{}/arg{}", funcidx, i)))
+            .collect();
+        debug!("panic entry point {} at {}", name, funcidx);
+
+        entry_points.push(EntryPoint {
+            funcidx,
+            name,
+            entered_global,
+            arg_globals,
+        });
+    }
+
+    entry_points
+}
+
+/// Save the arguments of the entry point.
+pub(crate) fn make_prologue(entry_point: &EntryPoint) -> Vec<ast::Instr> {
+    let mut body = vec![
+        ast::Instr::i32_const(1),
+        ast::Instr::global_set(entry_point.entered_global),
+    ];
+    for (i, &global) in entry_point.arg_globals.iter().enumerate() {
+        body.push(ast::Instr::local_get(i as u32));
+        body.push(ast::Instr::global_set(global));
+    }
+    body
+}
+
+/// Build a function that writes the coredump, using `write_coredump`, and
+/// appends the `crash_metadata` section if a panic entry point was called.
+///
+/// The frames record the funcidx of the original module, without the
+/// `funcidx_shift` imports added by the rewriter. The coredump is in the
+/// memory `coredump_memory`.
+pub(crate) fn make_write_crash_metadata(
+    entry_points: &[EntryPoint],
+    write_coredump: u32,
+    funcidx_shift: u32,
+    coredump_memory: u32,
+) -> ast::Code {
+    // locals
+    let ptr = 0;
+    let entry = 1;
+    let count = 2;
+
+    let mut body = vec![ast::Instr::call(write_coredump)];
+
+    // Nothing to add unless the program panicked
+    for (i, entry_point) in entry_points.iter().enumerate() {
+        body.push(ast::Instr::global_get(entry_point.entered_global));
+        if i > 0 {
+            body.push(ast::Instr::i32_or);
+        }
+    }
+    body.push(ast::Instr::i32_eqz);
+    let if_body = vec![
+        ast::Value::new(ast::Instr::Return),
+        ast::Value::new(ast::Instr::end),
+    ];
+    body.push(ast::Instr::If(
+        ast::BlockType::Empty,
+        ast::Value::new(if_body),
+    ));
+
+    let content_size = entry_points
+        .iter()
+        .map(|entry_point| 8 + entry_point.arg_globals.len() as u32 * 4)
+        .sum::<u32>()
+        + 4;
+    body.extend(coredump_section::make_append(
+        CRASH_METADATA_SECTION,
+        content_size,
+        ptr,
+        coredump_memory,
+    ));
+
+    body.push(ast::Instr::local_get(ptr));
+    body.push(ast::Instr::i32_const(4));
+    body.push(ast::Instr::i32_add);
+    body.push(ast::Instr::local_set(entry));
+
+    for entry_point in entry_points {
+        let arg_count = entry_point.arg_globals.len() as u32;
+
+        let mut if_body = vec![
+            ast::Instr::local_get(entry),
+            ast::Instr::i32_const((entry_point.funcidx - funcidx_shift) as i64),
+            ast::Instr::i32_store(ast::MemArg::with_memidx(2, 0, coredump_memory)),
+            ast::Instr::local_get(entry),
+            ast::Instr::i32_const(arg_count as i64),
+            ast::Instr::i32_store(ast::MemArg::with_memidx(2, 4, coredump_memory)),
+        ];
+        for (i, &global) in entry_point.arg_globals.iter().enumerate() {
+            if_body.push(ast::Instr::local_get(entry));
+            if_body.push(ast::Instr::global_get(global));
+            if_body.push(ast::Instr::i32_store(ast::MemArg::with_memidx(
+                2,
+                8 + i as u32 * 4,
+                coredump_memory,
+            )));
+        }
+        if_body.extend([
+            ast::Instr::local_get(entry),
+            ast::Instr::i32_const((8 + arg_count * 4) as i64),
+            ast::Instr::i32_add,
+            ast::Instr::local_set(entry),
+            ast::Instr::local_get(count),
+            ast::Instr::i32_const(1),
+            ast::Instr::i32_add,
+            ast::Instr::local_set(count),
+            ast::Instr::end,
+        ]);
+
+        body.push(ast::Instr::global_get(entry_point.entered_global));
+        body.push(ast::Instr::If(
+            ast::BlockType::Empty,
+            ast::Value::new(if_body.into_iter().map(ast::Value::new).collect()),
+        ));
+    }
+
+    body.push(ast::Instr::local_get(ptr));
+    body.push(ast::Instr::local_get(count));
+    body.push(ast::Instr::i32_store(ast::MemArg::with_memidx(
+        2,
+        0,
+        coredump_memory,
+    )));
+
+    body.push(ast::Instr::end);
+
+    ast::Code {
+        locals: vec![ast::CodeLocal {
+            count: 3,
+            value_type: ast::ValueType::NumType(ast::NumType::I32),
+        }],
+        size: ast::Value::new(0), // printer calculates based on the body
+        body: ast::Value::new(body.into_iter().map(ast::Value::new).collect()),
+    }
+}
+
+/// Parse the content of the `crash_metadata` section of a coredump.
+pub fn read_crash_metadata(content: &[u8]) -> Result<Vec<PanicRecord>, BoxError> {
+    let mut offset = 0;
+    let mut read_u32 = || -> Result<u32, BoxError> {
+        let bytes = content
+            .get(offset..offset + 4)
+            .ok_or("truncated crash_metadata section")?;
+        offset += 4;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let count = read_u32()?;
+    let mut records = vec![];
+    for _ in 0..count {
+        let funcidx = read_u32()?;
+        let arg_count = read_u32()?;
+        let args = (0..arg_count)
+            .map(|_| read_u32())
+            .collect::<Result<_, _>>()?;
+        records.push(PanicRecord { funcidx, args });
+    }
+
+    Ok(records)
+}
@@ -1,37 +1,385 @@
 //! Guest coredump generation.
 //!
-//! Informations about the stack is recorded at offset 0 in memory with the
-//! following structure:
+//! Informations about the stack is recorded in memory, at the location
+//! selected by [CoredumpPlacement], with the following structure:
 //!
 //! | frame* |
 //!
 //! Where a `frame` is the Coredump frame encoding.
-
-use crate::runtime::get_runtime;
+//!
+//! The coredump is then written at the same location and spans until the end
+//! of the memory. The location is exported by the `get_coredump_ptr` function,
+//! in the memory exported as `coredump_memory` with
+//! [CoredumpPlacement::Memory].
+
+use crate::dwarf;
+use crate::exceptions;
+use crate::filter;
+use crate::flight_recorder::{self, FlightRecorder};
+use crate::globals;
+use crate::instance::{self, InstanceId, InstanceIdSource};
+use crate::marker;
+use crate::options::{RewriteOptions, RewriteReport};
+use crate::panic;
+use crate::runtime::{Runtime, RuntimeGlobals};
+use crate::shadow_stack::{self, ShadowStack};
+use crate::snapshot;
+use crate::threads;
+use crate::wasi::{add_wasi_imports, make_write_coredump_file};
 use core_wasm_ast as ast;
-use core_wasm_ast::traverse::{self, Visitor, VisitorContext, WasmModule};
-use log::debug;
-use std::sync::Arc;
-use std::sync::Mutex;
+use core_wasm_ast::traverse::{self, CatchForm, Visitor, VisitorContext, WasmModule};
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 // Default value for the entry_funcidx global, indicates the absence of a
 // value.
 const NO_ENTRY_FUNCIDX_VALUE: i32 = i32::MAX;
 
+// Name of the thread in the coredump.
+const THREAD_NAME: &str = "main";
+
+/// Export name of the memory added by [CoredumpPlacement::Memory].
+pub const COREDUMP_MEMORY_EXPORT: &str = "coredump_memory";
+
 type BoxError = Box<dyn std::error::Error>;
 
+/// Location of the coredump in memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoredumpPlacement {
+    /// Range of memory reserved by the program, starting at `offset` and
+    /// spanning `size` bytes. Memory before `offset` is copied in the
+    /// coredump, the range has to fit the copy.
+    Reserved { offset: u32, size: u32 },
+    /// Grow the memory at the time of the crash and write the coredump past
+    /// the end of the heap.
+    Grow,
+    /// Write the coredump in a memory added to the module and exported as
+    /// [COREDUMP_MEMORY_EXPORT], keeping the program's memory intact. The
+    /// engine needs to support multiple memories.
+    Memory,
+}
+
+impl Default for CoredumpPlacement {
+    fn default() -> Self {
+        CoredumpPlacement::Reserved {
+            offset: 0,
+            size: u32::MAX,
+        }
+    }
+}
+
+/// Params and locals recorded in each frame. Recording fewer values makes
+/// the rewritten module smaller.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FrameLocals {
+    /// All the params and locals.
+    #[default]
+    All,
+    /// No values, frames only have the funcidx and the code offset.
+    None,
+    /// The first params and locals, up to the limit. Compilers usually keep
+    /// the frame base in the first locals.
+    First(u32),
+}
+
+/// Parse, rewrite and print a Wasm module.
+pub fn rewrite_bytes(
+    input: &[u8],
+    options: &RewriteOptions,
+) -> Result<(Vec<u8>, RewriteReport), BoxError> {
+    let mut module = WasmModule::new(
+        wasm_parser::parse(input).map_err(|err| format!("failed to parse Wasm module: {}", err))?,
+    );
+
+    let mut report = rewrite(&mut module, options)?;
+
+    let output = wasm_printer::wasm::print(module.inner())
+        .map_err(|err| format!("failed to print Wasm module: {}", err))?;
+    report.size_delta = Some(output.len() as i64 - input.len() as i64);
+
+    Ok((output, report))
+}
+
+/// Add the coredump runtime to each core module of a component.
+///
+/// The modules are given consecutive instance indices starting at
+/// [RewriteOptions::instance_id], in the order they appear in the binary.
+pub fn rewrite_component(
+    component: &mut ast::Component,
+    options: &RewriteOptions,
+) -> Result<Vec<RewriteReport>, BoxError> {
+    if options.wasi_file.is_some() {
+        return Err("writing the coredump with WASI adds imports to the core modules, which the component doesn't provide".into());
+    }
+
+    let mut reports = vec![];
+    for (i, module) in component.core_modules_mut().into_iter().enumerate() {
+        let options = options.clone().instance_id(options.instance_id + i as u32);
+
+        let mut wasm_module = WasmModule::new(std::mem::take(module));
+        let report = rewrite(&mut wasm_module, &options)
+            .map_err(|err| format!("failed to rewrite core module {}: {}", i, err))?;
+        *module = wasm_module.into_inner();
+
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+/// Add the coredump runtime to the module.
 pub fn rewrite(
-    module_ast: Arc<ast::Module>,
-    check_memory_operations: bool,
-    debug: bool,
-    instance_id: u32,
-) -> Result<(), BoxError> {
-    let module = WasmModule::new(Arc::clone(&module_ast));
+    module: &mut WasmModule,
+    options: &RewriteOptions,
+) -> Result<RewriteReport, BoxError> {
+    let RewriteOptions {
+        check_memory_operations,
+        debug,
+        instance_id,
+        ref instance_id_source,
+        placement,
+        ref wasi_file,
+        ref filter,
+        offset_map,
+        rewrite_dwarf,
+        max_call_depth,
+        shadow_stack,
+        capture_panics,
+        capture_exceptions,
+        snapshot: _,
+        ref snapshot_funcs,
+        flight_recorder,
+        thread_frames,
+        ref record_globals,
+        frame_locals,
+    } = *options;
+    let snapshot = options.snapshot_enabled();
+    let mut report = RewriteReport::default();
+
+    if marker::is_rewritten(module, options)? {
+        info!("module already rewritten with the same options, leaving it untouched");
+        report.already_rewritten = true;
+        return Ok(report);
+    }
+
+    if snapshot && shadow_stack.is_none() {
+        return Err(
+            "coredump snapshots read the frames from the shadow stack, which isn't enabled".into(),
+        );
+    }
+    if snapshot && placement == CoredumpPlacement::default() {
+        return Err("coredump snapshots would overwrite the start of the memory, reserve a range for the coredump or grow the memory".into());
+    }
+    if let Some(file) = wasi_file {
+        file.check()?;
+        if placement == CoredumpPlacement::Memory {
+            return Err("the coredump file is written from the memory of the program, it can't be used with a coredump memory".into());
+        }
+    }
+    if let Some(recorder) = &flight_recorder {
+        recorder.check()?;
+    }
+    if let Some(threads) = &thread_frames {
+        threads.check(placement)?;
+        if shadow_stack.is_some() || flight_recorder.is_some() {
+            return Err("the shadow stack and the flight recorder are shared by the threads, they can't be used with per-thread frame buffers".into());
+        }
+    }
+    let snapshot_patterns = snapshot_funcs
+        .iter()
+        .map(|pattern| filter::glob_to_regex(pattern))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Imports need to be added first, they shift the funcidx
+    let wasi_imports = wasi_file
+        .as_ref()
+        .map(|_| add_wasi_imports(module))
+        .transpose()?;
+    let funcidx_shift = wasi_imports.as_ref().map(|i| i.added).unwrap_or_default();
+
+    // Globals of the input module recorded in the coredump, selected before
+    // the globalidx are shifted
+    let mut recorded_globals = globals::select(module, record_globals)?;
+
+    // Same for the global import, it shifts the globalidx
+    let instance_id_import = match instance_id_source {
+        InstanceIdSource::Import {
+            module: import_module,
+            name,
+        } => {
+            let global_type = ast::GlobalType {
+                valtype: ast::ValueType::NumType(ast::NumType::I32),
+                mutable: false,
+            };
+            let globalidx = module.add_global_import(import_module, name, &global_type);
+            debug!("instance_id global imported at {}", globalidx);
+            module.add_global_name(globalidx, "instance_id");
+            for global in recorded_globals.iter_mut() {
+                if global.globalidx >= globalidx {
+                    global.globalidx += 1;
+                }
+            }
+            Some(globalidx)
+        }
+        _ => None,
+    };
+
+    // Functions of the input module, our runtime is added after them
+    let original_funcs = module.defined_funcs();
+
+    // Select the functions to instrument, before our runtime is added
+    let mut uninstrumented_funcs = HashSet::new();
+    let mut no_locals_funcs = HashSet::new();
+    for funcidx in original_funcs.clone() {
+        let name = module.get_func_name(funcidx);
+        if !filter.is_included(name.as_deref()) {
+            uninstrumented_funcs.insert(funcidx);
+        }
+
+        let local_count =
+            module.func_locals_count(funcidx) + module.get_func_type(funcidx).params.len() as u32;
+        if !filter.records_locals(local_count) {
+            no_locals_funcs.insert(funcidx);
+        }
+    }
+    debug!(
+        "{} functions not instrumented, {} without locals",
+        uninstrumented_funcs.len(),
+        no_locals_funcs.len()
+    );
+    report.skipped_funcs = uninstrumented_funcs.iter().copied().collect();
+    report.skipped_funcs.sort_unstable();
+    report.no_locals_funcs = no_locals_funcs.iter().copied().collect();
+    report.no_locals_funcs.sort_unstable();
+
+    // Functions taking a snapshot when they are entered
+    let snapshot_funcs: HashSet<u32> = original_funcs
+        .clone()
+        .filter(|&funcidx| {
+            module
+                .get_func_name(funcidx)
+                .is_some_and(|name| snapshot_patterns.iter().any(|re| re.is_match(&name)))
+        })
+        .collect();
+    report.snapshot_funcs = snapshot_funcs.iter().copied().collect();
+    report.snapshot_funcs.sort_unstable();
+
+    // Arguments of the panic entry points are saved in globals
+    let panic_entry_points = if capture_panics {
+        panic::add_entry_points(module)
+    } else {
+        vec![]
+    };
+    report.panic_entry_points = panic_entry_points
+        .iter()
+        .map(|entry_point| (entry_point.funcidx, entry_point.name.clone()))
+        .collect();
+
+    // Exported functions and the start function catch the exceptions
+    // escaping them, with the form of instructions the module uses.
+    let catch_form = if capture_exceptions {
+        module.exception_handling()
+    } else {
+        None
+    };
+    let mut catch_block_types = HashMap::new();
+    if catch_form.is_some() {
+        for funcidx in original_funcs.clone() {
+            if module.is_func_entry_point(funcidx) {
+                let t = module.get_func_type(funcidx).clone();
+                catch_block_types.insert(funcidx, exceptions::block_type(module, &t));
+            }
+        }
+    }
+    debug!(
+        "{} exported functions catching exceptions ({:?})",
+        catch_block_types.len(),
+        catch_form
+    );
+
+    // Locals holding the shadow stack count at the function's entry and the
+    // address of its entry.
+    let mut shadow_locals = HashMap::new();
+    if let Some(stack) = &shadow_stack {
+        stack.check()?;
+
+        for funcidx in original_funcs.clone() {
+            let params = module.get_func_type(funcidx).params.len() as u32;
+            let count_local = params + module.func_locals_count(funcidx);
+            let locals = ast::CodeLocal {
+                count: 2,
+                value_type: ast::ValueType::NumType(ast::NumType::I32),
+            };
+            assert!(module.add_func_local(funcidx, locals));
+            shadow_locals.insert(funcidx, (count_local, count_local + 1));
+        }
+    }
+
+    // Locals holding the call depth at the entry of the exported functions
+    let mut depth_locals = HashMap::new();
+    if max_call_depth.is_some() {
+        for funcidx in original_funcs.clone() {
+            if module.is_func_entry_point(funcidx) {
+                let params = module.get_func_type(funcidx).params.len() as u32;
+                let depth_local = params + module.func_locals_count(funcidx);
+                let locals = ast::CodeLocal {
+                    count: 1,
+                    value_type: ast::ValueType::NumType(ast::NumType::I32),
+                };
+                assert!(module.add_func_local(funcidx, locals));
+                depth_locals.insert(funcidx, depth_local);
+            }
+        }
+    }
+
+    let coredump_offset = match placement {
+        CoredumpPlacement::Reserved { size: 0, .. } => {
+            return Err("reserved coredump range can not be empty".into());
+        }
+        // Each thread takes its frame buffer when it records its first frame
+        CoredumpPlacement::Reserved { .. } if thread_frames.is_some() => 0,
+        CoredumpPlacement::Reserved { offset, .. } => offset,
+        CoredumpPlacement::Grow | CoredumpPlacement::Memory => 0,
+    };
+
+    // Memory holding the frames and the coredump, a page to start with
+    let coredump_memory = if placement == CoredumpPlacement::Memory {
+        let memory = ast::Memory {
+            min: ast::Value::new(1),
+            max: None,
+            shared: false,
+        };
+        let memidx = module.add_memory(&memory);
+        debug!("coredump memory at {}", memidx);
+        module.add_export_memory(COREDUMP_MEMORY_EXPORT, memidx);
+        memidx
+    } else {
+        0
+    };
+
+    // Start of the coredump in memory
+    let coredump_base_global = {
+        let expr = ast::Value::new(vec![
+            ast::Value::new(ast::Instr::i32_const(coredump_offset as i32 as i64)),
+            ast::Value::new(ast::Instr::end),
+        ]);
+        let global = ast::Global {
+            global_type: ast::GlobalType {
+                valtype: ast::ValueType::NumType(ast::NumType::I32),
+                mutable: true,
+            },
+            expr,
+        };
+        module.add_global(&global).unwrap()
+    };
+    debug!("coredump_base_global global at {}", coredump_base_global);
+    module.add_global_name(coredump_base_global, "coredump_base_global");
 
     // Pointer or cursor to the latest frame
     let frames_ptr_global = {
         let expr = ast::Value::new(vec![
-            ast::Value::new(ast::Instr::i32_const(0)),
+            ast::Value::new(ast::Instr::i32_const(coredump_offset as i32 as i64)),
             ast::Value::new(ast::Instr::end),
         ]);
         let global = ast::Global {
@@ -64,7 +412,59 @@ pub fn rewrite(
     debug!("frames_count_global global at {}", frames_count_global);
     module.add_global_name(frames_count_global, "frames_count_global");
 
-    let runtime = get_runtime(frames_ptr_global, frames_count_global)?;
+    // Id of the thread, given when the thread starts
+    let thread_id_global = thread_frames.map(|_| {
+        let expr = ast::Value::new(vec![
+            ast::Value::new(ast::Instr::i32_const(0)),
+            ast::Value::new(ast::Instr::end),
+        ]);
+        let global = ast::Global {
+            global_type: ast::GlobalType {
+                valtype: ast::ValueType::NumType(ast::NumType::I32),
+                mutable: true,
+            },
+            expr,
+        };
+        let globalidx = module.add_global(&global).unwrap();
+        debug!("thread_id global at {}", globalidx);
+        module.add_global_name(globalidx, "thread_id");
+        globalidx
+    });
+    // Start of the region when growing the memory, reused by the snapshots
+    let region_start_global = (snapshot && placement == CoredumpPlacement::Grow).then(|| {
+        let expr = ast::Value::new(vec![
+            ast::Value::new(ast::Instr::i32_const(-1)),
+            ast::Value::new(ast::Instr::end),
+        ]);
+        let global = ast::Global {
+            global_type: ast::GlobalType {
+                valtype: ast::ValueType::NumType(ast::NumType::I32),
+                mutable: true,
+            },
+            expr,
+        };
+        let globalidx = module.add_global(&global).unwrap();
+        debug!("region_start global at {}", globalidx);
+        module.add_global_name(globalidx, "region_start");
+        globalidx
+    });
+    let thread_start =
+        thread_id_global.and_then(|_| module.get_export_funcidx(threads::THREAD_START_EXPORT));
+
+    let runtime = Runtime {
+        placement,
+        coredump_memory,
+        globals: RuntimeGlobals {
+            frames_ptr: frames_ptr_global,
+            frames_count: frames_count_global,
+            coredump_base: coredump_base_global,
+            thread_id: thread_id_global,
+            region_start: region_start_global,
+        },
+        thread_name: THREAD_NAME,
+        threads: thread_frames,
+        recorded_globals,
+    };
 
     // Add `is_unwinding` global
     let is_unwinding = {
@@ -84,6 +484,25 @@ pub fn rewrite(
     debug!("is_unwinding global at {}", is_unwinding);
     module.add_global_name(is_unwinding, "is_unwinding");
 
+    // Add `call_depth` global, counting the calls in progress
+    let call_depth = max_call_depth.map(|_| {
+        let expr = ast::Value::new(vec![
+            ast::Value::new(ast::Instr::i32_const(0)),
+            ast::Value::new(ast::Instr::end),
+        ]);
+        let global = ast::Global {
+            global_type: ast::GlobalType {
+                valtype: ast::ValueType::NumType(ast::NumType::I32),
+                mutable: true,
+            },
+            expr,
+        };
+        let globalidx = module.add_global(&global).unwrap();
+        debug!("call_depth global at {}", globalidx);
+        module.add_global_name(globalidx, "call_depth");
+        globalidx
+    });
+
     // Add `entry_funcidx` global. Tracking the exported function
     // was the entrypoint
     let entry_funcidx = {
@@ -103,112 +522,357 @@ pub fn rewrite(
     debug!("entry_funcidx global at {}", entry_funcidx);
     module.add_global_name(entry_funcidx, "entry_funcidx");
 
+    // Instance index recorded in the frames
+    let instance_id = match instance_id_source {
+        InstanceIdSource::Constant => InstanceId::Constant(instance_id),
+        InstanceIdSource::Import { .. } => InstanceId::Global(instance_id_import.unwrap()),
+        InstanceIdSource::Setter => {
+            let expr = ast::Value::new(vec![
+                ast::Value::new(ast::Instr::i32_const(instance_id as i32 as i64)),
+                ast::Value::new(ast::Instr::end),
+            ]);
+            let global = ast::Global {
+                global_type: ast::GlobalType {
+                    valtype: ast::ValueType::NumType(ast::NumType::I32),
+                    mutable: true,
+                },
+                expr,
+            };
+            let globalidx = module.add_global(&global).unwrap();
+            debug!("instance_id global at {}", globalidx);
+            module.add_global_name(globalidx, "instance_id");
+            InstanceId::Global(globalidx)
+        }
+    };
+
+    // Add `reset`, dropping the coredump of a previous snapshot
+    let reset = if snapshot {
+        let t = ast::make_type! {};
+        let typeidx = module.add_type(&t);
+
+        let func = snapshot::make_reset(placement, &runtime.globals, coredump_memory);
+        let funcidx = add_runtime_func(module, &mut report, &func, typeidx, "coredump/reset");
+        debug!("reset func at {}", funcidx);
+        Some(funcidx)
+    } else {
+        None
+    };
+
     // Add `unreachable_shim`
     let unreachable_shim = {
         let t = ast::make_type! {};
         let typeidx = module.add_type(&t);
 
-        let body = ast::body![[
-            ast::Value::new(ast::Instr::i32_const(1)),
-            ast::Value::new(ast::Instr::global_set(is_unwinding)),
-        ]];
+        let mut body = vec![];
+        if let Some(reset) = reset {
+            body.push(ast::Value::new(ast::Instr::call(reset)));
+        }
+        body.push(ast::Value::new(ast::Instr::i32_const(1)));
+        body.push(ast::Value::new(ast::Instr::global_set(is_unwinding)));
+        let body = ast::body![body];
         let func = ast::Code {
             locals: vec![],
             size: ast::Value::new(0), // printer calculates based on the body
-            body: Arc::new(Mutex::new(body)),
+            body,
         };
-        let funcidx = module.add_function(&func, typeidx);
-        module.add_func_name(funcidx, "coredump/unreachable_shim");
-        funcidx
+        add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/unreachable_shim",
+        )
     };
     debug!("unreachable_shim func at {}", unreachable_shim);
 
     let write_coredump = {
-        let (func, t) = runtime
-            .get_export_func("write_coredump")
-            .expect("failed to get write_coredump");
+        let (t, func) = runtime.make_write_coredump();
         let typeidx = module.add_type(&t);
-        let funcidx = module.add_function(&func, typeidx);
-        module.add_func_name(funcidx, "coredump/write_coredump");
-        funcidx
+        add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/write_coredump",
+        )
     };
     debug!("write_coredump func at {}", write_coredump);
 
+    // Functions calling the previous one to write the coredump and adding
+    // to it, the last one writes the complete coredump.
+    let mut write_coredump_wrappers: Vec<u32> = vec![];
+
+    if !panic_entry_points.is_empty() {
+        let t = ast::make_type! {};
+        let typeidx = module.add_type(&t);
+
+        let func = panic::make_write_crash_metadata(
+            &panic_entry_points,
+            write_coredump,
+            funcidx_shift,
+            coredump_memory,
+        );
+        let funcidx = add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/write_crash_metadata",
+        );
+        debug!("write_crash_metadata func at {}", funcidx);
+        write_coredump_wrappers.push(funcidx);
+    }
+
+    if let Some(recorder) = &flight_recorder {
+        let t = ast::make_type! {};
+        let typeidx = module.add_type(&t);
+
+        let func = flight_recorder::make_write_flight_recorder(
+            recorder,
+            write_coredump_wrappers
+                .last()
+                .copied()
+                .unwrap_or(write_coredump),
+            coredump_memory,
+        );
+        let funcidx = add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/write_flight_recorder",
+        );
+        debug!("write_flight_recorder func at {}", funcidx);
+        write_coredump_wrappers.push(funcidx);
+    }
+
+    if let (Some(file), Some(imports)) = (wasi_file, &wasi_imports) {
+        let t = ast::make_type! {};
+        let typeidx = module.add_type(&t);
+
+        let func = make_write_coredump_file(
+            file,
+            imports,
+            write_coredump_wrappers
+                .last()
+                .copied()
+                .unwrap_or(write_coredump),
+            coredump_base_global,
+        );
+        let funcidx = add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/write_coredump_file",
+        );
+        debug!("write_coredump_file func at {}", funcidx);
+        write_coredump_wrappers.push(funcidx);
+    }
+
     let start_frame = {
-        let (func, t) = runtime
-            .get_export_func("start_frame")
-            .expect("failed to get start_frame");
+        let (t, func) = runtime.make_start_frame();
         let typeidx = module.add_type(&t);
-        let funcidx = module.add_function(&func, typeidx);
-        module.add_func_name(funcidx, "coredump/start_frame");
-        funcidx
+        add_runtime_func(module, &mut report, &func, typeidx, "coredump/start_frame")
     };
     debug!("start_frame func at {}", start_frame);
 
+    let snapshot_func = if let (Some(reset), Some(stack)) = (reset, &shadow_stack) {
+        let t = ast::make_type! {};
+        let typeidx = module.add_type(&t);
+
+        let func = snapshot::make_snapshot(
+            stack,
+            reset,
+            start_frame,
+            write_coredump_wrappers
+                .last()
+                .copied()
+                .unwrap_or(write_coredump),
+            instance_id,
+        );
+        let funcidx = add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/coredump_snapshot",
+        );
+        module.add_export_func("coredump_snapshot", funcidx);
+        debug!("coredump_snapshot func at {}", funcidx);
+        Some(funcidx)
+    } else {
+        None
+    };
+
     let add_i32_local = {
-        let (func, t) = runtime
-            .get_export_func("add_i32_local")
-            .expect("failed to get add_i32_local");
+        let (t, func) = runtime.make_add_local(ast::NumType::I32);
         let typeidx = module.add_type(&t);
-        let funcidx = module.add_function(&func, typeidx);
-        module.add_func_name(funcidx, "coredump/add_i32_local");
-        funcidx
+        add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/add_i32_local",
+        )
     };
     debug!("add_i32_local func at {}", add_i32_local);
 
     let add_f32_local = {
-        let (func, t) = runtime
-            .get_export_func("add_f32_local")
-            .expect("failed to get add_f32_local");
+        let (t, func) = runtime.make_add_local(ast::NumType::F32);
         let typeidx = module.add_type(&t);
-        let funcidx = module.add_function(&func, typeidx);
-        module.add_func_name(funcidx, "coredump/add_f32_local");
-        funcidx
+        add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/add_f32_local",
+        )
     };
     debug!("add_f32_local func at {}", add_f32_local);
 
     let add_f64_local = {
-        let (func, t) = runtime
-            .get_export_func("add_f64_local")
-            .expect("failed to get add_f64_local");
+        let (t, func) = runtime.make_add_local(ast::NumType::F64);
         let typeidx = module.add_type(&t);
-        let funcidx = module.add_function(&func, typeidx);
-        module.add_func_name(funcidx, "coredump/add_f64_local");
-        funcidx
+        add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/add_f64_local",
+        )
     };
     debug!("add_f64_local func at {}", add_f64_local);
 
     let add_i64_local = {
-        let (func, t) = runtime
-            .get_export_func("add_i64_local")
-            .expect("failed to get add_i64_local");
+        let (t, func) = runtime.make_add_local(ast::NumType::I64);
         let typeidx = module.add_type(&t);
-        let funcidx = module.add_function(&func, typeidx);
-        module.add_func_name(funcidx, "coredump/add_i64_local");
-        funcidx
+        add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/add_i64_local",
+        )
     };
     debug!("add_i64_local func at {}", add_i64_local);
 
+    let add_missing_local = {
+        let (t, func) = runtime.make_add_missing_local();
+        let typeidx = module.add_type(&t);
+        add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/add_missing_local",
+        )
+    };
+    debug!("add_missing_local func at {}", add_missing_local);
+
     let visitor = CoredumpTransform {
         is_unwinding,
         entry_funcidx,
         unreachable_shim,
         write_coredump,
+        write_coredump_wrappers,
         start_frame,
+        reset,
+        snapshot: snapshot_func,
+        snapshot_funcs,
 
         add_i32_local,
         add_i64_local,
         add_f32_local,
         add_f64_local,
+        add_missing_local,
+
+        call_depth,
+        max_call_depth,
+        depth_locals,
+        shadow_stack,
+        shadow_locals,
+        flight_recorder,
+        thread_id: thread_id_global,
+        thread_start,
+        panic_entry_points: panic_entry_points
+            .into_iter()
+            .map(|entry_point| (entry_point.funcidx, entry_point))
+            .collect(),
+        catch_form,
+        catch_block_types,
 
         check_memory_operations,
         debug,
         instance_id,
+        funcidx_shift,
+        uninstrumented_funcs,
+        no_locals_funcs,
+        frame_locals,
+
+        unreachable_sites: AtomicUsize::new(0),
+        call_sites: AtomicUsize::new(0),
+        memory_check_sites: AtomicUsize::new(0),
     };
-    traverse::traverse(Arc::clone(&module_ast), Arc::new(visitor));
+    traverse::traverse(module, &visitor);
+    report.unreachable_sites = visitor.unreachable_sites.load(Ordering::Relaxed);
+    report.call_sites = visitor.call_sites.load(Ordering::Relaxed);
+    report.memory_check_sites = visitor.memory_check_sites.load(Ordering::Relaxed);
+
+    // export get_coredump_ptr
+    {
+        let t = ast::make_type! { () -> I32 };
+        let typeidx = module.add_type(&t);
+
+        let body = runtime
+            .coredump_ptr()
+            .into_iter()
+            .map(ast::Value::new)
+            .collect::<Vec<_>>();
+        let body = ast::body![body];
+        let func = ast::Code {
+            locals: vec![],
+            size: ast::Value::new(0), // printer calculates based on the body
+            body,
+        };
+        let funcidx = add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/get_coredump_ptr",
+        );
+        module.add_export_func("get_coredump_ptr", funcidx);
+    }
+
+    // export coredump_set_instance_id
+    if let (InstanceIdSource::Setter, InstanceId::Global(globalidx)) =
+        (instance_id_source, instance_id)
+    {
+        let t = ast::make_type! { (I32) -> () };
+        let typeidx = module.add_type(&t);
+
+        let body = ast::body![[
+            ast::Value::new(ast::Instr::local_get(0)),
+            ast::Value::new(ast::Instr::global_set(globalidx))
+        ]];
+        let func = ast::Code {
+            locals: vec![],
+            size: ast::Value::new(0), // printer calculates based on the body
+            body,
+        };
+        let funcidx = add_runtime_func(
+            module,
+            &mut report,
+            &func,
+            typeidx,
+            "coredump/set_instance_id",
+        );
+        module.add_export_func(instance::SETTER_EXPORT, funcidx);
+    }
 
     if debug {
-        module.add_export_func("write_coredump", write_coredump);
+        module.add_export_func("write_coredump", visitor.write_coredump_funcidx());
 
         // export get_entry_funcidx
         {
@@ -219,10 +883,15 @@ pub fn rewrite(
             let func = ast::Code {
                 locals: vec![],
                 size: ast::Value::new(0), // printer calculates based on the body
-                body: Arc::new(Mutex::new(body)),
+                body,
             };
-            let funcidx = module.add_function(&func, typeidx);
-            module.add_func_name(funcidx, "coredump/get_entry_funcidx");
+            let funcidx = add_runtime_func(
+                module,
+                &mut report,
+                &func,
+                typeidx,
+                "coredump/get_entry_funcidx",
+            );
             module.add_export_func("get_entry_funcidx", funcidx);
             funcidx
         };
@@ -236,19 +905,63 @@ pub fn rewrite(
             let func = ast::Code {
                 locals: vec![],
                 size: ast::Value::new(0), // printer calculates based on the body
-                body: Arc::new(Mutex::new(body)),
+                body,
             };
-            let funcidx = module.add_function(&func, typeidx);
-            module.add_func_name(funcidx, "coredump/is_unwinding");
+            let funcidx =
+                add_runtime_func(module, &mut report, &func, typeidx, "coredump/is_unwinding");
             module.add_export_func("is_unwinding", funcidx);
             funcidx
         };
     }
 
-    Ok(())
+    if offset_map || rewrite_dwarf {
+        // Only keep the functions of the input module, not the ones we
+        // added.
+        let mut code_offsets = wasm_printer::wasm::code_offset_map(module.inner());
+        code_offsets
+            .funcs
+            .retain(|funcidx, _| original_funcs.contains(funcidx));
+
+        if rewrite_dwarf {
+            if let Err(err) = dwarf::rewrite_dwarf(module, &code_offsets, &original_funcs) {
+                warn!(
+                    "failed to update DWARF, it describes the original module: {}",
+                    err
+                );
+            }
+        }
+
+        if offset_map {
+            // Keyed by the funcidx recorded in the frames, before the WASI
+            // imports were added
+            code_offsets.funcs = code_offsets
+                .funcs
+                .into_iter()
+                .map(|(funcidx, entries)| (funcidx - funcidx_shift, entries))
+                .collect();
+            module.add_custom_section(ast::CustomSection::CodeOffsets(code_offsets));
+        }
+    }
+
+    module.add_custom_section(marker::make_section(options));
+
+    Ok(report)
 }
 
-pub fn locals_flatten(locals: Vec<ast::CodeLocal>) -> Vec<ast::CodeLocal> {
+fn add_runtime_func(
+    module: &mut WasmModule,
+    report: &mut RewriteReport,
+    func: &ast::Code,
+    typeidx: u32,
+    name: &str,
+) -> u32 {
+    let funcidx = module.add_function(func, typeidx);
+    module.add_func_name(funcidx, name);
+    report.added_funcs.push((funcidx, name.to_owned()));
+    funcidx
+}
+
+pub fn locals_flatten(locals: &[ast::CodeLocal]) -> Vec<ast::CodeLocal> {
     let mut out = Vec::new();
 
     for local in locals {
@@ -263,60 +976,342 @@ pub fn locals_flatten(locals: Vec<ast::CodeLocal>) -> Vec<ast::CodeLocal> {
     out
 }
 
+/// Value of the given type, returned in place of the function's results
+/// when unwinding the stack. It doesn't need to be meaningful.
+fn placeholder_value(value_type: &ast::ValueType, value: i64) -> ast::Instr {
+    match value_type {
+        ast::ValueType::NumType(ast::NumType::I32) => ast::Instr::i32_const(value),
+        ast::ValueType::NumType(ast::NumType::I64) => ast::Instr::i64_const(value),
+        ast::ValueType::NumType(ast::NumType::F32) => ast::Instr::f32_const(value as f32),
+        ast::ValueType::NumType(ast::NumType::F64) => ast::Instr::f64_const(value as f64),
+        ast::ValueType::VectorType => ast::Instr::v128_const(value as u128),
+        ast::ValueType::RefType(reftype) => ast::Instr::ref_null(reftype.clone()),
+    }
+}
+
+/// Memory access of a load or store instruction: its memory argument, the
+/// number of bytes accessed and, for stores, the type of the stored value.
+fn memory_access(instr: &ast::Instr) -> Option<(&ast::MemArg, u32, Option<ast::ValueType>)> {
+    use ast::Instr::*;
+    use ast::NumType::*;
+
+    let num = |t| Some(ast::ValueType::NumType(t));
+    Some(match instr {
+        i32_load8_s(m) | i32_load8_u(m) | i64_load8_s(m) | i64_load8_u(m) => (m, 1, None),
+        i32_load16_s(m) | i32_load16_u(m) | i64_load16_s(m) | i64_load16_u(m) => (m, 2, None),
+        i32_load(m) | f32_load(m) | i64_load32_s(m) | i64_load32_u(m) => (m, 4, None),
+        i64_load(m) | f64_load(m) => (m, 8, None),
+        v128_load(m) => (m, 16, None),
+        i32_store8(m) => (m, 1, num(I32)),
+        i64_store8(m) => (m, 1, num(I64)),
+        i32_store16(m) => (m, 2, num(I32)),
+        i64_store16(m) => (m, 2, num(I64)),
+        i32_store(m) => (m, 4, num(I32)),
+        i64_store32(m) => (m, 4, num(I64)),
+        f32_store(m) => (m, 4, num(F32)),
+        i64_store(m) => (m, 8, num(I64)),
+        f64_store(m) => (m, 8, num(F64)),
+        v128_store(m) => (m, 16, Some(ast::ValueType::VectorType)),
+        _ => return None,
+    })
+}
+
 struct CoredumpTransform {
     is_unwinding: u32,
     entry_funcidx: u32,
     unreachable_shim: u32,
     write_coredump: u32,
+    /// Functions wrapping write_coredump, like the one appending the
+    /// crash_metadata section or writing the coredump to a file. Each
+    /// calls the previous one.
+    write_coredump_wrappers: Vec<u32>,
     start_frame: u32,
+    /// Drops the coredump of a previous snapshot, with snapshots enabled.
+    reset: Option<u32>,
+    snapshot: Option<u32>,
+    /// Functions taking a snapshot when they are entered.
+    snapshot_funcs: HashSet<u32>,
 
     add_i32_local: u32,
     add_i64_local: u32,
     add_f32_local: u32,
     add_f64_local: u32,
+    /// Records a local whose value isn't written in the coredump.
+    add_missing_local: u32,
+
+    /// Global counting the calls in progress and the limit above which the
+    /// stack is unwound, with --max-call-depth.
+    call_depth: Option<u32>,
+    max_call_depth: Option<u32>,
+    /// Local saving the call depth of the host's caller, by exported funcidx.
+    depth_locals: HashMap<u32, u32>,
+    shadow_stack: Option<ShadowStack>,
+    /// Locals of each function holding the shadow stack count at the
+    /// function's entry and the address of its entry.
+    shadow_locals: HashMap<u32, (u32, u32)>,
+    flight_recorder: Option<FlightRecorder>,
+    /// Global holding the thread id and the function starting the threads,
+    /// with per-thread frame buffers.
+    thread_id: Option<u32>,
+    thread_start: Option<u32>,
+    /// Panic entry points, by funcidx.
+    panic_entry_points: HashMap<u32, panic::EntryPoint>,
+    /// Exception handling instructions of the module and the block type of
+    /// the exported functions catching the exceptions, by funcidx.
+    catch_form: Option<CatchForm>,
+    catch_block_types: HashMap<u32, ast::BlockType>,
 
     check_memory_operations: bool,
     debug: bool,
-    instance_id: u32,
+    instance_id: InstanceId,
+    /// Number of function imports added by the rewriter. Frames record the
+    /// funcidx of the original module.
+    funcidx_shift: u32,
+    /// Functions excluded by the filter, they don't record a frame.
+    uninstrumented_funcs: HashSet<u32>,
+    /// Functions with too many locals to record them.
+    no_locals_funcs: HashSet<u32>,
+    frame_locals: FrameLocals,
+
+    unreachable_sites: AtomicUsize,
+    call_sites: AtomicUsize,
+    memory_check_sites: AtomicUsize,
 }
 
-fn prepend<T>(v: Vec<T>, s: &[T]) -> Vec<T>
-where
-    T: Clone,
-{
-    let mut tmp: Vec<_> = s.to_owned();
-    tmp.extend(v);
-    tmp
+impl CoredumpTransform {
+    fn records_frame(&self, funcidx: u32) -> bool {
+        !self.uninstrumented_funcs.contains(&funcidx)
+    }
+
+    fn records_locals(&self, funcidx: u32) -> bool {
+        !self.no_locals_funcs.contains(&funcidx)
+    }
+
+    /// Number of params and the locals recorded in the frames of the
+    /// function. The values are a prefix of the params and locals, so that
+    /// their index is kept.
+    fn recorded_locals(
+        &self,
+        funcidx: u32,
+        param_count: usize,
+        func_locals: &[ast::CodeLocal],
+    ) -> (usize, Vec<ast::CodeLocal>) {
+        if !self.records_locals(funcidx) {
+            return (0, vec![]);
+        }
+        let limit = match self.frame_locals {
+            FrameLocals::All => usize::MAX,
+            FrameLocals::None => 0,
+            FrameLocals::First(limit) => limit as usize,
+        };
+
+        let param_count = param_count.min(limit);
+        let mut locals = locals_flatten(func_locals);
+        locals.truncate(limit - param_count);
+        (param_count, locals)
+    }
+
+    /// Record the frame of a function, with the value of its params and
+    /// locals at this point.
+    fn make_frame(
+        &self,
+        funcidx: u32,
+        code_offset: u32,
+        func_type: &ast::Type,
+        func_locals: &[ast::CodeLocal],
+    ) -> Vec<ast::Instr> {
+        let (param_count, locals) =
+            self.recorded_locals(funcidx, func_type.params.len(), func_locals);
+
+        let mut frame = vec![
+            ast::Instr::i32_const(code_offset as i64),
+            ast::Instr::i32_const((funcidx - self.funcidx_shift) as i64),
+            ast::Instr::i32_const((locals.len() + param_count) as i64), // value count
+            self.instance_id.instr(),
+            ast::Instr::call(self.start_frame),
+        ];
+
+        let params = func_type.params.iter().take(param_count);
+        let locals = locals.iter().map(|local| &local.value_type);
+        for (localidx, value_type) in params.chain(locals).enumerate() {
+            frame.extend(self.record_local(localidx as u32, value_type));
+        }
+        frame
+    }
+
+    /// Add the value of a param or local to the latest frame. References
+    /// and vectors can't be written in the coredump, they are recorded as
+    /// missing.
+    fn record_local(&self, localidx: u32, value_type: &ast::ValueType) -> Vec<ast::Instr> {
+        let add_local = match value_type {
+            ast::ValueType::NumType(ast::NumType::I32) => self.add_i32_local,
+            ast::ValueType::NumType(ast::NumType::I64) => self.add_i64_local,
+            ast::ValueType::NumType(ast::NumType::F32) => self.add_f32_local,
+            ast::ValueType::NumType(ast::NumType::F64) => self.add_f64_local,
+            ast::ValueType::VectorType | ast::ValueType::RefType(_) => {
+                return vec![ast::Instr::call(self.add_missing_local)]
+            }
+        };
+        vec![ast::Instr::local_get(localidx), ast::Instr::call(add_local)]
+    }
+
+    fn is_runtime_func(&self, funcidx: u32) -> bool {
+        funcidx == self.unreachable_shim
+            || funcidx == self.write_coredump
+            || self.write_coredump_wrappers.contains(&funcidx)
+            || funcidx == self.start_frame
+            || Some(funcidx) == self.reset
+            || Some(funcidx) == self.snapshot
+            || funcidx == self.add_i32_local
+            || funcidx == self.add_i64_local
+            || funcidx == self.add_f32_local
+            || funcidx == self.add_f64_local
+            || funcidx == self.add_missing_local
+    }
+
+    fn write_coredump_funcidx(&self) -> u32 {
+        self.write_coredump_wrappers
+            .last()
+            .copied()
+            .unwrap_or(self.write_coredump)
+    }
 }
 
 impl Visitor for CoredumpTransform {
     fn visit_code<'a>(&self, ctx: &'_ mut VisitorContext<'a, ast::Code>, funcidx: u32) {
-        if ctx.module.is_func_exported(funcidx) {
-            let mut func_body = ctx.node.body.lock().unwrap();
+        // Take the snapshot once the function is on the shadow stack
+        if let (Some(snapshot), true) = (self.snapshot, self.snapshot_funcs.contains(&funcidx)) {
+            ctx.prepend_body(vec![ast::Instr::call(snapshot)]);
+        }
+
+        // Save the arguments of the panic entry points, before anything
+        // modifies them.
+        if let Some(entry_point) = self.panic_entry_points.get(&funcidx) {
+            ctx.prepend_body(panic::make_prologue(entry_point));
+        }
+
+        // Check the call depth. The entry code is prepended afterwards, it
+        // runs first: entry_funcidx is set and the count reset when the
+        // check runs. The count itself is updated at the call sites.
+        if let (Some(call_depth), Some(max_call_depth)) = (self.call_depth, self.max_call_depth) {
+            if !self.is_runtime_func(funcidx) {
+                let curr_func_type = ctx.module.get_func_type(funcidx);
+
+                let mut prologue = vec![];
+
+                prologue.push(ast::Instr::global_get(call_depth));
+                prologue.push(ast::Instr::i32_const(max_call_depth as i32 as i64));
+                prologue.push(ast::Instr::i32_ge_u);
+
+                let mut if_body = vec![];
+                {
+                    if let Some(reset) = self.reset {
+                        if_body.push(ast::Value::new(ast::Instr::call(reset)));
+                    }
+
+                    // The function was called from the host, there's no
+                    // caller to unwind to.
+                    if ctx.module.is_func_entry_point(funcidx) {
+                        if_body.push(ast::Value::new(ast::Instr::global_get(self.entry_funcidx)));
+                        if_body.push(ast::Value::new(ast::Instr::i32_const(funcidx as i64)));
+                        if_body.push(ast::Value::new(ast::Instr::i32_eq));
 
+                        let entry_body = ast::Value::new(vec![
+                            ast::Value::new(ast::Instr::call(self.write_coredump_funcidx())),
+                            ast::Value::new(ast::Instr::unreachable),
+                            ast::Value::new(ast::Instr::end),
+                        ]);
+                        if_body.push(ast::Value::new(ast::Instr::If(
+                            ast::BlockType::Empty,
+                            entry_body,
+                        )));
+                    }
+
+                    // Unwind the stack from the caller, the frames are
+                    // recorded after the call.
+                    if_body.push(ast::Value::new(ast::Instr::i32_const(1)));
+                    if_body.push(ast::Value::new(ast::Instr::global_set(self.is_unwinding)));
+
+                    // Add values on the stack to satisfy the current function result
+                    // type. Values don't need to be meaningful.
+                    for result in &curr_func_type.results {
+                        let instr = placeholder_value(result, 668);
+                        if_body.push(ast::Value::new(instr));
+                    }
+
+                    if_body.push(ast::Value::new(ast::Instr::Return));
+                    if_body.push(ast::Value::new(ast::Instr::end));
+                }
+
+                let if_body = ast::Value::new(if_body);
+                let if_node = ast::Instr::If(ast::BlockType::Empty, if_body);
+                prologue.push(if_node);
+
+                ctx.prepend_body(prologue);
+            }
+        }
+
+        // Push the function on the shadow stack. It runs after the entry
+        // code, which resets the shadow stack.
+        if let (Some(stack), Some(&(count_local, entry_local))) =
+            (&self.shadow_stack, self.shadow_locals.get(&funcidx))
+        {
+            let recorded_funcidx = self
+                .records_frame(funcidx)
+                .then_some(funcidx - self.funcidx_shift);
+            ctx.prepend_body(shadow_stack::make_prologue(
+                stack,
+                recorded_funcidx,
+                count_local,
+                entry_local,
+            ));
+        }
+
+        // Our runtime functions exported to the host, like coredump_snapshot,
+        // aren't entrypoints of the program
+        if ctx.module.is_func_entry_point(funcidx) && !self.is_runtime_func(funcidx) {
             // entry code
             // sets entry_funcidx
             {
                 let mut entry = vec![];
 
-                entry.push(ast::Value::new(ast::Instr::global_get(self.entry_funcidx)));
-                entry.push(ast::Value::new(ast::Instr::i32_const(
-                    NO_ENTRY_FUNCIDX_VALUE as i64,
-                )));
-                entry.push(ast::Value::new(ast::Instr::i32_eq));
+                entry.push(ast::Instr::global_get(self.entry_funcidx));
+                entry.push(ast::Instr::i32_const(NO_ENTRY_FUNCIDX_VALUE as i64));
+                entry.push(ast::Instr::i32_eq);
 
                 let mut if_body = vec![];
                 {
                     if_body.push(ast::Value::new(ast::Instr::i32_const(funcidx as i64)));
                     if_body.push(ast::Value::new(ast::Instr::global_set(self.entry_funcidx)));
+
+                    // Entering the module, drop what's left of a previous
+                    // call.
+                    if let Some(stack) = &self.shadow_stack {
+                        for instr in shadow_stack::make_reset(stack) {
+                            if_body.push(ast::Value::new(instr));
+                        }
+                    }
+
                     if_body.push(ast::Value::new(ast::Instr::end));
                 }
 
                 let if_body = ast::Value::new(if_body);
-                let if_node = ast::Instr::If(ast::BlockType::Empty, Arc::new(Mutex::new(if_body)));
-                entry.push(ast::Value::new(if_node));
+                let if_node = ast::Instr::If(ast::BlockType::Empty, if_body);
+                entry.push(if_node);
+
+                // Count the calls from here, a trap may have left the count
+                // of a previous call. It's restored on exit, in case the
+                // host was called by the module.
+                if let (Some(call_depth), Some(&depth_local)) =
+                    (self.call_depth, self.depth_locals.get(&funcidx))
+                {
+                    entry.push(ast::Instr::global_get(call_depth));
+                    entry.push(ast::Instr::local_set(depth_local));
+                    entry.push(ast::Instr::i32_const(0));
+                    entry.push(ast::Instr::global_set(call_depth));
+                }
 
-                func_body.value = prepend(func_body.value.clone(), &entry);
+                ctx.prepend_body(entry);
             }
 
             // exit code
@@ -325,8 +1320,8 @@ impl Visitor for CoredumpTransform {
             {
                 let mut exit = vec![];
 
-                exit.push(ast::Value::new(ast::Instr::global_get(self.is_unwinding)));
-                exit.push(ast::Value::new(ast::Instr::i32_eqz));
+                exit.push(ast::Instr::global_get(self.is_unwinding));
+                exit.push(ast::Instr::i32_eqz);
 
                 let mut if_body = vec![];
                 {
@@ -338,14 +1333,53 @@ impl Visitor for CoredumpTransform {
                 }
 
                 let if_body = ast::Value::new(if_body);
-                let if_node = ast::Instr::If(ast::BlockType::Empty, Arc::new(Mutex::new(if_body)));
-                exit.push(ast::Value::new(if_node));
+                let if_node = ast::Instr::If(ast::BlockType::Empty, if_body);
+                exit.push(if_node);
 
-                func_body.value.pop(); // remove the previous end instruction
-                func_body.value.extend(exit);
-                func_body.value.push(ast::Value::new(ast::Instr::end));
+                if let (Some(call_depth), Some(&depth_local)) =
+                    (self.call_depth, self.depth_locals.get(&funcidx))
+                {
+                    exit.push(ast::Instr::local_get(depth_local));
+                    exit.push(ast::Instr::global_set(call_depth));
+                }
+
+                ctx.append_body(exit);
             }
         }
+
+        // Record the entry first, the function is in the history even if the
+        // call depth check unwinds the stack.
+        if let Some(recorder) = &self.flight_recorder {
+            if self.records_frame(funcidx) && !self.is_runtime_func(funcidx) {
+                ctx.prepend_body(flight_recorder::make_entry(
+                    recorder,
+                    funcidx - self.funcidx_shift,
+                ));
+            }
+        }
+
+        // The thread id is known before anything in the thread can crash
+        if let (Some(thread_id), true) = (self.thread_id, self.thread_start == Some(funcidx)) {
+            ctx.prepend_body(vec![
+                ast::Instr::local_get(0),
+                ast::Instr::global_set(thread_id),
+            ]);
+        }
+
+        // Write the coredump when an exception escapes to the host
+        if let (Some(form), Some(block_type)) =
+            (self.catch_form, self.catch_block_types.get(&funcidx))
+        {
+            let frame = if self.records_frame(funcidx) {
+                let func_type = ctx.module.get_func_type(funcidx);
+                self.make_frame(funcidx, 0, func_type, &ctx.node.locals)
+            } else {
+                vec![]
+            };
+            let handler =
+                exceptions::make_handler(self.reset, frame, self.write_coredump_funcidx());
+            ctx.catch_body(form, block_type.clone(), handler);
+        }
     }
 
     fn visit_instr<'a>(&self, ctx: &mut VisitorContext<'a, ast::Value<ast::Instr>>) {
@@ -353,116 +1387,36 @@ impl Visitor for CoredumpTransform {
         let curr_func_type = ctx.module.get_func_type(curr_funcidx);
 
         // Don't transform our own runtime functions
-        if curr_funcidx == self.unreachable_shim
-            || curr_funcidx == self.write_coredump
-            || curr_funcidx == self.start_frame
-            || curr_funcidx == self.add_i32_local
-            || curr_funcidx == self.add_i64_local
-            || curr_funcidx == self.add_f32_local
-            || curr_funcidx == self.add_f64_local
-        {
+        if self.is_runtime_func(curr_funcidx) {
+            return;
+        }
+
+        // Nor the instructions we added, they aren't in the original module
+        if ctx.node.end_offset == 0 {
             return;
         }
 
         // Replace the `unreachable` instruction with our runtime, for all
         // instructions except the one in our runtime.
         if matches!(ctx.node.value, ast::Instr::unreachable) {
+            self.unreachable_sites.fetch_add(1, Ordering::Relaxed);
+
             // call unreachable_shim
             {
-                let unreachable_shim = Arc::new(Mutex::new(ast::Value::new(self.unreachable_shim)));
-                ctx.insert_node_before(ast::Instr::call(unreachable_shim));
+                ctx.insert_node_before(ast::Instr::call(self.unreachable_shim));
             }
 
             // create stack frame
-            {
-                let func_locals = ctx.module.func_locals(curr_funcidx);
-                let locals = locals_flatten(func_locals);
-                let func_start_offset = ctx
-                    .module
-                    .get_start_of_func(curr_funcidx)
-                    .expect("start of func to be known");
-
-                let param_count = curr_func_type.params.len();
-
-                let code_offset = ctx.node.start_offset as i64 - func_start_offset as i64;
-                ctx.insert_node_before(ast::Instr::i32_const(code_offset as i64));
-                ctx.insert_node_before(ast::Instr::i32_const(curr_funcidx as i64));
-                ctx.insert_node_before(ast::Instr::i32_const((locals.len() + param_count) as i64)); // value count
-                ctx.insert_node_before(ast::Instr::i32_const(self.instance_id as i64));
-
-                let start_frame = Arc::new(Mutex::new(ast::Value::new(self.start_frame)));
-                ctx.insert_node_before(ast::Instr::call(start_frame)); // value count
-
-                // Collect function params
-                // TODO; eventually share code with locals
-                {
-                    let mut i = 0;
-                    for param in &curr_func_type.params {
-                        ctx.insert_node_before(ast::Instr::local_get(i as u32));
-
-                        match param {
-                            ast::ValueType::NumType(ast::NumType::I64) => {
-                                let add_i64_local =
-                                    Arc::new(Mutex::new(ast::Value::new(self.add_i64_local)));
-                                ctx.insert_node_before(ast::Instr::call(add_i64_local));
-                            }
-
-                            ast::ValueType::NumType(ast::NumType::F64) => {
-                                let add_f64_local =
-                                    Arc::new(Mutex::new(ast::Value::new(self.add_f64_local)));
-                                ctx.insert_node_before(ast::Instr::call(add_f64_local));
-                            }
-
-                            ast::ValueType::NumType(ast::NumType::F32) => {
-                                let add_f32_local =
-                                    Arc::new(Mutex::new(ast::Value::new(self.add_f32_local)));
-                                ctx.insert_node_before(ast::Instr::call(add_f32_local));
-                            }
-
-                            ast::ValueType::NumType(ast::NumType::I32) => {
-                                let add_i32_local =
-                                    Arc::new(Mutex::new(ast::Value::new(self.add_i32_local)));
-                                ctx.insert_node_before(ast::Instr::call(add_i32_local));
-                            }
-                        }
-
-                        i += 1;
-                    }
-                }
-
-                // Collect locals (so after the function params)
-                // Usually Rust stores base/stack pointers the first few locals.
-                // TODO; eventually share code with params
-                let mut local_count = curr_func_type.params.len() as u32;
-
-                for local in locals {
-                    ctx.insert_node_before(ast::Instr::local_get(local_count));
-
-                    if local.value_type == ast::ValueType::NumType(ast::NumType::I64) {
-                        let add_i64_local =
-                            Arc::new(Mutex::new(ast::Value::new(self.add_i64_local)));
-                        ctx.insert_node_before(ast::Instr::call(add_i64_local));
-                    }
-
-                    if local.value_type == ast::ValueType::NumType(ast::NumType::F64) {
-                        let add_f64_local =
-                            Arc::new(Mutex::new(ast::Value::new(self.add_f64_local)));
-                        ctx.insert_node_before(ast::Instr::call(add_f64_local));
-                    }
-
-                    if local.value_type == ast::ValueType::NumType(ast::NumType::F32) {
-                        let add_f32_local =
-                            Arc::new(Mutex::new(ast::Value::new(self.add_f32_local)));
-                        ctx.insert_node_before(ast::Instr::call(add_f32_local));
-                    }
-
-                    if local.value_type == ast::ValueType::NumType(ast::NumType::I32) {
-                        let add_i32_local =
-                            Arc::new(Mutex::new(ast::Value::new(self.add_i32_local)));
-                        ctx.insert_node_before(ast::Instr::call(add_i32_local));
-                    }
-
-                    local_count += 1;
+            if self.records_frame(curr_funcidx) {
+                let code_offset = ctx.node.start_offset - ctx.func_start();
+                let frame = self.make_frame(
+                    curr_funcidx,
+                    code_offset as u32,
+                    curr_func_type,
+                    ctx.func_locals(),
+                );
+                for instr in frame {
+                    ctx.insert_node_before(instr);
                 }
             }
 
@@ -471,20 +1425,7 @@ impl Visitor for CoredumpTransform {
             // type. Values don't need to be meaningful.
             {
                 for result in &curr_func_type.results {
-                    match result {
-                        ast::ValueType::NumType(ast::NumType::I32) => {
-                            ctx.insert_node_before(ast::Instr::i32_const(666));
-                        }
-                        ast::ValueType::NumType(ast::NumType::I64) => {
-                            ctx.insert_node_before(ast::Instr::i64_const(666));
-                        }
-                        ast::ValueType::NumType(ast::NumType::F32) => {
-                            ctx.insert_node_before(ast::Instr::f32_const(666.0));
-                        }
-                        ast::ValueType::NumType(ast::NumType::F64) => {
-                            ctx.insert_node_before(ast::Instr::f64_const(666.0));
-                        }
-                    }
+                    ctx.insert_node_before(placeholder_value(result, 666));
                 }
             }
 
@@ -495,186 +1436,87 @@ impl Visitor for CoredumpTransform {
             return;
         }
 
-        if self.check_memory_operations {
-            if matches!(ctx.node.value, ast::Instr::i32_load(_, _)) {
+        // Functions that aren't instrumented skip the memory checks
+        if self.check_memory_operations && self.records_frame(curr_funcidx) {
+            if let Some((memarg, access_size, stored)) = memory_access(&ctx.node.value) {
+                // Memory the instruction accesses and the end of the access,
+                // past the address
+                let memidx = memarg.memidx;
+                let access_end = memarg.offset as i64 + access_size as i64;
+                self.memory_check_sites.fetch_add(1, Ordering::Relaxed);
+
                 let curr_funcidx = ctx.curr_funcidx.unwrap();
+                // Stores have the value on top of the address, save it in a
+                // local.
+                let value_local = stored.map(|value_type| {
+                    let localidx = ctx.add_local(value_type);
+                    ctx.insert_node_before(ast::Instr::local_set(localidx));
+                    localidx
+                });
+
                 // At this point we have one i32 on the stack; the memory address.
                 // Save it in a local.
                 // FIXME: check if function already has our local
                 let address_local = {
-                    let local = ast::CodeLocal {
-                        count: 1,
-                        value_type: ast::ValueType::NumType(ast::NumType::I32),
-                    };
-                    let localidx = ctx.module.func_locals_count(curr_funcidx);
-                    assert!(ctx.module.add_func_local(curr_funcidx, local));
+                    let localidx = ctx.add_local(ast::ValueType::NumType(ast::NumType::I32));
                     ctx.insert_node_before(ast::Instr::local_tee(localidx));
 
                     localidx
                 };
 
+                // End of the access, in 64 bits so that it doesn't overflow
+                ctx.insert_node_before(ast::Instr::i64_extend_i32_u);
+                ctx.insert_node_before(ast::Instr::i64_const(access_end));
+                ctx.insert_node_before(ast::Instr::i64_add);
+
                 // Compute the amount of memory available
                 // In the future we could compute it ahead of time, after
                 // each memory.grow/shrink call.
                 {
-                    ctx.insert_node_before(ast::Instr::memory_size(0));
-                    ctx.insert_node_before(ast::Instr::i32_const(16 * 1024)); // page size
-                    ctx.insert_node_before(ast::Instr::i32_mul);
+                    ctx.insert_node_before(ast::Instr::memory_size(memidx));
+                    ctx.insert_node_before(ast::Instr::i64_extend_i32_u);
+                    ctx.insert_node_before(ast::Instr::i64_const(16)); // 64 KiB pages
+                    ctx.insert_node_before(ast::Instr::i64_shl);
                 }
 
-                // Check that the available memory is greater than the address
-                // it's trying to read from.
-                ctx.insert_node_before(ast::Instr::i32_gt_u);
+                // Check that the access ends within the available memory
+                ctx.insert_node_before(ast::Instr::i64_gt_u);
 
                 // Build the consequent branch
                 let consequent = {
-                    if ctx.module.is_func_exported(curr_funcidx) {
-                        // The function with the memory fault is exported, at the
-                        // edge of the module, write the coredump instead of
-                        // unwinding.
-                        let write_coredump =
-                            Arc::new(Mutex::new(ast::Value::new(self.write_coredump)));
-                        ast::Value::new(vec![
-                            ast::Value::new(ast::Instr::call(write_coredump)),
-                            ast::Value::new(ast::Instr::unreachable),
-                            ast::Value::new(ast::Instr::end),
-                        ])
+                    if ctx.module.is_func_entry_point(curr_funcidx) {
+                        // The function with the memory fault is an entry point,
+                        // at the edge of the module, write the coredump instead
+                        // of unwinding.
+                        let mut body = vec![];
+                        if let Some(reset) = self.reset {
+                            body.push(ast::Value::new(ast::Instr::call(reset)));
+                        }
+                        body.push(ast::Value::new(ast::Instr::call(
+                            self.write_coredump_funcidx(),
+                        )));
+                        body.push(ast::Value::new(ast::Instr::unreachable));
+                        body.push(ast::Value::new(ast::Instr::end));
+                        ast::Value::new(body)
                     } else {
-                        // FIXME: add a frame here to mark the callsite
-
-                        let unreachable_shim =
-                            Arc::new(Mutex::new(ast::Value::new(self.unreachable_shim)));
-                        let mut body = vec![ast::Value::new(ast::Instr::call(unreachable_shim))];
+                        let mut body =
+                            vec![ast::Value::new(ast::Instr::call(self.unreachable_shim))];
 
                         // create stack frame
-                        // FIXME: duplicated with line 226
-                        {
-                            let func_locals = ctx.module.func_locals(curr_funcidx);
-                            let func_start_offset = ctx
-                                .module
-                                .get_start_of_func(curr_funcidx)
-                                .expect("start of func to be known");
-                            let locals = locals_flatten(func_locals);
-
-                            let param_count = curr_func_type.params.len();
-
-                            let code_offset =
-                                ctx.node.start_offset as i64 - func_start_offset as i64;
-                            body.push(ast::Value::new(ast::Instr::i32_const(code_offset as i64)));
-                            body.push(ast::Value::new(ast::Instr::i32_const(curr_funcidx as i64)));
-                            body.push(ast::Value::new(ast::Instr::i32_const(
-                                (locals.len() + param_count) as i64,
-                            ))); // value count
-                            body.push(ast::Value::new(ast::Instr::i32_const(
-                                self.instance_id as i64,
-                            )));
-
-                            let start_frame =
-                                Arc::new(Mutex::new(ast::Value::new(self.start_frame)));
-                            body.push(ast::Value::new(ast::Instr::call(start_frame))); // value count
-
-                            // Collect function params
-                            // TODO; eventually share code with locals
-                            {
-                                let mut i = 0;
-                                for param in &curr_func_type.params {
-                                    body.push(ast::Value::new(ast::Instr::local_get(i as u32)));
-
-                                    match param {
-                                        ast::ValueType::NumType(ast::NumType::I64) => {
-                                            let add_i64_local = Arc::new(Mutex::new(
-                                                ast::Value::new(self.add_i64_local),
-                                            ));
-                                            body.push(ast::Value::new(ast::Instr::call(
-                                                add_i64_local,
-                                            )));
-                                        }
-
-                                        ast::ValueType::NumType(ast::NumType::F64) => {
-                                            let add_f64_local = Arc::new(Mutex::new(
-                                                ast::Value::new(self.add_f64_local),
-                                            ));
-                                            body.push(ast::Value::new(ast::Instr::call(
-                                                add_f64_local,
-                                            )));
-                                        }
-
-                                        ast::ValueType::NumType(ast::NumType::F32) => {
-                                            let add_f32_local = Arc::new(Mutex::new(
-                                                ast::Value::new(self.add_f32_local),
-                                            ));
-                                            body.push(ast::Value::new(ast::Instr::call(
-                                                add_f32_local,
-                                            )));
-                                        }
-
-                                        ast::ValueType::NumType(ast::NumType::I32) => {
-                                            let add_i32_local = Arc::new(Mutex::new(
-                                                ast::Value::new(self.add_i32_local),
-                                            ));
-                                            body.push(ast::Value::new(ast::Instr::call(
-                                                add_i32_local,
-                                            )));
-                                        }
-                                    }
-
-                                    i += 1;
-                                }
-                            }
-
-                            // Collect the base/stack pointer, usually Rust stores it in
-                            // the first few locals (so after the function params).
-                            let mut local_count = curr_func_type.params.len() as u32;
-
-                            for local in locals {
-                                body.push(ast::Value::new(ast::Instr::local_get(local_count)));
-
-                                if local.value_type == ast::ValueType::NumType(ast::NumType::I64) {
-                                    let add_i64_local =
-                                        Arc::new(Mutex::new(ast::Value::new(self.add_i64_local)));
-                                    body.push(ast::Value::new(ast::Instr::call(add_i64_local)));
-                                }
-
-                                if local.value_type == ast::ValueType::NumType(ast::NumType::F64) {
-                                    let add_f64_local =
-                                        Arc::new(Mutex::new(ast::Value::new(self.add_f64_local)));
-                                    body.push(ast::Value::new(ast::Instr::call(add_f64_local)));
-                                }
-
-                                if local.value_type == ast::ValueType::NumType(ast::NumType::F32) {
-                                    let add_f32_local =
-                                        Arc::new(Mutex::new(ast::Value::new(self.add_f32_local)));
-                                    body.push(ast::Value::new(ast::Instr::call(add_f32_local)));
-                                }
-
-                                if local.value_type == ast::ValueType::NumType(ast::NumType::I32) {
-                                    let add_i32_local =
-                                        Arc::new(Mutex::new(ast::Value::new(self.add_i32_local)));
-                                    body.push(ast::Value::new(ast::Instr::call(add_i32_local)));
-                                }
-
-                                local_count += 1;
-                            }
-                        }
+                        let code_offset = ctx.node.start_offset - ctx.func_start();
+                        let frame = self.make_frame(
+                            curr_funcidx,
+                            code_offset as u32,
+                            curr_func_type,
+                            ctx.func_locals(),
+                        );
+                        body.extend(frame.into_iter().map(ast::Value::new));
 
                         // Add values on the stack to satisfy the current function result
                         // type. Values don't need to be meaningful.
                         {
                             for result in &curr_func_type.results {
-                                let instr = match result {
-                                    ast::ValueType::NumType(ast::NumType::I32) => {
-                                        ast::Instr::i32_const(667)
-                                    }
-                                    ast::ValueType::NumType(ast::NumType::I64) => {
-                                        ast::Instr::i64_const(667)
-                                    }
-                                    ast::ValueType::NumType(ast::NumType::F32) => {
-                                        ast::Instr::f32_const(667.0)
-                                    }
-                                    ast::ValueType::NumType(ast::NumType::F64) => {
-                                        ast::Instr::f64_const(667.0)
-                                    }
-                                };
+                                let instr = placeholder_value(result, 667);
                                 body.push(ast::Value::new(instr));
                             }
                         }
@@ -685,12 +1527,12 @@ impl Visitor for CoredumpTransform {
                     }
                 };
 
-                ctx.insert_node_before(ast::Instr::If(
-                    ast::BlockType::Empty,
-                    Arc::new(Mutex::new(consequent)),
-                ));
+                ctx.insert_node_before(ast::Instr::If(ast::BlockType::Empty, consequent));
 
                 ctx.insert_node_before(ast::Instr::local_get(address_local));
+                if let Some(value_local) = value_local {
+                    ctx.insert_node_before(ast::Instr::local_get(value_local));
+                }
             }
         }
 
@@ -701,79 +1543,67 @@ impl Visitor for CoredumpTransform {
             ctx.node.value,
             ast::Instr::call(_) | ast::Instr::call_indirect(_, _)
         ) {
-            ctx.insert_node_after(ast::Instr::global_get(self.is_unwinding));
-
-            // Insert if is_unwinding branch
-            {
-                let mut body = vec![];
-
-                // create stack frame
-                {
-                    let func_locals = ctx.module.func_locals(curr_funcidx);
-                    let func_start_offset = ctx
-                        .module
-                        .get_start_of_func(curr_funcidx)
-                        .expect("start of func to be known");
-                    let locals = locals_flatten(func_locals);
-
-                    let param_count = curr_func_type.params.len();
-
-                    let code_offset = ctx.node.start_offset as i64 - func_start_offset as i64;
-                    body.push(ast::Value::new(ast::Instr::i32_const(code_offset as i64)));
-                    body.push(ast::Value::new(ast::Instr::i32_const(curr_funcidx as i64)));
-                    body.push(ast::Value::new(ast::Instr::i32_const(
-                        (locals.len() + param_count) as i64,
-                    )));
-                    body.push(ast::Value::new(ast::Instr::i32_const(
-                        self.instance_id as i64,
-                    )));
-
-                    let start_frame = Arc::new(Mutex::new(ast::Value::new(self.start_frame)));
-                    body.push(ast::Value::new(ast::Instr::call(start_frame)));
-
-                    // TODO: for now we don't care about function arguments
-                    // because seems that Rust doesn't really use them anyway.
-                    for i in 0..param_count {
-                        body.push(ast::Value::new(ast::Instr::i32_const(669 + i as i64)));
+            self.call_sites.fetch_add(1, Ordering::Relaxed);
+
+            // Count the calls in progress. Counting at the call site, the
+            // depth stays balanced however the callee returns.
+            if let Some(call_depth) = self.call_depth {
+                ctx.insert_node_before(ast::Instr::global_get(call_depth));
+                ctx.insert_node_before(ast::Instr::i32_const(1));
+                ctx.insert_node_before(ast::Instr::i32_add);
+                ctx.insert_node_before(ast::Instr::global_set(call_depth));
+
+                ctx.insert_node_after(ast::Instr::global_get(call_depth));
+                ctx.insert_node_after(ast::Instr::i32_const(1));
+                ctx.insert_node_after(ast::Instr::i32_sub);
+                ctx.insert_node_after(ast::Instr::global_set(call_depth));
+            }
 
-                        let add_i32_local =
-                            Arc::new(Mutex::new(ast::Value::new(self.add_i32_local)));
-                        body.push(ast::Value::new(ast::Instr::call(add_i32_local)));
+            // Record the call in the shadow stack and pop the callee's
+            // entries once it returned.
+            if let Some(stack) = &self.shadow_stack {
+                let (count_local, entry_local) = self.shadow_locals[&curr_funcidx];
+                let pushed = self.records_frame(curr_funcidx);
+
+                if pushed {
+                    let func_start_offset = ctx.func_start();
+                    let code_offset = ctx.node.start_offset - func_start_offset;
+                    for instr in shadow_stack::make_before_call(entry_local, code_offset as u32) {
+                        ctx.insert_node_before(instr);
                     }
+                }
 
-                    // Collect the base/stack pointer, usually Rust stores it in
-                    // the first few locals (so after the function params).
-                    let mut local_count = curr_func_type.params.len() as u32;
-
-                    for local in locals {
-                        body.push(ast::Value::new(ast::Instr::local_get(local_count)));
-
-                        if local.value_type == ast::ValueType::NumType(ast::NumType::I64) {
-                            let add_i64_local =
-                                Arc::new(Mutex::new(ast::Value::new(self.add_i64_local)));
-                            body.push(ast::Value::new(ast::Instr::call(add_i64_local)));
-                        }
+                for instr in shadow_stack::make_after_call(stack, count_local, pushed) {
+                    ctx.insert_node_after(instr);
+                }
+            }
 
-                        if local.value_type == ast::ValueType::NumType(ast::NumType::F64) {
-                            let add_f64_local =
-                                Arc::new(Mutex::new(ast::Value::new(self.add_f64_local)));
-                            body.push(ast::Value::new(ast::Instr::call(add_f64_local)));
-                        }
+            // Record that the call returned to the current function
+            if let Some(recorder) = &self.flight_recorder {
+                if recorder.exits && self.records_frame(curr_funcidx) {
+                    let funcidx = curr_funcidx - self.funcidx_shift;
+                    for instr in flight_recorder::make_return(recorder, funcidx) {
+                        ctx.insert_node_after(instr);
+                    }
+                }
+            }
 
-                        if local.value_type == ast::ValueType::NumType(ast::NumType::F32) {
-                            let add_f32_local =
-                                Arc::new(Mutex::new(ast::Value::new(self.add_f32_local)));
-                            body.push(ast::Value::new(ast::Instr::call(add_f32_local)));
-                        }
+            ctx.insert_node_after(ast::Instr::global_get(self.is_unwinding));
 
-                        if local.value_type == ast::ValueType::NumType(ast::NumType::I32) {
-                            let add_i32_local =
-                                Arc::new(Mutex::new(ast::Value::new(self.add_i32_local)));
-                            body.push(ast::Value::new(ast::Instr::call(add_i32_local)));
-                        }
+            // Insert if is_unwinding branch
+            {
+                let mut body = vec![];
 
-                        local_count += 1;
-                    }
+                // create stack frame
+                if self.records_frame(curr_funcidx) {
+                    let code_offset = ctx.node.start_offset - ctx.func_start();
+                    let frame = self.make_frame(
+                        curr_funcidx,
+                        code_offset as u32,
+                        curr_func_type,
+                        ctx.func_locals(),
+                    );
+                    body.extend(frame.into_iter().map(ast::Value::new));
                 }
 
                 // if we are back to the entrypoint...
@@ -786,8 +1616,9 @@ impl Visitor for CoredumpTransform {
 
                     // We are at the edge of the module, stop unwinding the
                     // stack and trap.
-                    let write_coredump = Arc::new(Mutex::new(ast::Value::new(self.write_coredump)));
-                    if_body.push(ast::Value::new(ast::Instr::call(write_coredump)));
+                    if_body.push(ast::Value::new(ast::Instr::call(
+                        self.write_coredump_funcidx(),
+                    )));
                     if_body.push(ast::Value::new(ast::Instr::unreachable));
                     if_body.push(ast::Value::new(ast::Instr::else_end));
 
@@ -795,20 +1626,7 @@ impl Visitor for CoredumpTransform {
                     // type. Values don't need to be meaningful.
                     {
                         for result in &curr_func_type.results {
-                            let instr = match result {
-                                ast::ValueType::NumType(ast::NumType::I32) => {
-                                    ast::Instr::i32_const(667)
-                                }
-                                ast::ValueType::NumType(ast::NumType::I64) => {
-                                    ast::Instr::i64_const(667)
-                                }
-                                ast::ValueType::NumType(ast::NumType::F32) => {
-                                    ast::Instr::f32_const(667.0)
-                                }
-                                ast::ValueType::NumType(ast::NumType::F64) => {
-                                    ast::Instr::f64_const(667.0)
-                                }
-                            };
+                            let instr = placeholder_value(result, 667);
                             if_body.push(ast::Value::new(instr));
                         }
                     }
@@ -817,18 +1635,16 @@ impl Visitor for CoredumpTransform {
                     if_body.push(ast::Value::new(ast::Instr::end));
 
                     let if_body = ast::Value::new(if_body);
-                    let if_node =
-                        ast::Instr::If(ast::BlockType::Empty, Arc::new(Mutex::new(if_body)));
+                    let if_node = ast::Instr::If(ast::BlockType::Empty, if_body);
                     body.push(ast::Value::new(if_node));
                 }
 
                 body.push(ast::Value::new(ast::Instr::end));
 
                 let body = ast::Value::new(body);
-                let if_node = ast::Instr::If(ast::BlockType::Empty, Arc::new(Mutex::new(body)));
+                let if_node = ast::Instr::If(ast::BlockType::Empty, body);
                 ctx.insert_node_after(if_node);
             }
-            return;
         }
     }
 }
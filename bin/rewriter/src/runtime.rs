@@ -1,50 +1,1098 @@
+//! Coredump runtime, built for the options of the rewrite.
+//!
+//! `start_frame` and the `add_*_local` functions record the frames in memory,
+//! starting at `coredump_base`. `write_coredump` moves the frames and builds
+//! the coredump around them, it spans until the end of the memory:
+//!
+//! - in a reserved range, the rest of the memory is the content of the data
+//!   segment.
+//! - when growing the memory, the frames are recorded past the end of the
+//!   heap, which is copied in the data segment. The coredump is then moved to
+//!   the end of the memory.
+//! - in a memory added for the coredump, the runtime grows it like above and
+//!   the whole memory of the program is copied in the data segment.
+//!
+//! Sizes and values not known in advance are written as 5 bytes LEB128.
+//!
+//! The values of the recorded globals, see [crate::globals], are written in
+//! the global section, between the memory and data sections.
+//!
+//! With per-thread frame buffers, see [crate::threads], `coredump_base` is
+//! the buffer of the thread and the coredump is written by the first thread
+//! crashing.
+
+use crate::globals::{self, RecordedGlobal};
+use crate::rewriter::CoredumpPlacement;
+use crate::threads::{self, ThreadFrames};
 use core_wasm_ast as ast;
-use core_wasm_ast::traverse::{self, Visitor, VisitorContext, WasmModule};
-use std::sync::Arc;
-
-type BoxError = Box<dyn std::error::Error>;
-
-pub(crate) fn get_runtime(
-    frames_ptr_global: u32,
-    frames_count_global: u32,
-) -> Result<WasmModule, BoxError> {
-    let contents = include_bytes!("../runtime.wasm");
-    let module_ast = Arc::new(
-        wasm_parser::parse(contents)
-            .map_err(|err| format!("failed to parse runtime Wasm module: {}", err))?,
-    );
-
-    let visitor = RuntimeTransform {
-        frames_ptr_global,
-        frames_count_global,
+
+const PAGE_SIZE_SHIFT: i64 = 16;
+
+// "\0asm", read as an u32, present once a coredump is written.
+pub(crate) const WASM_MAGIC: i64 = 0x6d736100;
+
+// Names of the threads when they are named after their id.
+const MAIN_THREAD_NAME: &str = "main";
+const THREAD_NAME_PREFIX: &str = "thread-";
+
+// Largest frame header and local.
+const FRAME_HEADER_SIZE: i64 = 26;
+const LOCAL_SIZE: i64 = 9;
+
+/// Globals of the runtime.
+pub(crate) struct RuntimeGlobals {
+    /// Cursor after the latest frame.
+    pub(crate) frames_ptr: u32,
+    pub(crate) frames_count: u32,
+    /// Start of the frames, and of the coredump once written.
+    pub(crate) coredump_base: u32,
+    /// Id of the thread, with per-thread frame buffers.
+    pub(crate) thread_id: Option<u32>,
+    /// Start of the region when growing the memory, kept for the next
+    /// snapshot to reuse it. -1 until the region is placed.
+    pub(crate) region_start: Option<u32>,
+}
+
+pub(crate) struct Runtime<'a> {
+    pub(crate) placement: CoredumpPlacement,
+    /// Memory the frames and the coredump are written to, the memory of the
+    /// program unless it's [CoredumpPlacement::Memory].
+    pub(crate) coredump_memory: u32,
+    pub(crate) globals: RuntimeGlobals,
+    /// Name of the thread, unless it's named after its id.
+    pub(crate) thread_name: &'a str,
+    pub(crate) threads: Option<ThreadFrames>,
+    /// Globals whose value is written in the coredump.
+    pub(crate) recorded_globals: Vec<RecordedGlobal>,
+}
+
+fn block(body: Vec<ast::Instr>) -> ast::Expr {
+    ast::Value::new(body.into_iter().map(ast::Value::new).collect())
+}
+
+pub(crate) fn if_then(mut then: Vec<ast::Instr>) -> ast::Instr {
+    then.push(ast::Instr::end);
+    ast::Instr::If(ast::BlockType::Empty, block(then))
+}
+
+fn if_then_else(mut then: Vec<ast::Instr>, otherwise: Vec<ast::Instr>) -> ast::Instr {
+    then.push(ast::Instr::else_end);
+    then.extend(otherwise);
+    if_then(then)
+}
+
+fn make_code(locals: u32, body: Vec<ast::Instr>) -> ast::Code {
+    let locals = if locals > 0 {
+        vec![ast::CodeLocal {
+            count: locals,
+            value_type: ast::ValueType::NumType(ast::NumType::I32),
+        }]
+    } else {
+        vec![]
     };
-    traverse::traverse(Arc::clone(&module_ast), Arc::new(visitor));
 
-    let module = WasmModule::new(Arc::clone(&module_ast));
-    return Ok(module);
+    let mut body = body;
+    body.push(ast::Instr::end);
+
+    ast::Code {
+        locals,
+        size: ast::Value::new(0), // printer calculates based on the body
+        body: block(body),
+    }
+}
+
+/// End of the memory `memidx` in bytes.
+pub(crate) fn memory_end(memidx: u32) -> Vec<ast::Instr> {
+    vec![
+        ast::Instr::memory_size(memidx),
+        ast::Instr::i32_const(PAGE_SIZE_SHIFT),
+        ast::Instr::i32_shl,
+    ]
+}
+
+/// Write the bytes at `ptr_local` in the memory `memidx` and advance it.
+fn write_bytes(memidx: u32, ptr_local: u32, bytes: &[u8]) -> Vec<ast::Instr> {
+    let mut body = vec![];
+    for (i, byte) in bytes.iter().enumerate() {
+        body.push(ast::Instr::local_get(ptr_local));
+        body.push(ast::Instr::i32_const(*byte as i64));
+        body.push(ast::Instr::i32_store8(ast::MemArg::with_memidx(
+            0, i as u32, memidx,
+        )));
+    }
+    body.extend(advance(ptr_local, bytes.len() as i64));
+    body
+}
+
+fn advance(ptr_local: u32, size: i64) -> Vec<ast::Instr> {
+    vec![
+        ast::Instr::local_get(ptr_local),
+        ast::Instr::i32_const(size),
+        ast::Instr::i32_add,
+        ast::Instr::local_set(ptr_local),
+    ]
+}
+
+/// Write the value computed by `value` as LEB128 at `ptr_local` and advance
+/// it. Uses `value_local` and `byte_local`.
+fn write_leb128(
+    memidx: u32,
+    ptr_local: u32,
+    value: Vec<ast::Instr>,
+    value_local: u32,
+    byte_local: u32,
+) -> Vec<ast::Instr> {
+    let mut body = value;
+    body.push(ast::Instr::local_set(value_local));
+
+    let mut loop_body = vec![
+        ast::Instr::local_get(value_local),
+        ast::Instr::i32_const(0x7f),
+        ast::Instr::i32_and,
+        ast::Instr::local_set(byte_local),
+        ast::Instr::local_get(value_local),
+        ast::Instr::i32_const(7),
+        ast::Instr::i32_shr_u,
+        ast::Instr::local_tee(value_local),
+        // More bytes follow
+        if_then(vec![
+            ast::Instr::local_get(byte_local),
+            ast::Instr::i32_const(0x80),
+            ast::Instr::i32_or,
+            ast::Instr::local_set(byte_local),
+        ]),
+        ast::Instr::local_get(ptr_local),
+        ast::Instr::local_get(byte_local),
+        ast::Instr::i32_store8(ast::MemArg::with_memidx(0, 0, memidx)),
+    ];
+    loop_body.extend(advance(ptr_local, 1));
+    loop_body.extend([
+        ast::Instr::local_get(value_local),
+        ast::Instr::br_if(0),
+        ast::Instr::end,
+    ]);
+    body.push(ast::Instr::Loop(ast::BlockType::Empty, block(loop_body)));
+
+    body
+}
+
+/// Write the value computed by `value` as 5 bytes LEB128 at `ptr_local` and
+/// advance it.
+fn write_padded_leb128(
+    memidx: u32,
+    ptr_local: u32,
+    value: Vec<ast::Instr>,
+    signed: bool,
+) -> Vec<ast::Instr> {
+    let mut body = vec![];
+    for i in 0..5 {
+        body.push(ast::Instr::local_get(ptr_local));
+        body.extend(value.clone());
+        body.push(ast::Instr::i32_const(7 * i as i64));
+        if i == 4 && signed {
+            body.push(ast::Instr::i32_shr_s);
+        } else {
+            body.push(ast::Instr::i32_shr_u);
+        }
+        body.push(ast::Instr::i32_const(0x7f));
+        body.push(ast::Instr::i32_and);
+        if i < 4 {
+            body.push(ast::Instr::i32_const(0x80));
+            body.push(ast::Instr::i32_or);
+        }
+        body.push(ast::Instr::i32_store8(ast::MemArg::with_memidx(
+            0, i, memidx,
+        )));
+    }
+    body.extend(advance(ptr_local, 5));
+    body
+}
+
+/// Write the i64 value computed by `value` as 10 bytes signed LEB128 at
+/// `ptr_local` and advance it.
+fn write_padded_leb128_i64(memidx: u32, ptr_local: u32, value: Vec<ast::Instr>) -> Vec<ast::Instr> {
+    let mut body = vec![];
+    for i in 0..10 {
+        body.push(ast::Instr::local_get(ptr_local));
+        body.extend(value.clone());
+        body.push(ast::Instr::i64_const(7 * i as i64));
+        if i == 9 {
+            body.push(ast::Instr::i64_shr_s);
+        } else {
+            body.push(ast::Instr::i64_shr_u);
+        }
+        body.push(ast::Instr::i64_const(0x7f));
+        body.push(ast::Instr::i64_and);
+        if i < 9 {
+            body.push(ast::Instr::i64_const(0x80));
+            body.push(ast::Instr::i64_or);
+        }
+        body.push(ast::Instr::i32_wrap_i64);
+        body.push(ast::Instr::i32_store8(ast::MemArg::with_memidx(
+            0, i, memidx,
+        )));
+    }
+    body.extend(advance(ptr_local, 10));
+    body
 }
 
-struct RuntimeTransform {
-    frames_ptr_global: u32,
-    frames_count_global: u32,
+/// Write the value of `value_local` in decimal at `ptr_local` and advance
+/// it, for `digits` digits. Uses `cursor_local`.
+fn write_decimal(
+    memidx: u32,
+    ptr_local: u32,
+    value_local: u32,
+    digits: Vec<ast::Instr>,
+    cursor_local: u32,
+) -> Vec<ast::Instr> {
+    let mut body = vec![ast::Instr::local_get(ptr_local)];
+    body.extend(digits);
+    body.extend([
+        ast::Instr::i32_add,
+        ast::Instr::local_tee(ptr_local),
+        ast::Instr::local_set(cursor_local),
+    ]);
+
+    // From the last digit
+    let loop_body = vec![
+        ast::Instr::local_get(cursor_local),
+        ast::Instr::i32_const(1),
+        ast::Instr::i32_sub,
+        ast::Instr::local_tee(cursor_local),
+        ast::Instr::local_get(value_local),
+        ast::Instr::i32_const(10),
+        ast::Instr::i32_rem_u,
+        ast::Instr::i32_const(b'0' as i64),
+        ast::Instr::i32_add,
+        ast::Instr::i32_store8(ast::MemArg::with_memidx(0, 0, memidx)),
+        ast::Instr::local_get(value_local),
+        ast::Instr::i32_const(10),
+        ast::Instr::i32_div_u,
+        ast::Instr::local_tee(value_local),
+        ast::Instr::br_if(0),
+        ast::Instr::end,
+    ];
+    body.push(ast::Instr::Loop(ast::BlockType::Empty, block(loop_body)));
+
+    body
 }
 
-impl Visitor for RuntimeTransform {
-    fn visit_instr<'a>(&self, ctx: &mut VisitorContext<'a, ast::Value<ast::Instr>>) {
-        if let ast::Instr::global_get(globalidx) = ctx.node.value {
-            match globalidx {
-                0 => ctx.replace_node(ast::Instr::global_get(self.frames_ptr_global)),
-                1 => ctx.replace_node(ast::Instr::global_get(self.frames_count_global)),
-                _ => unreachable!(),
+impl<'a> Runtime<'a> {
+    fn is_grow(&self) -> bool {
+        self.placement == CoredumpPlacement::Grow
+    }
+
+    /// Whether the runtime grows the memory it writes to, past the heap or
+    /// in its own memory.
+    fn grows_memory(&self) -> bool {
+        matches!(
+            self.placement,
+            CoredumpPlacement::Grow | CoredumpPlacement::Memory
+        )
+    }
+
+    fn reserved_offset(&self) -> u32 {
+        match self.placement {
+            CoredumpPlacement::Reserved { offset, .. } => offset,
+            CoredumpPlacement::Grow | CoredumpPlacement::Memory => 0,
+        }
+    }
+
+    /// When growing the memory, the region starts at the end of the heap.
+    /// It's placed lazily, when the first frame is recorded.
+    fn place_region(&self) -> Vec<ast::Instr> {
+        if let Some(threads) = &self.threads {
+            return self.take_thread_buffer(threads);
+        }
+        if !self.is_grow() {
+            return vec![];
+        }
+
+        let mut then = memory_end(self.coredump_memory);
+        then.extend([
+            ast::Instr::global_set(self.globals.coredump_base),
+            ast::Instr::global_get(self.globals.coredump_base),
+            ast::Instr::global_set(self.globals.frames_ptr),
+        ]);
+        let mut body = vec![
+            ast::Instr::global_get(self.globals.frames_count),
+            ast::Instr::i32_eqz,
+        ];
+
+        // Unless a snapshot reuses the region of the previous one
+        if let Some(region_start) = self.globals.region_start {
+            then.extend([
+                ast::Instr::global_get(self.globals.coredump_base),
+                ast::Instr::global_set(region_start),
+            ]);
+            body.extend([
+                ast::Instr::global_get(self.globals.coredump_base),
+                ast::Instr::global_get(region_start),
+                ast::Instr::i32_ne,
+                ast::Instr::i32_and,
+            ]);
+        }
+
+        body.push(if_then(then));
+        body
+    }
+
+    /// Take the next free frame buffer, when the thread records its first
+    /// frame.
+    fn take_thread_buffer(&self, threads: &ThreadFrames) -> Vec<ast::Instr> {
+        let offset = self.reserved_offset();
+        let first_buffer = offset + threads::HEADER_SIZE;
+
+        let mut then = vec![
+            ast::Instr::i32_const(offset as i32 as i64),
+            ast::Instr::i32_const(1),
+            ast::Instr::i32_atomic_rmw_add(ast::MemArg::with_memidx(
+                2,
+                threads::NEXT_OFFSET,
+                self.coredump_memory,
+            )),
+            ast::Instr::i32_const(threads.size as i32 as i64),
+            ast::Instr::i32_mul,
+            ast::Instr::i32_const(first_buffer as i32 as i64),
+            ast::Instr::i32_add,
+            ast::Instr::global_set(self.globals.coredump_base),
+            ast::Instr::global_get(self.globals.coredump_base),
+            ast::Instr::global_set(self.globals.frames_ptr),
+        ];
+        // Too many threads for the reserved range
+        if let Some(capacity) = threads.capacity(self.placement) {
+            let end = first_buffer + capacity * threads.size;
+            then.extend([
+                ast::Instr::global_get(self.globals.coredump_base),
+                ast::Instr::i32_const(end as i32 as i64),
+                ast::Instr::i32_ge_u,
+                if_then(vec![ast::Instr::unreachable]),
+            ]);
+        }
+
+        vec![
+            ast::Instr::global_get(self.globals.coredump_base),
+            ast::Instr::i32_eqz,
+            if_then(then),
+        ]
+    }
+
+    /// Ensure that the memory up to the address computed by `end` is
+    /// available, growing the memory or trapping if the reserved range is
+    /// too small. Uses `end_local`.
+    fn reserve(&self, end: Vec<ast::Instr>, end_local: u32) -> Vec<ast::Instr> {
+        let mut body = end;
+        body.push(ast::Instr::local_set(end_local));
+
+        match self.placement {
+            CoredumpPlacement::Grow | CoredumpPlacement::Memory => {
+                let mut then = vec![ast::Instr::local_get(end_local)];
+                then.extend(memory_end(self.coredump_memory));
+                then.extend([
+                    ast::Instr::i32_sub,
+                    ast::Instr::i32_const(PAGE_SIZE_SHIFT),
+                    ast::Instr::i32_shr_u,
+                    ast::Instr::i32_const(1),
+                    ast::Instr::i32_add,
+                    ast::Instr::memory_grow(self.coredump_memory),
+                    ast::Instr::drop,
+                ]);
+
+                body.push(ast::Instr::local_get(end_local));
+                body.extend(memory_end(self.coredump_memory));
+                body.push(ast::Instr::i32_gt_u);
+                body.push(if_then(then));
+            }
+            // The whole memory is available
+            CoredumpPlacement::Reserved { size: u32::MAX, .. } if self.threads.is_none() => {}
+            CoredumpPlacement::Reserved { size, .. } => {
+                // Each thread writes in its own buffer
+                let size = self.threads.map_or(size, |threads| threads.size);
+                body.extend([
+                    ast::Instr::local_get(end_local),
+                    ast::Instr::global_get(self.globals.coredump_base),
+                    ast::Instr::i32_sub,
+                    ast::Instr::i32_const(size as i32 as i64),
+                    ast::Instr::i32_gt_u,
+                    if_then(vec![ast::Instr::unreachable]),
+                ]);
             }
         }
 
-        if let ast::Instr::global_set(globalidx) = ctx.node.value {
-            match globalidx {
-                0 => ctx.replace_node(ast::Instr::global_set(self.frames_ptr_global)),
-                1 => ctx.replace_node(ast::Instr::global_set(self.frames_count_global)),
-                _ => unreachable!(),
-            };
+        body
+    }
+
+    /// Trap if a coredump was already written.
+    fn check_no_coredump(&self) -> Vec<ast::Instr> {
+        if self.threads.is_some() {
+            return vec![
+                ast::Instr::i32_const(self.reserved_offset() as i32 as i64),
+                ast::Instr::i32_atomic_load(ast::MemArg::with_memidx(
+                    2,
+                    threads::COREDUMP_OFFSET,
+                    self.coredump_memory,
+                )),
+                if_then(vec![ast::Instr::unreachable]),
+            ];
         }
+
+        vec![
+            ast::Instr::global_get(self.globals.coredump_base),
+            ast::Instr::i32_load(ast::MemArg::with_memidx(2, 0, self.coredump_memory)),
+            ast::Instr::i32_const(WASM_MAGIC),
+            ast::Instr::i32_eq,
+            if_then(vec![ast::Instr::unreachable]),
+        ]
+    }
+
+    /// Trap if a coredump was already written, otherwise claim the coredump
+    /// for this thread with per-thread frame buffers.
+    fn claim_coredump(&self) -> Vec<ast::Instr> {
+        if self.threads.is_none() {
+            return self.check_no_coredump();
+        }
+
+        vec![
+            ast::Instr::i32_const(self.reserved_offset() as i32 as i64),
+            ast::Instr::i32_const(0),
+            ast::Instr::global_get(self.globals.coredump_base),
+            ast::Instr::i32_atomic_rmw_cmpxchg(ast::MemArg::with_memidx(
+                2,
+                threads::COREDUMP_OFFSET,
+                self.coredump_memory,
+            )),
+            if_then(vec![ast::Instr::unreachable]),
+        ]
+    }
+
+    /// Address of the written coredump.
+    pub(crate) fn coredump_ptr(&self) -> Vec<ast::Instr> {
+        if self.threads.is_some() {
+            return vec![
+                ast::Instr::i32_const(self.reserved_offset() as i32 as i64),
+                ast::Instr::i32_atomic_load(ast::MemArg::with_memidx(
+                    2,
+                    threads::COREDUMP_OFFSET,
+                    self.coredump_memory,
+                )),
+            ];
+        }
+
+        vec![ast::Instr::global_get(self.globals.coredump_base)]
+    }
+
+    /// Compute the length of the thread name in `name_len_local`, when it's
+    /// named after its id. Uses `value_local`.
+    fn thread_name_len(&self, name_len_local: u32, value_local: u32) -> Vec<ast::Instr> {
+        let Some(thread_id) = self.globals.thread_id else {
+            return vec![];
+        };
+
+        let loop_body = vec![
+            ast::Instr::local_get(name_len_local),
+            ast::Instr::i32_const(1),
+            ast::Instr::i32_add,
+            ast::Instr::local_set(name_len_local),
+            ast::Instr::local_get(value_local),
+            ast::Instr::i32_const(10),
+            ast::Instr::i32_div_u,
+            ast::Instr::local_tee(value_local),
+            ast::Instr::br_if(0),
+            ast::Instr::end,
+        ];
+
+        vec![
+            ast::Instr::global_get(thread_id),
+            ast::Instr::i32_eqz,
+            if_then_else(
+                vec![
+                    ast::Instr::i32_const(MAIN_THREAD_NAME.len() as i64),
+                    ast::Instr::local_set(name_len_local),
+                ],
+                vec![
+                    ast::Instr::i32_const(THREAD_NAME_PREFIX.len() as i64),
+                    ast::Instr::local_set(name_len_local),
+                    ast::Instr::global_get(thread_id),
+                    ast::Instr::local_set(value_local),
+                    ast::Instr::Loop(ast::BlockType::Empty, block(loop_body)),
+                ],
+            ),
+        ]
+    }
+
+    /// Write the thread name, prefixed by its length, at `ptr_local` and
+    /// advance it. Uses `value_local` and `cursor_local`.
+    fn write_thread_name(
+        &self,
+        ptr_local: u32,
+        name_len_local: u32,
+        value_local: u32,
+        cursor_local: u32,
+    ) -> Vec<ast::Instr> {
+        let Some(thread_id) = self.globals.thread_id else {
+            let thread_name = self.thread_name.as_bytes();
+            let mut bytes = vec![thread_name.len() as u8];
+            bytes.extend(thread_name);
+            return write_bytes(self.coredump_memory, ptr_local, &bytes);
+        };
+
+        let mut body = vec![
+            ast::Instr::local_get(ptr_local),
+            ast::Instr::local_get(name_len_local),
+            ast::Instr::i32_store8(ast::MemArg::with_memidx(0, 0, self.coredump_memory)),
+        ];
+        body.extend(advance(ptr_local, 1));
+
+        let mut named_after_id = write_bytes(
+            self.coredump_memory,
+            ptr_local,
+            THREAD_NAME_PREFIX.as_bytes(),
+        );
+        named_after_id.extend([
+            ast::Instr::global_get(thread_id),
+            ast::Instr::local_set(value_local),
+        ]);
+        named_after_id.extend(write_decimal(
+            self.coredump_memory,
+            ptr_local,
+            value_local,
+            vec![
+                ast::Instr::local_get(name_len_local),
+                ast::Instr::i32_const(THREAD_NAME_PREFIX.len() as i64),
+                ast::Instr::i32_sub,
+            ],
+            cursor_local,
+        ));
+
+        body.extend([
+            ast::Instr::global_get(thread_id),
+            ast::Instr::i32_eqz,
+            if_then_else(
+                write_bytes(self.coredump_memory, ptr_local, MAIN_THREAD_NAME.as_bytes()),
+                named_after_id,
+            ),
+        ]);
+        body
+    }
+
+    /// Size of the copy of the memory below the reserved range, and of its
+    /// segment header, in the data section.
+    fn low_memory_size(&self) -> i64 {
+        match self.reserved_offset() {
+            0 => 0,
+            offset => offset as i64 + 13,
+        }
+    }
+
+    /// Size of the global section and the name section naming its globals.
+    fn globals_size(&self) -> i64 {
+        if self.recorded_globals.is_empty() {
+            return 0;
+        }
+        let header = globals::global_section_header(&self.recorded_globals);
+        let entries: u32 = self.recorded_globals.iter().map(|g| g.entry_size()).sum();
+        let names = globals::name_section(&self.recorded_globals);
+        (header.len() as u32 + entries + names.len() as u32) as i64
+    }
+
+    /// Write the global section with the current value of the recorded
+    /// globals, and the name section naming them.
+    fn write_globals(&self, ptr_local: u32) -> Vec<ast::Instr> {
+        if self.recorded_globals.is_empty() {
+            return vec![];
+        }
+
+        let header = globals::global_section_header(&self.recorded_globals);
+        let mut body = write_bytes(self.coredump_memory, ptr_local, &header);
+        for global in &self.recorded_globals {
+            let value = vec![ast::Instr::global_get(global.globalidx)];
+            // type, immutable, constant instruction
+            match global.valtype {
+                ast::NumType::I32 => {
+                    body.extend(write_bytes(
+                        self.coredump_memory,
+                        ptr_local,
+                        &[0x7f, 0, 0x41],
+                    ));
+                    body.extend(write_padded_leb128(
+                        self.coredump_memory,
+                        ptr_local,
+                        value,
+                        true,
+                    ));
+                }
+                ast::NumType::I64 => {
+                    body.extend(write_bytes(
+                        self.coredump_memory,
+                        ptr_local,
+                        &[0x7e, 0, 0x42],
+                    ));
+                    body.extend(write_padded_leb128_i64(
+                        self.coredump_memory,
+                        ptr_local,
+                        value,
+                    ));
+                }
+                ast::NumType::F32 => {
+                    body.extend(write_bytes(
+                        self.coredump_memory,
+                        ptr_local,
+                        &[0x7d, 0, 0x43],
+                    ));
+                    body.push(ast::Instr::local_get(ptr_local));
+                    body.extend(value);
+                    body.push(ast::Instr::f32_store(ast::MemArg::with_memidx(
+                        0,
+                        0,
+                        self.coredump_memory,
+                    )));
+                    body.extend(advance(ptr_local, 4));
+                }
+                ast::NumType::F64 => {
+                    body.extend(write_bytes(
+                        self.coredump_memory,
+                        ptr_local,
+                        &[0x7c, 0, 0x44],
+                    ));
+                    body.push(ast::Instr::local_get(ptr_local));
+                    body.extend(value);
+                    body.push(ast::Instr::f64_store(ast::MemArg::with_memidx(
+                        0,
+                        0,
+                        self.coredump_memory,
+                    )));
+                    body.extend(advance(ptr_local, 8));
+                }
+            }
+            body.extend(write_bytes(self.coredump_memory, ptr_local, &[0x0b]));
+        }
+
+        let names = globals::name_section(&self.recorded_globals);
+        body.extend(write_bytes(self.coredump_memory, ptr_local, &names));
+        body
+    }
+
+    /// Build `start_frame(codeoffset, funcidx, local_count, instance_id)`,
+    /// starting a new frame.
+    pub(crate) fn make_start_frame(&self) -> (ast::Type, ast::Code) {
+        // params
+        let codeoffset = 0;
+        let funcidx = 1;
+        let local_count = 2;
+        let instance_id = 3;
+        // locals
+        let ptr = 4;
+        let value = 5;
+        let byte = 6;
+        let end = 7;
+
+        let mut body = self.place_region();
+        body.extend([
+            ast::Instr::global_get(self.globals.frames_ptr),
+            ast::Instr::local_set(ptr),
+        ]);
+        body.extend(self.reserve(
+            vec![
+                ast::Instr::local_get(ptr),
+                ast::Instr::i32_const(FRAME_HEADER_SIZE),
+                ast::Instr::local_get(local_count),
+                ast::Instr::i32_const(LOCAL_SIZE),
+                ast::Instr::i32_mul,
+                ast::Instr::i32_add,
+                ast::Instr::i32_add,
+            ],
+            end,
+        ));
+        body.extend(self.check_no_coredump());
+
+        body.extend(write_bytes(self.coredump_memory, ptr, &[0])); // frame version
+        for param in [instance_id, funcidx, codeoffset, local_count] {
+            body.extend(write_leb128(
+                self.coredump_memory,
+                ptr,
+                vec![ast::Instr::local_get(param)],
+                value,
+                byte,
+            ));
+        }
+        body.extend(write_bytes(self.coredump_memory, ptr, &[0])); // stack count
+
+        body.extend([
+            ast::Instr::local_get(ptr),
+            ast::Instr::global_set(self.globals.frames_ptr),
+            ast::Instr::global_get(self.globals.frames_count),
+            ast::Instr::i32_const(1),
+            ast::Instr::i32_add,
+            ast::Instr::global_set(self.globals.frames_count),
+        ]);
+
+        let t = ast::make_type! {(I32, I32, I32, I32) -> ()};
+        (t, make_code(4, body))
+    }
+
+    /// Build `add_<valtype>_local(value)`, adding a local to the latest
+    /// frame.
+    pub(crate) fn make_add_local(&self, valtype: ast::NumType) -> (ast::Type, ast::Code) {
+        // locals
+        let ptr = 1;
+
+        let (code, store, size) = match valtype {
+            ast::NumType::I32 => (
+                0x7f,
+                ast::Instr::i32_store(ast::MemArg::with_memidx(2, 1, self.coredump_memory)),
+                4,
+            ),
+            ast::NumType::I64 => (
+                0x7e,
+                ast::Instr::i64_store(ast::MemArg::with_memidx(3, 1, self.coredump_memory)),
+                8,
+            ),
+            ast::NumType::F32 => (
+                0x7d,
+                ast::Instr::f32_store(ast::MemArg::with_memidx(2, 1, self.coredump_memory)),
+                4,
+            ),
+            ast::NumType::F64 => (
+                0x7c,
+                ast::Instr::f64_store(ast::MemArg::with_memidx(3, 1, self.coredump_memory)),
+                8,
+            ),
+        };
+
+        let body = vec![
+            ast::Instr::global_get(self.globals.frames_ptr),
+            ast::Instr::local_tee(ptr),
+            ast::Instr::i32_const(code),
+            ast::Instr::i32_store8(ast::MemArg::with_memidx(0, 0, self.coredump_memory)),
+            ast::Instr::local_get(ptr),
+            ast::Instr::local_get(0),
+            store,
+            ast::Instr::local_get(ptr),
+            ast::Instr::i32_const(1 + size),
+            ast::Instr::i32_add,
+            ast::Instr::global_set(self.globals.frames_ptr),
+        ];
+
+        let t = ast::Type {
+            params: vec![ast::ValueType::NumType(valtype)],
+            results: vec![],
+        };
+        (t, make_code(1, body))
+    }
+
+    /// Build `add_missing_local()`, adding a local whose value isn't
+    /// recorded to the latest frame, like references and vectors.
+    pub(crate) fn make_add_missing_local(&self) -> (ast::Type, ast::Code) {
+        // locals
+        let ptr = 0;
+
+        let body = vec![
+            ast::Instr::global_get(self.globals.frames_ptr),
+            ast::Instr::local_tee(ptr),
+            ast::Instr::i32_const(0x01),
+            ast::Instr::i32_store8(ast::MemArg::with_memidx(0, 0, self.coredump_memory)),
+            ast::Instr::local_get(ptr),
+            ast::Instr::i32_const(1),
+            ast::Instr::i32_add,
+            ast::Instr::global_set(self.globals.frames_ptr),
+        ];
+
+        let t = ast::make_type! {};
+        (t, make_code(1, body))
+    }
+
+    /// Build `write_coredump()`, writing the coredump from the recorded
+    /// frames.
+    pub(crate) fn make_write_coredump(&self) -> (ast::Type, ast::Code) {
+        // locals
+        let ptr = 0;
+        let end = 1;
+        let heap = 2;
+        let frames = 3;
+        let data_start = 4;
+        let name_len = 5;
+        let value = 6;
+        let cursor = 7;
+
+        // Length of the thread name, only known at runtime when the thread
+        // is named after its id.
+        let name_len_value = if self.globals.thread_id.is_some() {
+            ast::Instr::local_get(name_len)
+        } else {
+            assert!(self.thread_name.len() < 0x80);
+            ast::Instr::i32_const(self.thread_name.len() as i64)
+        };
+
+        // name, thread info and frame count
+        let corestack_header_size = vec![
+            name_len_value,
+            ast::Instr::i32_const(1 + 9 + 1 + 1 + 5),
+            ast::Instr::i32_add,
+        ];
+        // Wasm header, section id and size
+        let mut frames_start = corestack_header_size.clone();
+        frames_start.extend([ast::Instr::i32_const(8 + 1 + 5), ast::Instr::i32_add]);
+
+        let mut body = self.thread_name_len(name_len, value);
+        body.extend(self.place_region());
+        body.extend([
+            ast::Instr::global_get(self.globals.coredump_base),
+            ast::Instr::local_set(ptr),
+        ]);
+
+        // Memory used by the program. When growing the memory it ends where
+        // the coredump region starts.
+        if self.is_grow() {
+            body.push(ast::Instr::global_get(self.globals.coredump_base));
+        } else {
+            body.extend(memory_end(0));
+        }
+        body.push(ast::Instr::local_set(heap));
+
+        body.extend([
+            ast::Instr::global_get(self.globals.frames_ptr),
+            ast::Instr::global_get(self.globals.coredump_base),
+            ast::Instr::i32_sub,
+            ast::Instr::local_set(frames),
+        ]);
+
+        // Room for the sections headers, around the frames
+        let mut coredump_end = vec![ast::Instr::local_get(ptr), ast::Instr::local_get(frames)];
+        coredump_end.extend(frames_start.clone());
+        coredump_end.extend([
+            ast::Instr::i32_const(64 + self.globals_size() + self.low_memory_size()),
+            ast::Instr::i32_add,
+            ast::Instr::i32_add,
+            ast::Instr::i32_add,
+        ]);
+        body.extend(self.reserve(coredump_end, end));
+        body.extend(self.claim_coredump());
+
+        // Move the frames after the corestack header
+        body.push(ast::Instr::global_get(self.globals.coredump_base));
+        body.extend(frames_start);
+        body.extend([
+            ast::Instr::i32_add,
+            ast::Instr::global_get(self.globals.coredump_base),
+            ast::Instr::local_get(frames),
+            ast::Instr::memory_copy(self.coredump_memory, self.coredump_memory),
+        ]);
+
+        body.extend(write_bytes(
+            self.coredump_memory,
+            ptr,
+            &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00],
+        ));
+
+        // corestack section
+        body.extend(write_bytes(self.coredump_memory, ptr, &[0]));
+        let mut section_size = vec![ast::Instr::local_get(frames)];
+        section_size.extend(corestack_header_size);
+        section_size.push(ast::Instr::i32_add);
+        body.extend(write_padded_leb128(
+            self.coredump_memory,
+            ptr,
+            section_size,
+            false,
+        ));
+        let mut header = vec![9];
+        header.extend(b"corestack");
+        header.push(0); // thread info type
+        body.extend(write_bytes(self.coredump_memory, ptr, &header));
+        body.extend(self.write_thread_name(ptr, name_len, value, cursor));
+        body.extend(write_padded_leb128(
+            self.coredump_memory,
+            ptr,
+            vec![ast::Instr::global_get(self.globals.frames_count)],
+            false,
+        ));
+        body.extend([
+            ast::Instr::local_get(ptr),
+            ast::Instr::local_get(frames),
+            ast::Instr::i32_add,
+            ast::Instr::local_set(ptr),
+        ]);
+
+        // core section, with an empty executable name
+        body.extend(write_bytes(
+            self.coredump_memory,
+            ptr,
+            &[0, 7, 4, b'c', b'o', b'r', b'e', 0, 0],
+        ));
+
+        // memory section
+        body.extend(write_bytes(self.coredump_memory, ptr, &[5, 12, 1, 1]));
+        body.extend(write_padded_leb128(
+            self.coredump_memory,
+            ptr,
+            vec![ast::Instr::i32_const(0)],
+            false,
+        ));
+        body.extend(write_padded_leb128(
+            self.coredump_memory,
+            ptr,
+            vec![
+                ast::Instr::local_get(heap),
+                ast::Instr::i32_const(PAGE_SIZE_SHIFT),
+                ast::Instr::i32_shr_u,
+            ],
+            false,
+        ));
+
+        body.extend(self.write_globals(ptr));
+
+        // data section
+        if self.grows_memory() {
+            // The coredump is past the heap, copy the whole heap in the data
+            // segment.
+            body.extend(self.reserve(
+                vec![
+                    ast::Instr::local_get(ptr),
+                    ast::Instr::local_get(heap),
+                    ast::Instr::i32_const(32),
+                    ast::Instr::i32_add,
+                    ast::Instr::i32_add,
+                ],
+                end,
+            ));
+            body.extend(write_bytes(self.coredump_memory, ptr, &[11]));
+            body.extend(write_padded_leb128(
+                self.coredump_memory,
+                ptr,
+                vec![
+                    ast::Instr::local_get(heap),
+                    ast::Instr::i32_const(14),
+                    ast::Instr::i32_add,
+                ],
+                false,
+            ));
+            body.extend(write_bytes(self.coredump_memory, ptr, &[1, 0, 0x41]));
+            body.extend(write_padded_leb128(
+                self.coredump_memory,
+                ptr,
+                vec![ast::Instr::i32_const(0)],
+                true,
+            ));
+            body.extend(write_bytes(self.coredump_memory, ptr, &[0x0b]));
+            body.extend(write_padded_leb128(
+                self.coredump_memory,
+                ptr,
+                vec![ast::Instr::local_get(heap)],
+                false,
+            ));
+            body.extend([
+                ast::Instr::local_get(ptr),
+                ast::Instr::i32_const(0),
+                ast::Instr::local_get(heap),
+                ast::Instr::memory_copy(self.coredump_memory, 0),
+            ]);
+            body.extend([
+                ast::Instr::local_get(ptr),
+                ast::Instr::local_get(heap),
+                ast::Instr::i32_add,
+                ast::Instr::local_set(ptr),
+            ]);
+
+            // Move the coredump to the end of the memory, so that it spans
+            // until the end like in a reserved range.
+            body.extend(memory_end(self.coredump_memory));
+            body.extend([
+                ast::Instr::local_get(ptr),
+                ast::Instr::global_get(self.globals.coredump_base),
+                ast::Instr::i32_sub,
+                ast::Instr::i32_sub,
+                ast::Instr::local_set(end),
+                ast::Instr::local_get(end),
+                ast::Instr::global_get(self.globals.coredump_base),
+                ast::Instr::local_get(ptr),
+                ast::Instr::global_get(self.globals.coredump_base),
+                ast::Instr::i32_sub,
+                ast::Instr::memory_copy(self.coredump_memory, self.coredump_memory),
+                ast::Instr::local_get(end),
+                ast::Instr::global_set(self.globals.coredump_base),
+            ]);
+
+            // The end of the coredump tells the next snapshot whether the
+            // program has grown the memory since.
+            if self.globals.region_start.is_some() {
+                body.extend(memory_end(self.coredump_memory));
+                body.push(ast::Instr::global_set(self.globals.frames_ptr));
+            }
+        } else {
+            // The memory below the reserved range is copied in a first
+            // segment. The rest of the memory is the content of the last
+            // segment, starting after its header, at its own address.
+            let low_memory = self.reserved_offset();
+            let segments_header = if low_memory > 0 { 33 } else { 20 };
+            body.extend([
+                ast::Instr::local_get(ptr),
+                ast::Instr::i32_const(segments_header + low_memory as i64),
+                ast::Instr::i32_add,
+                ast::Instr::local_set(data_start),
+            ]);
+            body.extend(write_bytes(self.coredump_memory, ptr, &[11]));
+            body.extend(write_padded_leb128(
+                self.coredump_memory,
+                ptr,
+                vec![
+                    ast::Instr::local_get(heap),
+                    ast::Instr::local_get(ptr),
+                    ast::Instr::i32_const(5),
+                    ast::Instr::i32_add,
+                    ast::Instr::i32_sub,
+                ],
+                false,
+            ));
+            if low_memory > 0 {
+                body.extend(write_bytes(self.coredump_memory, ptr, &[2, 0, 0x41]));
+                body.extend(write_padded_leb128(
+                    self.coredump_memory,
+                    ptr,
+                    vec![ast::Instr::i32_const(0)],
+                    true,
+                ));
+                body.extend(write_bytes(self.coredump_memory, ptr, &[0x0b]));
+                body.extend(write_padded_leb128(
+                    self.coredump_memory,
+                    ptr,
+                    vec![ast::Instr::i32_const(low_memory as i32 as i64)],
+                    false,
+                ));
+                body.extend([
+                    ast::Instr::local_get(ptr),
+                    ast::Instr::i32_const(0),
+                    ast::Instr::i32_const(low_memory as i32 as i64),
+                    ast::Instr::memory_copy(self.coredump_memory, 0),
+                ]);
+                body.extend(advance(ptr, low_memory as i64));
+                body.extend(write_bytes(self.coredump_memory, ptr, &[0, 0x41]));
+            } else {
+                body.extend(write_bytes(self.coredump_memory, ptr, &[1, 0, 0x41]));
+            }
+            body.extend(write_padded_leb128(
+                self.coredump_memory,
+                ptr,
+                vec![ast::Instr::local_get(data_start)],
+                true,
+            ));
+            body.extend(write_bytes(self.coredump_memory, ptr, &[0x0b]));
+            body.extend(write_padded_leb128(
+                self.coredump_memory,
+                ptr,
+                vec![
+                    ast::Instr::local_get(heap),
+                    ast::Instr::local_get(data_start),
+                    ast::Instr::i32_sub,
+                ],
+                false,
+            ));
+        }
+
+        let locals = if self.globals.thread_id.is_some() {
+            8
+        } else {
+            5
+        };
+        (ast::make_type! {}, make_code(locals, body))
     }
 }
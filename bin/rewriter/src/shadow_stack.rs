@@ -0,0 +1,174 @@
+//! Shadow call stack in a reserved memory region.
+//!
+//! Each function pushes an entry when it's called and records the offset
+//! of its calls, so the frames are in memory after any trap, including the
+//! ones the rewriter can't intercept. The region is structured as follows:
+//!
+//! | count: u32 | (funcidx: u32, codeoffset: u32)* |
+//!
+//! `count` is the number of active functions, it can exceed the number of
+//! entries fitting in the region; the deepest functions then share the last
+//! entry.
+//!
+//! The caller resets `count` after each call, however the callee returned.
+//! It's also reset when entering the module through an export, unless the
+//! previous call trapped.
+
+use core_wasm_ast as ast;
+
+type BoxError = Box<dyn std::error::Error>;
+
+pub(crate) const HEADER_SIZE: u32 = 4;
+pub(crate) const ENTRY_SIZE: u32 = 8;
+
+/// Memory region of the shadow stack, starting at `offset` and spanning
+/// `size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowStack {
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl ShadowStack {
+    pub(crate) fn capacity(&self) -> u32 {
+        self.size.saturating_sub(HEADER_SIZE) / ENTRY_SIZE
+    }
+
+    pub(crate) fn check(&self) -> Result<(), BoxError> {
+        if self.capacity() == 0 {
+            return Err(format!(
+                "shadow stack must be at least {} bytes",
+                HEADER_SIZE + ENTRY_SIZE
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Read the frames from the memory of the instance, the innermost frame
+    /// first.
+    pub fn frames(
+        &self,
+        memory: &[u8],
+        instanceidx: u32,
+    ) -> Result<Vec<wasm_coredump_types::StackFrame>, BoxError> {
+        let read_u32 = |addr: u32| -> Result<u32, BoxError> {
+            let addr = addr as usize;
+            let bytes = memory
+                .get(addr..addr + 4)
+                .ok_or("shadow stack out of memory bounds")?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let count = read_u32(self.offset)?.min(self.capacity());
+
+        let mut frames = vec![];
+        for i in (0..count).rev() {
+            let entry = self.offset + HEADER_SIZE + i * ENTRY_SIZE;
+            let frame = wasm_coredump_builder::FrameBuilder::new()
+                .instanceidx(instanceidx)
+                .funcidx(read_u32(entry)?)
+                .codeoffset(read_u32(entry + 4)?)
+                .build();
+            frames.push(frame);
+        }
+
+        Ok(frames)
+    }
+
+    /// Empty the shadow stack. A trap leaves the frames of the failed call
+    /// in the shadow stack, reset it before calling the instance again.
+    pub fn reset(&self, memory: &mut [u8]) -> Result<(), BoxError> {
+        let addr = self.offset as usize;
+        let bytes = memory
+            .get_mut(addr..addr + 4)
+            .ok_or("shadow stack out of memory bounds")?;
+        bytes.copy_from_slice(&0u32.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Save `count` in `count_local` and push an entry for the function, unless
+/// `funcidx` is None. The entry's address is saved in `entry_local`.
+pub(crate) fn make_prologue(
+    stack: &ShadowStack,
+    funcidx: Option<u32>,
+    count_local: u32,
+    entry_local: u32,
+) -> Vec<ast::Instr> {
+    let mut body = vec![
+        ast::Instr::i32_const(stack.offset as i32 as i64),
+        ast::Instr::i32_load(ast::MemArg::new(2, 0)),
+        ast::Instr::local_set(count_local),
+    ];
+
+    if let Some(funcidx) = funcidx {
+        let last = stack.capacity() - 1;
+
+        // min(count, last)
+        body.push(ast::Instr::local_get(count_local));
+        body.push(ast::Instr::i32_const(last as i64));
+        body.push(ast::Instr::local_get(count_local));
+        body.push(ast::Instr::i32_const(last as i64));
+        body.push(ast::Instr::i32_lt_u);
+        body.push(ast::Instr::select);
+
+        body.push(ast::Instr::i32_const(ENTRY_SIZE as i64));
+        body.push(ast::Instr::i32_mul);
+        body.push(ast::Instr::i32_const(
+            (stack.offset + HEADER_SIZE) as i32 as i64,
+        ));
+        body.push(ast::Instr::i32_add);
+        body.push(ast::Instr::local_tee(entry_local));
+        body.push(ast::Instr::i32_const(funcidx as i64));
+        body.push(ast::Instr::i32_store(ast::MemArg::new(2, 0)));
+
+        body.push(ast::Instr::local_get(entry_local));
+        body.push(ast::Instr::i32_const(0));
+        body.push(ast::Instr::i32_store(ast::MemArg::new(2, 4)));
+
+        body.push(ast::Instr::i32_const(stack.offset as i32 as i64));
+        body.push(ast::Instr::local_get(count_local));
+        body.push(ast::Instr::i32_const(1));
+        body.push(ast::Instr::i32_add);
+        body.push(ast::Instr::i32_store(ast::MemArg::new(2, 0)));
+    }
+
+    body
+}
+
+/// Record the offset of the call in the function's entry.
+pub(crate) fn make_before_call(entry_local: u32, codeoffset: u32) -> Vec<ast::Instr> {
+    vec![
+        ast::Instr::local_get(entry_local),
+        ast::Instr::i32_const(codeoffset as i64),
+        ast::Instr::i32_store(ast::MemArg::new(2, 4)),
+    ]
+}
+
+/// Pop the entries of the callee.
+pub(crate) fn make_after_call(
+    stack: &ShadowStack,
+    count_local: u32,
+    pushed: bool,
+) -> Vec<ast::Instr> {
+    let mut body = vec![
+        ast::Instr::i32_const(stack.offset as i32 as i64),
+        ast::Instr::local_get(count_local),
+    ];
+    if pushed {
+        body.push(ast::Instr::i32_const(1));
+        body.push(ast::Instr::i32_add);
+    }
+    body.push(ast::Instr::i32_store(ast::MemArg::new(2, 0)));
+    body
+}
+
+/// Empty the shadow stack.
+pub(crate) fn make_reset(stack: &ShadowStack) -> Vec<ast::Instr> {
+    vec![
+        ast::Instr::i32_const(stack.offset as i32 as i64),
+        ast::Instr::i32_const(0),
+        ast::Instr::i32_store(ast::MemArg::new(2, 0)),
+    ]
+}
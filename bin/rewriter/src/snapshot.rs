@@ -0,0 +1,182 @@
+//! Coredumps of the running program.
+//!
+//! `coredump_snapshot` writes a coredump of the current call stack and
+//! returns, the program keeps running. The frames are read from the shadow
+//! stack and don't have locals.
+//!
+//! A written coredump prevents the runtime from writing another one, the
+//! runtime state is reset before a snapshot and when the program starts
+//! crashing.
+
+use crate::instance::InstanceId;
+use crate::rewriter::CoredumpPlacement;
+use crate::runtime::{self, RuntimeGlobals};
+use crate::shadow_stack::{self, ShadowStack};
+use core_wasm_ast as ast;
+
+fn make_code(locals: u32, body: Vec<ast::Instr>) -> ast::Code {
+    let locals = if locals > 0 {
+        vec![ast::CodeLocal {
+            count: locals,
+            value_type: ast::ValueType::NumType(ast::NumType::I32),
+        }]
+    } else {
+        vec![]
+    };
+
+    ast::Code {
+        locals,
+        size: ast::Value::new(0), // printer calculates based on the body
+        body: ast::Value::new(body.into_iter().map(ast::Value::new).collect()),
+    }
+}
+
+/// Build a function that drops the frames and the coredump written by the
+/// runtime in the memory `coredump_memory`.
+pub(crate) fn make_reset(
+    placement: CoredumpPlacement,
+    globals: &RuntimeGlobals,
+    coredump_memory: u32,
+) -> ast::Code {
+    let mut body = vec![
+        ast::Instr::i32_const(0),
+        ast::Instr::global_set(globals.frames_count),
+    ];
+
+    // When growing the memory, the region of the previous coredump is
+    // reused, unless the program has grown the memory past it. The runtime
+    // then places a new region at the end of the memory.
+    if let Some(region_start) = globals.region_start {
+        let mut unchanged = vec![
+            ast::Instr::global_get(globals.coredump_base),
+            ast::Instr::i32_load(ast::MemArg::with_memidx(2, 0, coredump_memory)),
+            ast::Instr::i32_const(runtime::WASM_MAGIC),
+            ast::Instr::i32_eq,
+            ast::Instr::global_get(globals.frames_ptr),
+        ];
+        unchanged.extend(runtime::memory_end(coredump_memory));
+        unchanged.extend([ast::Instr::i32_eq, ast::Instr::i32_and]);
+
+        body.extend(unchanged);
+        body.push(runtime::if_then(vec![
+            // Erase the Wasm header of the previous coredump, and of its copy
+            // at the start of the region
+            ast::Instr::global_get(globals.coredump_base),
+            ast::Instr::i32_const(0),
+            ast::Instr::i32_store(ast::MemArg::with_memidx(2, 0, coredump_memory)),
+            ast::Instr::global_get(region_start),
+            ast::Instr::i32_const(0),
+            ast::Instr::i32_store(ast::MemArg::with_memidx(2, 0, coredump_memory)),
+            ast::Instr::global_get(region_start),
+            ast::Instr::global_set(globals.coredump_base),
+            ast::Instr::global_get(region_start),
+            ast::Instr::global_set(globals.frames_ptr),
+        ]));
+    }
+
+    if let CoredumpPlacement::Reserved { offset, .. } = placement {
+        body.extend([
+            ast::Instr::i32_const(offset as i32 as i64),
+            ast::Instr::global_set(globals.coredump_base),
+            ast::Instr::i32_const(offset as i32 as i64),
+            ast::Instr::global_set(globals.frames_ptr),
+            // Erase the Wasm header of the previous coredump
+            ast::Instr::i32_const(offset as i32 as i64),
+            ast::Instr::i32_const(0),
+            ast::Instr::i32_store(ast::MemArg::with_memidx(2, 0, coredump_memory)),
+        ]);
+    }
+
+    // The coredump memory is only written by the runtime, the region at its
+    // start is always reused.
+    if placement == CoredumpPlacement::Memory {
+        body.extend([
+            // Erase the Wasm header of the previous coredump, and of its copy
+            // at the start of the memory
+            ast::Instr::global_get(globals.coredump_base),
+            ast::Instr::i32_const(0),
+            ast::Instr::i32_store(ast::MemArg::with_memidx(2, 0, coredump_memory)),
+            ast::Instr::i32_const(0),
+            ast::Instr::i32_const(0),
+            ast::Instr::i32_store(ast::MemArg::with_memidx(2, 0, coredump_memory)),
+            ast::Instr::i32_const(0),
+            ast::Instr::global_set(globals.coredump_base),
+            ast::Instr::i32_const(0),
+            ast::Instr::global_set(globals.frames_ptr),
+        ]);
+    }
+
+    body.push(ast::Instr::end);
+    make_code(0, body)
+}
+
+/// Build the `coredump_snapshot` function, recording a frame for each entry
+/// of the shadow stack, the innermost first.
+pub(crate) fn make_snapshot(
+    stack: &ShadowStack,
+    reset: u32,
+    start_frame: u32,
+    write_coredump: u32,
+    instance_id: InstanceId,
+) -> ast::Code {
+    // locals
+    let entry = 0;
+
+    let first_entry = stack.offset + shadow_stack::HEADER_SIZE;
+    let last = stack.capacity() - 1;
+
+    let mut body = vec![ast::Instr::call(reset)];
+
+    // Past the last entry in use: first_entry + min(count, capacity) * size
+    body.extend([
+        ast::Instr::i32_const(stack.offset as i32 as i64),
+        ast::Instr::i32_load(ast::MemArg::new(2, 0)),
+        ast::Instr::local_tee(entry),
+        ast::Instr::i32_const(last as i64 + 1),
+        ast::Instr::local_get(entry),
+        ast::Instr::i32_const(last as i64 + 1),
+        ast::Instr::i32_lt_u,
+        ast::Instr::select,
+        ast::Instr::i32_const(shadow_stack::ENTRY_SIZE as i64),
+        ast::Instr::i32_mul,
+        ast::Instr::i32_const(first_entry as i32 as i64),
+        ast::Instr::i32_add,
+        ast::Instr::local_set(entry),
+    ]);
+
+    let loop_body = vec![
+        ast::Instr::local_get(entry),
+        ast::Instr::i32_const(first_entry as i32 as i64),
+        ast::Instr::i32_le_u,
+        ast::Instr::br_if(1),
+        ast::Instr::local_get(entry),
+        ast::Instr::i32_const(shadow_stack::ENTRY_SIZE as i64),
+        ast::Instr::i32_sub,
+        ast::Instr::local_tee(entry),
+        ast::Instr::i32_load(ast::MemArg::new(2, 4)), // codeoffset
+        ast::Instr::local_get(entry),
+        ast::Instr::i32_load(ast::MemArg::new(2, 0)), // funcidx
+        ast::Instr::i32_const(0),                     // no locals
+        instance_id.instr(),
+        ast::Instr::call(start_frame),
+        ast::Instr::br(0),
+        ast::Instr::end,
+    ];
+    let loop_body = loop_body.into_iter().map(ast::Value::new).collect();
+    let block_body = vec![
+        ast::Value::new(ast::Instr::Loop(
+            ast::BlockType::Empty,
+            ast::Value::new(loop_body),
+        )),
+        ast::Value::new(ast::Instr::end),
+    ];
+    body.push(ast::Instr::Block(
+        ast::BlockType::Empty,
+        ast::Value::new(block_body),
+    ));
+
+    body.push(ast::Instr::call(write_coredump));
+    body.push(ast::Instr::end);
+
+    make_code(1, body)
+}
@@ -0,0 +1,1428 @@
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::WasmModule;
+
+use super::{
+    read_crash_metadata, read_flight_recorder, rewrite_bytes, rewrite_component, write_history,
+    CallEvent, CoredumpPlacement, FlightRecorder, FrameLocals, FuncFilter, InstanceIdSource,
+    PanicRecord, RecordedGlobals, RewriteOptions, ShadowStack, ThreadFrames, WasiCoredumpFile,
+    COREDUMP_MEMORY_EXPORT, CRASH_METADATA_SECTION, FLIGHT_RECORDER_SECTION,
+};
+
+const PROGRAM: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "heap")
+  (func $crash (param i32 i64 f32 f64)
+    unreachable)
+  (func $middle (param i32)
+    (call $crash (local.get 0) (i64.const 4503599627370496) (f32.const 0.5) (f64.const 0.25)))
+  (func $main (export "main")
+    (call $middle (i32.const 1)))
+)
+"#;
+
+// Each thread is an instance, over a shared memory.
+const THREADS_PROGRAM: &str = r#"
+(module
+  (import "env" "memory" (memory 1 1 shared))
+  (func $crash (param i32)
+    unreachable)
+  (func $main (export "main")
+    (call $crash (i32.const 1)))
+  (func $start (export "wasi_thread_start") (param i32 i32)
+    (call $crash (local.get 1)))
+)
+"#;
+
+const GLOBALS_PROGRAM: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $__stack_pointer (mut i32) (i32.const 65536))
+  (global $counter (mut i64) (i64.const 0))
+  (global $ratio f32 (f32.const 0.5))
+  (global $scale (mut f64) (f64.const 0))
+  (func $crash
+    unreachable)
+  (func $main (export "main")
+    (global.set $__stack_pointer (i32.const -16))
+    (global.set $counter (i64.const -4503599627370496))
+    (global.set $scale (f64.const 0.25))
+    (call $crash))
+)
+"#;
+
+// The memory is imported, it outlives the failed instantiation.
+const START_PROGRAM: &str = r#"
+(module
+  (import "env" "memory" (memory 1))
+  (func $crash (param i32)
+    unreachable)
+  (func $init
+    (call $crash (i32.const 1)))
+  (start $init)
+)
+"#;
+
+// References and vectors are params, locals and results of the crashing
+// functions.
+const REFERENCES_PROGRAM: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (table 1 funcref)
+  (func $crash (param i32 funcref externref v128)
+    (local v128 externref i64)
+    (local.set 6 (i64.const 2))
+    unreachable)
+  (func $middle (param i32) (result v128 externref)
+    (call $crash (local.get 0) (ref.func $crash) (ref.null extern) (v128.const i32x4 1 2 3 4))
+    (v128.const i64x2 0 0)
+    (ref.null extern))
+  (func $main (export "main")
+    (call $middle (i32.const 1))
+    drop
+    drop)
+  (elem declare func $crash)
+)
+"#;
+
+// Accesses the second memory, larger than the first one.
+const MULTI_MEMORY_PROGRAM: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (memory 2)
+  (func (export "read") (param i32) (result i32)
+    (i32.load 1 (local.get 0)))
+  (func (export "read_byte") (param i32) (result i32)
+    (i32.load8_u 1 (local.get 0)))
+  (func (export "write") (param i32 i64)
+    (i64.store 1 offset=4 (local.get 0) (local.get 1)))
+)
+"#;
+
+// Without names, the binary round-trips through the parser and printer.
+const EXCEPTIONS_PROGRAM: &str = r#"
+(module
+  (type (func (param i32)))
+  (type (func (result i32)))
+  (import "env" "error" (tag (type 0)))
+  (memory (export "memory") 1)
+  (tag (type 0))
+  (export "error" (tag 1))
+  (func (type 0)
+    (throw 1 (local.get 0)))
+  (func (export "main") (type 1)
+    try (result i32)
+      try
+        (call 0 (i32.const 1))
+      delegate 0
+      (i32.const 0)
+    catch 0
+      drop
+      (i32.const 1)
+    catch_all
+      rethrow 0
+    end)
+  (func (export "other") (type 1)
+    (block (result i32)
+      (try_table (catch 1 0)
+        (call 0 (i32.const 2)))
+      (i32.const 0))
+    (block
+      (try_table (catch_all 0)
+        (call 0 (i32.const 3))))))
+"#;
+
+fn instantiate(options: &RewriteOptions) -> (wasmtime::Store<()>, wasmtime::Instance) {
+    let input = wat::parse_str(PROGRAM).unwrap();
+    let (output, _) = rewrite_bytes(&input, options).unwrap();
+    instantiate_module(&output)
+}
+
+fn instantiate_module(bytes: &[u8]) -> (wasmtime::Store<()>, wasmtime::Instance) {
+    // For the memory added by CoredumpPlacement::Memory
+    let mut config = wasmtime::Config::new();
+    config.wasm_multi_memory(true);
+    let engine = wasmtime::Engine::new(&config).unwrap();
+    let module = wasmtime::Module::new(&engine, bytes).unwrap();
+    let mut store = wasmtime::Store::new(&engine, ());
+    let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+    (store, instance)
+}
+
+/// Run `main` until it traps and dump the coredump it wrote.
+fn crash(options: &RewriteOptions) -> String {
+    let (store, instance) = instantiate(options);
+    crash_instance(store, instance)
+}
+
+fn crash_instance(store: wasmtime::Store<()>, instance: wasmtime::Instance) -> String {
+    let coredump = crash_coredump(store, instance);
+    let mut out = String::new();
+    wasm_printer::wast::coredump::dump_coredump(&mut out, &coredump).unwrap();
+    out
+}
+
+/// Run `main` until it traps and parse the coredump it wrote.
+fn crash_coredump(
+    mut store: wasmtime::Store<()>,
+    instance: wasmtime::Instance,
+) -> wasm_coredump_types::Coredump {
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    assert!(main.call(&mut store, ()).is_err());
+
+    written_coredump(&mut store, &instance)
+        .get_coredump()
+        .unwrap()
+}
+
+/// Parse the latest coredump written by the runtime, in the memory added for
+/// it if any.
+fn written_coredump(store: &mut wasmtime::Store<()>, instance: &wasmtime::Instance) -> WasmModule {
+    let get_coredump_ptr = instance
+        .get_typed_func::<(), u32>(&mut *store, "get_coredump_ptr")
+        .unwrap();
+    let ptr = get_coredump_ptr.call(&mut *store, ()).unwrap() as usize;
+    let memory = instance
+        .get_memory(&mut *store, COREDUMP_MEMORY_EXPORT)
+        .or_else(|| instance.get_memory(&mut *store, "memory"))
+        .unwrap();
+    WasmModule::new(wasm_parser::parse(&memory.data(&*store)[ptr..]).unwrap())
+}
+
+#[test]
+fn test_reserved() {
+    let options = RewriteOptions::new()
+        .instance_id(3)
+        .placement(CoredumpPlacement::Reserved {
+            offset: 1024,
+            size: 4096,
+        });
+
+    assert_eq!(
+        crash(&options),
+        r#"(module (coredump)
+    (process (name ""))
+    (thread (name "main")
+        (func 0
+            (instance 3)
+            (offset 0)
+            (local i32 1)
+            (local i64 4503599627370496)
+            (local f32 0.5)
+            (local f64 0.25)
+        )
+        (func 1
+            (instance 3)
+            (offset 25)
+            (local i32 1)
+        )
+        (func 2 (instance 3) (offset 2))
+    )
+    (data (i32.const 0) "...65536 bytes")
+    (memory 0 1)
+)"#
+    );
+
+    // The memory below the reserved range is in the coredump
+    let (store, instance) = instantiate(&options);
+    let coredump = crash_coredump(store, instance);
+    assert_eq!(&coredump.data[0..4], b"heap");
+}
+
+#[test]
+fn test_grow() {
+    let options = RewriteOptions::new()
+        .instance_id(3)
+        .placement(CoredumpPlacement::Grow);
+
+    assert_eq!(
+        crash(&options),
+        r#"(module (coredump)
+    (process (name ""))
+    (thread (name "main")
+        (func 0
+            (instance 3)
+            (offset 0)
+            (local i32 1)
+            (local i64 4503599627370496)
+            (local f32 0.5)
+            (local f64 0.25)
+        )
+        (func 1
+            (instance 3)
+            (offset 25)
+            (local i32 1)
+        )
+        (func 2 (instance 3) (offset 2))
+    )
+    (data (i32.const 0) "...65536 bytes")
+    (memory 0 1)
+)"#
+    );
+}
+
+#[test]
+fn test_memory_placement() {
+    let options = RewriteOptions::new()
+        .instance_id(3)
+        .placement(CoredumpPlacement::Memory);
+    let (mut store, instance) = instantiate(&options);
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    assert!(main.call(&mut store, ()).is_err());
+
+    // The coredump is the same as when growing the memory, the memory of the
+    // program is left intact
+    let coredump = written_coredump(&mut store, &instance)
+        .get_coredump()
+        .unwrap();
+    let mut out = String::new();
+    wasm_printer::wast::coredump::dump_coredump(&mut out, &coredump).unwrap();
+    let grow = RewriteOptions::new()
+        .instance_id(3)
+        .placement(CoredumpPlacement::Grow);
+    assert_eq!(out, crash(&grow));
+    assert_eq!(&coredump.data[0..4], b"heap");
+
+    assert_eq!(memory.size(&store), 1);
+    assert_eq!(&memory.data(&store)[0..4], b"heap");
+    assert!(memory.data(&store)[4..].iter().all(|&byte| byte == 0));
+
+    // The coredump is written from the memory of the program
+    let file = WasiCoredumpFile {
+        name: "core.wasm".to_owned(),
+        dirfd: 3,
+    };
+    let options = options.wasi_file(Some(file));
+    let input = wat::parse_str(PROGRAM).unwrap();
+    assert!(rewrite_bytes(&input, &options).is_err());
+}
+
+#[test]
+fn test_filter() {
+    // Excluded functions only propagate the unwinding
+    let mut filter = FuncFilter::default();
+    filter.exclude_glob("mid*").unwrap();
+    let coredump = crash(&RewriteOptions::new().filter(filter));
+    assert!(coredump.contains("(func 0"), "{}", coredump);
+    assert!(!coredump.contains("(func 1"), "{}", coredump);
+    assert!(coredump.contains("(func 2"), "{}", coredump);
+
+    let mut filter = FuncFilter::default();
+    filter.include_regex("^(crash|main)$").unwrap();
+    filter.exclude_glob("main").unwrap();
+    let coredump = crash(&RewriteOptions::new().filter(filter));
+    assert!(coredump.contains("(func 0"), "{}", coredump);
+    assert!(!coredump.contains("(func 1"), "{}", coredump);
+    assert!(!coredump.contains("(func 2"), "{}", coredump);
+
+    // Functions with more locals keep their frame, without the locals
+    let mut filter = FuncFilter::default();
+    filter.max_locals = Some(1);
+    let coredump = crash(&RewriteOptions::new().filter(filter));
+    assert!(
+        coredump.contains("(func 0 (instance 0) (offset 0))"),
+        "{}",
+        coredump
+    );
+    assert!(coredump.contains("(local i32 1)"), "{}", coredump);
+}
+
+#[test]
+fn test_frame_locals() {
+    let options = RewriteOptions::new()
+        .instance_id(3)
+        .frame_locals(FrameLocals::First(2));
+    let coredump = crash(&options);
+    assert!(
+        coredump.contains(
+            r#"        (func 0
+            (instance 3)
+            (offset 0)
+            (local i32 1)
+            (local i64 4503599627370496)
+        )
+        (func 1
+            (instance 3)
+            (offset 25)
+            (local i32 1)
+        )
+        (func 2 (instance 3) (offset 2))
+"#
+        ),
+        "{}",
+        coredump
+    );
+
+    let options = RewriteOptions::new()
+        .instance_id(3)
+        .frame_locals(FrameLocals::None);
+    let coredump = crash(&options);
+    assert!(
+        coredump.contains(
+            r#"        (func 0 (instance 3) (offset 0))
+        (func 1 (instance 3) (offset 25))
+        (func 2 (instance 3) (offset 2))
+"#
+        ),
+        "{}",
+        coredump
+    );
+
+    // Fewer recorded values make a smaller module
+    let input = wat::parse_str(
+        r#"(module
+    (memory 1)
+    (func (export "f") (param i32 i64 f32 f64)
+        (local i32 i32 i64 i64 f32 f32 f64 f64)
+        (call $crash)
+    )
+    (func $crash (unreachable))
+)"#,
+    )
+    .unwrap();
+    let size_delta = |frame_locals| {
+        let options = RewriteOptions::new().frame_locals(frame_locals);
+        let (_, report) = rewrite_bytes(&input, &options).unwrap();
+        report.size_delta.unwrap()
+    };
+    assert!(size_delta(FrameLocals::None) < size_delta(FrameLocals::First(2)));
+    assert!(size_delta(FrameLocals::First(2)) < size_delta(FrameLocals::All));
+}
+
+#[test]
+fn test_component() {
+    let module = PROGRAM.replace("(module", "(core module");
+    let input = wat::parse_str(format!("(component {} {})", module, module)).unwrap();
+
+    let mut component = match wasm_parser::parse_binary(&input).unwrap() {
+        core_wasm_ast::Binary::Component(component) => component,
+        core_wasm_ast::Binary::Module(_) => panic!("expected a component"),
+    };
+    let options = RewriteOptions::new().instance_id(3);
+    let reports = rewrite_component(&mut component, &options).unwrap();
+    assert_eq!(reports.len(), 2);
+
+    // Reassemble and decode the component again
+    let output = wasm_printer::wasm::print_component(&component).unwrap();
+    let mut component = match wasm_parser::parse_binary(&output).unwrap() {
+        core_wasm_ast::Binary::Component(component) => component,
+        core_wasm_ast::Binary::Module(_) => panic!("expected a component"),
+    };
+
+    let modules = component.core_modules_mut();
+    assert_eq!(modules.len(), 2);
+    for (i, module) in modules.into_iter().enumerate() {
+        let bytes = wasm_printer::wasm::print(module).unwrap();
+        let (store, instance) = instantiate_module(&bytes);
+        let coredump = crash_instance(store, instance);
+
+        let frame = format!("(func 2 (instance {}) (offset 2))", 3 + i);
+        assert!(coredump.contains(&frame), "{}", coredump);
+    }
+}
+
+#[test]
+fn test_instance_id_source() {
+    let input = wat::parse_str(PROGRAM).unwrap();
+    let engine = wasmtime::Engine::default();
+
+    // Given by the host at instantiation
+    let options = RewriteOptions::new().instance_id_source(InstanceIdSource::Import {
+        module: "env".to_owned(),
+        name: "instance_id".to_owned(),
+    });
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let module = wasmtime::Module::new(&engine, output).unwrap();
+    for instance_id in [5, 6] {
+        let mut store = wasmtime::Store::new(&engine, ());
+        let global_type =
+            wasmtime::GlobalType::new(wasmtime::ValType::I32, wasmtime::Mutability::Const);
+        let global =
+            wasmtime::Global::new(&mut store, global_type, wasmtime::Val::I32(instance_id))
+                .unwrap();
+        let instance = wasmtime::Instance::new(&mut store, &module, &[global.into()]).unwrap();
+
+        let coredump = crash_instance(store, instance);
+        let frame = format!("(func 2 (instance {}) (offset 2))", instance_id);
+        assert!(coredump.contains(&frame), "{}", coredump);
+    }
+
+    // Set by the host after instantiation
+    let options = RewriteOptions::new()
+        .instance_id(3)
+        .instance_id_source(InstanceIdSource::Setter);
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let set_instance_id = instance
+        .get_typed_func::<u32, ()>(&mut store, "coredump_set_instance_id")
+        .unwrap();
+    set_instance_id.call(&mut store, 7).unwrap();
+
+    let coredump = crash_instance(store, instance);
+    assert!(
+        coredump.contains("(func 2 (instance 7) (offset 2))"),
+        "{}",
+        coredump
+    );
+}
+
+#[test]
+fn test_record_globals() {
+    let input = wat::parse_str(GLOBALS_PROGRAM).unwrap();
+
+    let options = RewriteOptions::new().record_globals(RecordedGlobals::All);
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (store, instance) = instantiate_module(&output);
+    let coredump = crash_instance(store, instance);
+    assert!(
+        coredump.contains(
+            r#"    (global (name "__stack_pointer") i32 -16)
+    (global (name "counter") i64 -4503599627370496)
+    (global (name "ratio") f32 0.5)
+    (global (name "scale") f64 0.25)
+"#
+        ),
+        "{}",
+        coredump
+    );
+
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Grow)
+        .record_globals(RecordedGlobals::Matching(vec!["__stack_*".to_owned()]));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (store, instance) = instantiate_module(&output);
+    let coredump = crash_instance(store, instance);
+    assert!(
+        coredump.contains("    (global (name \"__stack_pointer\") i32 -16)\n    (data"),
+        "{}",
+        coredump
+    );
+}
+
+#[test]
+fn test_thread_frames() {
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Reserved {
+            offset: 1024,
+            size: 8192,
+        })
+        .thread_frames(Some(ThreadFrames { size: 2048 }));
+    let input = wat::parse_str(THREADS_PROGRAM).unwrap();
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+
+    let mut config = wasmtime::Config::new();
+    config.wasm_threads(true);
+    let engine = wasmtime::Engine::new(&config).unwrap();
+    let module = wasmtime::Module::new(&engine, output).unwrap();
+    let memory = wasmtime::SharedMemory::new(&engine, wasmtime::MemoryType::shared(1, 1)).unwrap();
+
+    let mut store = wasmtime::Store::new(&engine, ());
+    let main_thread =
+        wasmtime::Instance::new(&mut store, &module, &[memory.clone().into()]).unwrap();
+    let thread = wasmtime::Instance::new(&mut store, &module, &[memory.clone().into()]).unwrap();
+
+    // The first thread crashing writes the coredump
+    let start = thread
+        .get_typed_func::<(i32, i32), ()>(&mut store, "wasi_thread_start")
+        .unwrap();
+    assert!(start.call(&mut store, (42, 7)).is_err());
+    let main = main_thread
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    assert!(main.call(&mut store, ()).is_err());
+
+    let get_coredump_ptr = main_thread
+        .get_typed_func::<(), u32>(&mut store, "get_coredump_ptr")
+        .unwrap();
+    let ptr = get_coredump_ptr.call(&mut store, ()).unwrap() as usize;
+    // The first frame buffer, after the header
+    assert_eq!(ptr, 1024 + 8);
+
+    let coredump = memory.data()[ptr..]
+        .iter()
+        .map(|byte| unsafe { *byte.get() })
+        .collect::<Vec<u8>>();
+    let coredump_wasm = wasm_parser::parse(&coredump).unwrap();
+    let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(coredump_wasm);
+    let coredump = coredump_wasm.get_coredump().unwrap();
+
+    assert_eq!(coredump.stacks.len(), 1);
+    assert_eq!(coredump.stacks[0].thread_info.thread_name, "thread-42");
+    assert_eq!(coredump.stacks[0].frames.len(), 2);
+}
+
+#[test]
+fn test_reserved_range_too_small() {
+    let options = RewriteOptions::new().placement(CoredumpPlacement::Reserved {
+        offset: 1024,
+        size: 16,
+    });
+    let (mut store, instance) = instantiate(&options);
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    assert!(main.call(&mut store, ()).is_err());
+
+    // The runtime trapped before writing the coredump
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    assert_ne!(&memory.data(&store)[1024..1028], b"\0asm");
+}
+
+#[test]
+fn test_duplicated_coredump() {
+    let options = RewriteOptions::new().debug(true);
+    let (mut store, instance) = instantiate(&options);
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    assert!(main.call(&mut store, ()).is_err());
+
+    // A coredump was already written
+    let write_coredump = instance
+        .get_typed_func::<(), ()>(&mut store, "write_coredump")
+        .unwrap();
+    assert!(write_coredump.call(&mut store, ()).is_err());
+}
+
+#[test]
+fn test_exceptions() {
+    let input = wat::parse_str(EXCEPTIONS_PROGRAM).unwrap();
+    let module = wasm_parser::parse(&input).unwrap();
+    assert_eq!(wasm_printer::wasm::print(&module).unwrap(), input);
+
+    let exported_body = |input: &[u8], options: &RewriteOptions| {
+        let (output, _) = rewrite_bytes(input, options).unwrap();
+        let module = WasmModule::new(wasm_parser::parse(&output).unwrap());
+        let (code, _) = module.get_export_func("main").unwrap();
+        code.body.value.clone()
+    };
+
+    // The module uses try_table, the body runs in a try_table whose clause
+    // branches to the handler
+    let options = RewriteOptions::new().capture_exceptions(true);
+    let body = exported_body(&input, &options);
+    let ast::Instr::Block(ast::BlockType::Empty, block) = &body[0].value else {
+        panic!("expected a block: {:?}", body[0]);
+    };
+    assert!(matches!(
+        &block.value[0].value,
+        ast::Instr::TryTable(ast::BlockType::ValueType(_), catches, _)
+            if catches == &[ast::Catch::CatchAll(0)]
+    ));
+    assert!(matches!(block.value[1].value, ast::Instr::Return));
+    let handler = &body[1..];
+    assert!(matches!(handler[0].value, ast::Instr::i32_const(0))); // code offset
+    assert!(matches!(
+        handler[handler.len() - 2].value,
+        ast::Instr::unreachable
+    ));
+
+    let body = exported_body(&input, &RewriteOptions::new());
+    assert!(matches!(body[0].value, ast::Instr::global_get(_)));
+
+    // Legacy instructions, with a type for the results of the block
+    let input = wat::parse_str(
+        r#"(module
+    (tag (param i32))
+    (memory (export "memory") 1)
+    (func (export "main") (param i32) (result i32 i64)
+        (throw 0 (local.get 0)))
+)"#,
+    )
+    .unwrap();
+    let body = exported_body(&input, &options);
+    assert_eq!(body.len(), 2);
+    let ast::Instr::Try(ast::BlockType::Typeidx(_), try_body) = &body[0].value else {
+        panic!("expected a try: {:?}", body[0]);
+    };
+    let catch_all = try_body
+        .value
+        .iter()
+        .position(|instr| matches!(instr.value, ast::Instr::catch_all))
+        .unwrap();
+    let handler = &try_body.value[catch_all + 1..];
+    assert!(matches!(handler[0].value, ast::Instr::i32_const(0))); // code offset
+    assert!(matches!(
+        handler[handler.len() - 2].value,
+        ast::Instr::unreachable
+    ));
+}
+
+#[test]
+fn test_start_func() {
+    let input = wat::parse_str(START_PROGRAM).unwrap();
+    let (output, _) = rewrite_bytes(&input, &RewriteOptions::new()).unwrap();
+
+    let engine = wasmtime::Engine::default();
+    let module = wasmtime::Module::new(&engine, output).unwrap();
+    let mut store = wasmtime::Store::new(&engine, ());
+    let memory = wasmtime::Memory::new(&mut store, wasmtime::MemoryType::new(1, None)).unwrap();
+
+    // The start function is an entry point, the trap reaches the host
+    assert!(wasmtime::Instance::new(&mut store, &module, &[memory.into()]).is_err());
+
+    let data = memory.data(&store);
+    let ptr = data
+        .windows(4)
+        .position(|bytes| bytes == b"\0asm")
+        .expect("no coredump");
+    let coredump_wasm = wasm_parser::parse(&data[ptr..]).unwrap();
+    let coredump_wasm = WasmModule::new(coredump_wasm);
+    let coredump = coredump_wasm.get_coredump().unwrap();
+
+    let frames = &coredump.stacks[0].frames;
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].funcidx, 0);
+    assert_eq!(frames[1].funcidx, 1);
+}
+
+#[test]
+fn test_reference_and_vector_locals() {
+    let input = wat::parse_str(REFERENCES_PROGRAM).unwrap();
+    let (output, _) = rewrite_bytes(&input, &RewriteOptions::new()).unwrap();
+    let (store, instance) = instantiate_module(&output);
+    let coredump = crash_instance(store, instance);
+
+    // The values of references and vectors aren't in the coredump
+    assert!(
+        coredump.contains(
+            r#"        (func 0
+            (instance 0)
+            (offset 4)
+            (local i32 1)
+            (local  (optimized out))
+            (local  (optimized out))
+            (local  (optimized out))
+            (local  (optimized out))
+            (local  (optimized out))
+            (local i64 2)
+        )
+"#
+        ),
+        "{}",
+        coredump
+    );
+}
+
+#[test]
+fn test_memory_check_multi_memory() {
+    let input = wat::parse_str(MULTI_MEMORY_PROGRAM).unwrap();
+    let options = RewriteOptions::new().check_memory_operations(true);
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+
+    let mut config = wasmtime::Config::new();
+    config.wasm_multi_memory(true);
+    let engine = wasmtime::Engine::new(&config).unwrap();
+    let module = wasmtime::Module::new(&engine, output).unwrap();
+    let mut store = wasmtime::Store::new(&engine, ());
+    let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+    let read = instance
+        .get_typed_func::<i32, i32>(&mut store, "read")
+        .unwrap();
+
+    let read_byte = instance
+        .get_typed_func::<i32, i32>(&mut store, "read_byte")
+        .unwrap();
+    let write = instance
+        .get_typed_func::<(i32, i64), ()>(&mut store, "write")
+        .unwrap();
+
+    // Past the first memory, the check uses the size of the memory accessed
+    let memory_end = 2 * 65536;
+    assert_eq!(read.call(&mut store, 100000).unwrap(), 0);
+    assert_eq!(read.call(&mut store, memory_end - 4).unwrap(), 0);
+    assert_eq!(read_byte.call(&mut store, memory_end - 1).unwrap(), 0);
+    write.call(&mut store, (memory_end - 12, 1)).unwrap();
+    assert_eq!(read.call(&mut store, memory_end - 8).unwrap(), 1);
+
+    // The check covers the size of the access and the offset
+    let err = read.call(&mut store, memory_end - 3).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<wasmtime::Trap>(),
+        Some(&wasmtime::Trap::UnreachableCodeReached)
+    );
+    let err = read_byte.call(&mut store, memory_end).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<wasmtime::Trap>(),
+        Some(&wasmtime::Trap::UnreachableCodeReached)
+    );
+}
+
+#[test]
+fn test_memory_check_store() {
+    let input = wat::parse_str(MULTI_MEMORY_PROGRAM).unwrap();
+    let options = RewriteOptions::new().check_memory_operations(true);
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+
+    let mut config = wasmtime::Config::new();
+    config.wasm_multi_memory(true);
+    let engine = wasmtime::Engine::new(&config).unwrap();
+    let module = wasmtime::Module::new(&engine, output).unwrap();
+    let mut store = wasmtime::Store::new(&engine, ());
+    let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+    let write = instance
+        .get_typed_func::<(i32, i64), ()>(&mut store, "write")
+        .unwrap();
+
+    // The store crosses the end of the memory, the runtime writes the
+    // coredump
+    let err = write.call(&mut store, (2 * 65536 - 11, 1)).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<wasmtime::Trap>(),
+        Some(&wasmtime::Trap::UnreachableCodeReached)
+    );
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    assert_eq!(&memory.data(&store)[0..4], b"\0asm");
+}
+
+/// Files written by the module through the WASI imports.
+#[derive(Default)]
+struct WasiFiles {
+    path: String,
+    content: Vec<u8>,
+    closed: Vec<i32>,
+    /// Bytes written by each call to `fd_write`.
+    write_size: usize,
+}
+
+/// Run `main` until it traps, with WASI imports writing in memory.
+fn crash_wasi(output: &[u8], write_size: usize) -> WasiFiles {
+    const FD: i32 = 5;
+
+    fn memory(caller: &mut wasmtime::Caller<'_, WasiFiles>) -> wasmtime::Memory {
+        caller.get_export("memory").unwrap().into_memory().unwrap()
+    }
+    fn read_u32(data: &[u8], addr: usize) -> usize {
+        u32::from_le_bytes(data[addr..addr + 4].try_into().unwrap()) as usize
+    }
+
+    let engine = wasmtime::Engine::default();
+    let module = wasmtime::Module::new(&engine, output).unwrap();
+    let mut linker = wasmtime::Linker::new(&engine);
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "path_open",
+            |mut caller: wasmtime::Caller<'_, WasiFiles>,
+             dirfd: i32,
+             _: i32,
+             path: i32,
+             path_len: i32,
+             _: i32,
+             _: i64,
+             _: i64,
+             _: i32,
+             fd_out: i32| {
+                assert_eq!(dirfd, 3);
+                let memory = memory(&mut caller);
+                let (data, files) = memory.data_and_store_mut(&mut caller);
+                let path = &data[path as usize..(path + path_len) as usize];
+                files.path = String::from_utf8(path.to_vec()).unwrap();
+                data[fd_out as usize..fd_out as usize + 4].copy_from_slice(&FD.to_le_bytes());
+                0
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "fd_write",
+            |mut caller: wasmtime::Caller<'_, WasiFiles>,
+             fd: i32,
+             iovs: i32,
+             iovs_len: i32,
+             nwritten: i32| {
+                assert_eq!((fd, iovs_len), (FD, 1));
+                let memory = memory(&mut caller);
+                let (data, files) = memory.data_and_store_mut(&mut caller);
+                let ptr = read_u32(data, iovs as usize);
+                let len = read_u32(data, iovs as usize + 4).min(files.write_size);
+                files.content.extend(&data[ptr..ptr + len]);
+                data[nwritten as usize..nwritten as usize + 4]
+                    .copy_from_slice(&(len as u32).to_le_bytes());
+                0
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "fd_close",
+            |mut caller: wasmtime::Caller<'_, WasiFiles>, fd: i32| {
+                caller.data_mut().closed.push(fd);
+                0
+            },
+        )
+        .unwrap();
+
+    let files = WasiFiles {
+        write_size,
+        ..Default::default()
+    };
+    let mut store = wasmtime::Store::new(&engine, files);
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    assert!(main.call(&mut store, ()).is_err());
+    store.into_data()
+}
+
+#[test]
+fn test_wasi_file() {
+    let input = wat::parse_str(PROGRAM).unwrap();
+    let options = RewriteOptions::new().wasi_file(Some(WasiCoredumpFile {
+        name: "crash".to_owned(),
+        dirfd: 3,
+    }));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+
+    // fd_write writes less than requested
+    let files = crash_wasi(&output, 4096);
+    assert_eq!(files.path, "crash.coredump.wasm");
+    assert_eq!(files.closed, [5]);
+    let coredump_wasm = wasm_parser::parse(&files.content).unwrap();
+    let coredump = WasmModule::new(coredump_wasm).get_coredump().unwrap();
+    assert_eq!(coredump.stacks[0].frames.len(), 3);
+
+    // fd_write writing nothing doesn't loop forever
+    let files = crash_wasi(&output, 0);
+    assert!(files.content.is_empty());
+    assert_eq!(files.closed, [5]);
+
+    // The path has to fit in the scratch page
+    let options = RewriteOptions::new().wasi_file(Some(WasiCoredumpFile {
+        name: "a".repeat(64 * 1024),
+        dirfd: 3,
+    }));
+    assert!(rewrite_bytes(&input, &options).is_err());
+}
+
+#[test]
+fn test_offset_map_with_wasi_file() {
+    let input = wat::parse_str(PROGRAM).unwrap();
+    let options = RewriteOptions::new()
+        .offset_map(true)
+        .wasi_file(Some(WasiCoredumpFile {
+            name: "crash".to_owned(),
+            dirfd: 3,
+        }));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+
+    // The added imports don't shift the functions of the map
+    let module = WasmModule::new(wasm_parser::parse(&output).unwrap());
+    let map = module.get_code_offset_map().unwrap();
+    assert_eq!(map.funcs.keys().copied().collect::<Vec<_>>(), [0, 1, 2]);
+    assert_eq!(map.original_offset(1, 0), 0);
+}
+
+#[test]
+fn test_rewrite_dwarf() {
+    use gimli::write;
+
+    let input = wat::parse_str(PROGRAM).unwrap();
+    let mut module = WasmModule::new(wasm_parser::parse(&input).unwrap());
+    let original = wasm_printer::wasm::func_positions(module.inner());
+
+    // A line row at the start of each function's instructions
+    let encoding = gimli::Encoding {
+        format: gimli::Format::Dwarf32,
+        version: 4,
+        address_size: 4,
+    };
+    let mut dwarf = write::DwarfUnit::new(encoding);
+    let mut program = write::LineProgram::new(
+        encoding,
+        gimli::LineEncoding::default(),
+        write::LineString::String(b"/src".to_vec()),
+        write::LineString::String(b"main.c".to_vec()),
+        None,
+    );
+    let dir = program.default_directory();
+    let file = program.add_file(write::LineString::String(b"main.c".to_vec()), dir, None);
+    program.begin_sequence(Some(write::Address::Constant(0)));
+    for (line, position) in original.iter().enumerate() {
+        program.row().address_offset = position.instrs_start as u64;
+        program.row().file = file;
+        program.row().line = line as u64 + 1;
+        program.generate_row();
+    }
+    program.end_sequence(original.last().unwrap().end as u64);
+    dwarf.unit.line_program = program;
+
+    let mut sections = write::Sections::new(write::EndianVec::new(gimli::LittleEndian));
+    dwarf.write(&mut sections).unwrap();
+    sections
+        .for_each(|id, data| -> Result<(), ()> {
+            if !data.slice().is_empty() {
+                let section =
+                    ast::CustomSection::Unknown(id.name().to_owned(), data.slice().to_owned());
+                module.add_custom_section(section);
+            }
+            Ok(())
+        })
+        .unwrap();
+    let input = wasm_printer::wasm::print(module.inner()).unwrap();
+
+    let options = RewriteOptions::new().offset_map(true).rewrite_dwarf(true);
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let module = WasmModule::new(wasm_parser::parse(&output).unwrap());
+    let rewritten = wasm_printer::wasm::func_positions(module.inner());
+    let code_offsets = module.get_code_offset_map().unwrap();
+
+    let dwarf = gimli::read::Dwarf::load(|id| -> Result<_, gimli::Error> {
+        let bytes = module.get_custom_section(id.name()).unwrap_or_default();
+        Ok(gimli::EndianRcSlice::new(
+            std::rc::Rc::from(bytes),
+            gimli::LittleEndian,
+        ))
+    })
+    .unwrap();
+    let mut units = dwarf.units();
+    let unit = dwarf.unit(units.next().unwrap().unwrap()).unwrap();
+    let mut rows = unit.line_program.unwrap().rows();
+    let mut addresses = vec![];
+    while let Some((_, row)) = rows.next_row().unwrap() {
+        addresses.push((row.address(), row.line().map(|line| line.get())));
+    }
+
+    // The rows moved with the first original instruction of each function,
+    // which stays at the start when it was replaced
+    let mut expected = rewritten
+        .iter()
+        .take(original.len())
+        .enumerate()
+        .map(|(funcidx, position)| {
+            let first_instr = code_offsets.funcs[&(funcidx as u32)]
+                .iter()
+                .find(|(_, original)| *original == 0)
+                .map(|(rewritten, _)| *rewritten)
+                .unwrap_or(0);
+            let address = (position.instrs_start + first_instr as usize) as u64;
+            (address, Some(funcidx as u64 + 1))
+        })
+        .collect::<Vec<_>>();
+    let end = rewritten[original.len() - 1].end as u64;
+    expected.push((end, Some(original.len() as u64)));
+    assert_eq!(addresses, expected);
+
+    // The second function's row is shifted by the growth of the first one
+    assert_eq!(
+        addresses[1].0 - original[1].instrs_start as u64,
+        (rewritten[0].end - original[0].end) as u64
+    );
+}
+
+#[test]
+fn test_already_rewritten() {
+    let input = wat::parse_str(PROGRAM).unwrap();
+    let options = RewriteOptions::new().placement(CoredumpPlacement::Grow);
+    let (output, report) = rewrite_bytes(&input, &options).unwrap();
+    assert!(!report.already_rewritten);
+
+    // Same options, the module is left untouched
+    let (again, report) = rewrite_bytes(&output, &options).unwrap();
+    assert!(report.already_rewritten);
+    assert_eq!(again, output);
+
+    // Other options
+    let err = rewrite_bytes(&output, &RewriteOptions::new()).unwrap_err();
+    assert!(err.to_string().contains("with other options"), "{}", err);
+
+    // Rewritten by a version without the marker section
+    let mut module = WasmModule::new(wasm_parser::parse(&output).unwrap());
+    module.remove_custom_section("coredump_rewriter").unwrap();
+    let legacy = wasm_printer::wasm::print(module.inner()).unwrap();
+    let err = rewrite_bytes(&legacy, &options).unwrap_err();
+    assert!(
+        err.to_string().contains("module was already rewritten"),
+        "{}",
+        err
+    );
+}
+
+// Out of bounds memory accesses aren't caught by the runtime.
+const MEMORY_TRAP_PROGRAM: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func $leaf (param i32)
+    (drop (i32.load (i32.mul (local.get 0) (i32.const 65536)))))
+  (func $middle (param i32)
+    (call $leaf (local.get 0)))
+  (func $main (export "main") (param i32)
+    (call $middle (local.get 0)))
+)
+"#;
+
+#[test]
+fn test_call_depth_after_trap() {
+    let input = wat::parse_str(MEMORY_TRAP_PROGRAM).unwrap();
+    let options = RewriteOptions::new().max_call_depth(Some(3));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<i32, ()>(&mut store, "main")
+        .unwrap();
+
+    // The trap skips the end of the calls in progress
+    assert!(main.call(&mut store, 1).is_err());
+
+    // Entering the module again starts from an empty call stack
+    for _ in 0..2 {
+        main.call(&mut store, 0).unwrap();
+    }
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    assert_ne!(&memory.data(&store)[0..4], b"\0asm");
+
+    // Past the limit
+    let options = RewriteOptions::new().max_call_depth(Some(2));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<i32, ()>(&mut store, "main")
+        .unwrap();
+    assert!(main.call(&mut store, 0).is_err());
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    assert_eq!(&memory.data(&store)[0..4], b"\0asm");
+}
+
+#[test]
+fn test_shadow_stack() {
+    let input = wat::parse_str(MEMORY_TRAP_PROGRAM).unwrap();
+    let stack = ShadowStack {
+        offset: 64,
+        size: 64,
+    };
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Grow)
+        .shadow_stack(Some(stack));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<i32, ()>(&mut store, "main")
+        .unwrap();
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    // The frames of the trap the runtime didn't catch, innermost first
+    assert!(main.call(&mut store, 1).is_err());
+    let frames = stack.frames(memory.data(&store), 0).unwrap();
+    let funcs = frames.iter().map(|f| f.funcidx).collect::<Vec<_>>();
+    assert_eq!(funcs, [0, 1, 2]);
+    assert_eq!(frames[0].codeoffset, 0);
+    assert_ne!(frames[1].codeoffset, 0);
+    assert_ne!(frames[2].codeoffset, 0);
+
+    // The calls that returned are popped, the host pops the exported
+    // function
+    stack.reset(memory.data_mut(&mut store)).unwrap();
+    main.call(&mut store, 0).unwrap();
+    let frames = stack.frames(memory.data(&store), 0).unwrap();
+    let funcs = frames.iter().map(|f| f.funcidx).collect::<Vec<_>>();
+    assert_eq!(funcs, [2]);
+
+    // The deepest functions share the last entry
+    let stack = ShadowStack {
+        offset: 64,
+        size: 20,
+    };
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Grow)
+        .shadow_stack(Some(stack));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<i32, ()>(&mut store, "main")
+        .unwrap();
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    assert!(main.call(&mut store, 1).is_err());
+    let frames = stack.frames(memory.data(&store), 0).unwrap();
+    let funcs = frames.iter().map(|f| f.funcidx).collect::<Vec<_>>();
+    assert_eq!(funcs, [0, 2]);
+}
+
+const SNAPSHOT_PROGRAM: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func $leaf
+    (i32.store (i32.const 0) (i32.const 0x70616568)))
+  (func $main (export "main")
+    (call $leaf))
+)
+"#;
+
+/// Parse the coredump written by the latest snapshot.
+fn snapshot_coredump(
+    store: &mut wasmtime::Store<()>,
+    instance: &wasmtime::Instance,
+) -> wasm_coredump_types::Coredump {
+    written_coredump(store, instance).get_coredump().unwrap()
+}
+
+#[test]
+fn test_snapshots() {
+    let input = wat::parse_str(SNAPSHOT_PROGRAM).unwrap();
+    let stack = ShadowStack {
+        offset: 64,
+        size: 64,
+    };
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Grow)
+        .shadow_stack(Some(stack))
+        .snapshot_funcs(vec!["leaf".to_owned()]);
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    // The first snapshot grows the memory, the next ones reuse its region
+    let mut sizes = vec![];
+    for i in 0..4 {
+        main.call(&mut store, ()).unwrap();
+        let coredump = snapshot_coredump(&mut store, &instance);
+        let funcs = coredump.stacks[0]
+            .frames
+            .iter()
+            .map(|f| f.funcidx)
+            .collect::<Vec<_>>();
+        assert_eq!(funcs, [0, 1]);
+        // The snapshot is taken when entering the function, before its store
+        let heap = if i == 0 { [0; 4] } else { *b"heap" };
+        assert_eq!(coredump.data[0..4], heap);
+        assert_eq!(coredump.memory, [(0, Some(1))]);
+
+        stack.reset(memory.data_mut(&mut store)).unwrap();
+        sizes.push(memory.size(&store));
+    }
+    assert!(sizes[0] > 1);
+    assert!(sizes.iter().all(|&size| size == sizes[0]), "{sizes:?}");
+
+    // The program grows the memory: the region is placed past it
+    memory.grow(&mut store, 1).unwrap();
+    let grown = memory.size(&store);
+    memory.data_mut(&mut store)[grown as usize * 65536 - 4..].copy_from_slice(b"tail");
+    main.call(&mut store, ()).unwrap();
+    let coredump = snapshot_coredump(&mut store, &instance);
+    assert_eq!(coredump.memory, [(0, Some(grown as u32))]);
+    assert_eq!(coredump.data[coredump.data.len() - 4..], *b"tail");
+}
+
+#[test]
+fn test_snapshot_placement() {
+    let input = wat::parse_str(SNAPSHOT_PROGRAM).unwrap();
+    let stack = ShadowStack {
+        offset: 64,
+        size: 64,
+    };
+    let options = RewriteOptions::new()
+        .shadow_stack(Some(stack))
+        .snapshot(true);
+    assert!(rewrite_bytes(&input, &options).is_err());
+
+    // Without a shadow stack
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Grow)
+        .snapshot(true);
+    assert!(rewrite_bytes(&input, &options).is_err());
+
+    // In a reserved range, the snapshots leave the memory intact
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Reserved {
+            offset: 1024,
+            size: 4096,
+        })
+        .shadow_stack(Some(stack))
+        .snapshot_funcs(vec!["leaf".to_owned()]);
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    for _ in 0..3 {
+        main.call(&mut store, ()).unwrap();
+        let coredump = snapshot_coredump(&mut store, &instance);
+        assert_eq!(coredump.stacks[0].frames.len(), 2);
+        stack.reset(memory.data_mut(&mut store)).unwrap();
+    }
+    assert_eq!(memory.size(&store), 1);
+    assert_eq!(&memory.data(&store)[0..4], b"heap");
+
+    // In a coredump memory, the snapshots reuse its start
+    let options = options.placement(CoredumpPlacement::Memory);
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    let coredump_memory = instance
+        .get_memory(&mut store, COREDUMP_MEMORY_EXPORT)
+        .unwrap();
+    let mut sizes = vec![];
+    for _ in 0..3 {
+        main.call(&mut store, ()).unwrap();
+        let coredump = snapshot_coredump(&mut store, &instance);
+        assert_eq!(coredump.stacks[0].frames.len(), 2);
+        stack.reset(memory.data_mut(&mut store)).unwrap();
+        sizes.push(coredump_memory.size(&store));
+    }
+    assert!(sizes.iter().all(|&size| size == sizes[0]), "{sizes:?}");
+    assert_eq!(memory.size(&store), 1);
+    assert_eq!(&memory.data(&store)[0..4], b"heap");
+}
+
+const NESTED_TRAP_PROGRAM: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func $leaf (param i32)
+    (if (local.get 0) (then unreachable)))
+  (func $middle (param i32)
+    (call $leaf (local.get 0)))
+  (func $main (export "main") (param i32)
+    (call $middle (local.get 0)))
+)
+"#;
+
+#[test]
+fn test_flight_recorder() {
+    let input = wat::parse_str(NESTED_TRAP_PROGRAM).unwrap();
+    let recorder = FlightRecorder {
+        offset: 64,
+        size: 4 + 4 * 4,
+        exits: true,
+    };
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Grow)
+        .flight_recorder(Some(recorder));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<i32, ()>(&mut store, "main")
+        .unwrap();
+
+    // 10 events in 4 slots, the buffer wraps around. The calls unwound by
+    // the trap return too.
+    main.call(&mut store, 0).unwrap();
+    assert!(main.call(&mut store, 1).is_err());
+
+    let get_coredump_ptr = instance
+        .get_typed_func::<(), u32>(&mut store, "get_coredump_ptr")
+        .unwrap();
+    let ptr = get_coredump_ptr.call(&mut store, ()).unwrap() as usize;
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    let coredump = WasmModule::new(wasm_parser::parse(&memory.data(&store)[ptr..]).unwrap());
+    let content = coredump
+        .get_custom_section(FLIGHT_RECORDER_SECTION)
+        .unwrap();
+    assert_eq!(content[0..8], [4, 0, 0, 0, 2, 0, 0, 0]);
+
+    let events = read_flight_recorder(&content).unwrap();
+    assert_eq!(
+        events,
+        [
+            CallEvent::Entry(1),
+            CallEvent::Entry(0),
+            CallEvent::Return(1),
+            CallEvent::Return(2),
+        ]
+    );
+
+    let mut out = String::new();
+    write_history(&mut out, &events, |funcidx| format!("f{}", funcidx)).unwrap();
+    assert_eq!(out, "#0\t-> f1\n#1\t-> f0\n#2\t<- f1\n#3\t<- f2\n");
+
+    // Empty slots are skipped, returns are recorded with exits only
+    let options = options.flight_recorder(Some(FlightRecorder {
+        exits: false,
+        ..recorder
+    }));
+    let (output, _) = rewrite_bytes(&input, &options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<i32, ()>(&mut store, "main")
+        .unwrap();
+    main.call(&mut store, 0).unwrap();
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    let mut content = 4u32.to_le_bytes().to_vec();
+    content.extend(&memory.data(&store)[64..64 + recorder.size as usize]);
+    assert_eq!(
+        read_flight_recorder(&content).unwrap(),
+        [
+            CallEvent::Entry(2),
+            CallEvent::Entry(1),
+            CallEvent::Entry(0)
+        ]
+    );
+
+    assert!(read_flight_recorder(&[4, 0, 0, 0, 4, 0, 0, 0]).is_err());
+    assert!(read_flight_recorder(&[4, 0, 0, 0, 0, 0, 0, 0]).is_err());
+}
+
+// panic_fmt is found by its mangled name.
+const PANIC_PROGRAM: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func $_ZN4core9panicking9panic_fmt17h0123456789abcdefE (param i32 i32)
+    unreachable)
+  (func $main (export "main")
+    (call $_ZN4core9panicking9panic_fmt17h0123456789abcdefE
+      (i32.const 100) (i32.const 200)))
+)
+"#;
+
+/// Run `main` until it traps and read the `crash_metadata` section of the
+/// coredump, if any.
+fn crash_metadata(input: &str, options: &RewriteOptions) -> Option<Vec<PanicRecord>> {
+    let input = wat::parse_str(input).unwrap();
+    let (output, _) = rewrite_bytes(&input, options).unwrap();
+    let (mut store, instance) = instantiate_module(&output);
+    let main = instance
+        .get_typed_func::<(), ()>(&mut store, "main")
+        .unwrap();
+    assert!(main.call(&mut store, ()).is_err());
+
+    let coredump = written_coredump(&mut store, &instance);
+    let content = coredump.get_custom_section(CRASH_METADATA_SECTION)?;
+    Some(read_crash_metadata(&content).unwrap())
+}
+
+#[test]
+fn test_panic_metadata() {
+    let options = RewriteOptions::new()
+        .placement(CoredumpPlacement::Grow)
+        .capture_panics(true);
+    assert_eq!(
+        crash_metadata(PANIC_PROGRAM, &options),
+        Some(vec![PanicRecord {
+            funcidx: 0,
+            args: vec![100, 200]
+        }])
+    );
+
+    // Without a panic
+    assert_eq!(crash_metadata(PROGRAM, &options), None);
+
+    // Appended to the coredump in its own memory
+    let memory = options.clone().placement(CoredumpPlacement::Memory);
+    assert_eq!(
+        crash_metadata(PANIC_PROGRAM, &memory),
+        Some(vec![PanicRecord {
+            funcidx: 0,
+            args: vec![100, 200]
+        }])
+    );
+
+    let options = options.capture_panics(false);
+    assert_eq!(crash_metadata(PANIC_PROGRAM, &options), None);
+
+    assert!(read_crash_metadata(&[1, 0, 0, 0, 0]).is_err());
+}
@@ -0,0 +1,83 @@
+//! Frame buffers of the threads sharing the memory.
+//!
+//! With wasi-threads each thread runs in its own instance, with its own
+//! globals, over a shared memory. The reserved coredump range is divided in
+//! frame buffers, a thread takes the next one atomically when it records its
+//! first frame. The range is structured as follows:
+//!
+//! | next: u32 | coredump: u32 | buffer* |
+//!
+//! `next` is the index of the next free buffer. `coredump` is the address of
+//! the written coredump, zero until then. The first thread to write its
+//! coredump claims it, the other threads trap instead of writing theirs.
+//!
+//! Threads are identified by the id given to the `wasi_thread_start` export.
+//! The thread of the main instance is named `main`, the other ones
+//! `thread-<id>`.
+
+use crate::rewriter::CoredumpPlacement;
+
+type BoxError = Box<dyn std::error::Error>;
+
+/// Export starting a thread, called with the thread id and the start
+/// argument.
+pub(crate) const THREAD_START_EXPORT: &str = "wasi_thread_start";
+
+pub(crate) const HEADER_SIZE: u32 = 8;
+pub(crate) const NEXT_OFFSET: u32 = 0;
+pub(crate) const COREDUMP_OFFSET: u32 = 4;
+
+// Room for the coredump headers, which are written in the buffer.
+const MIN_BUFFER_SIZE: u32 = 256;
+
+/// Frame buffer of `size` bytes for each thread, in the reserved coredump
+/// range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThreadFrames {
+    pub size: u32,
+}
+
+impl ThreadFrames {
+    /// Number of buffers fitting in the reserved range, unbounded when the
+    /// range spans the rest of the memory.
+    pub(crate) fn capacity(&self, placement: CoredumpPlacement) -> Option<u32> {
+        match placement {
+            CoredumpPlacement::Reserved { size: u32::MAX, .. }
+            | CoredumpPlacement::Grow
+            | CoredumpPlacement::Memory => None,
+            CoredumpPlacement::Reserved { size, .. } => {
+                Some(size.saturating_sub(HEADER_SIZE) / self.size)
+            }
+        }
+    }
+
+    pub(crate) fn check(&self, placement: CoredumpPlacement) -> Result<(), BoxError> {
+        if placement == CoredumpPlacement::Grow {
+            return Err(
+                "per-thread frame buffers are allocated in the reserved coredump range, they can't be used when growing the memory"
+                    .into(),
+            );
+        }
+        if placement == CoredumpPlacement::Memory {
+            return Err(
+                "per-thread frame buffers are allocated in the reserved coredump range, they can't be used with a coredump memory"
+                    .into(),
+            );
+        }
+        if self.size < MIN_BUFFER_SIZE {
+            return Err(format!(
+                "per-thread frame buffers must be at least {} bytes",
+                MIN_BUFFER_SIZE
+            )
+            .into());
+        }
+        if self.capacity(placement) == Some(0) {
+            return Err(format!(
+                "reserved coredump range must be at least {} bytes to hold a frame buffer",
+                HEADER_SIZE + self.size
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+//! Write the coredump to a file using WASI.
+//!
+//! Once the coredump is written in memory, a page of memory is grown to hold
+//! the arguments of the WASI calls; the coredump itself is left untouched.
+
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::WasmModule;
+
+type BoxError = Box<dyn std::error::Error>;
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+// `oflags` for path_open: create the file or truncate it.
+const OFLAGS_CREAT: i64 = 1 << 0;
+const OFLAGS_TRUNC: i64 = 1 << 3;
+// `rights` for path_open: write to the file descriptor.
+const RIGHTS_FD_WRITE: i64 = 1 << 6;
+
+// Layout of the scratch memory, relative to its start.
+const SCRATCH_IOVEC: u32 = 0;
+const SCRATCH_OPENED_FD: u32 = 8;
+const SCRATCH_NWRITTEN: u32 = 12;
+const SCRATCH_PATH: u32 = 16;
+const SCRATCH_SIZE: u32 = 64 * 1024;
+
+/// Write the coredump to `<name>.coredump.wasm` in a preopened directory.
+#[derive(Debug, Clone)]
+pub struct WasiCoredumpFile {
+    pub name: String,
+    /// File descriptor of the preopened directory. The first preopened
+    /// directory usually is 3.
+    pub dirfd: u32,
+}
+
+impl WasiCoredumpFile {
+    fn path(&self) -> String {
+        format!("{}.coredump.wasm", self.name)
+    }
+
+    /// The path is copied in the page of scratch memory.
+    pub(crate) fn check(&self) -> Result<(), BoxError> {
+        let max_len = (SCRATCH_SIZE - SCRATCH_PATH) as usize;
+        if self.path().len() > max_len {
+            return Err(format!("coredump file path must be at most {} bytes", max_len).into());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct WasiImports {
+    pub(crate) path_open: u32,
+    pub(crate) fd_write: u32,
+    pub(crate) fd_close: u32,
+    /// Number of function imports that were added to the module.
+    pub(crate) added: u32,
+}
+
+/// Add the WASI imports, unless the module already imports them.
+///
+/// Adding an import shifts the funcidx of the module's functions, so this
+/// must run before any funcidx of the module is recorded.
+pub(crate) fn add_wasi_imports(module: &mut WasmModule) -> Result<WasiImports, BoxError> {
+    let mut added = 0;
+
+    let path_open = match find_func_import(module, "path_open") {
+        Some(funcidx) => funcidx,
+        None => {
+            let t = ast::make_type! { (I32, I32, I32, I32, I32, I64, I64, I32, I32) -> I32 };
+            added += 1;
+            add_func_import(module, "path_open", &t)?
+        }
+    };
+
+    let fd_write = match find_func_import(module, "fd_write") {
+        Some(funcidx) => funcidx,
+        None => {
+            let t = ast::make_type! { (I32, I32, I32, I32) -> I32 };
+            added += 1;
+            add_func_import(module, "fd_write", &t)?
+        }
+    };
+
+    let fd_close = match find_func_import(module, "fd_close") {
+        Some(funcidx) => funcidx,
+        None => {
+            let t = ast::make_type! { (I32) -> I32 };
+            added += 1;
+            add_func_import(module, "fd_close", &t)?
+        }
+    };
+
+    Ok(WasiImports {
+        path_open,
+        fd_write,
+        fd_close,
+        added,
+    })
+}
+
+fn find_func_import(module: &WasmModule, name: &str) -> Option<u32> {
+    module
+        .imports()
+        .iter()
+        .filter(|import| matches!(import.import_type, ast::ImportType::Func(_)))
+        .position(|import| import.module == WASI_MODULE && import.name == name)
+        .map(|funcidx| funcidx as u32)
+}
+
+fn add_func_import(module: &mut WasmModule, name: &str, t: &ast::Type) -> Result<u32, BoxError> {
+    let typeidx = module.add_type(t);
+
+    let import = ast::Import {
+        module: WASI_MODULE.to_owned(),
+        name: name.to_owned(),
+        import_type: ast::ImportType::Func(typeidx),
+    };
+    module.add_import(&import)
+}
+
+/// Build a function that writes the coredump, using `write_coredump`, and
+/// then writes it to a file.
+pub(crate) fn make_write_coredump_file(
+    file: &WasiCoredumpFile,
+    imports: &WasiImports,
+    write_coredump: u32,
+    coredump_base_global: u32,
+) -> ast::Code {
+    let path = file.path();
+
+    // locals
+    let mem_end = 0;
+    let ptr = 1;
+    let len = 2;
+    let scratch = 3;
+    let fd = 4;
+    let nwritten = 5;
+
+    let mut body = vec![
+        ast::Instr::call(write_coredump),
+        // The coredump spans until the end of the memory
+        ast::Instr::global_get(coredump_base_global),
+        ast::Instr::local_set(ptr),
+        ast::Instr::memory_size(0),
+        ast::Instr::i32_const(16),
+        ast::Instr::i32_shl,
+        ast::Instr::local_tee(mem_end),
+        ast::Instr::local_get(ptr),
+        ast::Instr::i32_sub,
+        ast::Instr::local_set(len),
+    ];
+
+    // Grow a page for the scratch memory
+    {
+        body.push(ast::Instr::i32_const(1));
+        body.push(ast::Instr::memory_grow(0));
+        body.push(ast::Instr::i32_const(-1));
+        body.push(ast::Instr::i32_eq);
+
+        let if_body = vec![
+            ast::Value::new(ast::Instr::Return),
+            ast::Value::new(ast::Instr::end),
+        ];
+        body.push(ast::Instr::If(
+            ast::BlockType::Empty,
+            ast::Value::new(if_body),
+        ));
+
+        body.push(ast::Instr::local_get(mem_end));
+        body.push(ast::Instr::local_set(scratch));
+    }
+
+    // Copy the path in the scratch memory
+    for (i, byte) in path.bytes().enumerate() {
+        body.push(ast::Instr::local_get(scratch));
+        body.push(ast::Instr::i32_const(byte as i64));
+        body.push(ast::Instr::i32_store8(ast::MemArg::new(
+            0,
+            SCRATCH_PATH + i as u32,
+        )));
+    }
+
+    // Open the file
+    {
+        body.push(ast::Instr::i32_const(file.dirfd as i64));
+        body.push(ast::Instr::i32_const(0)); // lookupflags
+        body.push(ast::Instr::local_get(scratch));
+        body.push(ast::Instr::i32_const(SCRATCH_PATH as i64));
+        body.push(ast::Instr::i32_add);
+        body.push(ast::Instr::i32_const(path.len() as i64));
+        body.push(ast::Instr::i32_const(OFLAGS_CREAT | OFLAGS_TRUNC));
+        body.push(ast::Instr::i64_const(RIGHTS_FD_WRITE)); // rights base
+        body.push(ast::Instr::i64_const(0)); // rights inheriting
+        body.push(ast::Instr::i32_const(0)); // fdflags
+        body.push(ast::Instr::local_get(scratch));
+        body.push(ast::Instr::i32_const(SCRATCH_OPENED_FD as i64));
+        body.push(ast::Instr::i32_add);
+        body.push(ast::Instr::call(imports.path_open));
+
+        // Give up if the file couldn't be opened
+        let if_body = vec![
+            ast::Value::new(ast::Instr::Return),
+            ast::Value::new(ast::Instr::end),
+        ];
+        body.push(ast::Instr::If(
+            ast::BlockType::Empty,
+            ast::Value::new(if_body),
+        ));
+
+        body.push(ast::Instr::local_get(scratch));
+        body.push(ast::Instr::i32_load(ast::MemArg::new(2, SCRATCH_OPENED_FD)));
+        body.push(ast::Instr::local_set(fd));
+    }
+
+    // Write the coredump, fd_write can write less than requested. Give up
+    // on errors or if nothing was written.
+    {
+        let loop_body = vec![
+            ast::Instr::local_get(len),
+            ast::Instr::i32_eqz,
+            ast::Instr::br_if(1),
+            // iovec
+            ast::Instr::local_get(scratch),
+            ast::Instr::local_get(ptr),
+            ast::Instr::i32_store(ast::MemArg::new(2, SCRATCH_IOVEC)),
+            ast::Instr::local_get(scratch),
+            ast::Instr::local_get(len),
+            ast::Instr::i32_store(ast::MemArg::new(2, SCRATCH_IOVEC + 4)),
+            ast::Instr::local_get(fd),
+            ast::Instr::local_get(scratch),
+            ast::Instr::i32_const(1), // iovec count
+            ast::Instr::local_get(scratch),
+            ast::Instr::i32_const(SCRATCH_NWRITTEN as i64),
+            ast::Instr::i32_add,
+            ast::Instr::call(imports.fd_write),
+            ast::Instr::br_if(1),
+            ast::Instr::local_get(scratch),
+            ast::Instr::i32_load(ast::MemArg::new(2, SCRATCH_NWRITTEN)),
+            ast::Instr::local_tee(nwritten),
+            ast::Instr::i32_eqz,
+            ast::Instr::br_if(1),
+            ast::Instr::local_get(ptr),
+            ast::Instr::local_get(nwritten),
+            ast::Instr::i32_add,
+            ast::Instr::local_set(ptr),
+            ast::Instr::local_get(len),
+            ast::Instr::local_get(nwritten),
+            ast::Instr::i32_sub,
+            ast::Instr::local_set(len),
+            ast::Instr::br(0),
+            ast::Instr::end,
+        ];
+
+        let loop_body = loop_body.into_iter().map(ast::Value::new).collect();
+        let block_body = vec![
+            ast::Value::new(ast::Instr::Loop(
+                ast::BlockType::Empty,
+                ast::Value::new(loop_body),
+            )),
+            ast::Value::new(ast::Instr::end),
+        ];
+        body.push(ast::Instr::Block(
+            ast::BlockType::Empty,
+            ast::Value::new(block_body),
+        ));
+    }
+
+    body.push(ast::Instr::local_get(fd));
+    body.push(ast::Instr::call(imports.fd_close));
+    body.push(ast::Instr::drop);
+    body.push(ast::Instr::end);
+
+    ast::Code {
+        locals: vec![ast::CodeLocal {
+            count: 6,
+            value_type: ast::ValueType::NumType(ast::NumType::I32),
+        }],
+        size: ast::Value::new(0), // printer calculates based on the body
+        body: ast::Value::new(body.into_iter().map(ast::Value::new).collect()),
+    }
+}
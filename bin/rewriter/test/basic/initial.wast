@@ -1,16 +0,0 @@
-(module
-  (memory 10)
-  (func $entry (param i32 i32) (result i32)
-    (local.get 0)
-    (local.get 1)
-    (call $addTwo)
-  )
-  (func $addTwo (param i32 i32) (result i32)
-    (local.get 0)
-    (local.get 1)
-    (i32.add)
-    (unreachable)
-  )
-  (export "entry" (func $entry))
-  (export "memory" (memory 0))
-)
@@ -1,28 +0,0 @@
-(module
-  (memory 10)
-  (func $first (param i32 i32) (result i32)
-    (unreachable)
-    (nop)
-  )
-  (func $one (param i32 i32) (result i32)
-    (nop)
-    (unreachable)
-  )
-  (func $far (param i32 i32) (result i32)
-    (nop)
-    (nop)
-    (nop)
-    (nop)
-    (nop)
-    (nop)
-    (nop)
-    (nop)
-    (nop)
-    (nop)
-    (nop)
-    (unreachable)
-  )
-  (export "first" (func $first))
-  (export "far" (func $far))
-  (export "one" (func $one))
-)
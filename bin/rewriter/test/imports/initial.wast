@@ -1,15 +0,0 @@
-(module
-  (import "env" "foo" (func $foo))
-  (import "env" "bar" (func $bar))
-  (import "env" "global" (global $global i32))
-  (import "env" "foo2" (func $foo2))
-  (memory 10)
-  (func $addTwo (param i32 i32) (result i32)
-    (local.get 0)
-    (local.get 1)
-    (i32.add)
-    (unreachable)
-  )
-  (export "addTwo" (func $addTwo))
-  (export "memory" (memory 0))
-)
@@ -1,14 +1,12 @@
 use crate::{BoxError, Context};
 use colored::Colorize;
 use core_wasm_ast as ast;
-use core_wasm_ast::traverse::{self, Visitor, VisitorContext, WasmModule};
-use std::sync::Arc;
+use core_wasm_ast::traverse::{Visitor, VisitorContext};
 
 pub(crate) fn set_breakpoint(ctx: &Context, pos: u32) -> Result<(), BoxError> {
     ctx.break_points.lock().unwrap().insert(pos);
 
-    let module = WasmModule::new(Arc::clone(&ctx.source.inner));
-    let func_name = module.get_func_name(pos).ok_or("function not found")?;
+    let func_name = ctx.source.get_func_name(pos).ok_or("function not found")?;
 
     let func = if let Some(func) = ctx.ddbug.functions_by_linkage_name.get(&func_name) {
         let source = format!(
@@ -48,34 +46,20 @@ pub(crate) fn set_breakpoint(ctx: &Context, pos: u32) -> Result<(), BoxError> {
         format!("{} as {} at ???", addr, func_name)
     };
 
-    // FIXME: move to rewriter.
-    let visitor = AddBreakpointVisitor { funcidx: pos };
-    traverse::traverse(Arc::clone(&&ctx.source.inner), Arc::new(visitor));
-
     println!("Breakpoint added on {}", func);
     Ok(())
 }
 
-struct AddBreakpointVisitor {
-    funcidx: u32,
+/// Traps at the start of the function, the breakpoints are applied to a copy
+/// of the source module when the program is run.
+pub(crate) struct AddBreakpointVisitor {
+    pub(crate) funcidx: u32,
 }
 
 impl Visitor for AddBreakpointVisitor {
     fn visit_code<'a>(&self, ctx: &'_ mut VisitorContext<'a, ast::Code>, funcidx: u32) {
         if funcidx == self.funcidx {
-            let breakpoint = vec![ast::Value::new(ast::Instr::unreachable)];
-
-            let mut body = ctx.node.body.lock().unwrap();
-            body.value = prepend(body.value.clone(), &breakpoint);
+            ctx.prepend_body(vec![ast::Instr::unreachable]);
         }
     }
 }
-
-fn prepend<T>(v: Vec<T>, s: &[T]) -> Vec<T>
-where
-    T: Clone,
-{
-    let mut tmp: Vec<_> = s.to_owned();
-    tmp.extend(v);
-    tmp
-}
@@ -69,7 +69,7 @@ pub(crate) fn print_frame<'a>(
                     // TODO: not always 4 bytes, right?
                     let size_of = 4;
 
-                    let value = match memory::get_param_addr(frame, &func, param) {
+                    let value = match memory::get_param_addr(ctx, frame, &func, param) {
                         Ok(abs_addr) => match memory::read(&coredump.data, abs_addr, size_of) {
                             Ok(bytes) => {
                                 format!("0x{}", hex::encode(&bytes))
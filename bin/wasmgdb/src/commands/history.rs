@@ -0,0 +1,23 @@
+use crate::{BoxError, Context};
+use rustc_demangle::demangle;
+
+/// Print the calls recorded by the flight recorder, the oldest first.
+pub(crate) fn history<'a>(ctx: &'a Context<'a>) -> Result<(), BoxError> {
+    let history = ctx.history.borrow();
+    let history = history
+        .as_ref()
+        .ok_or("no call history, rewrite the module with --flight-recorder-size")?;
+
+    let func_name = |funcidx: u32| {
+        ctx.source
+            .get_func_name(funcidx)
+            .map(|name| demangle(&name).to_string())
+            .unwrap_or_else(|| format!("<func {}>", funcidx))
+    };
+
+    let mut out = String::new();
+    wasm_coredump_rewriter::write_history(&mut out, history, func_name)?;
+    print!("{}", out);
+
+    Ok(())
+}
@@ -37,7 +37,7 @@ pub(crate) fn info<'src, 'input>(
             for (name, param) in ctx.variables.borrow().iter() {
                 let ty = param.ty(&ctx.ddbug).unwrap();
 
-                let addr = memory::get_param_addr(selected_frame, func, &param)?;
+                let addr = memory::get_param_addr(ctx, selected_frame, func, &param)?;
                 let value = print_value(ctx, addr, ty.as_ref(), 0)?;
 
                 println!("{}: {}", name, value)
@@ -101,7 +101,13 @@ pub(crate) fn info<'src, 'input>(
         "globals" => {
             let globals = ctx.source.globals();
             println!("{} global(s).", globals.len());
-            let mut globalidx = 0;
+            // Defined globals come after the imported ones
+            let mut globalidx = ctx
+                .source
+                .imports()
+                .iter()
+                .filter(|import| matches!(import.import_type, core_wasm_ast::ImportType::Global(_)))
+                .count() as u32;
             for global in globals {
                 let mut printer = wasm_printer::WastPrinter {
                     module: &ctx.source,
@@ -109,7 +115,9 @@ pub(crate) fn info<'src, 'input>(
                 };
                 printer.print_global(global)?;
 
-                let value = if global.global_type.mutable {
+                let value = if let Some(value) = ctx.global_value(globalidx) {
+                    format_value(&value)
+                } else if global.global_type.mutable {
                     "???".to_owned()
                 } else {
                     global.compute_value().to_string()
@@ -131,8 +139,8 @@ pub(crate) fn info<'src, 'input>(
             if ctx.ddbug.functions_by_address.len() == 0 {
                 println!("no functions defined.");
             }
-            let func_name = ctx.source.func_names.lock().unwrap();
-            for (funcidx, func_name) in func_name.iter() {
+            let func_names = ctx.source.func_names().ok_or("no function names found")?;
+            for (funcidx, func_name) in func_names.iter() {
                 if let Some(func) = ctx.ddbug.functions_by_linkage_name.get(func_name) {
                     let source = format!(
                         "{}/{}",
@@ -168,3 +176,13 @@ pub(crate) fn info<'src, 'input>(
         _ => Err(format!("info {} not implemented", what).into()),
     }
 }
+
+fn format_value(value: &wasm_coredump_types::Value) -> String {
+    match value {
+        wasm_coredump_types::Value::Missing => "???".to_owned(),
+        wasm_coredump_types::Value::I32(v) => v.to_string(),
+        wasm_coredump_types::Value::I64(v) => v.to_string(),
+        wasm_coredump_types::Value::F32(v) => v.to_string(),
+        wasm_coredump_types::Value::F64(v) => v.to_string(),
+    }
+}
@@ -3,10 +3,11 @@
 use crate::{BoxError, Context};
 use std::fmt;
 
-mod breakpoint;
+pub(crate) mod breakpoint;
 mod examine;
 mod find;
 mod frames;
+mod history;
 mod info;
 pub(crate) mod parser;
 mod print;
@@ -59,6 +60,7 @@ pub(crate) enum Command<'a> {
     Info(&'a str, Vec<Expr<'a>>),
     Run,
     BreakPoint(u32),
+    History,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -98,6 +100,10 @@ pub(crate) fn run_command<'src, 'input>(
             find::find(ctx, start, end, expr)?;
         }
 
+        Command::History => {
+            history::history(ctx)?;
+        }
+
         Command::Info(what, args) => {
             info::info(ctx, what, args)?;
         }
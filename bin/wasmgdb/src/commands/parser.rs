@@ -101,6 +101,7 @@ pub(crate) fn parse_command<'a>(input: &'a str) -> IResult<&'a str, Command<'a>>
     Ok(match word {
         "bt" => (input, Command::Backtrace),
         "run" | "r" => (input, Command::Run),
+        "history" => (input, Command::History),
         "b" => {
             let (input, n) = preceded(tag(" "), digit1)(input)?;
             let n = n.parse::<u32>().unwrap();
@@ -270,6 +271,12 @@ mod tests {
         assert_eq!(cmd, Print(PrintFormat::String, Name("var")));
     }
 
+    #[test]
+    fn test_history() {
+        let (_, cmd) = parse_command("history").unwrap();
+        assert_eq!(cmd, Command::History);
+    }
+
     #[test]
     fn test_info_types() {
         use Command::*;
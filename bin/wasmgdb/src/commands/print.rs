@@ -130,7 +130,7 @@ pub(crate) fn print<'src, 'input>(
     if let Some(object) = what.object() {
         if let Some(variable) = ctx.variables.borrow().get(object) {
             let what_type = variable.ty(&ctx.ddbug).unwrap();
-            let base_addr = memory::get_param_addr(&selected_frame, &func, &variable)?;
+            let base_addr = memory::get_param_addr(ctx, &selected_frame, &func, &variable)?;
 
             // Evaluate the `what` expression
             let eval_ctx = EvaluationCtx {
@@ -1,20 +1,31 @@
+use crate::commands::breakpoint::AddBreakpointVisitor;
 use crate::{BoxError, Context};
-use std::sync::Arc;
+use core_wasm_ast::traverse::{self, WasmModule};
 use wasmtime_wasi::sync::WasiCtxBuilder;
 
 pub(crate) fn run<'a>(ctx: &Context<'a>) -> Result<(), BoxError> {
-    let module = ctx.source.inner.clone();
-    let check_memory_operations = true;
-    let debug = false;
-    let instance_id = 0;
-    wasm_coredump_rewriter::rewrite(
-        Arc::clone(&module),
-        check_memory_operations,
-        debug,
-        instance_id,
-    )?;
-
-    let engine = wasmtime::Engine::default();
+    let mut module = WasmModule::new(ctx.source.inner().clone());
+
+    for &funcidx in ctx.break_points.lock().unwrap().iter() {
+        let visitor = AddBreakpointVisitor { funcidx };
+        traverse::traverse(&mut module, &visitor);
+    }
+
+    // Write the coredump past the heap to keep the program's memory intact.
+    // The runtime already records the original code offsets and we don't
+    // read the DWARF of the rewritten module. The panic message is shown
+    // with the coredump.
+    let options = wasm_coredump_rewriter::RewriteOptions::new()
+        .check_memory_operations(true)
+        .placement(wasm_coredump_rewriter::CoredumpPlacement::Grow)
+        .capture_panics(true)
+        .capture_exceptions(true);
+    wasm_coredump_rewriter::rewrite(&mut module, &options)?;
+
+    // A module already rewritten may write the coredump in its own memory
+    let mut config = wasmtime::Config::new();
+    config.wasm_multi_memory(true);
+    let engine = wasmtime::Engine::new(&config)?;
     let mut linker = wasmtime::Linker::new(&engine);
     wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
 
@@ -24,7 +35,7 @@ pub(crate) fn run<'a>(ctx: &Context<'a>) -> Result<(), BoxError> {
         .build();
     let mut store = wasmtime::Store::new(&engine, wasi);
 
-    let bytes = wasm_printer::wasm::print(&module)
+    let bytes = wasm_printer::wasm::print(module.inner())
         .map_err(|err| format!("failed to print Wasm module: {}", err))?;
     let wasmtime_module = wasmtime::Module::new(&engine, bytes)?;
 
@@ -37,16 +48,25 @@ pub(crate) fn run<'a>(ctx: &Context<'a>) -> Result<(), BoxError> {
             println!("program failed: {}", err);
 
             // Extract coredump
+            let get_coredump_ptr =
+                instance.get_typed_func::<(), u32>(&mut store, "get_coredump_ptr")?;
+            let coredump_ptr = get_coredump_ptr.call(&mut store, ())? as usize;
+
             let mem = instance
-                .get_memory(&mut store, "memory")
+                .get_memory(&mut store, wasm_coredump_rewriter::COREDUMP_MEMORY_EXPORT)
+                .or_else(|| instance.get_memory(&mut store, "memory"))
                 .ok_or("failed to get memory")?;
-            let data = mem.data(&mut store);
+            let data = mem
+                .data(&mut store)
+                .get(coredump_ptr..)
+                .ok_or("coredump pointer out of bounds")?;
 
-            let coredump_wasm = wasm_parser::parse(&data)
+            let coredump_wasm = wasm_parser::parse(data)
                 .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
-            let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(Arc::new(coredump_wasm));
+            let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(coredump_wasm);
 
             *ctx.coredump.borrow_mut() = Some(coredump_wasm.get_coredump()?);
+            *ctx.history.borrow_mut() = None;
             *ctx.selected_thread.borrow_mut() = Some(0);
         }
         Ok(o) => {
@@ -13,6 +13,9 @@ pub(crate) struct Context<'src> {
 
     pub(crate) coredump: RefCell<Option<wasm_coredump_types::Coredump>>,
 
+    /// Recent calls, from the flight recorder of the rewritten module
+    pub(crate) history: RefCell<Option<Vec<wasm_coredump_rewriter::CallEvent>>>,
+
     /// DWARF informations
     pub(crate) ddbug: ddbug_parser::FileHash<'src>,
 
@@ -33,6 +36,35 @@ impl<'a> Context<'a> {
             .ok_or("No coredump present".into())
     }
 
+    /// Value of a global recorded in the coredump by the rewriter. Globals
+    /// are found by name, or by index when all of them were recorded.
+    pub(crate) fn global_value(&self, globalidx: u32) -> Option<wasm_coredump_types::Value> {
+        let coredump = self.coredump.borrow();
+        let coredump = coredump.as_ref()?;
+
+        if let Some(value) = self
+            .source
+            .get_global_name(globalidx)
+            .and_then(|name| coredump.global(&name))
+        {
+            return Some(value.clone());
+        }
+
+        let imported_globals = self
+            .source
+            .imports()
+            .iter()
+            .filter(|import| matches!(import.import_type, core_wasm_ast::ImportType::Global(_)))
+            .count();
+        if coredump.globals.len() == imported_globals + self.source.globals().len() {
+            return coredump
+                .globals
+                .get(globalidx as usize)
+                .map(|global| global.value.clone());
+        }
+        None
+    }
+
     pub(crate) fn thread(&self) -> Result<wasm_coredump_types::CoreStack, BoxError> {
         let coredump = self.coredump()?;
 
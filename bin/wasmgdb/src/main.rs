@@ -5,13 +5,13 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::prelude::*;
-use std::sync::Arc;
 use std::sync::Mutex;
 use wasmgdb_ddbug_parser as ddbug_parser;
 
 mod commands;
 mod context;
 mod memory;
+mod panic;
 mod repl;
 
 pub(crate) use context::Context;
@@ -24,6 +24,11 @@ struct Args {
     source: String,
     /// Coredump
     coredump: Option<String>,
+    /// Code offset map of the rewritten module, either the module itself or
+    /// the file written by the rewriter's --offset-map. Needed for coredumps
+    /// taken by the engine running the rewritten module.
+    #[clap(long)]
+    offset_map: Option<String>,
 }
 
 pub(crate) type BoxError = Box<dyn std::error::Error>;
@@ -34,6 +39,8 @@ pub fn main() -> Result<(), BoxError> {
     let args = Args::parse();
     let source_filename = args.source;
 
+    let mut panic_records = vec![];
+    let mut history = None;
     let coredump = if let Some(coredump_filename) = args.coredump {
         let mut coredump = Vec::new();
         {
@@ -44,8 +51,36 @@ pub fn main() -> Result<(), BoxError> {
 
         let coredump_wasm = wasm_parser::parse(&coredump)
             .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
-        let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(Arc::new(coredump_wasm));
-        let coredump = coredump_wasm.get_coredump()?;
+        let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(coredump_wasm);
+        let mut coredump = coredump_wasm.get_coredump()?;
+
+        if let Some(content) =
+            coredump_wasm.get_custom_section(wasm_coredump_rewriter::CRASH_METADATA_SECTION)
+        {
+            panic_records = wasm_coredump_rewriter::read_crash_metadata(&content)?;
+        }
+        if let Some(content) =
+            coredump_wasm.get_custom_section(wasm_coredump_rewriter::FLIGHT_RECORDER_SECTION)
+        {
+            history = Some(wasm_coredump_rewriter::read_flight_recorder(&content)?);
+        }
+
+        if let Some(offset_map_filename) = &args.offset_map {
+            let offset_map = std::fs::read(offset_map_filename)?;
+            let offset_map = wasm_parser::parse(&offset_map)
+                .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
+            let offset_map = core_wasm_ast::traverse::WasmModule::new(offset_map);
+            let offset_map = offset_map
+                .get_code_offset_map()
+                .ok_or("code offset map not found")?;
+
+            for stack in &mut coredump.stacks {
+                for frame in &mut stack.frames {
+                    frame.codeoffset = offset_map.original_offset(frame.funcidx, frame.codeoffset);
+                }
+            }
+        }
+
         Some(coredump)
     } else {
         None
@@ -74,11 +109,12 @@ pub fn main() -> Result<(), BoxError> {
 
     let source = wasm_parser::parse(&source)
         .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
-    let source = core_wasm_ast::traverse::WasmModule::new(Arc::new(source));
+    let source = core_wasm_ast::traverse::WasmModule::new(source);
 
     let ctx = Context {
         ddbug,
         coredump: RefCell::new(coredump),
+        history: RefCell::new(history),
         source,
         addr2line: RefCell::new(addr2line),
         variables: RefCell::new(HashMap::new()),
@@ -89,5 +125,9 @@ pub fn main() -> Result<(), BoxError> {
         break_points: Mutex::new(HashSet::new()),
     };
 
+    if let Some(message) = panic::describe(&ctx, &panic_records) {
+        println!("{}", message);
+    }
+
     repl::repl(&ctx)
 }
@@ -1,4 +1,4 @@
-use crate::BoxError;
+use crate::{BoxError, Context};
 use wasmgdb_ddbug_parser as ddbug_parser;
 
 /// Get the absolute addr of a member in memory
@@ -14,16 +14,18 @@ pub(crate) fn get_member_addr<'a>(
 
 /// Get the absolute addr of a function parameter in memory
 pub(crate) fn get_param_addr<'a>(
+    ctx: &Context,
     frame: &wasm_coredump_types::StackFrame,
     func: &ddbug_parser::Function<'a>,
     param: &ddbug_parser::Parameter<'a>,
 ) -> Result<u32, BoxError> {
     let location = param.data_location().ok_or("no data location for param")?;
-    get_addr(frame, func, location)
+    get_addr(ctx, frame, func, location)
 }
 
 /// Get the absolute addr in memory, computed using the base of the func frame.
 pub(crate) fn get_addr<'a>(
+    ctx: &Context,
     frame: &wasm_coredump_types::StackFrame,
     func: &ddbug_parser::Function<'a>,
     location: &ddbug_parser::DataLocation,
@@ -57,6 +59,16 @@ pub(crate) fn get_addr<'a>(
                 Err(format!("failed to load base addr in local {}", base_local).into())
             }
         }
+        // Needs the global to be recorded in the coredump, see the rewriter's
+        // --record-globals
+        ddbug_parser::DataLocation::WasmGlobal(base_global) => {
+            match ctx.global_value(*base_global) {
+                Some(wasm_coredump_types::Value::I32(base_addr)) => {
+                    Ok((base_addr + offset_from_base as i32) as u32)
+                }
+                _ => Err(format!("failed to load base addr in global {}", base_global).into()),
+            }
+        }
         e => Err(format!("get_addr {:?} not implemented", e).into()),
     }
 }
@@ -0,0 +1,119 @@
+//! Message of a Rust panic, from the `crash_metadata` section the rewriter
+//! appends to the coredump.
+//!
+//! The structures are read assuming the field order of their declaration:
+//!
+//! - `core::panic::Location`: `file: &str`, `line: u32`, `col: u32`.
+//! - `core::fmt::Arguments`: `pieces: &[&str]`, `fmt`, `args: &[_]`.
+//!
+//! The arguments of the message aren't formatted, they are shown as `{}`.
+//! These layouts aren't stable: when `fmt::Arguments` doesn't look like the
+//! expected one, only the location of the panic is shown.
+
+use crate::memory;
+use crate::{BoxError, Context};
+use log::debug;
+use rustc_demangle::demangle;
+use wasm_coredump_rewriter::PanicRecord;
+
+// Larger slices are assumed to be garbage.
+const MAX_PIECES: u32 = 64;
+const MAX_STR_LEN: u32 = 4096;
+
+/// Describe the panic if the module panicked, for instance
+/// `panicked at src/foo.rs:12: index out of bounds`.
+pub(crate) fn describe(ctx: &Context, records: &[PanicRecord]) -> Option<String> {
+    if records.is_empty() {
+        return None;
+    }
+
+    for record in records {
+        let name = ctx
+            .source
+            .get_func_name(record.funcidx)
+            .map(|name| format!("{:#}", demangle(&name)));
+
+        // panic_fmt(fmt: &Arguments, location: &Location)
+        if name.as_deref() == Some("core::panicking::panic_fmt") && record.args.len() == 2 {
+            match panic_fmt_message(ctx, record.args[0], record.args[1]) {
+                Ok(message) => return Some(message),
+                Err(err) => debug!("failed to decode panic_fmt arguments: {}", err),
+            }
+        }
+    }
+
+    Some("panicked, the message is unavailable".to_owned())
+}
+
+fn panic_fmt_message(ctx: &Context, fmt: u32, location: u32) -> Result<String, BoxError> {
+    let file = read_str(ctx, read_u32(ctx, location)?, read_u32(ctx, location + 4)?)?;
+    let line = read_u32(ctx, location + 8)?;
+
+    match fmt_message(ctx, fmt) {
+        Ok(message) => Ok(format!("panicked at {}:{}: {}", file, line, message)),
+        Err(err) => {
+            debug!("failed to decode fmt::Arguments: {}", err);
+            Ok(format!("panicked at {}:{}", file, line))
+        }
+    }
+}
+
+fn fmt_message(ctx: &Context, fmt: u32) -> Result<String, BoxError> {
+    let pieces_ptr = read_u32(ctx, fmt)?;
+    let pieces_len = read_u32(ctx, fmt + 4)?;
+    let args_len = read_u32(ctx, fmt + 20)?;
+    if pieces_len > MAX_PIECES || args_len > MAX_PIECES {
+        return Err("invalid fmt::Arguments".into());
+    }
+    // The message has a piece before each argument, and maybe one after
+    // them. Otherwise the layout isn't the expected one.
+    if pieces_len < args_len || pieces_len > args_len + 1 {
+        return Err("unknown fmt::Arguments layout".into());
+    }
+
+    let mut message = String::new();
+    for i in 0..pieces_len.max(args_len) {
+        if i < pieces_len {
+            let piece = pieces_ptr + i * 8;
+            message.push_str(&read_str(
+                ctx,
+                read_u32(ctx, piece)?,
+                read_u32(ctx, piece + 4)?,
+            )?);
+        }
+        if i < args_len {
+            message.push_str("{}");
+        }
+    }
+
+    Ok(message)
+}
+
+/// Read memory from the coredump, or from the data segments of the module
+/// for the memory past the range the coredump captured.
+fn read(ctx: &Context, addr: u32, size: u32) -> Result<Vec<u8>, BoxError> {
+    if let Some(coredump) = ctx.coredump.borrow().as_ref() {
+        if let Ok(bytes) = memory::read(&coredump.data, addr, size as u64) {
+            return Ok(bytes.to_vec());
+        }
+    }
+
+    ctx.source
+        .read_data(addr, size)
+        .ok_or_else(|| format!("memory at 0x{:x} not captured", addr).into())
+}
+
+fn read_u32(ctx: &Context, addr: u32) -> Result<u32, BoxError> {
+    let bytes = read(ctx, addr, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_str(ctx: &Context, addr: u32, len: u32) -> Result<String, BoxError> {
+    if len > MAX_STR_LEN {
+        return Err("invalid str".into());
+    }
+    if len == 0 {
+        return Ok(String::new());
+    }
+    Ok(String::from_utf8(read(ctx, addr, len)?)?)
+}
@@ -1,6 +1,5 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::Mutex;
 
 #[macro_export]
 macro_rules! body {
@@ -54,14 +53,6 @@ macro_rules! make_type {
 pub use body;
 pub use make_type;
 
-#[macro_export]
-macro_rules! make_value {
-    ($v:expr) => {
-        Arc::new(Mutex::new(ast::Value::new($v)))
-    };
-}
-pub use make_value;
-
 #[derive(Debug, PartialEq, Clone)]
 pub struct Value<T> {
     pub value: T,
@@ -78,19 +69,19 @@ impl<T> Value<T> {
     }
 }
 
-pub type MutableValue<T> = Arc<Mutex<Value<T>>>;
-
 #[derive(Debug, Clone)]
 pub struct Memory {
     pub min: Value<u32>,
     pub max: Option<u32>,
+    /// Shared between threads, with the threads proposal.
+    pub shared: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct Code {
     pub size: Value<u32>,
     pub locals: Vec<CodeLocal>,
-    pub body: MutableValue<Vec<Value<Instr>>>,
+    pub body: Expr,
 }
 
 #[derive(Debug, Clone)]
@@ -102,8 +93,9 @@ pub struct CodeLocal {
 #[derive(Debug, PartialEq, Clone)]
 pub enum ValueType {
     NumType(NumType),
-    // VectorType(),
-    // RefType(),
+    /// v128
+    VectorType,
+    RefType(Reftype),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -114,10 +106,12 @@ pub enum NumType {
     F64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Reftype {
     Func,
     Extern,
+    /// Exception reference, caught by `catch_ref` and `catch_all_ref`.
+    Exn,
 }
 
 #[derive(Debug, Clone)]
@@ -158,6 +152,32 @@ impl DataSegment {
     }
 }
 
+/// Memory argument of the load and store instructions.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MemArg {
+    /// Alignment, as the exponent of a power of 2.
+    pub align: u32,
+    pub offset: u32,
+    /// Memory accessed, with the multi-memory proposal.
+    pub memidx: u32,
+}
+
+impl MemArg {
+    /// Memory argument accessing the first memory.
+    pub fn new(align: u32, offset: u32) -> Self {
+        Self::with_memidx(align, offset, 0)
+    }
+
+    /// Memory argument accessing the memory `memidx`.
+    pub fn with_memidx(align: u32, offset: u32, memidx: u32) -> Self {
+        Self {
+            align,
+            offset,
+            memidx,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum BlockType {
     Empty,
@@ -170,7 +190,7 @@ pub enum Instr {
     unreachable,
     nop,
 
-    call(MutableValue<u32>),
+    call(u32),
     call_indirect(u32, u32),
 
     drop,
@@ -184,35 +204,119 @@ pub enum Instr {
     table_get(u32),
     table_set(u32),
 
-    i32_load(MutableValue<u32>, u32),
-    i64_load(MutableValue<u32>, u32),
-    f32_load(MutableValue<u32>, u32),
-    f64_load(MutableValue<u32>, u32),
-    i32_load8_s(MutableValue<u32>, u32),
-    i32_load8_u(MutableValue<u32>, u32),
-    i32_load16_s(MutableValue<u32>, u32),
-    i32_load16_u(MutableValue<u32>, u32),
-    i64_load8_s(MutableValue<u32>, u32),
-    i64_load8_u(MutableValue<u32>, u32),
-    i64_load16_s(MutableValue<u32>, u32),
-    i64_load16_u(MutableValue<u32>, u32),
-    i64_load32_s(MutableValue<u32>, u32),
-    i64_load32_u(MutableValue<u32>, u32),
-
-    i32_store(MutableValue<u32>, u32),
-    i64_store(MutableValue<u32>, u32),
-    f32_store(MutableValue<u32>, u32),
-    f64_store(MutableValue<u32>, u32),
-    i32_store8(MutableValue<u32>, u32),
-    i32_store16(MutableValue<u32>, u32),
-    i64_store8(MutableValue<u32>, u32),
-    i64_store16(MutableValue<u32>, u32),
-    i64_store32(MutableValue<u32>, u32),
-
-    memory_size(u8),
-    memory_grow(u8),
-    memory_copy(u8, u8),
-    memory_fill(u8),
+    ref_null(Reftype),
+    ref_is_null,
+    ref_func(u32),
+
+    i32_load(MemArg),
+    i64_load(MemArg),
+    f32_load(MemArg),
+    f64_load(MemArg),
+    i32_load8_s(MemArg),
+    i32_load8_u(MemArg),
+    i32_load16_s(MemArg),
+    i32_load16_u(MemArg),
+    i64_load8_s(MemArg),
+    i64_load8_u(MemArg),
+    i64_load16_s(MemArg),
+    i64_load16_u(MemArg),
+    i64_load32_s(MemArg),
+    i64_load32_u(MemArg),
+
+    i32_store(MemArg),
+    i64_store(MemArg),
+    f32_store(MemArg),
+    f64_store(MemArg),
+    i32_store8(MemArg),
+    i32_store16(MemArg),
+    i64_store8(MemArg),
+    i64_store16(MemArg),
+    i64_store32(MemArg),
+
+    memory_size(u32),
+    memory_grow(u32),
+    memory_copy(u32, u32),
+    memory_fill(u32),
+    /// (Dataidx, Memidx)
+    memory_init(u32, u8),
+    data_drop(u32),
+    /// (Elemidx, Tableidx)
+    table_init(u32, u32),
+    elem_drop(u32),
+    /// (Destination tableidx, Source tableidx)
+    table_copy(u32, u32),
+    table_grow(u32),
+    table_size(u32),
+    table_fill(u32),
+
+    // Threads
+    memory_atomic_notify(MemArg),
+    memory_atomic_wait32(MemArg),
+    memory_atomic_wait64(MemArg),
+    atomic_fence,
+    i32_atomic_load(MemArg),
+    i64_atomic_load(MemArg),
+    i32_atomic_load8_u(MemArg),
+    i32_atomic_load16_u(MemArg),
+    i64_atomic_load8_u(MemArg),
+    i64_atomic_load16_u(MemArg),
+    i64_atomic_load32_u(MemArg),
+    i32_atomic_store(MemArg),
+    i64_atomic_store(MemArg),
+    i32_atomic_store8(MemArg),
+    i32_atomic_store16(MemArg),
+    i64_atomic_store8(MemArg),
+    i64_atomic_store16(MemArg),
+    i64_atomic_store32(MemArg),
+    i32_atomic_rmw_add(MemArg),
+    i64_atomic_rmw_add(MemArg),
+    i32_atomic_rmw8_add_u(MemArg),
+    i32_atomic_rmw16_add_u(MemArg),
+    i64_atomic_rmw8_add_u(MemArg),
+    i64_atomic_rmw16_add_u(MemArg),
+    i64_atomic_rmw32_add_u(MemArg),
+    i32_atomic_rmw_sub(MemArg),
+    i64_atomic_rmw_sub(MemArg),
+    i32_atomic_rmw8_sub_u(MemArg),
+    i32_atomic_rmw16_sub_u(MemArg),
+    i64_atomic_rmw8_sub_u(MemArg),
+    i64_atomic_rmw16_sub_u(MemArg),
+    i64_atomic_rmw32_sub_u(MemArg),
+    i32_atomic_rmw_and(MemArg),
+    i64_atomic_rmw_and(MemArg),
+    i32_atomic_rmw8_and_u(MemArg),
+    i32_atomic_rmw16_and_u(MemArg),
+    i64_atomic_rmw8_and_u(MemArg),
+    i64_atomic_rmw16_and_u(MemArg),
+    i64_atomic_rmw32_and_u(MemArg),
+    i32_atomic_rmw_or(MemArg),
+    i64_atomic_rmw_or(MemArg),
+    i32_atomic_rmw8_or_u(MemArg),
+    i32_atomic_rmw16_or_u(MemArg),
+    i64_atomic_rmw8_or_u(MemArg),
+    i64_atomic_rmw16_or_u(MemArg),
+    i64_atomic_rmw32_or_u(MemArg),
+    i32_atomic_rmw_xor(MemArg),
+    i64_atomic_rmw_xor(MemArg),
+    i32_atomic_rmw8_xor_u(MemArg),
+    i32_atomic_rmw16_xor_u(MemArg),
+    i64_atomic_rmw8_xor_u(MemArg),
+    i64_atomic_rmw16_xor_u(MemArg),
+    i64_atomic_rmw32_xor_u(MemArg),
+    i32_atomic_rmw_xchg(MemArg),
+    i64_atomic_rmw_xchg(MemArg),
+    i32_atomic_rmw8_xchg_u(MemArg),
+    i32_atomic_rmw16_xchg_u(MemArg),
+    i64_atomic_rmw8_xchg_u(MemArg),
+    i64_atomic_rmw16_xchg_u(MemArg),
+    i64_atomic_rmw32_xchg_u(MemArg),
+    i32_atomic_rmw_cmpxchg(MemArg),
+    i64_atomic_rmw_cmpxchg(MemArg),
+    i32_atomic_rmw8_cmpxchg_u(MemArg),
+    i32_atomic_rmw16_cmpxchg_u(MemArg),
+    i64_atomic_rmw8_cmpxchg_u(MemArg),
+    i64_atomic_rmw16_cmpxchg_u(MemArg),
+    i64_atomic_rmw32_cmpxchg_u(MemArg),
 
     br(u32),
     br_if(u32),
@@ -220,9 +324,20 @@ pub enum Instr {
     else_end,
     end,
     Return,
-    Block(BlockType, MutableValue<Vec<Value<Instr>>>),
-    Loop(BlockType, MutableValue<Vec<Value<Instr>>>),
-    If(BlockType, MutableValue<Vec<Value<Instr>>>),
+    Block(BlockType, Expr),
+    Loop(BlockType, Expr),
+    If(BlockType, Expr),
+
+    // Exception handling, the legacy `try` contains its `catch` and
+    // `catch_all` clauses and ends with `end` or `delegate`.
+    Try(BlockType, Expr),
+    catch(u32),
+    catch_all,
+    delegate(u32),
+    rethrow(u32),
+    throw(u32),
+    throw_ref,
+    TryTable(BlockType, Vec<Catch>, Expr),
 
     i32_const(i64),
     i64_const(i64),
@@ -374,24 +489,299 @@ pub enum Instr {
     i64_extend8_s,
     i64_extend16_s,
     i64_extend32_s,
+
+    // SIMD, the lane loads and stores are followed by the lane index.
+    v128_load(MemArg),
+    v128_load8x8_s(MemArg),
+    v128_load8x8_u(MemArg),
+    v128_load16x4_s(MemArg),
+    v128_load16x4_u(MemArg),
+    v128_load32x2_s(MemArg),
+    v128_load32x2_u(MemArg),
+    v128_load8_splat(MemArg),
+    v128_load16_splat(MemArg),
+    v128_load32_splat(MemArg),
+    v128_load64_splat(MemArg),
+    v128_store(MemArg),
+    v128_const(u128),
+    i8x16_shuffle([u8; 16]),
+    i8x16_swizzle,
+    i8x16_splat,
+    i16x8_splat,
+    i32x4_splat,
+    i64x2_splat,
+    f32x4_splat,
+    f64x2_splat,
+    i8x16_extract_lane_s(u8),
+    i8x16_extract_lane_u(u8),
+    i8x16_replace_lane(u8),
+    i16x8_extract_lane_s(u8),
+    i16x8_extract_lane_u(u8),
+    i16x8_replace_lane(u8),
+    i32x4_extract_lane(u8),
+    i32x4_replace_lane(u8),
+    i64x2_extract_lane(u8),
+    i64x2_replace_lane(u8),
+    f32x4_extract_lane(u8),
+    f32x4_replace_lane(u8),
+    f64x2_extract_lane(u8),
+    f64x2_replace_lane(u8),
+    i8x16_eq,
+    i8x16_ne,
+    i8x16_lt_s,
+    i8x16_lt_u,
+    i8x16_gt_s,
+    i8x16_gt_u,
+    i8x16_le_s,
+    i8x16_le_u,
+    i8x16_ge_s,
+    i8x16_ge_u,
+    i16x8_eq,
+    i16x8_ne,
+    i16x8_lt_s,
+    i16x8_lt_u,
+    i16x8_gt_s,
+    i16x8_gt_u,
+    i16x8_le_s,
+    i16x8_le_u,
+    i16x8_ge_s,
+    i16x8_ge_u,
+    i32x4_eq,
+    i32x4_ne,
+    i32x4_lt_s,
+    i32x4_lt_u,
+    i32x4_gt_s,
+    i32x4_gt_u,
+    i32x4_le_s,
+    i32x4_le_u,
+    i32x4_ge_s,
+    i32x4_ge_u,
+    f32x4_eq,
+    f32x4_ne,
+    f32x4_lt,
+    f32x4_gt,
+    f32x4_le,
+    f32x4_ge,
+    f64x2_eq,
+    f64x2_ne,
+    f64x2_lt,
+    f64x2_gt,
+    f64x2_le,
+    f64x2_ge,
+    v128_not,
+    v128_and,
+    v128_andnot,
+    v128_or,
+    v128_xor,
+    v128_bitselect,
+    v128_any_true,
+    v128_load8_lane(MemArg, u8),
+    v128_load16_lane(MemArg, u8),
+    v128_load32_lane(MemArg, u8),
+    v128_load64_lane(MemArg, u8),
+    v128_store8_lane(MemArg, u8),
+    v128_store16_lane(MemArg, u8),
+    v128_store32_lane(MemArg, u8),
+    v128_store64_lane(MemArg, u8),
+    v128_load32_zero(MemArg),
+    v128_load64_zero(MemArg),
+    f32x4_demote_f64x2_zero,
+    f64x2_promote_low_f32x4,
+    i8x16_abs,
+    i8x16_neg,
+    i8x16_popcnt,
+    i8x16_all_true,
+    i8x16_bitmask,
+    i8x16_narrow_i16x8_s,
+    i8x16_narrow_i16x8_u,
+    f32x4_ceil,
+    f32x4_floor,
+    f32x4_trunc,
+    f32x4_nearest,
+    i8x16_shl,
+    i8x16_shr_s,
+    i8x16_shr_u,
+    i8x16_add,
+    i8x16_add_sat_s,
+    i8x16_add_sat_u,
+    i8x16_sub,
+    i8x16_sub_sat_s,
+    i8x16_sub_sat_u,
+    f64x2_ceil,
+    f64x2_floor,
+    i8x16_min_s,
+    i8x16_min_u,
+    i8x16_max_s,
+    i8x16_max_u,
+    f64x2_trunc,
+    i8x16_avgr_u,
+    i16x8_extadd_pairwise_i8x16_s,
+    i16x8_extadd_pairwise_i8x16_u,
+    i32x4_extadd_pairwise_i16x8_s,
+    i32x4_extadd_pairwise_i16x8_u,
+    i16x8_abs,
+    i16x8_neg,
+    i16x8_q15mulr_sat_s,
+    i16x8_all_true,
+    i16x8_bitmask,
+    i16x8_narrow_i32x4_s,
+    i16x8_narrow_i32x4_u,
+    i16x8_extend_low_i8x16_s,
+    i16x8_extend_high_i8x16_s,
+    i16x8_extend_low_i8x16_u,
+    i16x8_extend_high_i8x16_u,
+    i16x8_shl,
+    i16x8_shr_s,
+    i16x8_shr_u,
+    i16x8_add,
+    i16x8_add_sat_s,
+    i16x8_add_sat_u,
+    i16x8_sub,
+    i16x8_sub_sat_s,
+    i16x8_sub_sat_u,
+    f64x2_nearest,
+    i16x8_mul,
+    i16x8_min_s,
+    i16x8_min_u,
+    i16x8_max_s,
+    i16x8_max_u,
+    i16x8_avgr_u,
+    i16x8_extmul_low_i8x16_s,
+    i16x8_extmul_high_i8x16_s,
+    i16x8_extmul_low_i8x16_u,
+    i16x8_extmul_high_i8x16_u,
+    i32x4_abs,
+    i32x4_neg,
+    i32x4_all_true,
+    i32x4_bitmask,
+    i32x4_extend_low_i16x8_s,
+    i32x4_extend_high_i16x8_s,
+    i32x4_extend_low_i16x8_u,
+    i32x4_extend_high_i16x8_u,
+    i32x4_shl,
+    i32x4_shr_s,
+    i32x4_shr_u,
+    i32x4_add,
+    i32x4_sub,
+    i32x4_mul,
+    i32x4_min_s,
+    i32x4_min_u,
+    i32x4_max_s,
+    i32x4_max_u,
+    i32x4_dot_i16x8_s,
+    i32x4_extmul_low_i16x8_s,
+    i32x4_extmul_high_i16x8_s,
+    i32x4_extmul_low_i16x8_u,
+    i32x4_extmul_high_i16x8_u,
+    i64x2_abs,
+    i64x2_neg,
+    i64x2_all_true,
+    i64x2_bitmask,
+    i64x2_extend_low_i32x4_s,
+    i64x2_extend_high_i32x4_s,
+    i64x2_extend_low_i32x4_u,
+    i64x2_extend_high_i32x4_u,
+    i64x2_shl,
+    i64x2_shr_s,
+    i64x2_shr_u,
+    i64x2_add,
+    i64x2_sub,
+    i64x2_mul,
+    i64x2_eq,
+    i64x2_ne,
+    i64x2_lt_s,
+    i64x2_gt_s,
+    i64x2_le_s,
+    i64x2_ge_s,
+    i64x2_extmul_low_i32x4_s,
+    i64x2_extmul_high_i32x4_s,
+    i64x2_extmul_low_i32x4_u,
+    i64x2_extmul_high_i32x4_u,
+    f32x4_abs,
+    f32x4_neg,
+    f32x4_sqrt,
+    f32x4_add,
+    f32x4_sub,
+    f32x4_mul,
+    f32x4_div,
+    f32x4_min,
+    f32x4_max,
+    f32x4_pmin,
+    f32x4_pmax,
+    f64x2_abs,
+    f64x2_neg,
+    f64x2_sqrt,
+    f64x2_add,
+    f64x2_sub,
+    f64x2_mul,
+    f64x2_div,
+    f64x2_min,
+    f64x2_max,
+    f64x2_pmin,
+    f64x2_pmax,
+    i32x4_trunc_sat_f32x4_s,
+    i32x4_trunc_sat_f32x4_u,
+    f32x4_convert_i32x4_s,
+    f32x4_convert_i32x4_u,
+    i32x4_trunc_sat_f64x2_s_zero,
+    i32x4_trunc_sat_f64x2_u_zero,
+    f64x2_convert_low_i32x4_s,
+    f64x2_convert_low_i32x4_u,
+    i8x16_relaxed_swizzle,
+    i32x4_relaxed_trunc_f32x4_s,
+    i32x4_relaxed_trunc_f32x4_u,
+    i32x4_relaxed_trunc_f64x2_s_zero,
+    i32x4_relaxed_trunc_f64x2_u_zero,
+    f32x4_relaxed_madd,
+    f32x4_relaxed_nmadd,
+    f64x2_relaxed_madd,
+    f64x2_relaxed_nmadd,
+    i8x16_relaxed_laneselect,
+    i16x8_relaxed_laneselect,
+    i32x4_relaxed_laneselect,
+    i64x2_relaxed_laneselect,
+    f32x4_relaxed_min,
+    f32x4_relaxed_max,
+    f64x2_relaxed_min,
+    f64x2_relaxed_max,
+    i16x8_relaxed_q15mulr_s,
+    i16x8_relaxed_dot_i8x16_i7x16_s,
+    i32x4_relaxed_dot_i8x16_i7x16_add_s,
 }
 
 pub type Expr = Value<Vec<Value<Instr>>>;
 
+/// Catch clause of a `try_table`, branching to the label when it catches an
+/// exception.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Catch {
+    /// (Tagidx, Labelidx)
+    Catch(u32, u32),
+    /// (Tagidx, Labelidx), also pushes the exception reference.
+    CatchRef(u32, u32),
+    CatchAll(u32),
+    CatchAllRef(u32),
+}
+
 #[derive(Debug, Clone)]
 pub enum Section {
     /// (Size, Section)
     Memory((Value<u32>, Vec<Memory>)),
-    Data((Value<u32>, Arc<Mutex<Vec<DataSegment>>>)),
-    Code((Value<u32>, MutableValue<Vec<Code>>)),
-    Type((Value<u32>, Arc<Mutex<Vec<Type>>>)),
-    Func((Value<u32>, Arc<Mutex<Vec<u32>>>)),
-    Import((Value<u32>, Arc<Mutex<Vec<Import>>>)),
-    Table((Value<u32>, Arc<Mutex<Vec<Table>>>)),
-    Export((Value<u32>, Arc<Mutex<Vec<Export>>>)),
-    Element((Value<u32>, Arc<Mutex<Vec<Element>>>)),
-    Custom((Value<u32>, Arc<Mutex<CustomSection>>)),
-    Global((Value<u32>, Arc<Mutex<Vec<Global>>>)),
+    Data((Value<u32>, Vec<DataSegment>)),
+    Code((Value<u32>, Value<Vec<Code>>)),
+    Type((Value<u32>, Vec<Type>)),
+    Func((Value<u32>, Vec<u32>)),
+    Import((Value<u32>, Vec<Import>)),
+    Table((Value<u32>, Vec<Table>)),
+    Export((Value<u32>, Vec<Export>)),
+    Element((Value<u32>, Vec<Element>)),
+    Custom((Value<u32>, CustomSection)),
+    Global((Value<u32>, Vec<Global>)),
+    Tag((Value<u32>, Vec<Tag>)),
+    /// (Size, Funcidx)
+    Start((Value<u32>, u32)),
+    /// (Size, Number of data segments)
+    DataCount((Value<u32>, u32)),
     /// (Id, Size, Section)
     Unknown((u8, u32, Vec<u8>)),
 }
@@ -406,12 +796,14 @@ impl Value<Section> {
             Func(_) => 3,
             Table(_) => 4,
             Memory(_) => 5,
-            Global(_) => 6,
-            Export(_) => 7,
-            // Start(_) => 8,
-            Element(_) => 9,
-            Code(_) => 10,
-            Data(_) => 11,
+            Tag(_) => 6,
+            Global(_) => 7,
+            Export(_) => 8,
+            Start(_) => 9,
+            Element(_) => 10,
+            DataCount(_) => 11,
+            Code(_) => 12,
+            Data(_) => 13,
             Custom(_) | Unknown(_) => 99,
         }
     }
@@ -424,11 +816,52 @@ pub enum CustomSection {
     CoredumpCore(wasm_coredump_types::ProcessInfo),
     CoredumpCoreStack(wasm_coredump_types::CoreStack),
     BuildId(Vec<u8>),
+    CodeOffsets(CodeOffsetMap),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Module {
-    pub sections: Arc<Mutex<Vec<Value<Section>>>>,
+    pub sections: Vec<Value<Section>>,
+}
+
+/// A component-model binary. Only the core modules are decoded, the other
+/// sections are kept as is.
+#[derive(Debug, Clone, Default)]
+pub struct Component {
+    pub sections: Vec<ComponentSection>,
+}
+
+impl Component {
+    /// Core modules embedded in the component and its nested components, in
+    /// the order they appear in the binary.
+    pub fn core_modules_mut(&mut self) -> Vec<&mut Module> {
+        let mut modules = vec![];
+        for section in self.sections.iter_mut() {
+            match section {
+                ComponentSection::CoreModule(module) => modules.push(module),
+                ComponentSection::Component(component) => {
+                    modules.extend(component.core_modules_mut())
+                }
+                ComponentSection::Unknown(_) => {}
+            }
+        }
+        modules
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ComponentSection {
+    CoreModule(Module),
+    Component(Component),
+    /// (Id, Section)
+    Unknown((u8, Vec<u8>)),
+}
+
+/// A Wasm binary, either a core module or a component.
+#[derive(Debug, Clone)]
+pub enum Binary {
+    Module(Module),
+    Component(Component),
 }
 
 #[derive(Debug, Clone)]
@@ -450,6 +883,7 @@ pub enum ImportType {
     Table(Table),
     Memory(Memory),
     Global(GlobalType),
+    Tag(Tag),
 }
 
 #[derive(Debug, Clone)]
@@ -460,15 +894,72 @@ pub struct Export {
 
 #[derive(Debug, Clone)]
 pub enum ExportDescr {
-    Func(Arc<Mutex<u32>>),
-    Table(Arc<Mutex<u32>>),
-    Mem(Arc<Mutex<u32>>),
-    Global(Arc<Mutex<u32>>),
+    Func(u32),
+    Table(u32),
+    Mem(u32),
+    Global(u32),
+    Tag(u32),
 }
 
+/// Element segment, in one of its eight encodings. The segments listing
+/// function indices have the `funcref` element kind.
 #[derive(Debug, Clone)]
 pub enum Element {
-    FuncActive(Expr, Arc<Mutex<Vec<u32>>>),
+    /// (Offset, Funcidxs), active in table 0.
+    FuncActive(Expr, Vec<u32>),
+    /// (Funcidxs)
+    FuncPassive(Vec<u32>),
+    /// (Tableidx, Offset, Funcidxs)
+    FuncActiveTable(u32, Expr, Vec<u32>),
+    /// (Funcidxs)
+    FuncDeclarative(Vec<u32>),
+    /// (Offset, Exprs), active in table 0 with `funcref` elements.
+    ExprActive(Expr, Vec<Expr>),
+    /// (Reftype, Exprs)
+    ExprPassive(Reftype, Vec<Expr>),
+    /// (Tableidx, Offset, Reftype, Exprs)
+    ExprActiveTable(u32, Expr, Reftype, Vec<Expr>),
+    /// (Reftype, Exprs)
+    ExprDeclarative(Reftype, Vec<Expr>),
+}
+
+impl Element {
+    /// Offset in the table of the active segments.
+    pub fn offset(&self) -> Option<&Expr> {
+        use Element::*;
+
+        match self {
+            FuncActive(offset, _)
+            | FuncActiveTable(_, offset, _)
+            | ExprActive(offset, _)
+            | ExprActiveTable(_, offset, _, _) => Some(offset),
+            FuncPassive(_) | FuncDeclarative(_) | ExprPassive(..) | ExprDeclarative(..) => None,
+        }
+    }
+
+    /// Functions referenced by the segment, by index or by `ref.func` in its
+    /// expressions.
+    pub fn funcs(&self) -> Vec<u32> {
+        use Element::*;
+
+        match self {
+            FuncActive(_, funcs)
+            | FuncPassive(funcs)
+            | FuncActiveTable(_, _, funcs)
+            | FuncDeclarative(funcs) => funcs.clone(),
+            ExprActive(_, exprs)
+            | ExprPassive(_, exprs)
+            | ExprActiveTable(_, _, _, exprs)
+            | ExprDeclarative(_, exprs) => exprs
+                .iter()
+                .flat_map(|expr| &expr.value)
+                .filter_map(|instr| match instr.value {
+                    Instr::ref_func(funcidx) => Some(funcidx),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -492,6 +983,12 @@ impl Global {
     }
 }
 
+/// Exception tag, with the type of the exception's values.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub typeidx: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct GlobalType {
     pub valtype: ValueType,
@@ -501,7 +998,36 @@ pub struct GlobalType {
 #[derive(Debug, Clone)]
 pub struct DebugNames {
     pub module: Option<String>,
-    pub func_names: Option<Arc<Mutex<HashMap<u32, String>>>>,
+    pub func_names: Option<HashMap<u32, String>>,
     pub func_local_names: Option<HashMap<u32, HashMap<u32, String>>>,
-    pub global_names: Arc<Mutex<HashMap<u32, String>>>,
+    pub global_names: HashMap<u32, String>,
+}
+
+/// Maps the code offsets of a rewritten module to the offsets in the module
+/// it was rewritten from. Offsets are relative to the start of the function's
+/// instructions, after its locals.
+#[derive(Debug, Clone, Default)]
+pub struct CodeOffsetMap {
+    /// funcidx in the original module to (rewritten offset, original
+    /// offset), sorted by rewritten offset.
+    pub funcs: BTreeMap<u32, Vec<(u32, u32)>>,
+}
+
+impl CodeOffsetMap {
+    /// Functions that aren't in the map are returned unchanged. Offsets of
+    /// inserted instructions map to the previous original instruction.
+    pub fn original_offset(&self, funcidx: u32, offset: u32) -> u32 {
+        let Some(entries) = self.funcs.get(&funcidx) else {
+            return offset;
+        };
+
+        match entries.binary_search_by_key(&offset, |(rewritten, _)| *rewritten) {
+            Ok(idx) => entries[idx].1,
+            Err(0) => entries
+                .first()
+                .map(|(_, original)| *original)
+                .unwrap_or(offset),
+            Err(idx) => entries[idx - 1].1,
+        }
+    }
 }
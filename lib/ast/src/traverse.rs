@@ -1,189 +1,156 @@
 use crate::ast;
 use log::debug;
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::Mutex;
-use threadpool::ThreadPool;
+use std::ops::Range;
 
 type BoxError = Box<dyn std::error::Error>;
 
+/// Number of functions a traversal thread visits at once.
+const FUNCS_PER_CHUNK: usize = 64;
+
+/// Owns a module and provides lookups and edits on it.
+///
+/// Lookups read the sections directly, they always reflect the previous
+/// edits.
 pub struct WasmModule {
-    pub inner: Arc<ast::Module>,
-    types: Mutex<HashMap<u32, ast::Type>>,
-    /// Mapping between funcidx and count of func locals
-    func_locals: HashMap<u32, Vec<ast::CodeLocal>>,
-    func_to_typeidx: Mutex<Vec<u32>>,
-    func_code: HashMap<u32, ast::Code>,
-    pub func_starts: HashMap<u32, usize>,
-    pub func_names: Mutex<HashMap<u32, String>>,
-    global_names: Mutex<HashMap<u32, String>>,
-    imports: Vec<ast::Import>,
-    globals: Vec<ast::Global>,
-    exports: Vec<ast::Export>,
-    custom_sections: Vec<ast::CustomSection>,
-    build_id: Option<Vec<u8>>,
+    inner: ast::Module,
     imported_func_count: u32,
+    /// Number of locals entries added to each defined function by
+    /// [WasmModule::add_func_local], by index in the code section. Unlike
+    /// funcidx, it isn't shifted by new imports.
+    added_func_locals: HashMap<u32, usize>,
 }
 impl WasmModule {
-    pub fn new(inner: Arc<ast::Module>) -> Self {
-        let mut types = HashMap::new();
-        let mut func_locals = HashMap::new();
-        let mut func_to_typeidx = Vec::new();
-        let mut imports = Vec::new();
-        let mut globals = Vec::new();
-        let mut exports = Vec::new();
-        let mut custom_sections = Vec::new();
-        let mut func_starts = HashMap::new();
-        let mut func_code = HashMap::new();
-        let mut func_names = HashMap::new();
-        let mut global_names = HashMap::new();
-        let mut build_id = None;
-        let mut imported_func_count = 0;
-
-        let mut funcidx = 0;
-
-        for section in inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Type((_size, content)) => {
-                    let mut typeidx = 0;
-                    for t in &*content.lock().unwrap() {
-                        types.insert(typeidx, t.to_owned());
-                        typeidx += 1;
-                    }
-                }
-
-                ast::Section::Import((_size, content)) => {
-                    imports = content.lock().unwrap().clone();
-
-                    for import in &imports {
-                        match import.import_type {
-                            ast::ImportType::Func(_) => {
-                                imported_func_count += 1;
-                                funcidx += 1;
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-
-                ast::Section::Global((_size, content)) => {
-                    globals = content.lock().unwrap().clone();
-                }
-
-                ast::Section::Func((_size, content)) => {
-                    func_to_typeidx = content.lock().unwrap().clone();
-                }
+    pub fn new(inner: ast::Module) -> Self {
+        let mut module = Self {
+            inner,
+            imported_func_count: 0,
+            added_func_locals: HashMap::new(),
+        };
+        module.imported_func_count = module
+            .imports()
+            .iter()
+            .filter(|import| matches!(import.import_type, ast::ImportType::Func(_)))
+            .count() as u32;
+        module
+    }
 
-                ast::Section::Export((_section_size, content)) => {
-                    exports = content.lock().unwrap().clone();
-                }
+    pub fn inner(&self) -> &ast::Module {
+        &self.inner
+    }
 
-                ast::Section::Code((_section_size, content)) => {
-                    for c in &content.lock().unwrap().value {
-                        func_code.insert(funcidx, c.clone());
+    pub fn into_inner(self) -> ast::Module {
+        self.inner
+    }
 
-                        func_starts.insert(funcidx as u32, c.body.lock().unwrap().start_offset);
-                        func_locals.insert(funcidx, c.locals.clone());
-                        funcidx += 1;
-                    }
-                }
+    fn types(&self) -> &[ast::Type] {
+        self.inner
+            .sections
+            .iter()
+            .find_map(|section| match &section.value {
+                ast::Section::Type((_size, content)) => Some(content.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
 
-                ast::Section::Custom((_size, section)) => {
-                    custom_sections.push(section.lock().unwrap().clone());
+    fn func_to_typeidx(&self) -> &[u32] {
+        self.inner
+            .sections
+            .iter()
+            .find_map(|section| match &section.value {
+                ast::Section::Func((_size, content)) => Some(content.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
 
-                    match &*section.lock().unwrap() {
-                        ast::CustomSection::Name(names) => {
-                            if let Some(v) = &names.func_names {
-                                func_names = v.lock().unwrap().clone();
-                            }
+    fn codes(&self) -> &[ast::Code] {
+        self.inner
+            .sections
+            .iter()
+            .find_map(|section| match &section.value {
+                ast::Section::Code((_size, content)) => Some(content.value.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
 
-                            global_names = names.global_names.lock().unwrap().clone();
-                        }
-                        ast::CustomSection::BuildId(id) => {
-                            build_id = Some(id.clone());
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
-            }
-        }
+    fn codes_mut(&mut self) -> Option<&mut Vec<ast::Code>> {
+        self.inner
+            .sections
+            .iter_mut()
+            .find_map(|section| match &mut section.value {
+                ast::Section::Code((_size, content)) => Some(&mut content.value),
+                _ => None,
+            })
+    }
 
-        Self {
-            inner,
-            imports,
-            globals,
-            exports,
-            func_locals,
-            func_starts,
-            func_code,
-            custom_sections,
-            build_id,
-            imported_func_count,
-            func_names: Mutex::new(func_names),
-            global_names: Mutex::new(global_names),
-            types: Mutex::new(types),
-            func_to_typeidx: Mutex::new(func_to_typeidx),
-        }
+    fn exports(&self) -> &[ast::Export] {
+        self.inner
+            .sections
+            .iter()
+            .find_map(|section| match &section.value {
+                ast::Section::Export((_size, content)) => Some(content.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
     }
 
-    pub fn into_inner(self) -> ast::Module {
-        Arc::into_inner(self.inner).unwrap()
+    fn names(&self) -> Option<&ast::DebugNames> {
+        self.get_custom_sections()
+            .find_map(|section| match section {
+                ast::CustomSection::Name(names) => Some(names),
+                _ => None,
+            })
     }
 
-    pub fn add_func_name(&self, funcidx: u32, name: &str) {
-        let mut func_names = self.func_names.lock().unwrap();
-        func_names.insert(funcidx, name.to_owned());
+    fn names_mut(&mut self) -> Option<&mut ast::DebugNames> {
+        self.inner
+            .sections
+            .iter_mut()
+            .find_map(|section| match &mut section.value {
+                ast::Section::Custom((_size, ast::CustomSection::Name(names))) => Some(names),
+                _ => None,
+            })
+    }
 
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Custom((_size, section)) => match &*section.lock().unwrap() {
-                    ast::CustomSection::Name(names) => {
-                        if let Some(names) = &names.func_names {
-                            let mut names = names.lock().unwrap();
-                            names.insert(funcidx, name.to_owned());
-                        }
-                    }
-                    _ => {}
-                },
-                _ => {}
-            }
+    pub fn add_func_name(&mut self, funcidx: u32, name: &str) {
+        if let Some(func_names) = self.names_mut().and_then(|names| names.func_names.as_mut()) {
+            func_names.insert(funcidx, name.to_owned());
         }
     }
 
-    pub fn add_global_name(&self, globalidx: u32, name: &str) {
-        let mut global_names = self.global_names.lock().unwrap();
-        global_names.insert(globalidx, name.to_owned());
-
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Custom((_size, section)) => match &*section.lock().unwrap() {
-                    ast::CustomSection::Name(section) => {
-                        let mut names = section.global_names.lock().unwrap();
-                        names.insert(globalidx, name.to_owned());
-                    }
-                    _ => {}
-                },
-                _ => {}
-            }
+    pub fn add_global_name(&mut self, globalidx: u32, name: &str) {
+        if let Some(names) = self.names_mut() {
+            names.global_names.insert(globalidx, name.to_owned());
         }
     }
 
+    pub fn func_names(&self) -> Option<&HashMap<u32, String>> {
+        self.names().and_then(|names| names.func_names.as_ref())
+    }
+
     pub fn get_coredump(&self) -> Result<wasm_coredump_types::Coredump, BoxError> {
         let mut data = vec![];
         let mut stacks = vec![];
         let mut process_info = None;
         let mut memory = vec![];
+        let mut globals = vec![];
 
-        for section in self.inner.sections.lock().unwrap().iter() {
+        for section in &self.inner.sections {
             match &section.value {
                 ast::Section::Data((_section_size, content)) => {
-                    let content = content.lock().unwrap();
-                    let segment = content.first().unwrap();
-                    let offset = segment.compute_offset();
-                    debug!("data offset: {}", offset);
-                    let padding = vec![0u8; offset as usize];
-                    data = [padding, segment.bytes.clone()].concat();
+                    // Segments are placed at their address in the memory
+                    for segment in content {
+                        let offset = segment.compute_offset() as usize;
+                        debug!("data offset: {}", offset);
+                        let end = offset + segment.bytes.len();
+                        if data.len() < end {
+                            data.resize(end, 0);
+                        }
+                        data[offset..end].copy_from_slice(&segment.bytes);
+                    }
                 }
 
                 ast::Section::Memory((_section_size, content)) => {
@@ -192,7 +159,26 @@ impl WasmModule {
                     }
                 }
 
-                ast::Section::Custom((_size, section)) => match &*section.lock().unwrap() {
+                ast::Section::Global((_section_size, content)) => {
+                    for (globalidx, global) in content.iter().enumerate() {
+                        let name = self
+                            .names()
+                            .and_then(|names| names.global_names.get(&(globalidx as u32)))
+                            .cloned();
+                        let value = match global.expr.value.first().map(|instr| &instr.value) {
+                            Some(ast::Instr::i32_const(v)) => {
+                                wasm_coredump_types::Value::I32(*v as i32)
+                            }
+                            Some(ast::Instr::i64_const(v)) => wasm_coredump_types::Value::I64(*v),
+                            Some(ast::Instr::f32_const(v)) => wasm_coredump_types::Value::F32(*v),
+                            Some(ast::Instr::f64_const(v)) => wasm_coredump_types::Value::F64(*v),
+                            _ => wasm_coredump_types::Value::Missing,
+                        };
+                        globals.push(wasm_coredump_types::Global { name, value });
+                    }
+                }
+
+                ast::Section::Custom((_size, section)) => match section {
                     ast::CustomSection::CoredumpCore(info) => process_info = Some(info.clone()),
                     ast::CustomSection::CoredumpCoreStack(stack) => stacks.push(stack.clone()),
 
@@ -212,382 +198,803 @@ impl WasmModule {
             stacks,
             process_info,
             memory,
+            globals,
         })
     }
 
-    pub fn add_data(&self, offset: u32, bytes: &[u8]) -> (u32, u32) {
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Data((_section_size, content)) => {
-                    let segment = ast::DataSegment {
-                        offset: Some(ast::Value::new(vec![
-                            ast::Value::new(ast::Instr::i32_const(offset as i64)),
-                            ast::Value::new(ast::Instr::end),
-                        ])),
-                        bytes: bytes.to_vec(),
-                        mode: ast::DataSegmentMode::Active,
-                    };
-                    content.lock().unwrap().push(segment);
+    /// Read `size` bytes at `addr` from the active data segments, ie the
+    /// memory as initialized by the module.
+    pub fn read_data(&self, addr: u32, size: u32) -> Option<Vec<u8>> {
+        for section in &self.inner.sections {
+            if let ast::Section::Data((_section_size, content)) = &section.value {
+                for segment in content {
+                    if segment.mode != ast::DataSegmentMode::Active {
+                        continue;
+                    }
+                    let start = addr as i64 - segment.compute_offset();
+                    let end = start + size as i64;
+                    if start >= 0 && end <= segment.bytes.len() as i64 {
+                        return Some(segment.bytes[start as usize..end as usize].to_vec());
+                    }
                 }
-                _ => {}
             }
         }
 
-        (offset, offset + bytes.len() as u32)
-    }
-
-    pub fn is_func_imported(&self, funcidx: u32) -> bool {
-        funcidx <= self.imported_func_count
+        None
     }
 
-    pub fn imports(&self) -> &Vec<ast::Import> {
-        &self.imports
-    }
+    /// Add an active data segment at `offset`, the data count section, if
+    /// any, is kept in sync.
+    pub fn add_data(&mut self, offset: u32, bytes: &[u8]) -> (u32, u32) {
+        let segment = ast::DataSegment {
+            offset: Some(ast::Value::new(vec![
+                ast::Value::new(ast::Instr::i32_const(offset as i64)),
+                ast::Value::new(ast::Instr::end),
+            ])),
+            bytes: bytes.to_vec(),
+            mode: ast::DataSegmentMode::Active,
+        };
+        self.push_data_segment(segment);
 
-    pub fn globals(&self) -> &Vec<ast::Global> {
-        &self.globals
+        (offset, offset + bytes.len() as u32)
     }
 
-    pub fn func_locals_count(&self, funcidx: u32) -> u32 {
-        let locals = self.func_locals(funcidx);
-        let mut count = 0;
-        for local in locals {
-            count += local.count;
+    fn push_data_segment(&mut self, segment: ast::DataSegment) {
+        let data = self
+            .inner
+            .sections
+            .iter_mut()
+            .find_map(|section| match &mut section.value {
+                ast::Section::Data((_section_size, content)) => Some(content),
+                _ => None,
+            });
+        if let Some(content) = data {
+            content.push(segment);
+        } else {
+            self.add_section(ast::Section::Data((ast::Value::new(0), vec![segment])));
         }
 
-        count
+        for section in self.inner.sections.iter_mut() {
+            if let ast::Section::DataCount((_section_size, count)) = &mut section.value {
+                *count += 1;
+            }
+        }
     }
 
-    pub fn func_locals(&self, funcidx: u32) -> Vec<ast::CodeLocal> {
-        let locals = self
-            .func_locals
-            .get(&funcidx)
-            .expect(&format!("locals for funcidx {}", funcidx));
-        locals.to_owned()
-    }
+    /// Add the nodes appended by a visitor. Function and global imports
+    /// shift the indices like `add_import` and `add_global_import`.
+    fn apply_section_edits(&mut self, edits: SectionEdits) {
+        let SectionEdits {
+            imports,
+            funcs: mut new_funcs,
+            codes: mut new_codes,
+            segments,
+        } = edits;
+
+        for import in imports {
+            debug!("inject new import: {:?}", import);
+            match &import.import_type {
+                ast::ImportType::Global(ty) => {
+                    self.add_global_import(&import.module, &import.name, ty);
+                }
+                _ => {
+                    self.add_import(&import).unwrap();
+                }
+            }
+        }
 
-    pub fn is_func_exported(&self, funcidx: u32) -> bool {
-        for export in &self.exports {
-            match &export.descr {
-                ast::ExportDescr::Func(f) => {
-                    if *f.lock().unwrap() == funcidx {
-                        return true;
-                    }
+        for section in self.inner.sections.iter_mut() {
+            match &mut section.value {
+                ast::Section::Func((_section_size, funcs)) => {
+                    funcs.append(&mut new_funcs);
+                }
+                ast::Section::Code((_section_size, codes)) => {
+                    debug!("inject {} new code(s)", new_codes.len());
+                    codes.value.append(&mut new_codes);
                 }
                 _ => {}
             }
         }
 
-        false
+        for segment in segments {
+            debug!("inject new data: {:?}", segment);
+            self.push_data_segment(segment);
+        }
     }
 
-    pub fn get_export_func(&self, name: &str) -> Result<(&ast::Code, ast::Type), BoxError> {
-        for export in &self.exports {
-            if export.name == name {
-                match &export.descr {
-                    ast::ExportDescr::Func(f) => {
-                        let funcidx = &*f.lock().unwrap();
-                        let code = self
-                            .func_code
-                            .get(funcidx)
-                            .ok_or("exported function not found")?;
-                        let t = self.get_func_type(*funcidx);
-                        return Ok((code, t.clone()));
-                    }
-                    _ => return Err("export is not a function".into()),
-                }
+    pub fn is_func_imported(&self, funcidx: u32) -> bool {
+        funcidx < self.imported_func_count
+    }
+
+    /// Functions defined in the module, ie with code.
+    pub fn defined_funcs(&self) -> Range<u32> {
+        self.imported_func_count..self.imported_func_count + self.codes().len() as u32
+    }
+
+    fn get_code(&self, funcidx: u32) -> Option<&ast::Code> {
+        let idx = funcidx.checked_sub(self.imported_func_count)?;
+        self.codes().get(idx as usize)
+    }
+
+    pub fn imports(&self) -> &[ast::Import] {
+        self.inner
+            .sections
+            .iter()
+            .find_map(|section| match &section.value {
+                ast::Section::Import((_size, content)) => Some(content.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn globals(&self) -> &[ast::Global] {
+        self.inner
+            .sections
+            .iter()
+            .find_map(|section| match &section.value {
+                ast::Section::Global((_size, content)) => Some(content.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn func_locals_count(&self, funcidx: u32) -> u32 {
+        self.func_locals(funcidx)
+            .iter()
+            .map(|local| local.count)
+            .sum()
+    }
+
+    pub fn func_locals(&self, funcidx: u32) -> &[ast::CodeLocal] {
+        &self
+            .get_code(funcidx)
+            .unwrap_or_else(|| panic!("locals for funcidx {}", funcidx))
+            .locals
+    }
+
+    /// Form of the exception handling instructions used by the module, if
+    /// it uses any. Modules using `try_table` may run on engines without the
+    /// legacy instructions.
+    pub fn exception_handling(&self) -> Option<CatchForm> {
+        let mut form = None;
+        for code in self.codes() {
+            match exception_handling_expr(&code.body.value) {
+                Some(CatchForm::TryTable) => return Some(CatchForm::TryTable),
+                Some(CatchForm::Try) => form = Some(CatchForm::Try),
+                None => {}
             }
         }
+        form
+    }
 
-        Err("export not found".into())
+    pub fn is_func_exported(&self, funcidx: u32) -> bool {
+        self.exports()
+            .iter()
+            .any(|export| matches!(export.descr, ast::ExportDescr::Func(f) if f == funcidx))
     }
 
-    /// Retrieve the type of a function,
-    /// note that this doesn't work for imported functions as they
-    /// have their type expressed differently.
-    pub fn get_func_type(&self, funcidx: u32) -> ast::Type {
-        let typeidx = self.get_func_typeidx(funcidx);
-        let types = self.types.lock().unwrap();
-        types.get(&typeidx).expect("type not found").clone()
+    /// Function called when the module is instantiated.
+    pub fn start_func(&self) -> Option<u32> {
+        self.inner
+            .sections
+            .iter()
+            .find_map(|section| match &section.value {
+                ast::Section::Start((_size, funcidx)) => Some(*funcidx),
+                _ => None,
+            })
     }
 
-    pub fn add_func_local(&self, target_funcidx: u32, local: ast::CodeLocal) -> bool {
-        let mut funcidx = self.imported_func_count;
+    /// Whether the function is called by the host, ie exported or the start
+    /// function.
+    pub fn is_func_entry_point(&self, funcidx: u32) -> bool {
+        self.is_func_exported(funcidx) || self.start_func() == Some(funcidx)
+    }
 
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Code((_section_size, content)) => {
-                    for c in &mut content.lock().unwrap().value {
-                        if funcidx == target_funcidx {
-                            c.locals.push(local);
-                            return true;
-                        }
+    pub fn get_export_funcidx(&self, name: &str) -> Option<u32> {
+        self.exports().iter().find_map(|export| match export.descr {
+            ast::ExportDescr::Func(funcidx) if export.name == name => Some(funcidx),
+            _ => None,
+        })
+    }
 
-                        funcidx += 1;
-                    }
-                }
-                _ => {}
+    pub fn get_export_func(&self, name: &str) -> Result<(&ast::Code, &ast::Type), BoxError> {
+        let export = self
+            .exports()
+            .iter()
+            .find(|export| export.name == name)
+            .ok_or("export not found")?;
+        match export.descr {
+            ast::ExportDescr::Func(funcidx) => {
+                let code = self
+                    .get_code(funcidx)
+                    .ok_or("exported function not found")?;
+                Ok((code, self.get_func_type(funcidx)))
             }
+            _ => Err("export is not a function".into()),
         }
+    }
 
-        false
+    /// Retrieve the type of a function, imported or defined.
+    pub fn get_func_type(&self, funcidx: u32) -> &ast::Type {
+        let typeidx = self.get_func_typeidx(funcidx);
+        self.types().get(typeidx as usize).expect("type not found")
+    }
+
+    pub fn add_func_local(&mut self, funcidx: u32, local: ast::CodeLocal) -> bool {
+        let Some(idx) = funcidx.checked_sub(self.imported_func_count) else {
+            return false;
+        };
+        match self
+            .codes_mut()
+            .and_then(|codes| codes.get_mut(idx as usize))
+        {
+            Some(code) => {
+                code.locals.push(local);
+                *self.added_func_locals.entry(idx).or_default() += 1;
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn get_type(&self, typeidx: u32) -> Option<ast::Type> {
-        let types = self.types.lock().unwrap();
-        types.get(&typeidx).cloned()
+        self.types().get(typeidx as usize).cloned()
     }
 
     pub fn get_func_typeidx(&self, funcidx: u32) -> u32 {
-        let func_to_typeidx = self.func_to_typeidx.lock().unwrap();
-
         if funcidx < self.imported_func_count {
-            todo!()
+            // Func is imported, its type is in the import
+            *self
+                .imports()
+                .iter()
+                .filter_map(|import| match &import.import_type {
+                    ast::ImportType::Func(typeidx) => Some(typeidx),
+                    _ => None,
+                })
+                .nth(funcidx as usize)
+                .unwrap_or_else(|| panic!("type not found for funcidx: {}", funcidx))
         } else {
             // Func is an implemented function
             let funcidx = funcidx - self.imported_func_count;
 
-            *func_to_typeidx
+            *self
+                .func_to_typeidx()
                 .get(funcidx as usize)
-                .expect(&format!("type not found for funcidx: {}", funcidx))
+                .unwrap_or_else(|| panic!("type not found for funcidx: {}", funcidx))
         }
     }
 
     /// Get the start binary offset of a function
     pub fn get_start_of_func(&self, funcidx: u32) -> Option<usize> {
-        self.func_starts.get(&funcidx).cloned()
+        self.get_code(funcidx).map(|code| code.body.start_offset)
     }
 
     pub fn get_custom_section(&self, name: &str) -> Option<Vec<u8>> {
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Custom((_size, section)) => match &*section.lock().unwrap() {
-                    ast::CustomSection::Unknown(section_name, bytes) => {
-                        if section_name == name {
-                            return Some(bytes.to_owned());
-                        }
-                    }
-                    _ => {}
-                },
-                _ => {}
-            }
-        }
-
-        None
+        self.get_custom_sections()
+            .find_map(|section| match section {
+                ast::CustomSection::Unknown(section_name, bytes) if section_name == name => {
+                    Some(bytes.to_owned())
+                }
+                _ => None,
+            })
     }
 
-    pub fn get_custom_sections(&self) -> &Vec<ast::CustomSection> {
-        &self.custom_sections
+    pub fn get_custom_sections(&self) -> impl Iterator<Item = &ast::CustomSection> {
+        self.inner
+            .sections
+            .iter()
+            .filter_map(|section| match &section.value {
+                ast::Section::Custom((_size, section)) => Some(section),
+                _ => None,
+            })
     }
 
-    pub fn remove_custom_section(&self, name: &str) -> Option<()> {
-        let mut idx = None;
-        let mut i = 0;
-
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Custom((_size, section)) => match &*section.lock().unwrap() {
-                    ast::CustomSection::Unknown(section_name, _) => {
-                        if section_name == name {
-                            idx = Some(i)
-                        }
-                    }
-
-                    ast::CustomSection::Name(_) if name == "name" => idx = Some(i),
-                    _ => {}
+    pub fn remove_custom_section(&mut self, name: &str) -> Option<()> {
+        let idx = self
+            .inner
+            .sections
+            .iter()
+            .rposition(|section| match &section.value {
+                ast::Section::Custom((_size, section)) => match section {
+                    ast::CustomSection::Unknown(section_name, _) => section_name == name,
+                    ast::CustomSection::Name(_) => name == "name",
+                    ast::CustomSection::CodeOffsets(_) => name == "code_offsets",
+                    _ => false,
                 },
-                _ => {}
-            }
-
-            i += 1;
-        }
+                _ => false,
+            })?;
 
-        if let Some(idx) = idx {
-            let mut sections = self.inner.sections.lock().unwrap();
-            sections.remove(idx);
-
-            Some(())
-        } else {
-            None
-        }
+        self.inner.sections.remove(idx);
+        Some(())
     }
 
     pub fn get_func_name(&self, funcidx: u32) -> Option<String> {
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Custom((_size, section)) => match &*section.lock().unwrap() {
-                    ast::CustomSection::Name(names) => {
-                        if let Some(v) = &names.func_names {
-                            if let Some(name) = v.lock().unwrap().get(&funcidx) {
-                                return Some(name.clone());
-                            }
-                        }
-                    }
-                    _ => {}
-                },
-                _ => {}
-            }
-        }
+        self.func_names()
+            .and_then(|func_names| func_names.get(&funcidx))
+            .cloned()
+    }
 
-        None
+    pub fn get_global_name(&self, globalidx: u32) -> Option<String> {
+        self.names()
+            .and_then(|names| names.global_names.get(&globalidx))
+            .cloned()
     }
 
     pub fn find_import(&self, name: &str) -> u32 {
-        let mut funcidx = 0;
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Import((_section_size, content)) => {
-                    for import in &*content.lock().unwrap() {
-                        if import.name == name {
-                            return funcidx;
-                        }
-                        funcidx += 1;
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        0
+        self.imports()
+            .iter()
+            .position(|import| import.name == name)
+            .unwrap_or_default() as u32
     }
 
-    pub fn add_global_import(&self, module: &str, name: &str, ty: &ast::GlobalType) -> u32 {
+    /// Add a global import and return its globalidx.
+    ///
+    /// The globalidx of the globals defined in the module are shifted by
+    /// one; instructions, exports, constant expressions and the name section
+    /// are updated accordingly.
+    pub fn add_global_import(&mut self, module: &str, name: &str, ty: &ast::GlobalType) -> u32 {
         let import = ast::Import {
             module: module.to_owned(),
             name: name.to_owned(),
             import_type: ast::ImportType::Global(ty.to_owned()),
         };
 
-        todo!()
+        let globalidx = self
+            .imports()
+            .iter()
+            .filter(|import| matches!(import.import_type, ast::ImportType::Global(_)))
+            .count() as u32;
+        shift_globalidx(&mut self.inner, globalidx);
+
+        let mut has_import_section = false;
+
+        for section in self.inner.sections.iter_mut() {
+            if let ast::Section::Import((_section_size, content)) = &mut section.value {
+                content.push(import.to_owned());
+                has_import_section = true;
+            }
+        }
+
+        if !has_import_section {
+            let import_section = ast::Section::Import((
+                ast::Value::new(0), // section size will be set during encoding
+                vec![import],
+            ));
+            self.add_section(import_section);
+        }
+
+        globalidx
     }
 
-    pub fn add_import(&self, _import: &ast::Import) -> u32 {
-        unimplemented!("Adding an import requires to shifts all references to functions by one, which is unsafe (func tables) or inconvenient (name section).");
+    /// Add a function import and return its funcidx.
+    ///
+    /// The funcidx of the functions defined in the module are shifted by
+    /// one; calls, `ref.func`, exports, elements, globals, the start function
+    /// and the name section are updated accordingly.
+    pub fn add_import(&mut self, import: &ast::Import) -> Result<u32, BoxError> {
+        if !matches!(import.import_type, ast::ImportType::Func(_)) {
+            return Err(format!(
+                "only function imports can be added, got {}.{}",
+                import.module, import.name
+            )
+            .into());
+        }
+
+        let mut has_import_section = false;
+
+        for section in self.inner.sections.iter_mut() {
+            if let ast::Section::Import((_section_size, content)) = &mut section.value {
+                content.push(import.to_owned());
+                has_import_section = true;
+            }
+        }
+
+        let funcidx = self.imported_func_count;
+        shift_funcidx(&mut self.inner, funcidx);
+        self.imported_func_count += 1;
+
+        if !has_import_section {
+            let import_section = ast::Section::Import((
+                ast::Value::new(0), // section size will be set during encoding
+                vec![import.to_owned()],
+            ));
+            self.add_section(import_section);
+        }
+
+        Ok(funcidx)
     }
 
-    pub fn add_global(&self, global: &ast::Global) -> Option<u32> {
-        let mut globalidx = 0;
+    /// Add a memory after the imported and defined ones and return its
+    /// memidx.
+    pub fn add_memory(&mut self, memory: &ast::Memory) -> u32 {
+        let imported_memory_count = self
+            .imports()
+            .iter()
+            .filter(|import| matches!(import.import_type, ast::ImportType::Memory(_)))
+            .count() as u32;
+
+        for section in self.inner.sections.iter_mut() {
+            if let ast::Section::Memory((_section_size, content)) = &mut section.value {
+                let memidx = imported_memory_count + content.len() as u32;
+                content.push(memory.to_owned());
+                return memidx;
+            }
+        }
 
-        // FIXME: we should really pre-compute all that
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Import((_section_size, content)) => {
-                    let imports = content.lock().unwrap();
-                    for import in imports.iter() {
-                        match import.import_type {
-                            ast::ImportType::Global(_) => globalidx += 1,
-                            _ => {}
-                        }
-                    }
-                }
+        let memory_section = ast::Section::Memory((
+            ast::Value::new(0), // section size will be set during encoding
+            vec![memory.to_owned()],
+        ));
+        self.add_section(memory_section);
+        imported_memory_count
+    }
 
-                ast::Section::Global((_section_size, content)) => {
-                    globalidx += content.lock().unwrap().len() as u32;
-                    content.lock().unwrap().push(global.to_owned());
-                    return Some(globalidx);
-                }
-                _ => {}
+    pub fn add_global(&mut self, global: &ast::Global) -> Option<u32> {
+        let imported_global_count = self
+            .imports()
+            .iter()
+            .filter(|import| matches!(import.import_type, ast::ImportType::Global(_)))
+            .count() as u32;
+
+        for section in self.inner.sections.iter_mut() {
+            if let ast::Section::Global((_section_size, content)) = &mut section.value {
+                let globalidx = imported_global_count + content.len() as u32;
+                content.push(global.to_owned());
+                return Some(globalidx);
             }
         }
 
         let globals = vec![global.to_owned()];
         let global_section = ast::Section::Global((
             ast::Value::new(0), // section size will be set during encoding
-            Arc::new(Mutex::new(globals)),
+            globals,
         ));
 
         self.add_section(global_section);
-        return Some(globalidx);
+        Some(imported_global_count)
     }
 
-    pub fn add_export_func(&self, name: &str, funcidx: u32) {
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Export((_section_size, content)) => {
-                    let export = ast::Export {
-                        name: name.to_owned(),
-                        descr: ast::ExportDescr::Func(Arc::new(Mutex::new(funcidx))),
-                    };
-                    content.lock().unwrap().push(export.to_owned());
-                    return;
-                }
-                _ => {}
+    pub fn add_export_func(&mut self, name: &str, funcidx: u32) {
+        self.add_export(ast::Export {
+            name: name.to_owned(),
+            descr: ast::ExportDescr::Func(funcidx),
+        });
+    }
+
+    pub fn add_export_memory(&mut self, name: &str, memidx: u32) {
+        self.add_export(ast::Export {
+            name: name.to_owned(),
+            descr: ast::ExportDescr::Mem(memidx),
+        });
+    }
+
+    fn add_export(&mut self, export: ast::Export) {
+        for section in self.inner.sections.iter_mut() {
+            if let ast::Section::Export((_section_size, content)) = &mut section.value {
+                content.push(export);
+                return;
             }
         }
 
-        unimplemented!("no export section")
+        let export_section = ast::Section::Export((
+            ast::Value::new(0), // section size will be set during encoding
+            vec![export],
+        ));
+        self.add_section(export_section);
     }
 
-    pub fn add_function(&self, func: &ast::Code, typeidx: u32) -> u32 {
+    pub fn add_function(&mut self, func: &ast::Code, typeidx: u32) -> u32 {
         let mut funcidx = self.imported_func_count;
 
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
+        for section in self.inner.sections.iter_mut() {
+            match &mut section.value {
                 ast::Section::Code((_section_size, content)) => {
-                    funcidx += content.lock().unwrap().value.len() as u32;
-                    content.lock().unwrap().value.push(func.to_owned());
+                    funcidx += content.value.len() as u32;
+                    content.value.push(func.to_owned());
                 }
                 ast::Section::Func((_section_size, content)) => {
-                    content.lock().unwrap().push(typeidx);
+                    content.push(typeidx);
                 }
                 _ => {}
             }
         }
 
-        self.func_to_typeidx.lock().unwrap().push(typeidx);
         funcidx
     }
 
-    pub fn add_type(&self, t: &ast::Type) -> u32 {
+    pub fn add_type(&mut self, t: &ast::Type) -> u32 {
         let mut typeidx = 0;
 
-        for section in self.inner.sections.lock().unwrap().iter() {
-            match &section.value {
-                ast::Section::Type((_section_size, content)) => {
-                    typeidx = content.lock().unwrap().len() as u32;
-                    content.lock().unwrap().push(t.clone());
-
-                    self.types.lock().unwrap().insert(typeidx, t.to_owned());
-                }
-                _ => {}
+        for section in self.inner.sections.iter_mut() {
+            if let ast::Section::Type((_section_size, content)) = &mut section.value {
+                typeidx = content.len() as u32;
+                content.push(t.clone());
             }
         }
 
         typeidx
     }
 
-    pub fn add_section(&self, s: ast::Section) {
-        let mut sections = self.inner.sections.lock().unwrap();
+    pub fn add_section(&mut self, s: ast::Section) {
+        let sections = &mut self.inner.sections;
         sections.push(ast::Value::new(s));
-        sections.sort_by(|a, b| a.pos().cmp(&b.pos()));
+        sections.sort_by_key(|section| section.pos());
     }
 
-    pub fn add_custom_section(&self, s: ast::CustomSection) {
-        let section = ast::Section::Custom((ast::Value::new(0), Arc::new(Mutex::new(s))));
+    pub fn add_custom_section(&mut self, s: ast::CustomSection) {
+        let section = ast::Section::Custom((ast::Value::new(0), s));
         self.add_section(section);
     }
 
-    pub fn get_build_id(&self) -> &Option<Vec<u8>> {
-        &self.build_id
+    pub fn get_code_offset_map(&self) -> Option<&ast::CodeOffsetMap> {
+        self.get_custom_sections()
+            .find_map(|section| match section {
+                ast::CustomSection::CodeOffsets(map) => Some(map),
+                _ => None,
+            })
     }
 
-    pub fn set_build_id(&self, build_id: &[u8]) {
+    pub fn get_build_id(&self) -> Option<&Vec<u8>> {
+        self.get_custom_sections()
+            .find_map(|section| match section {
+                ast::CustomSection::BuildId(id) => Some(id),
+                _ => None,
+            })
+    }
+
+    pub fn set_build_id(&mut self, build_id: &[u8]) {
         let section = ast::CustomSection::BuildId(build_id.to_owned());
         self.add_custom_section(section);
     }
 }
 
+/// Increment by one all references to functions starting at `from`.
+fn shift_funcidx(module: &mut ast::Module, from: u32) {
+    let shift = |funcidx: &mut u32| {
+        if *funcidx >= from {
+            *funcidx += 1;
+        }
+    };
+
+    for section in module.sections.iter_mut() {
+        match &mut section.value {
+            ast::Section::Code((_section_size, content)) => {
+                for code in content.value.iter_mut() {
+                    shift_funcidx_expr(&mut code.body.value, from);
+                }
+            }
+            ast::Section::Export((_section_size, content)) => {
+                for export in content.iter_mut() {
+                    if let ast::ExportDescr::Func(funcidx) = &mut export.descr {
+                        shift(funcidx);
+                    }
+                }
+            }
+            ast::Section::Global((_section_size, content)) => {
+                for global in content.iter_mut() {
+                    shift_funcidx_expr(&mut global.expr.value, from);
+                }
+            }
+            ast::Section::Element((_section_size, content)) => {
+                for element in content.iter_mut() {
+                    match element {
+                        ast::Element::FuncActive(_, funcs)
+                        | ast::Element::FuncPassive(funcs)
+                        | ast::Element::FuncActiveTable(_, _, funcs)
+                        | ast::Element::FuncDeclarative(funcs) => {
+                            funcs.iter_mut().for_each(shift);
+                        }
+                        ast::Element::ExprActive(_, exprs)
+                        | ast::Element::ExprPassive(_, exprs)
+                        | ast::Element::ExprActiveTable(_, _, _, exprs)
+                        | ast::Element::ExprDeclarative(_, exprs) => {
+                            for expr in exprs.iter_mut() {
+                                shift_funcidx_expr(&mut expr.value, from);
+                            }
+                        }
+                    }
+                }
+            }
+            ast::Section::Custom((_size, ast::CustomSection::Name(names))) => {
+                if let Some(func_names) = &mut names.func_names {
+                    *func_names = func_names
+                        .drain()
+                        .map(|(mut funcidx, name)| {
+                            shift(&mut funcidx);
+                            (funcidx, name)
+                        })
+                        .collect();
+                }
+                if let Some(func_local_names) = &mut names.func_local_names {
+                    *func_local_names = func_local_names
+                        .drain()
+                        .map(|(mut funcidx, names)| {
+                            shift(&mut funcidx);
+                            (funcidx, names)
+                        })
+                        .collect();
+                }
+            }
+            ast::Section::Start((_size, funcidx)) => {
+                shift(funcidx);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn shift_funcidx_expr(expr: &mut [ast::Value<ast::Instr>], from: u32) {
+    for instr in expr.iter_mut() {
+        match &mut instr.value {
+            ast::Instr::call(funcidx) | ast::Instr::ref_func(funcidx) if *funcidx >= from => {
+                *funcidx += 1;
+            }
+            ast::Instr::Block(_, body)
+            | ast::Instr::Loop(_, body)
+            | ast::Instr::If(_, body)
+            | ast::Instr::Try(_, body)
+            | ast::Instr::TryTable(_, _, body) => {
+                shift_funcidx_expr(&mut body.value, from);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn exception_handling_expr(expr: &[ast::Value<ast::Instr>]) -> Option<CatchForm> {
+    let mut form = None;
+    for instr in expr {
+        let found = match &instr.value {
+            ast::Instr::TryTable(..) | ast::Instr::throw_ref => Some(CatchForm::TryTable),
+            ast::Instr::Try(_, body) => {
+                exception_handling_expr(&body.value).or(Some(CatchForm::Try))
+            }
+            ast::Instr::throw(_) | ast::Instr::rethrow(_) => Some(CatchForm::Try),
+            ast::Instr::Block(_, body) | ast::Instr::Loop(_, body) | ast::Instr::If(_, body) => {
+                exception_handling_expr(&body.value)
+            }
+            _ => None,
+        };
+        match found {
+            Some(CatchForm::TryTable) => return found,
+            Some(CatchForm::Try) => form = found,
+            None => {}
+        }
+    }
+    form
+}
+
+/// Increment by one all references to globals starting at `from`.
+fn shift_globalidx(module: &mut ast::Module, from: u32) {
+    let shift = |globalidx: &mut u32| {
+        if *globalidx >= from {
+            *globalidx += 1;
+        }
+    };
+
+    for section in module.sections.iter_mut() {
+        match &mut section.value {
+            ast::Section::Code((_section_size, content)) => {
+                for code in content.value.iter_mut() {
+                    shift_globalidx_expr(&mut code.body.value, from);
+                }
+            }
+            ast::Section::Global((_section_size, content)) => {
+                for global in content.iter_mut() {
+                    shift_globalidx_expr(&mut global.expr.value, from);
+                }
+            }
+            ast::Section::Export((_section_size, content)) => {
+                for export in content.iter_mut() {
+                    if let ast::ExportDescr::Global(globalidx) = &mut export.descr {
+                        shift(globalidx);
+                    }
+                }
+            }
+            ast::Section::Element((_section_size, content)) => {
+                for element in content.iter_mut() {
+                    match element {
+                        ast::Element::FuncActive(offset, _)
+                        | ast::Element::FuncActiveTable(_, offset, _) => {
+                            shift_globalidx_expr(&mut offset.value, from);
+                        }
+                        ast::Element::ExprActive(offset, exprs)
+                        | ast::Element::ExprActiveTable(_, offset, _, exprs) => {
+                            shift_globalidx_expr(&mut offset.value, from);
+                            for expr in exprs.iter_mut() {
+                                shift_globalidx_expr(&mut expr.value, from);
+                            }
+                        }
+                        ast::Element::ExprPassive(_, exprs)
+                        | ast::Element::ExprDeclarative(_, exprs) => {
+                            for expr in exprs.iter_mut() {
+                                shift_globalidx_expr(&mut expr.value, from);
+                            }
+                        }
+                        ast::Element::FuncPassive(_) | ast::Element::FuncDeclarative(_) => {}
+                    }
+                }
+            }
+            ast::Section::Data((_section_size, content)) => {
+                for segment in content.iter_mut() {
+                    if let Some(offset) = &mut segment.offset {
+                        shift_globalidx_expr(&mut offset.value, from);
+                    }
+                }
+            }
+            ast::Section::Custom((_size, ast::CustomSection::Name(names))) => {
+                names.global_names = names
+                    .global_names
+                    .drain()
+                    .map(|(mut globalidx, name)| {
+                        shift(&mut globalidx);
+                        (globalidx, name)
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn shift_globalidx_expr(expr: &mut [ast::Value<ast::Instr>], from: u32) {
+    for instr in expr.iter_mut() {
+        match &mut instr.value {
+            ast::Instr::global_get(globalidx) | ast::Instr::global_set(globalidx)
+                if *globalidx >= from =>
+            {
+                *globalidx += 1;
+            }
+            ast::Instr::Block(_, body)
+            | ast::Instr::Loop(_, body)
+            | ast::Instr::If(_, body)
+            | ast::Instr::Try(_, body)
+            | ast::Instr::TryTable(_, _, body) => {
+                shift_globalidx_expr(&mut body.value, from);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// State of the function being visited. The function is owned by the thread
+/// visiting it, edits of its locals are applied once it has been visited.
+struct FuncState {
+    locals: Vec<ast::CodeLocal>,
+    /// Number of locals entries the function had in the original module,
+    /// before [WasmModule::add_func_local].
+    original_locals: usize,
+    start_offset: usize,
+    /// Count of params and locals, including the added locals.
+    local_count: u32,
+    added_locals: Vec<ast::CodeLocal>,
+}
+
+/// Exception handling instructions catching the exceptions escaping a
+/// function, see [VisitorContext::catch_body].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CatchForm {
+    /// Legacy `try` and `catch_all`.
+    Try,
+    /// `try_table` with a `catch_all` clause.
+    TryTable,
+}
+
 pub struct VisitorContext<'a, T> {
-    pub module: Arc<WasmModule>,
+    pub module: &'a WasmModule,
     insert_nodes_after: Vec<T>,
     insert_nodes_before: Vec<T>,
     replace_node: Option<T>,
     pub curr_funcidx: Option<u32>,
     pub node: &'a T,
     traverse_stop: bool,
+    func: Option<&'a mut FuncState>,
+    prepend_body: Vec<Vec<ast::Value<ast::Instr>>>,
+    append_body: Vec<ast::Value<ast::Instr>>,
+    catch_body: Option<(CatchForm, ast::BlockType, Vec<ast::Value<ast::Instr>>)>,
 }
 impl<'a, T> VisitorContext<'a, T> {
-    pub fn new(module: Arc<WasmModule>, node: &'a T) -> Self {
+    pub fn new(module: &'a WasmModule, node: &'a T) -> Self {
         Self {
             node,
             module,
@@ -596,17 +1003,49 @@ impl<'a, T> VisitorContext<'a, T> {
             replace_node: None,
             curr_funcidx: None,
             traverse_stop: false,
+            func: None,
+            prepend_body: vec![],
+            append_body: vec![],
+            catch_body: None,
         }
     }
 }
 
 impl<'a, T> VisitorContext<'a, Vec<T>> {
+    /// Append a node to the section. Nodes are appended in the order of the
+    /// calls, once all the sections have been visited.
     pub fn insert_node_after(&mut self, new_node: T) {
         self.insert_nodes_after.push(vec![new_node]);
     }
+}
+
+impl<'a> VisitorContext<'a, ast::Code> {
+    /// Insert instructions at the start of the function. Instructions
+    /// prepended last run first.
+    pub fn prepend_body(&mut self, instrs: Vec<ast::Instr>) {
+        self.prepend_body
+            .push(instrs.into_iter().map(ast::Value::new).collect());
+    }
 
-    pub fn insert_node_before(&mut self, new_node: T) {
-        self.insert_nodes_before.push(vec![new_node]);
+    /// Insert instructions at the end of the function, before its final
+    /// `end`.
+    pub fn append_body(&mut self, instrs: Vec<ast::Instr>) {
+        self.append_body
+            .extend(instrs.into_iter().map(ast::Value::new));
+    }
+
+    /// Run the function's body, with the prepended and appended
+    /// instructions, in a block of `block_type` catching all exceptions, and
+    /// the handler when one escapes it. The handler must not fall through,
+    /// it ends with a trap, a return or a rethrow.
+    pub fn catch_body(
+        &mut self,
+        form: CatchForm,
+        block_type: ast::BlockType,
+        handler: Vec<ast::Instr>,
+    ) {
+        let handler = handler.into_iter().map(ast::Value::new).collect();
+        self.catch_body = Some((form, block_type, handler));
     }
 }
 
@@ -626,6 +1065,34 @@ impl<'a> VisitorContext<'a, ast::Value<ast::Instr>> {
     pub fn replace_node(&mut self, new_node: ast::Instr) {
         self.replace_node = Some(ast::Value::new(new_node));
     }
+
+    /// Locals of the current function, as in the module before the
+    /// traversal.
+    pub fn func_locals(&self) -> &[ast::CodeLocal] {
+        let func = self.func();
+        &func.locals[..func.original_locals]
+    }
+
+    /// Start binary offset of the current function.
+    pub fn func_start(&self) -> usize {
+        self.func().start_offset
+    }
+
+    /// Add a local to the current function and return its localidx.
+    pub fn add_local(&mut self, value_type: ast::ValueType) -> u32 {
+        let func = self.func.as_mut().expect("visiting a function");
+        let localidx = func.local_count;
+        func.local_count += 1;
+        func.added_locals.push(ast::CodeLocal {
+            count: 1,
+            value_type,
+        });
+        localidx
+    }
+
+    fn func(&self) -> &FuncState {
+        self.func.as_ref().expect("visiting a function")
+    }
 }
 
 pub trait Visitor {
@@ -641,202 +1108,269 @@ pub trait Visitor {
     fn visit_code<'a>(&self, _ctx: &'_ mut VisitorContext<'a, ast::Code>, _funcidx: u32) {}
 }
 
-pub fn traverse(module: Arc<ast::Module>, visitor: Arc<dyn Visitor + Send + Sync>) {
-    let pool = ThreadPool::new(num_cpus::get());
-
-    let mut curr_funcidx = 0;
+/// Nodes appended to the sections by the visitor.
+#[derive(Default)]
+struct SectionEdits {
+    imports: Vec<ast::Import>,
+    funcs: Vec<u32>,
+    codes: Vec<ast::Code>,
+    segments: Vec<ast::DataSegment>,
+}
 
-    let module_ast = Arc::new(WasmModule::new(Arc::clone(&module)));
+/// Visit the module and apply the edits requested by the visitor.
+///
+/// The sections are visited first, the nodes appended to them are added
+/// before visiting the functions, appended code included. Functions are
+/// visited in parallel; each thread owns the functions it visits, the rest
+/// of the module is shared and read-only.
+pub fn traverse(module: &mut WasmModule, visitor: &(dyn Visitor + Sync)) {
+    let mut edits = SectionEdits::default();
 
-    for section in module.sections.lock().unwrap().iter() {
+    for section in &module.inner.sections {
         match &section.value {
+            ast::Section::Import((_section_size, imports)) => {
+                let mut ctx = VisitorContext::new(module, imports);
+                visitor.visit_import_section(&mut ctx);
+                edits.imports.extend(ctx.insert_nodes_after.concat());
+            }
             ast::Section::Func((_section_size, funcs)) => {
-                let nodes = funcs.lock().unwrap().clone();
-                let mut ctx = VisitorContext::new(Arc::clone(&module_ast), &nodes);
-                Arc::clone(&visitor).visit_func_section(&mut ctx);
-                assert!(ctx.insert_nodes_before.is_empty());
-
-                {
-                    let mut new_nodes = ctx.insert_nodes_after;
-                    new_nodes.reverse();
-
-                    for new_node in new_nodes {
-                        debug!("inject new func: {:?}", new_node);
-                        funcs.lock().unwrap().extend_from_slice(&new_node);
-                    }
-                }
+                let mut ctx = VisitorContext::new(module, funcs);
+                visitor.visit_func_section(&mut ctx);
+                edits.funcs.extend(ctx.insert_nodes_after.concat());
+            }
+            ast::Section::Code((_section_size, codes)) => {
+                let mut ctx = VisitorContext::new(module, &codes.value);
+                visitor.visit_code_section(&mut ctx);
+                edits.codes.extend(ctx.insert_nodes_after.concat());
+            }
+            ast::Section::Data((_section_size, segments)) => {
+                let mut ctx = VisitorContext::new(module, segments);
+                visitor.visit_data_section(&mut ctx);
+                edits.segments.extend(ctx.insert_nodes_after.concat());
             }
             ast::Section::Export((_section_size, exports)) => {
-                for export in exports.lock().unwrap().iter() {
-                    let mut ctx = VisitorContext::new(Arc::clone(&module_ast), export);
+                for export in exports {
+                    let mut ctx = VisitorContext::new(module, export);
                     visitor.visit_export(&mut ctx);
-                    assert!(ctx.insert_nodes_before.is_empty());
-                    assert!(ctx.insert_nodes_after.is_empty());
                 }
             }
             ast::Section::Element((_section_size, elements)) => {
-                for element in elements.lock().unwrap().iter() {
-                    let mut ctx = VisitorContext::new(Arc::clone(&module_ast), element);
+                for element in elements {
+                    let mut ctx = VisitorContext::new(module, element);
                     visitor.visit_element(&mut ctx);
-                    assert!(ctx.insert_nodes_before.is_empty());
-                    assert!(ctx.insert_nodes_after.is_empty());
                 }
             }
             ast::Section::Table((_section_size, tables)) => {
-                let module_ast = Arc::clone(&module_ast);
-                for table in tables.lock().unwrap().iter() {
-                    let mut ctx = VisitorContext::new(Arc::clone(&module_ast), table);
+                for table in tables {
+                    let mut ctx = VisitorContext::new(module, table);
                     visitor.visit_table(&mut ctx);
-                    assert!(ctx.insert_nodes_before.is_empty());
-                    assert!(ctx.insert_nodes_after.is_empty());
                 }
             }
             ast::Section::Type((_section_size, types)) => {
-                let mut typeidx = 0;
-                let types_copy = types.lock().unwrap().clone();
-                for t in types_copy {
-                    let mut ctx = VisitorContext::new(Arc::clone(&module_ast), &t);
-                    visitor.visit_type(&mut ctx, typeidx);
-                    typeidx += 1;
-
-                    assert!(ctx.insert_nodes_before.is_empty());
-                    assert!(ctx.insert_nodes_after.is_empty());
+                for (typeidx, t) in types.iter().enumerate() {
+                    let mut ctx = VisitorContext::new(module, t);
+                    visitor.visit_type(&mut ctx, typeidx as u32);
                 }
             }
-            ast::Section::Import((_section_size, content)) => {
-                let nodes = content.lock().unwrap().clone();
-                let mut ctx = VisitorContext::new(Arc::clone(&module_ast), &nodes);
-                Arc::clone(&visitor).visit_import_section(&mut ctx);
-                assert!(ctx.insert_nodes_before.is_empty());
+            _ => {}
+        }
+    }
 
-                {
-                    for new_node in ctx.insert_nodes_after {
-                        debug!("inject new import: {:?}", new_node);
-                        content.lock().unwrap().extend_from_slice(&new_node);
-                    }
-                }
+    module.apply_section_edits(edits);
 
-                for import in &nodes {
-                    match import.import_type {
-                        ast::ImportType::Func(_) => {
-                            curr_funcidx += 1;
+    let Some(codes) = module.codes_mut() else {
+        return;
+    };
+    let mut codes = std::mem::take(codes);
+    let first_funcidx = module.imported_func_count;
+
+    {
+        let module = &*module;
+        let threads = num_cpus::get().max(1);
+
+        // Spread the functions in chunks over the threads, the functions of
+        // a module don't have the same size.
+        let mut chunks: Vec<Vec<(u32, &mut [ast::Code])>> = (0..threads).map(|_| vec![]).collect();
+        for (i, chunk) in codes.chunks_mut(FUNCS_PER_CHUNK).enumerate() {
+            let funcidx = first_funcidx + (i * FUNCS_PER_CHUNK) as u32;
+            chunks[i % threads].push((funcidx, chunk));
+        }
+
+        std::thread::scope(|scope| {
+            for chunks in chunks {
+                scope.spawn(move || {
+                    for (funcidx, chunk) in chunks {
+                        for (code, funcidx) in chunk.iter_mut().zip(funcidx..) {
+                            visit_func(module, visitor, code, funcidx);
                         }
-                        _ => {}
                     }
-                }
+                });
             }
-            ast::Section::Code((_section_size, codes)) => {
-                {
-                    let nodes = codes.lock().unwrap().clone().value;
-                    let mut ctx = VisitorContext::new(Arc::clone(&module_ast), &nodes);
-                    Arc::clone(&visitor).visit_code_section(&mut ctx);
-                    assert!(ctx.insert_nodes_before.is_empty());
-
-                    let mut new_nodes = ctx.insert_nodes_after;
-                    new_nodes.reverse();
-
-                    for new_node in new_nodes {
-                        debug!("inject new code: {:?}", new_node);
-                        codes.lock().unwrap().value.extend_from_slice(&new_node);
-                    }
-                }
+        });
+    }
 
-                let codes = codes.lock().unwrap().clone();
-                for code in codes.value {
-                    {
-                        let mut ctx = VisitorContext::new(Arc::clone(&module_ast), &code);
-                        Arc::clone(&visitor).visit_code(&mut ctx, curr_funcidx);
-                    }
+    *module.codes_mut().unwrap() = codes;
+}
 
-                    {
-                        let visitor = Arc::clone(&visitor);
-                        let module_ast = Arc::clone(&module_ast);
-                        pool.execute(move || {
-                            visit_expr(
-                                Arc::clone(&module_ast),
-                                Arc::clone(&code.body),
-                                Arc::clone(&visitor),
-                                curr_funcidx,
-                            );
-                        });
-                    }
+fn visit_func(
+    module: &WasmModule,
+    visitor: &(dyn Visitor + Sync),
+    code: &mut ast::Code,
+    funcidx: u32,
+) {
+    let (prepend_body, append_body, catch_body) = {
+        let mut ctx = VisitorContext::new(module, &*code);
+        visitor.visit_code(&mut ctx, funcidx);
+        (ctx.prepend_body, ctx.append_body, ctx.catch_body)
+    };
+
+    if !prepend_body.is_empty() || !append_body.is_empty() {
+        let body = std::mem::take(&mut code.body.value);
+        let mut new_body = vec![];
+        for instrs in prepend_body.into_iter().rev() {
+            new_body.extend(instrs);
+        }
+        new_body.extend(body);
+        if !append_body.is_empty() {
+            let end = new_body.pop(); // the function's end
+            new_body.extend(append_body);
+            new_body.extend(end);
+        }
+        code.body.value = new_body;
+    }
 
-                    curr_funcidx += 1;
-                }
-            }
-            ast::Section::Data((_section_size, segments)) => {
-                let nodes = segments.lock().unwrap().clone();
-                let mut ctx = VisitorContext::new(Arc::clone(&module_ast), &nodes);
-                Arc::clone(&visitor).visit_data_section(&mut ctx);
-                assert!(ctx.insert_nodes_before.is_empty());
+    if let Some((form, block_type, handler)) = catch_body {
+        let body = std::mem::take(&mut code.body.value);
+        code.body.value = wrap_catch_body(body, form, block_type, handler);
+    }
 
-                let mut new_nodes = ctx.insert_nodes_after;
-                new_nodes.reverse();
+    let local_count = module.get_func_type(funcidx).params.len() as u32
+        + code.locals.iter().map(|local| local.count).sum::<u32>();
+    let added_func_locals = module
+        .added_func_locals
+        .get(&(funcidx - module.imported_func_count))
+        .copied()
+        .unwrap_or_default();
+    let mut func = FuncState {
+        original_locals: code.locals.len() - added_func_locals,
+        locals: std::mem::take(&mut code.locals),
+        start_offset: code.body.start_offset,
+        local_count,
+        added_locals: vec![],
+    };
+
+    visit_expr(module, visitor, &mut code.body.value, funcidx, &mut func);
+
+    code.locals = func.locals;
+    code.locals.extend(func.added_locals);
+}
 
-                for new_node in new_nodes {
-                    debug!("inject new data: {:?}", new_node);
-                    segments.lock().unwrap().extend_from_slice(&new_node);
-                }
-            }
-            _ => {}
+/// Wrap the body of a function, ending with the function's `end`, in a block
+/// catching all exceptions. The labels of the body are shifted by the new
+/// block, which has the function's results: branching to it and falling
+/// through to the end of the function return the same values.
+fn wrap_catch_body(
+    mut body: Vec<ast::Value<ast::Instr>>,
+    form: CatchForm,
+    block_type: ast::BlockType,
+    handler: Vec<ast::Value<ast::Instr>>,
+) -> Vec<ast::Value<ast::Instr>> {
+    let end = || ast::Value::new(ast::Instr::end);
+
+    match form {
+        CatchForm::Try => {
+            // The function's end now ends the try
+            let try_end = body.pop().unwrap_or_else(end);
+            body.push(ast::Value::new(ast::Instr::catch_all));
+            body.extend(handler);
+            body.push(try_end);
+
+            let try_node = ast::Instr::Try(block_type, ast::Value::new(body));
+            vec![ast::Value::new(try_node), end()]
+        }
+        CatchForm::TryTable => {
+            // The catch clause branches out of the outer block, to the
+            // handler. Without exception the try_table's results are
+            // returned.
+            let try_table = ast::Instr::TryTable(
+                block_type,
+                vec![ast::Catch::CatchAll(0)],
+                ast::Value::new(body),
+            );
+            let block_body = vec![
+                ast::Value::new(try_table),
+                ast::Value::new(ast::Instr::Return),
+                end(),
+            ];
+
+            let mut out = vec![ast::Value::new(ast::Instr::Block(
+                ast::BlockType::Empty,
+                ast::Value::new(block_body),
+            ))];
+            out.extend(handler);
+            out.push(end());
+            out
         }
     }
-
-    pool.join();
 }
 
+/// Visit the instructions of an expression and rebuild it with the edits
+/// requested by the visitor.
 fn visit_expr(
-    module_ast: Arc<WasmModule>,
-    expr: ast::MutableValue<Vec<ast::Value<ast::Instr>>>,
-    visitor: Arc<dyn Visitor + Send + Sync>,
+    module: &WasmModule,
+    visitor: &(dyn Visitor + Sync),
+    expr: &mut Vec<ast::Value<ast::Instr>>,
     curr_funcidx: u32,
+    func: &mut FuncState,
 ) {
-    let expr_copy = expr.lock().unwrap().clone();
-
-    // Keep track of many nodes we injected since we started iterating, so that
-    // subsequent inserts are at the right place.
-    // The iterator is a copy of the array of nodes.
-    let mut added = 0;
-
-    for i in 0..expr_copy.value.len() {
-        let instr = expr_copy.value[i].clone();
-        if let ast::Instr::Block(_, body) = instr.value {
-            visit_expr(Arc::clone(&module_ast), body, visitor.clone(), curr_funcidx);
-        } else if let ast::Instr::If(_, body) = instr.value {
-            visit_expr(Arc::clone(&module_ast), body, visitor.clone(), curr_funcidx);
-        } else if let ast::Instr::Loop(_, body) = instr.value {
-            visit_expr(Arc::clone(&module_ast), body, visitor.clone(), curr_funcidx);
-        } else {
-            let mut ctx = VisitorContext::new(Arc::clone(&module_ast), &instr);
-            ctx.curr_funcidx = Some(curr_funcidx);
-            visitor.visit_instr(&mut ctx);
-
-            if let Some(replace_node) = ctx.replace_node {
-                debug!("replace instr: {:?}", replace_node);
-                expr.lock().unwrap().value[i + added] = replace_node;
+    let mut instrs = std::mem::take(expr).into_iter();
+    expr.reserve(instrs.len());
+
+    while let Some(mut instr) = instrs.next() {
+        match &mut instr.value {
+            ast::Instr::Block(_, body)
+            | ast::Instr::Loop(_, body)
+            | ast::Instr::If(_, body)
+            | ast::Instr::Try(_, body)
+            | ast::Instr::TryTable(_, _, body) => {
+                visit_expr(module, visitor, &mut body.value, curr_funcidx, func);
+                expr.push(instr);
             }
+            _ => {
+                let mut ctx = VisitorContext::new(module, &instr);
+                ctx.curr_funcidx = Some(curr_funcidx);
+                ctx.func = Some(&mut *func);
+                visitor.visit_instr(&mut ctx);
+
+                let VisitorContext {
+                    insert_nodes_before,
+                    insert_nodes_after,
+                    replace_node,
+                    traverse_stop,
+                    ..
+                } = ctx;
+
+                if !insert_nodes_before.is_empty() || !insert_nodes_after.is_empty() {
+                    debug!(
+                        "insert instr(s): {:?} {:?}",
+                        insert_nodes_before, insert_nodes_after
+                    );
+                }
 
-            if ctx.insert_nodes_after.len() > 0 {
-                debug!("insert instr(s): {:?}", ctx.insert_nodes_after);
-                expr.lock().unwrap().value.splice(
-                    (i + added + 1)..(i + added + 1),
-                    ctx.insert_nodes_after.clone(),
-                );
-                added += ctx.insert_nodes_after.len();
-            }
-
-            if ctx.insert_nodes_before.len() > 0 {
-                debug!("insert instr(s): {:?}", ctx.insert_nodes_before);
-
-                expr.lock()
-                    .unwrap()
-                    .value
-                    .splice((i + added)..(i + added), ctx.insert_nodes_before.clone());
-                added += ctx.insert_nodes_before.len();
-            }
+                expr.extend(insert_nodes_before);
+                match replace_node {
+                    Some(replace_node) => {
+                        debug!("replace instr: {:?}", replace_node);
+                        expr.push(replace_node);
+                    }
+                    None => expr.push(instr),
+                }
+                expr.extend(insert_nodes_after);
 
-            if ctx.traverse_stop {
-                break;
+                if traverse_stop {
+                    expr.extend(instrs);
+                    break;
+                }
             }
         }
     }
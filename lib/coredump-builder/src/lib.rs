@@ -127,6 +127,7 @@ impl CoredumpBuilder {
             stacks: self.threads,
             memory: vec![self.memory],
             data: self.data,
+            globals: vec![],
         }
     }
 
@@ -1,7 +1,5 @@
 use rustc_demangle::demangle;
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::Mutex;
 use wasmgdb_ddbug_parser as ddbug_parser;
 
 type BoxError = Box<dyn std::error::Error>;
@@ -10,6 +8,9 @@ type BoxError = Box<dyn std::error::Error>;
 pub struct Frame {
     pub name: String,
     pub location: FrameLocation,
+    /// Code offset in the original module, see
+    /// [CoredumpToStack::with_offset_map].
+    pub codeoffset: u32,
 }
 
 #[derive(Debug)]
@@ -35,6 +36,9 @@ pub struct CoredumpToStack {
 
     /// Wasm module containing debugging information, not necessarily valid Wasm.
     debug_module: Option<Vec<u8>>,
+
+    /// Code offsets of the rewritten module
+    offset_map: Option<core_wasm_ast::CodeOffsetMap>,
 }
 
 impl CoredumpToStack {
@@ -46,6 +50,7 @@ impl CoredumpToStack {
             coredump,
             func_names: None,
             debug_module: None,
+            offset_map: None,
         })
     }
 
@@ -64,7 +69,6 @@ impl CoredumpToStack {
         let func_names = name_section
             .func_names
             .ok_or::<BoxError>("missing function names in name section".into())?;
-        let func_names = func_names.lock().unwrap();
 
         for (k, v) in sections {
             if k == "name" {
@@ -84,37 +88,57 @@ impl CoredumpToStack {
             let custom_section = core_wasm_ast::CustomSection::Unknown(k.to_owned(), v);
             // Size will be overriden when priting the module
             let section_size = core_wasm_ast::Value::new(0);
-            let section = core_wasm_ast::Section::Custom((
-                section_size,
-                Arc::new(Mutex::new(custom_section)),
-            ));
+            let section = core_wasm_ast::Section::Custom((section_size, custom_section));
             wasm_printer::wasm::write_section(&mut debug_module, &section)
                 .map_err(|err| format!("failed to write custom section {k}: {err}"))?;
         }
 
         Ok(Self {
             coredump: self.coredump,
-            func_names: Some(func_names.clone()),
+            func_names: Some(func_names),
             debug_module: Some(debug_module.to_owned()),
+            offset_map: self.offset_map,
         })
     }
 
     pub fn with_debug_module(self, bytes: &[u8]) -> Result<Self, BoxError> {
         let module = wasm_parser::parse(&bytes)
             .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
-        let module = core_wasm_ast::traverse::WasmModule::new(Arc::new(module));
+        let module = core_wasm_ast::traverse::WasmModule::new(module);
 
-        let func_names = module.func_names.lock().unwrap();
+        let func_names = module.func_names().cloned().unwrap_or_default();
 
         Ok(Self {
             coredump: self.coredump,
-            func_names: Some(func_names.clone()),
+            func_names: Some(func_names),
             debug_module: Some(bytes.to_owned()),
+            offset_map: self.offset_map,
+        })
+    }
+
+    /// Translate the code offsets of a coredump taken by the engine running
+    /// a rewritten module. `bytes` is the rewritten module or the file
+    /// written by the rewriter's `--offset-map`.
+    ///
+    /// Coredumps written by the rewriter already contain the original code
+    /// offsets.
+    pub fn with_offset_map(self, bytes: &[u8]) -> Result<Self, BoxError> {
+        let module = wasm_parser::parse(bytes)
+            .map_err(|err| format!("failed to parse Wasm module: {}", err))?;
+        let module = core_wasm_ast::traverse::WasmModule::new(module);
+
+        let offset_map = module
+            .get_code_offset_map()
+            .ok_or::<BoxError>("missing code_offsets section".into())?;
+
+        Ok(Self {
+            offset_map: Some(offset_map.clone()),
+            ..self
         })
     }
 
     pub fn stack(self) -> Result<Vec<Frame>, BoxError> {
-        let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(Arc::new(self.coredump));
+        let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(self.coredump);
 
         let func_names = self
             .func_names
@@ -162,6 +186,11 @@ impl CoredumpToStack {
         frames.reverse();
 
         for frame in frames {
+            let codeoffset = match &self.offset_map {
+                Some(offset_map) => offset_map.original_offset(frame.funcidx, frame.codeoffset),
+                None => frame.codeoffset,
+            };
+
             let linkage_name = func_names
                 .get(&frame.funcidx)
                 .unwrap_or(&format!("<unknown-func{}>", frame.funcidx))
@@ -187,12 +216,17 @@ impl CoredumpToStack {
                         line: function.source().line(),
                     };
 
-                    out_frames.push(Frame { name, location })
+                    out_frames.push(Frame {
+                        name,
+                        location,
+                        codeoffset,
+                    })
                 } else {
                     let location = FrameLocation::unknown();
                     out_frames.push(Frame {
                         name: linkage_name,
                         location,
+                        codeoffset,
                     })
                 }
             } else {
@@ -200,6 +234,7 @@ impl CoredumpToStack {
                 out_frames.push(Frame {
                     name: linkage_name,
                     location,
+                    codeoffset,
                 })
             }
         }
@@ -46,10 +46,29 @@ pub struct CoreStack {
     pub frames: Vec<StackFrame>,
 }
 
+/// Value of a global at the time of the coredump, named after the global of
+/// the module if it has a name.
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub name: Option<String>,
+    pub value: Value,
+}
+
 #[derive(Debug, Clone)]
 pub struct Coredump {
     pub process_info: ProcessInfo,
     pub stacks: Vec<CoreStack>,
     pub memory: Vec<(u32, Option<u32>)>,
     pub data: Vec<u8>,
+    pub globals: Vec<Global>,
+}
+
+impl Coredump {
+    /// Find a global by name.
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals
+            .iter()
+            .find(|global| global.name.as_deref() == Some(name))
+            .map(|global| &global.value)
+    }
 }
@@ -2,8 +2,6 @@ use core_wasm_ast as ast;
 use log::{debug, warn};
 use nom::bytes::complete::take;
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::Mutex;
 
 mod coredump;
 #[cfg(test)]
@@ -16,6 +14,15 @@ pub(crate) struct ParserError(String);
 pub const CONTINUATION_BIT: u8 = 1 << 7;
 pub const SIGN_BIT: u8 = 1 << 6;
 
+// Version and layer of the binary, following the magic number.
+const MODULE_VERSION: u16 = 1;
+const MODULE_LAYER: u16 = 0;
+const COMPONENT_VERSION: u16 = 0x0d;
+const COMPONENT_LAYER: u16 = 1;
+
+const COMPONENT_SECTION_CORE_MODULE: u8 = 1;
+const COMPONENT_SECTION_COMPONENT: u8 = 4;
+
 #[inline]
 pub fn low_bits_of_byte(byte: u8) -> u8 {
     byte & !CONTINUATION_BIT
@@ -45,9 +52,9 @@ pub(crate) struct InputContext<'a> {
 }
 
 impl<'a> InputContext<'a> {
-    fn read_u32(self) -> IResult<InputContext<'a>, u32> {
-        let (input, bytes) = self.read_bytes(4usize)?;
-        let value = u32::from_le_bytes(bytes.try_into().unwrap());
+    fn read_u16(self) -> IResult<InputContext<'a>, u16> {
+        let (input, bytes) = self.read_bytes(2usize)?;
+        let value = u16::from_le_bytes(bytes.try_into().unwrap());
         Ok((input, value))
     }
 
@@ -168,15 +175,25 @@ impl<'a> InputContext<'a> {
     }
 }
 
-pub fn parse<'a>(input: &'a [u8]) -> Result<ast::Module, BoxError> {
+pub fn parse(input: &[u8]) -> Result<ast::Module, BoxError> {
+    match parse_binary(input)? {
+        ast::Binary::Module(module) => Ok(module),
+        ast::Binary::Component(_) => {
+            Err("expected a core module, found a component; use parse_binary".into())
+        }
+    }
+}
+
+/// Parse a core module or a component.
+pub fn parse_binary(input: &[u8]) -> Result<ast::Binary, BoxError> {
     let input = InputContext { input, offset: 0 };
-    match decode_module(input) {
-        Ok((_, module)) => Ok(module),
+    match decode_binary(input) {
+        Ok((_, binary)) => Ok(binary),
         Err(err) => Err(format!("failed to decode: {}", err).into()),
     }
 }
 
-pub fn parse_custom_section_name<'a>(input: &'a [u8]) -> Result<ast::DebugNames, BoxError> {
+pub fn parse_custom_section_name(input: &[u8]) -> Result<ast::DebugNames, BoxError> {
     let input = InputContext { input, offset: 0 };
     match decode_section_custom_name(input) {
         Ok((_, names)) => Ok(names),
@@ -184,7 +201,15 @@ pub fn parse_custom_section_name<'a>(input: &'a [u8]) -> Result<ast::DebugNames,
     }
 }
 
-pub fn parse_custom_section_build_id<'a>(input: &'a [u8]) -> Result<Vec<u8>, BoxError> {
+pub fn parse_custom_section_code_offsets(input: &[u8]) -> Result<ast::CodeOffsetMap, BoxError> {
+    let input = InputContext { input, offset: 0 };
+    match decode_section_custom_code_offsets(input) {
+        Ok((_, map)) => Ok(map),
+        Err(err) => Err(format!("failed to decode code_offsets section: {}", err).into()),
+    }
+}
+
+pub fn parse_custom_section_build_id(input: &[u8]) -> Result<Vec<u8>, BoxError> {
     let input = InputContext { input, offset: 0 };
     match decode_section_custom_build_id(input) {
         Ok((_, id)) => Ok(id),
@@ -192,18 +217,68 @@ pub fn parse_custom_section_build_id<'a>(input: &'a [u8]) -> Result<Vec<u8>, Box
     }
 }
 
-fn decode_module<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Module> {
+fn decode_binary<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Binary> {
     let (ctx, magic) = ctx.read_bytes(4)?;
     if magic != b"\0asm" {
         panic!("unsupported header: {:?}", magic)
     }
     assert_eq!(ctx.offset, 4);
-    let (ctx, version) = ctx.read_u32()?;
-    if version != 1 {
-        panic!("unsupported version: {:?}", version)
-    }
+    let (ctx, version) = ctx.read_u16()?;
+    let (ctx, layer) = ctx.read_u16()?;
     assert_eq!(ctx.offset, 8);
 
+    match (layer, version) {
+        (MODULE_LAYER, MODULE_VERSION) => {
+            let (ctx, module) = decode_module(ctx)?;
+            Ok((ctx, ast::Binary::Module(module)))
+        }
+        (COMPONENT_LAYER, COMPONENT_VERSION) => {
+            let (ctx, component) = decode_component(ctx)?;
+            Ok((ctx, ast::Binary::Component(component)))
+        }
+        _ => panic!("unsupported version: {:?} (layer {:?})", version, layer),
+    }
+}
+
+/// Decode the sections of a component. The core modules and the nested
+/// components are decoded, with code offsets relative to their own
+/// binary like when they are compiled at runtime.
+fn decode_component<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Component> {
+    let mut ctx = ctx;
+
+    let mut sections = vec![];
+    while !ctx.input.is_empty() {
+        let (next, id) = ctx.read_u8()?;
+        let (next, size) = next.read_leb128()?;
+        debug!(
+            "decoding component section {} ({} byte(s)) @ {}",
+            id, size, next.offset
+        );
+        let (next, section_bytes) = next.read_bytes(size as usize)?;
+        ctx = next;
+
+        let binary = InputContext {
+            input: section_bytes,
+            offset: 0,
+        };
+        let section = match id {
+            COMPONENT_SECTION_CORE_MODULE => match decode_binary(binary)?.1 {
+                ast::Binary::Module(module) => ast::ComponentSection::CoreModule(module),
+                ast::Binary::Component(_) => panic!("expected a core module, found a component"),
+            },
+            COMPONENT_SECTION_COMPONENT => match decode_binary(binary)?.1 {
+                ast::Binary::Component(component) => ast::ComponentSection::Component(component),
+                ast::Binary::Module(_) => panic!("expected a component, found a core module"),
+            },
+            id => ast::ComponentSection::Unknown((id, section_bytes.to_vec())),
+        };
+        sections.push(section);
+    }
+
+    Ok((ctx, ast::Component { sections }))
+}
+
+fn decode_module<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Module> {
     let mut ctx = ctx;
 
     let mut sections = vec![];
@@ -226,9 +301,7 @@ fn decode_module<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Mo
         }
     }
 
-    let module = ast::Module {
-        sections: Arc::new(Mutex::new(sections)),
-    };
+    let module = ast::Module { sections };
     Ok((ctx, module))
 }
 
@@ -244,6 +317,10 @@ fn decode_section_export<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>,
     decode_vec(ctx, decode_export)
 }
 
+fn decode_section_tag<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, Vec<ast::Tag>> {
+    decode_vec(ctx, decode_tag)
+}
+
 fn decode_section_element<'a>(
     ctx: InputContext<'a>,
 ) -> IResult<InputContext<'a>, Vec<ast::Element>> {
@@ -274,6 +351,10 @@ fn decode_section_custom<'a>(
             let (ctx, content) = decode_section_custom_build_id(ctx)?;
             (ctx, ast::CustomSection::BuildId(content))
         }
+        "code_offsets" => {
+            let (ctx, content) = decode_section_custom_code_offsets(ctx)?;
+            (ctx, ast::CustomSection::CodeOffsets(content))
+        }
         "core" => {
             let (ctx, content) = coredump::decode_process_info(ctx)?;
             (ctx, ast::CustomSection::CoredumpCore(content))
@@ -292,6 +373,25 @@ fn decode_section_custom<'a>(
     })
 }
 
+fn decode_section_custom_code_offsets<'a>(
+    ctx: InputContext<'a>,
+) -> IResult<InputContext<'a>, ast::CodeOffsetMap> {
+    let (ctx, funcs) = decode_vec(ctx, |ctx| {
+        let (ctx, funcidx) = ctx.read_leb128()?;
+        let (ctx, entries) = decode_vec(ctx, |ctx| {
+            let (ctx, rewritten) = ctx.read_leb128()?;
+            let (ctx, original) = ctx.read_leb128()?;
+            Ok((ctx, (rewritten, original)))
+        })?;
+        Ok((ctx, (funcidx, entries)))
+    })?;
+
+    let map = ast::CodeOffsetMap {
+        funcs: funcs.into_iter().collect(),
+    };
+    Ok((ctx, map))
+}
+
 fn decode_section_custom_build_id<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, Vec<u8>> {
     let (ctx, size) = ctx.read_leb128()?;
     let (ctx, bytes) = ctx.read_bytes(size as usize)?;
@@ -331,7 +431,7 @@ fn decode_section_custom_name<'a>(
             1 => {
                 let ret = decode_namemap(ctx)?;
                 ctx = ret.0;
-                func_names = Some(Arc::new(Mutex::new(ret.1)));
+                func_names = Some(ret.1);
             }
             // 2 => {
             //     let ret = ctx.read_leb128()?;
@@ -364,7 +464,7 @@ fn decode_section_custom_name<'a>(
         module,
         func_names,
         func_local_names,
-        global_names: Arc::new(Mutex::new(global_names)),
+        global_names,
     };
     Ok((ctx, debug_names))
 }
@@ -412,6 +512,7 @@ fn decode_reftype<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::R
         match t {
             0x70 => ast::Reftype::Func,
             0x6F => ast::Reftype::Extern,
+            0x69 => ast::Reftype::Exn,
             _ => unimplemented!("unsupported reftype: {}", t),
         },
     ))
@@ -468,6 +569,10 @@ fn decode_import<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Im
             let (ctx, globaltype) = decode_global_type(ctx)?;
             (ctx, ast::ImportType::Global(globaltype))
         }
+        0x04 => {
+            let (ctx, tag) = decode_tag(ctx)?;
+            (ctx, ast::ImportType::Tag(tag))
+        }
         _ => unimplemented!("import description: {:x}", descr_t),
     };
 
@@ -507,7 +612,6 @@ fn decode_code<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Code
 
         let (ctx, locals) = decode_vec(ctx, decode_code_local)?;
         let (_ctx, body) = decode_expr(ctx, ast::Instr::end)?;
-        let body = Arc::new(Mutex::new(body));
 
         // Bytes are split before, no need to propagate this context.
         ast::Code {
@@ -620,27 +724,6 @@ fn decode_instr<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Val
             }
         };
 
-        ($byte:expr, $instr:ident(MutableValue<u32>)) => {
-            if id == $byte {
-                let arg_start_offset = ctx.offset;
-                let (ctx, arg0) = ctx.read_leb128()?;
-                let end_offset = ctx.offset;
-
-                let arg0 = ast::Value {
-                    start_offset: arg_start_offset,
-                    value: arg0,
-                    end_offset,
-                };
-
-                let value = ast::Value {
-                    start_offset,
-                    value: ast::Instr::$instr(Arc::new(Mutex::new(arg0))),
-                    end_offset,
-                };
-                return Ok((ctx, value));
-            }
-        };
-
         ($byte:expr, $instr:ident(u32, u32)) => {
             if id == $byte {
                 let (ctx, arg0) = ctx.read_leb128()?;
@@ -656,23 +739,14 @@ fn decode_instr<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Val
             }
         };
 
-        ($byte:expr, $instr:ident(MutableValue<u32>, u32)) => {
+        ($byte:expr, $instr:ident(memarg)) => {
             if id == $byte {
-                let start_offset = ctx.offset;
-                let (ctx, arg0) = ctx.read_leb128()?;
-                let end_offset = ctx.offset;
-                let arg0 = Arc::new(Mutex::new(ast::Value {
-                    start_offset,
-                    value: arg0,
-                    end_offset,
-                }));
-
-                let (ctx, arg1) = ctx.read_leb128()?;
+                let (ctx, arg0) = decode_memarg(ctx)?;
                 let end_offset = ctx.offset;
 
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::$instr(arg0, arg1),
+                    value: ast::Instr::$instr(arg0),
                     end_offset,
                 };
                 return Ok((ctx, value));
@@ -705,7 +779,7 @@ fn decode_instr<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Val
 
         let value = ast::Value {
             start_offset,
-            value: ast::Instr::Block(block_type, Arc::new(Mutex::new(body))),
+            value: ast::Instr::Block(block_type, body),
             end_offset,
         };
         return Ok((ctx, value));
@@ -717,7 +791,7 @@ fn decode_instr<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Val
 
         let value = ast::Value {
             start_offset,
-            value: ast::Instr::Loop(block_type, Arc::new(Mutex::new(body))),
+            value: ast::Instr::Loop(block_type, body),
             end_offset,
         };
         return Ok((ctx, value));
@@ -731,11 +805,46 @@ fn decode_instr<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Val
 
         let value = ast::Value {
             start_offset,
-            value: ast::Instr::If(block_type, Arc::new(Mutex::new(body))),
+            value: ast::Instr::If(block_type, body),
+            end_offset,
+        };
+        return Ok((ctx, value));
+    }
+
+    // https://github.com/WebAssembly/exception-handling/blob/main/proposals/exception-handling/Exceptions.md
+    if id == 0x06 {
+        let (ctx, block_type) = decode_blocktype(ctx)?;
+        // The body contains the catch clauses and ends with `end` or
+        // `delegate`.
+        let (ctx, body) = decode_expr(ctx, ast::Instr::end)?;
+        let end_offset = ctx.offset;
+
+        let value = ast::Value {
+            start_offset,
+            value: ast::Instr::Try(block_type, body),
+            end_offset,
+        };
+        return Ok((ctx, value));
+    }
+    if id == 0x1f {
+        let (ctx, block_type) = decode_blocktype(ctx)?;
+        let (ctx, catches) = decode_vec(ctx, decode_catch)?;
+        let (ctx, body) = decode_expr(ctx, ast::Instr::end)?;
+        let end_offset = ctx.offset;
+
+        let value = ast::Value {
+            start_offset,
+            value: ast::Instr::TryTable(block_type, catches, body),
             end_offset,
         };
         return Ok((ctx, value));
     }
+    decode_instr!(0x07, catch(u32));
+    decode_instr!(0x08, throw(u32));
+    decode_instr!(0x09, rethrow(u32));
+    decode_instr!(0x0a, throw_ref);
+    decode_instr!(0x18, delegate(u32));
+    decode_instr!(0x19, catch_all);
 
     decode_instr!(0xc, br(u32));
     decode_instr!(0xd, br_if(u32));
@@ -743,7 +852,7 @@ fn decode_instr<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Val
     decode_instr!(0x05, else_end);
     decode_instr!(0x0e, br_table(Vec<u32>, u32));
     decode_instr!(0x0f, Return);
-    decode_instr!(0x10, call(MutableValue<u32>));
+    decode_instr!(0x10, call(u32));
     decode_instr!(0x11, call_indirect(u32, u32));
 
     decode_instr!(0x1a, drop);
@@ -758,33 +867,33 @@ fn decode_instr<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Val
     decode_instr!(0x25, table_get(u32));
     decode_instr!(0x26, table_set(u32));
 
-    decode_instr!(0x28, i32_load(MutableValue<u32>, u32));
-    decode_instr!(0x29, i64_load(MutableValue<u32>, u32));
-    decode_instr!(0x2a, f32_load(MutableValue<u32>, u32));
-    decode_instr!(0x2b, f64_load(MutableValue<u32>, u32));
-    decode_instr!(0x2c, i32_load8_s(MutableValue<u32>, u32));
-    decode_instr!(0x2d, i32_load8_u(MutableValue<u32>, u32));
-    decode_instr!(0x2e, i32_load16_s(MutableValue<u32>, u32));
-    decode_instr!(0x2f, i32_load16_u(MutableValue<u32>, u32));
-    decode_instr!(0x30, i64_load8_s(MutableValue<u32>, u32));
-    decode_instr!(0x31, i64_load8_u(MutableValue<u32>, u32));
-    decode_instr!(0x32, i64_load16_s(MutableValue<u32>, u32));
-    decode_instr!(0x33, i64_load16_u(MutableValue<u32>, u32));
-    decode_instr!(0x34, i64_load32_s(MutableValue<u32>, u32));
-    decode_instr!(0x35, i64_load32_u(MutableValue<u32>, u32));
-
-    decode_instr!(0x36, i32_store(MutableValue<u32>, u32));
-    decode_instr!(0x37, i64_store(MutableValue<u32>, u32));
-    decode_instr!(0x38, f32_store(MutableValue<u32>, u32));
-    decode_instr!(0x39, f64_store(MutableValue<u32>, u32));
-    decode_instr!(0x3a, i32_store8(MutableValue<u32>, u32));
-    decode_instr!(0x3b, i32_store16(MutableValue<u32>, u32));
-    decode_instr!(0x3c, i64_store8(MutableValue<u32>, u32));
-    decode_instr!(0x3d, i64_store16(MutableValue<u32>, u32));
-    decode_instr!(0x3e, i64_store32(MutableValue<u32>, u32));
-
-    decode_instr!(0x3f, memory_size(u8));
-    decode_instr!(0x40, memory_grow(u8));
+    decode_instr!(0x28, i32_load(memarg));
+    decode_instr!(0x29, i64_load(memarg));
+    decode_instr!(0x2a, f32_load(memarg));
+    decode_instr!(0x2b, f64_load(memarg));
+    decode_instr!(0x2c, i32_load8_s(memarg));
+    decode_instr!(0x2d, i32_load8_u(memarg));
+    decode_instr!(0x2e, i32_load16_s(memarg));
+    decode_instr!(0x2f, i32_load16_u(memarg));
+    decode_instr!(0x30, i64_load8_s(memarg));
+    decode_instr!(0x31, i64_load8_u(memarg));
+    decode_instr!(0x32, i64_load16_s(memarg));
+    decode_instr!(0x33, i64_load16_u(memarg));
+    decode_instr!(0x34, i64_load32_s(memarg));
+    decode_instr!(0x35, i64_load32_u(memarg));
+
+    decode_instr!(0x36, i32_store(memarg));
+    decode_instr!(0x37, i64_store(memarg));
+    decode_instr!(0x38, f32_store(memarg));
+    decode_instr!(0x39, f64_store(memarg));
+    decode_instr!(0x3a, i32_store8(memarg));
+    decode_instr!(0x3b, i32_store16(memarg));
+    decode_instr!(0x3c, i64_store8(memarg));
+    decode_instr!(0x3d, i64_store16(memarg));
+    decode_instr!(0x3e, i64_store32(memarg));
+
+    decode_instr!(0x3f, memory_size(u32));
+    decode_instr!(0x40, memory_grow(u32));
 
     decode_instr!(0x41, i32_const(i32));
     decode_instr!(0x42, i64_const(i64));
@@ -930,105 +1039,548 @@ fn decode_instr<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Val
     decode_instr!(0xc3, i64_extend16_s);
     decode_instr!(0xc4, i64_extend32_s);
 
-    if id == 0xfc {
-        let (ctx, b) = ctx.read_u8()?;
+    if id == 0xd0 {
+        let (ctx, reftype) = decode_reftype(ctx)?;
         let end_offset = ctx.offset;
 
-        match b {
-            // https://webassembly.github.io/spec/core/binary/instructions.html#numeric-instructions
-            // saturating truncation instructions
-            0 => {
+        let value = ast::Value {
+            start_offset,
+            value: ast::Instr::ref_null(reftype),
+            end_offset,
+        };
+        return Ok((ctx, value));
+    }
+    decode_instr!(0xd1, ref_is_null);
+    decode_instr!(0xd2, ref_func(u32));
+
+    if matches!(id, 0xfc..=0xfe) {
+        return decode_prefixed_instr(ctx, start_offset, id);
+    }
+
+    unimplemented!("unknown instruction: {:#x}", id);
+}
+
+/// Decode the instructions starting with a prefix byte followed by the
+/// operation:
+/// - 0xfc: saturating truncation, bulk memory and table instructions
+///   https://webassembly.github.io/spec/core/binary/instructions.html
+/// - 0xfd: SIMD, relaxed SIMD included
+///   https://github.com/WebAssembly/simd/blob/main/proposals/simd/BinarySIMD.md
+/// - 0xfe: threads
+///   https://github.com/WebAssembly/threads/blob/main/proposals/threads/Overview.md
+fn decode_prefixed_instr<'a>(
+    ctx: InputContext<'a>,
+    start_offset: usize,
+    prefix: u8,
+) -> IResult<InputContext<'a>, ast::Value<ast::Instr>> {
+    let (ctx, op) = ctx.read_leb128()?;
+
+    macro_rules! decode_op {
+        ($prefix:expr, $op:expr, $instr:ident) => {
+            if prefix == $prefix && op == $op {
+                let end_offset = ctx.offset;
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::i32_trunc_sat_f32_s,
+                    value: ast::Instr::$instr,
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            1 => {
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(fence)) => {
+            if prefix == $prefix && op == $op {
+                // reserved byte
+                let (ctx, _) = ctx.read_u8()?;
+                let end_offset = ctx.offset;
+
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::i32_trunc_sat_f32_u,
+                    value: ast::Instr::$instr,
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            2 => {
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(u8)) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, arg0) = ctx.read_u8()?;
+                let end_offset = ctx.offset;
+
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::i32_trunc_sat_f64_s,
+                    value: ast::Instr::$instr(arg0),
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            3 => {
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(u8, u8)) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, arg0) = ctx.read_u8()?;
+                let (ctx, arg1) = ctx.read_u8()?;
+                let end_offset = ctx.offset;
+
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::i32_trunc_sat_f64_u,
+                    value: ast::Instr::$instr(arg0, arg1),
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            4 => {
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(u32)) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, arg0) = ctx.read_leb128()?;
+                let end_offset = ctx.offset;
+
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::i64_trunc_sat_f32_s,
+                    value: ast::Instr::$instr(arg0),
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            5 => {
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(u32, u8)) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, arg0) = ctx.read_leb128()?;
+                let (ctx, arg1) = ctx.read_u8()?;
+                let end_offset = ctx.offset;
+
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::i64_trunc_sat_f32_u,
+                    value: ast::Instr::$instr(arg0, arg1),
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            6 => {
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(u32, u32)) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, arg0) = ctx.read_leb128()?;
+                let (ctx, arg1) = ctx.read_leb128()?;
+                let end_offset = ctx.offset;
+
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::i64_trunc_sat_f64_s,
+                    value: ast::Instr::$instr(arg0, arg1),
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            7 => {
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(memarg)) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, arg0) = decode_memarg(ctx)?;
+                let end_offset = ctx.offset;
+
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::i64_trunc_sat_f64_u,
+                    value: ast::Instr::$instr(arg0),
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(memarg, u8)) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, arg0) = decode_memarg(ctx)?;
+                let (ctx, arg1) = ctx.read_u8()?;
+                let end_offset = ctx.offset;
 
-            // other instructions
-            10 => {
-                let (ctx, imm0) = ctx.read_u8()?;
-                let (ctx, imm1) = ctx.read_u8()?;
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::memory_copy(imm0, imm1),
+                    value: ast::Instr::$instr(arg0, arg1),
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            11 => {
-                let (ctx, imm0) = ctx.read_u8()?;
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident(u128)) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, bytes) = ctx.read_bytes(16)?;
+                let arg0 = u128::from_le_bytes(bytes.try_into().unwrap());
+                let end_offset = ctx.offset;
+
                 let value = ast::Value {
                     start_offset,
-                    value: ast::Instr::memory_fill(imm0),
+                    value: ast::Instr::$instr(arg0),
                     end_offset,
                 };
                 return Ok((ctx, value));
             }
-            b => {
-                unimplemented!("unknown 0xfc operation {}", b)
+        };
+
+        ($prefix:expr, $op:expr, $instr:ident([u8; 16])) => {
+            if prefix == $prefix && op == $op {
+                let (ctx, bytes) = ctx.read_bytes(16)?;
+                let arg0: [u8; 16] = bytes.try_into().unwrap();
+                let end_offset = ctx.offset;
+
+                let value = ast::Value {
+                    start_offset,
+                    value: ast::Instr::$instr(arg0),
+                    end_offset,
+                };
+                return Ok((ctx, value));
             }
-        }
+        };
     }
 
-    unimplemented!("unknown instruction: {:#x}", id);
+    decode_op!(0xfc, 0x00, i32_trunc_sat_f32_s);
+    decode_op!(0xfc, 0x01, i32_trunc_sat_f32_u);
+    decode_op!(0xfc, 0x02, i32_trunc_sat_f64_s);
+    decode_op!(0xfc, 0x03, i32_trunc_sat_f64_u);
+    decode_op!(0xfc, 0x04, i64_trunc_sat_f32_s);
+    decode_op!(0xfc, 0x05, i64_trunc_sat_f32_u);
+    decode_op!(0xfc, 0x06, i64_trunc_sat_f64_s);
+    decode_op!(0xfc, 0x07, i64_trunc_sat_f64_u);
+    decode_op!(0xfc, 0x08, memory_init(u32, u8));
+    decode_op!(0xfc, 0x09, data_drop(u32));
+    decode_op!(0xfc, 0x0a, memory_copy(u32, u32));
+    decode_op!(0xfc, 0x0b, memory_fill(u32));
+    decode_op!(0xfc, 0x0c, table_init(u32, u32));
+    decode_op!(0xfc, 0x0d, elem_drop(u32));
+    decode_op!(0xfc, 0x0e, table_copy(u32, u32));
+    decode_op!(0xfc, 0x0f, table_grow(u32));
+    decode_op!(0xfc, 0x10, table_size(u32));
+    decode_op!(0xfc, 0x11, table_fill(u32));
+
+    decode_op!(0xfd, 0x00, v128_load(memarg));
+    decode_op!(0xfd, 0x01, v128_load8x8_s(memarg));
+    decode_op!(0xfd, 0x02, v128_load8x8_u(memarg));
+    decode_op!(0xfd, 0x03, v128_load16x4_s(memarg));
+    decode_op!(0xfd, 0x04, v128_load16x4_u(memarg));
+    decode_op!(0xfd, 0x05, v128_load32x2_s(memarg));
+    decode_op!(0xfd, 0x06, v128_load32x2_u(memarg));
+    decode_op!(0xfd, 0x07, v128_load8_splat(memarg));
+    decode_op!(0xfd, 0x08, v128_load16_splat(memarg));
+    decode_op!(0xfd, 0x09, v128_load32_splat(memarg));
+    decode_op!(0xfd, 0x0a, v128_load64_splat(memarg));
+    decode_op!(0xfd, 0x0b, v128_store(memarg));
+    decode_op!(0xfd, 0x0c, v128_const(u128));
+    decode_op!(0xfd, 0x0d, i8x16_shuffle([u8; 16]));
+    decode_op!(0xfd, 0x0e, i8x16_swizzle);
+    decode_op!(0xfd, 0x0f, i8x16_splat);
+    decode_op!(0xfd, 0x10, i16x8_splat);
+    decode_op!(0xfd, 0x11, i32x4_splat);
+    decode_op!(0xfd, 0x12, i64x2_splat);
+    decode_op!(0xfd, 0x13, f32x4_splat);
+    decode_op!(0xfd, 0x14, f64x2_splat);
+    decode_op!(0xfd, 0x15, i8x16_extract_lane_s(u8));
+    decode_op!(0xfd, 0x16, i8x16_extract_lane_u(u8));
+    decode_op!(0xfd, 0x17, i8x16_replace_lane(u8));
+    decode_op!(0xfd, 0x18, i16x8_extract_lane_s(u8));
+    decode_op!(0xfd, 0x19, i16x8_extract_lane_u(u8));
+    decode_op!(0xfd, 0x1a, i16x8_replace_lane(u8));
+    decode_op!(0xfd, 0x1b, i32x4_extract_lane(u8));
+    decode_op!(0xfd, 0x1c, i32x4_replace_lane(u8));
+    decode_op!(0xfd, 0x1d, i64x2_extract_lane(u8));
+    decode_op!(0xfd, 0x1e, i64x2_replace_lane(u8));
+    decode_op!(0xfd, 0x1f, f32x4_extract_lane(u8));
+    decode_op!(0xfd, 0x20, f32x4_replace_lane(u8));
+    decode_op!(0xfd, 0x21, f64x2_extract_lane(u8));
+    decode_op!(0xfd, 0x22, f64x2_replace_lane(u8));
+    decode_op!(0xfd, 0x23, i8x16_eq);
+    decode_op!(0xfd, 0x24, i8x16_ne);
+    decode_op!(0xfd, 0x25, i8x16_lt_s);
+    decode_op!(0xfd, 0x26, i8x16_lt_u);
+    decode_op!(0xfd, 0x27, i8x16_gt_s);
+    decode_op!(0xfd, 0x28, i8x16_gt_u);
+    decode_op!(0xfd, 0x29, i8x16_le_s);
+    decode_op!(0xfd, 0x2a, i8x16_le_u);
+    decode_op!(0xfd, 0x2b, i8x16_ge_s);
+    decode_op!(0xfd, 0x2c, i8x16_ge_u);
+    decode_op!(0xfd, 0x2d, i16x8_eq);
+    decode_op!(0xfd, 0x2e, i16x8_ne);
+    decode_op!(0xfd, 0x2f, i16x8_lt_s);
+    decode_op!(0xfd, 0x30, i16x8_lt_u);
+    decode_op!(0xfd, 0x31, i16x8_gt_s);
+    decode_op!(0xfd, 0x32, i16x8_gt_u);
+    decode_op!(0xfd, 0x33, i16x8_le_s);
+    decode_op!(0xfd, 0x34, i16x8_le_u);
+    decode_op!(0xfd, 0x35, i16x8_ge_s);
+    decode_op!(0xfd, 0x36, i16x8_ge_u);
+    decode_op!(0xfd, 0x37, i32x4_eq);
+    decode_op!(0xfd, 0x38, i32x4_ne);
+    decode_op!(0xfd, 0x39, i32x4_lt_s);
+    decode_op!(0xfd, 0x3a, i32x4_lt_u);
+    decode_op!(0xfd, 0x3b, i32x4_gt_s);
+    decode_op!(0xfd, 0x3c, i32x4_gt_u);
+    decode_op!(0xfd, 0x3d, i32x4_le_s);
+    decode_op!(0xfd, 0x3e, i32x4_le_u);
+    decode_op!(0xfd, 0x3f, i32x4_ge_s);
+    decode_op!(0xfd, 0x40, i32x4_ge_u);
+    decode_op!(0xfd, 0x41, f32x4_eq);
+    decode_op!(0xfd, 0x42, f32x4_ne);
+    decode_op!(0xfd, 0x43, f32x4_lt);
+    decode_op!(0xfd, 0x44, f32x4_gt);
+    decode_op!(0xfd, 0x45, f32x4_le);
+    decode_op!(0xfd, 0x46, f32x4_ge);
+    decode_op!(0xfd, 0x47, f64x2_eq);
+    decode_op!(0xfd, 0x48, f64x2_ne);
+    decode_op!(0xfd, 0x49, f64x2_lt);
+    decode_op!(0xfd, 0x4a, f64x2_gt);
+    decode_op!(0xfd, 0x4b, f64x2_le);
+    decode_op!(0xfd, 0x4c, f64x2_ge);
+    decode_op!(0xfd, 0x4d, v128_not);
+    decode_op!(0xfd, 0x4e, v128_and);
+    decode_op!(0xfd, 0x4f, v128_andnot);
+    decode_op!(0xfd, 0x50, v128_or);
+    decode_op!(0xfd, 0x51, v128_xor);
+    decode_op!(0xfd, 0x52, v128_bitselect);
+    decode_op!(0xfd, 0x53, v128_any_true);
+    decode_op!(0xfd, 0x54, v128_load8_lane(memarg, u8));
+    decode_op!(0xfd, 0x55, v128_load16_lane(memarg, u8));
+    decode_op!(0xfd, 0x56, v128_load32_lane(memarg, u8));
+    decode_op!(0xfd, 0x57, v128_load64_lane(memarg, u8));
+    decode_op!(0xfd, 0x58, v128_store8_lane(memarg, u8));
+    decode_op!(0xfd, 0x59, v128_store16_lane(memarg, u8));
+    decode_op!(0xfd, 0x5a, v128_store32_lane(memarg, u8));
+    decode_op!(0xfd, 0x5b, v128_store64_lane(memarg, u8));
+    decode_op!(0xfd, 0x5c, v128_load32_zero(memarg));
+    decode_op!(0xfd, 0x5d, v128_load64_zero(memarg));
+    decode_op!(0xfd, 0x5e, f32x4_demote_f64x2_zero);
+    decode_op!(0xfd, 0x5f, f64x2_promote_low_f32x4);
+    decode_op!(0xfd, 0x60, i8x16_abs);
+    decode_op!(0xfd, 0x61, i8x16_neg);
+    decode_op!(0xfd, 0x62, i8x16_popcnt);
+    decode_op!(0xfd, 0x63, i8x16_all_true);
+    decode_op!(0xfd, 0x64, i8x16_bitmask);
+    decode_op!(0xfd, 0x65, i8x16_narrow_i16x8_s);
+    decode_op!(0xfd, 0x66, i8x16_narrow_i16x8_u);
+    decode_op!(0xfd, 0x67, f32x4_ceil);
+    decode_op!(0xfd, 0x68, f32x4_floor);
+    decode_op!(0xfd, 0x69, f32x4_trunc);
+    decode_op!(0xfd, 0x6a, f32x4_nearest);
+    decode_op!(0xfd, 0x6b, i8x16_shl);
+    decode_op!(0xfd, 0x6c, i8x16_shr_s);
+    decode_op!(0xfd, 0x6d, i8x16_shr_u);
+    decode_op!(0xfd, 0x6e, i8x16_add);
+    decode_op!(0xfd, 0x6f, i8x16_add_sat_s);
+    decode_op!(0xfd, 0x70, i8x16_add_sat_u);
+    decode_op!(0xfd, 0x71, i8x16_sub);
+    decode_op!(0xfd, 0x72, i8x16_sub_sat_s);
+    decode_op!(0xfd, 0x73, i8x16_sub_sat_u);
+    decode_op!(0xfd, 0x74, f64x2_ceil);
+    decode_op!(0xfd, 0x75, f64x2_floor);
+    decode_op!(0xfd, 0x76, i8x16_min_s);
+    decode_op!(0xfd, 0x77, i8x16_min_u);
+    decode_op!(0xfd, 0x78, i8x16_max_s);
+    decode_op!(0xfd, 0x79, i8x16_max_u);
+    decode_op!(0xfd, 0x7a, f64x2_trunc);
+    decode_op!(0xfd, 0x7b, i8x16_avgr_u);
+    decode_op!(0xfd, 0x7c, i16x8_extadd_pairwise_i8x16_s);
+    decode_op!(0xfd, 0x7d, i16x8_extadd_pairwise_i8x16_u);
+    decode_op!(0xfd, 0x7e, i32x4_extadd_pairwise_i16x8_s);
+    decode_op!(0xfd, 0x7f, i32x4_extadd_pairwise_i16x8_u);
+    decode_op!(0xfd, 0x80, i16x8_abs);
+    decode_op!(0xfd, 0x81, i16x8_neg);
+    decode_op!(0xfd, 0x82, i16x8_q15mulr_sat_s);
+    decode_op!(0xfd, 0x83, i16x8_all_true);
+    decode_op!(0xfd, 0x84, i16x8_bitmask);
+    decode_op!(0xfd, 0x85, i16x8_narrow_i32x4_s);
+    decode_op!(0xfd, 0x86, i16x8_narrow_i32x4_u);
+    decode_op!(0xfd, 0x87, i16x8_extend_low_i8x16_s);
+    decode_op!(0xfd, 0x88, i16x8_extend_high_i8x16_s);
+    decode_op!(0xfd, 0x89, i16x8_extend_low_i8x16_u);
+    decode_op!(0xfd, 0x8a, i16x8_extend_high_i8x16_u);
+    decode_op!(0xfd, 0x8b, i16x8_shl);
+    decode_op!(0xfd, 0x8c, i16x8_shr_s);
+    decode_op!(0xfd, 0x8d, i16x8_shr_u);
+    decode_op!(0xfd, 0x8e, i16x8_add);
+    decode_op!(0xfd, 0x8f, i16x8_add_sat_s);
+    decode_op!(0xfd, 0x90, i16x8_add_sat_u);
+    decode_op!(0xfd, 0x91, i16x8_sub);
+    decode_op!(0xfd, 0x92, i16x8_sub_sat_s);
+    decode_op!(0xfd, 0x93, i16x8_sub_sat_u);
+    decode_op!(0xfd, 0x94, f64x2_nearest);
+    decode_op!(0xfd, 0x95, i16x8_mul);
+    decode_op!(0xfd, 0x96, i16x8_min_s);
+    decode_op!(0xfd, 0x97, i16x8_min_u);
+    decode_op!(0xfd, 0x98, i16x8_max_s);
+    decode_op!(0xfd, 0x99, i16x8_max_u);
+    decode_op!(0xfd, 0x9b, i16x8_avgr_u);
+    decode_op!(0xfd, 0x9c, i16x8_extmul_low_i8x16_s);
+    decode_op!(0xfd, 0x9d, i16x8_extmul_high_i8x16_s);
+    decode_op!(0xfd, 0x9e, i16x8_extmul_low_i8x16_u);
+    decode_op!(0xfd, 0x9f, i16x8_extmul_high_i8x16_u);
+    decode_op!(0xfd, 0xa0, i32x4_abs);
+    decode_op!(0xfd, 0xa1, i32x4_neg);
+    decode_op!(0xfd, 0xa3, i32x4_all_true);
+    decode_op!(0xfd, 0xa4, i32x4_bitmask);
+    decode_op!(0xfd, 0xa7, i32x4_extend_low_i16x8_s);
+    decode_op!(0xfd, 0xa8, i32x4_extend_high_i16x8_s);
+    decode_op!(0xfd, 0xa9, i32x4_extend_low_i16x8_u);
+    decode_op!(0xfd, 0xaa, i32x4_extend_high_i16x8_u);
+    decode_op!(0xfd, 0xab, i32x4_shl);
+    decode_op!(0xfd, 0xac, i32x4_shr_s);
+    decode_op!(0xfd, 0xad, i32x4_shr_u);
+    decode_op!(0xfd, 0xae, i32x4_add);
+    decode_op!(0xfd, 0xb1, i32x4_sub);
+    decode_op!(0xfd, 0xb5, i32x4_mul);
+    decode_op!(0xfd, 0xb6, i32x4_min_s);
+    decode_op!(0xfd, 0xb7, i32x4_min_u);
+    decode_op!(0xfd, 0xb8, i32x4_max_s);
+    decode_op!(0xfd, 0xb9, i32x4_max_u);
+    decode_op!(0xfd, 0xba, i32x4_dot_i16x8_s);
+    decode_op!(0xfd, 0xbc, i32x4_extmul_low_i16x8_s);
+    decode_op!(0xfd, 0xbd, i32x4_extmul_high_i16x8_s);
+    decode_op!(0xfd, 0xbe, i32x4_extmul_low_i16x8_u);
+    decode_op!(0xfd, 0xbf, i32x4_extmul_high_i16x8_u);
+    decode_op!(0xfd, 0xc0, i64x2_abs);
+    decode_op!(0xfd, 0xc1, i64x2_neg);
+    decode_op!(0xfd, 0xc3, i64x2_all_true);
+    decode_op!(0xfd, 0xc4, i64x2_bitmask);
+    decode_op!(0xfd, 0xc7, i64x2_extend_low_i32x4_s);
+    decode_op!(0xfd, 0xc8, i64x2_extend_high_i32x4_s);
+    decode_op!(0xfd, 0xc9, i64x2_extend_low_i32x4_u);
+    decode_op!(0xfd, 0xca, i64x2_extend_high_i32x4_u);
+    decode_op!(0xfd, 0xcb, i64x2_shl);
+    decode_op!(0xfd, 0xcc, i64x2_shr_s);
+    decode_op!(0xfd, 0xcd, i64x2_shr_u);
+    decode_op!(0xfd, 0xce, i64x2_add);
+    decode_op!(0xfd, 0xd1, i64x2_sub);
+    decode_op!(0xfd, 0xd5, i64x2_mul);
+    decode_op!(0xfd, 0xd6, i64x2_eq);
+    decode_op!(0xfd, 0xd7, i64x2_ne);
+    decode_op!(0xfd, 0xd8, i64x2_lt_s);
+    decode_op!(0xfd, 0xd9, i64x2_gt_s);
+    decode_op!(0xfd, 0xda, i64x2_le_s);
+    decode_op!(0xfd, 0xdb, i64x2_ge_s);
+    decode_op!(0xfd, 0xdc, i64x2_extmul_low_i32x4_s);
+    decode_op!(0xfd, 0xdd, i64x2_extmul_high_i32x4_s);
+    decode_op!(0xfd, 0xde, i64x2_extmul_low_i32x4_u);
+    decode_op!(0xfd, 0xdf, i64x2_extmul_high_i32x4_u);
+    decode_op!(0xfd, 0xe0, f32x4_abs);
+    decode_op!(0xfd, 0xe1, f32x4_neg);
+    decode_op!(0xfd, 0xe3, f32x4_sqrt);
+    decode_op!(0xfd, 0xe4, f32x4_add);
+    decode_op!(0xfd, 0xe5, f32x4_sub);
+    decode_op!(0xfd, 0xe6, f32x4_mul);
+    decode_op!(0xfd, 0xe7, f32x4_div);
+    decode_op!(0xfd, 0xe8, f32x4_min);
+    decode_op!(0xfd, 0xe9, f32x4_max);
+    decode_op!(0xfd, 0xea, f32x4_pmin);
+    decode_op!(0xfd, 0xeb, f32x4_pmax);
+    decode_op!(0xfd, 0xec, f64x2_abs);
+    decode_op!(0xfd, 0xed, f64x2_neg);
+    decode_op!(0xfd, 0xef, f64x2_sqrt);
+    decode_op!(0xfd, 0xf0, f64x2_add);
+    decode_op!(0xfd, 0xf1, f64x2_sub);
+    decode_op!(0xfd, 0xf2, f64x2_mul);
+    decode_op!(0xfd, 0xf3, f64x2_div);
+    decode_op!(0xfd, 0xf4, f64x2_min);
+    decode_op!(0xfd, 0xf5, f64x2_max);
+    decode_op!(0xfd, 0xf6, f64x2_pmin);
+    decode_op!(0xfd, 0xf7, f64x2_pmax);
+    decode_op!(0xfd, 0xf8, i32x4_trunc_sat_f32x4_s);
+    decode_op!(0xfd, 0xf9, i32x4_trunc_sat_f32x4_u);
+    decode_op!(0xfd, 0xfa, f32x4_convert_i32x4_s);
+    decode_op!(0xfd, 0xfb, f32x4_convert_i32x4_u);
+    decode_op!(0xfd, 0xfc, i32x4_trunc_sat_f64x2_s_zero);
+    decode_op!(0xfd, 0xfd, i32x4_trunc_sat_f64x2_u_zero);
+    decode_op!(0xfd, 0xfe, f64x2_convert_low_i32x4_s);
+    decode_op!(0xfd, 0xff, f64x2_convert_low_i32x4_u);
+    decode_op!(0xfd, 0x100, i8x16_relaxed_swizzle);
+    decode_op!(0xfd, 0x101, i32x4_relaxed_trunc_f32x4_s);
+    decode_op!(0xfd, 0x102, i32x4_relaxed_trunc_f32x4_u);
+    decode_op!(0xfd, 0x103, i32x4_relaxed_trunc_f64x2_s_zero);
+    decode_op!(0xfd, 0x104, i32x4_relaxed_trunc_f64x2_u_zero);
+    decode_op!(0xfd, 0x105, f32x4_relaxed_madd);
+    decode_op!(0xfd, 0x106, f32x4_relaxed_nmadd);
+    decode_op!(0xfd, 0x107, f64x2_relaxed_madd);
+    decode_op!(0xfd, 0x108, f64x2_relaxed_nmadd);
+    decode_op!(0xfd, 0x109, i8x16_relaxed_laneselect);
+    decode_op!(0xfd, 0x10a, i16x8_relaxed_laneselect);
+    decode_op!(0xfd, 0x10b, i32x4_relaxed_laneselect);
+    decode_op!(0xfd, 0x10c, i64x2_relaxed_laneselect);
+    decode_op!(0xfd, 0x10d, f32x4_relaxed_min);
+    decode_op!(0xfd, 0x10e, f32x4_relaxed_max);
+    decode_op!(0xfd, 0x10f, f64x2_relaxed_min);
+    decode_op!(0xfd, 0x110, f64x2_relaxed_max);
+    decode_op!(0xfd, 0x111, i16x8_relaxed_q15mulr_s);
+    decode_op!(0xfd, 0x112, i16x8_relaxed_dot_i8x16_i7x16_s);
+    decode_op!(0xfd, 0x113, i32x4_relaxed_dot_i8x16_i7x16_add_s);
+
+    decode_op!(0xfe, 0x00, memory_atomic_notify(memarg));
+    decode_op!(0xfe, 0x01, memory_atomic_wait32(memarg));
+    decode_op!(0xfe, 0x02, memory_atomic_wait64(memarg));
+    decode_op!(0xfe, 0x03, atomic_fence(fence));
+    decode_op!(0xfe, 0x10, i32_atomic_load(memarg));
+    decode_op!(0xfe, 0x11, i64_atomic_load(memarg));
+    decode_op!(0xfe, 0x12, i32_atomic_load8_u(memarg));
+    decode_op!(0xfe, 0x13, i32_atomic_load16_u(memarg));
+    decode_op!(0xfe, 0x14, i64_atomic_load8_u(memarg));
+    decode_op!(0xfe, 0x15, i64_atomic_load16_u(memarg));
+    decode_op!(0xfe, 0x16, i64_atomic_load32_u(memarg));
+    decode_op!(0xfe, 0x17, i32_atomic_store(memarg));
+    decode_op!(0xfe, 0x18, i64_atomic_store(memarg));
+    decode_op!(0xfe, 0x19, i32_atomic_store8(memarg));
+    decode_op!(0xfe, 0x1a, i32_atomic_store16(memarg));
+    decode_op!(0xfe, 0x1b, i64_atomic_store8(memarg));
+    decode_op!(0xfe, 0x1c, i64_atomic_store16(memarg));
+    decode_op!(0xfe, 0x1d, i64_atomic_store32(memarg));
+    decode_op!(0xfe, 0x1e, i32_atomic_rmw_add(memarg));
+    decode_op!(0xfe, 0x1f, i64_atomic_rmw_add(memarg));
+    decode_op!(0xfe, 0x20, i32_atomic_rmw8_add_u(memarg));
+    decode_op!(0xfe, 0x21, i32_atomic_rmw16_add_u(memarg));
+    decode_op!(0xfe, 0x22, i64_atomic_rmw8_add_u(memarg));
+    decode_op!(0xfe, 0x23, i64_atomic_rmw16_add_u(memarg));
+    decode_op!(0xfe, 0x24, i64_atomic_rmw32_add_u(memarg));
+    decode_op!(0xfe, 0x25, i32_atomic_rmw_sub(memarg));
+    decode_op!(0xfe, 0x26, i64_atomic_rmw_sub(memarg));
+    decode_op!(0xfe, 0x27, i32_atomic_rmw8_sub_u(memarg));
+    decode_op!(0xfe, 0x28, i32_atomic_rmw16_sub_u(memarg));
+    decode_op!(0xfe, 0x29, i64_atomic_rmw8_sub_u(memarg));
+    decode_op!(0xfe, 0x2a, i64_atomic_rmw16_sub_u(memarg));
+    decode_op!(0xfe, 0x2b, i64_atomic_rmw32_sub_u(memarg));
+    decode_op!(0xfe, 0x2c, i32_atomic_rmw_and(memarg));
+    decode_op!(0xfe, 0x2d, i64_atomic_rmw_and(memarg));
+    decode_op!(0xfe, 0x2e, i32_atomic_rmw8_and_u(memarg));
+    decode_op!(0xfe, 0x2f, i32_atomic_rmw16_and_u(memarg));
+    decode_op!(0xfe, 0x30, i64_atomic_rmw8_and_u(memarg));
+    decode_op!(0xfe, 0x31, i64_atomic_rmw16_and_u(memarg));
+    decode_op!(0xfe, 0x32, i64_atomic_rmw32_and_u(memarg));
+    decode_op!(0xfe, 0x33, i32_atomic_rmw_or(memarg));
+    decode_op!(0xfe, 0x34, i64_atomic_rmw_or(memarg));
+    decode_op!(0xfe, 0x35, i32_atomic_rmw8_or_u(memarg));
+    decode_op!(0xfe, 0x36, i32_atomic_rmw16_or_u(memarg));
+    decode_op!(0xfe, 0x37, i64_atomic_rmw8_or_u(memarg));
+    decode_op!(0xfe, 0x38, i64_atomic_rmw16_or_u(memarg));
+    decode_op!(0xfe, 0x39, i64_atomic_rmw32_or_u(memarg));
+    decode_op!(0xfe, 0x3a, i32_atomic_rmw_xor(memarg));
+    decode_op!(0xfe, 0x3b, i64_atomic_rmw_xor(memarg));
+    decode_op!(0xfe, 0x3c, i32_atomic_rmw8_xor_u(memarg));
+    decode_op!(0xfe, 0x3d, i32_atomic_rmw16_xor_u(memarg));
+    decode_op!(0xfe, 0x3e, i64_atomic_rmw8_xor_u(memarg));
+    decode_op!(0xfe, 0x3f, i64_atomic_rmw16_xor_u(memarg));
+    decode_op!(0xfe, 0x40, i64_atomic_rmw32_xor_u(memarg));
+    decode_op!(0xfe, 0x41, i32_atomic_rmw_xchg(memarg));
+    decode_op!(0xfe, 0x42, i64_atomic_rmw_xchg(memarg));
+    decode_op!(0xfe, 0x43, i32_atomic_rmw8_xchg_u(memarg));
+    decode_op!(0xfe, 0x44, i32_atomic_rmw16_xchg_u(memarg));
+    decode_op!(0xfe, 0x45, i64_atomic_rmw8_xchg_u(memarg));
+    decode_op!(0xfe, 0x46, i64_atomic_rmw16_xchg_u(memarg));
+    decode_op!(0xfe, 0x47, i64_atomic_rmw32_xchg_u(memarg));
+    decode_op!(0xfe, 0x48, i32_atomic_rmw_cmpxchg(memarg));
+    decode_op!(0xfe, 0x49, i64_atomic_rmw_cmpxchg(memarg));
+    decode_op!(0xfe, 0x4a, i32_atomic_rmw8_cmpxchg_u(memarg));
+    decode_op!(0xfe, 0x4b, i32_atomic_rmw16_cmpxchg_u(memarg));
+    decode_op!(0xfe, 0x4c, i64_atomic_rmw8_cmpxchg_u(memarg));
+    decode_op!(0xfe, 0x4d, i64_atomic_rmw16_cmpxchg_u(memarg));
+    decode_op!(0xfe, 0x4e, i64_atomic_rmw32_cmpxchg_u(memarg));
+
+    unimplemented!("unknown {:#x} operation {:#x}", prefix, op);
 }
 
 fn decode_expr<'a>(
@@ -1043,7 +1595,7 @@ fn decode_expr<'a>(
         let ret = decode_instr(ctx)?;
         ctx = ret.0;
         vec.push(ret.1.clone());
-        if matches!(ret.1.value, ast::Instr::end) {
+        if matches!(ret.1.value, ast::Instr::end | ast::Instr::delegate(_)) {
             break;
         }
     }
@@ -1057,6 +1609,49 @@ fn decode_expr<'a>(
     Ok((ctx, value))
 }
 
+fn decode_catch<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Catch> {
+    let (ctx, kind) = ctx.read_u8()?;
+    Ok(match kind {
+        0x00 => {
+            let (ctx, tagidx) = ctx.read_leb128()?;
+            let (ctx, labelidx) = ctx.read_leb128()?;
+            (ctx, ast::Catch::Catch(tagidx, labelidx))
+        }
+        0x01 => {
+            let (ctx, tagidx) = ctx.read_leb128()?;
+            let (ctx, labelidx) = ctx.read_leb128()?;
+            (ctx, ast::Catch::CatchRef(tagidx, labelidx))
+        }
+        0x02 => {
+            let (ctx, labelidx) = ctx.read_leb128()?;
+            (ctx, ast::Catch::CatchAll(labelidx))
+        }
+        0x03 => {
+            let (ctx, labelidx) = ctx.read_leb128()?;
+            (ctx, ast::Catch::CatchAllRef(labelidx))
+        }
+        _ => unimplemented!("catch clause: {:x}", kind),
+    })
+}
+
+// https://github.com/WebAssembly/multi-memory/blob/main/proposals/multi-memory/Overview.md
+fn decode_memarg<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::MemArg> {
+    let (ctx, flags) = ctx.read_leb128()?;
+    // Bit 6 of the alignment indicates that the memidx follows
+    let (ctx, memidx) = if flags & 0x40 != 0 {
+        ctx.read_leb128()?
+    } else {
+        (ctx, 0)
+    };
+    let (ctx, offset) = ctx.read_leb128()?;
+    let memarg = ast::MemArg {
+        align: flags & !0x40,
+        offset,
+        memidx,
+    };
+    Ok((ctx, memarg))
+}
+
 fn decode_code_local<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::CodeLocal> {
     let (ctx, count) = ctx.read_leb128()?;
     let (ctx, value_type) = decode_valtype(ctx)?;
@@ -1073,6 +1668,10 @@ fn decode_valtype<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::V
             0x7E => ast::ValueType::NumType(ast::NumType::I64),
             0x7D => ast::ValueType::NumType(ast::NumType::F32),
             0x7C => ast::ValueType::NumType(ast::NumType::F64),
+            0x7B => ast::ValueType::VectorType,
+            0x70 => ast::ValueType::RefType(ast::Reftype::Func),
+            0x6F => ast::ValueType::RefType(ast::Reftype::Extern),
+            0x69 => ast::ValueType::RefType(ast::Reftype::Exn),
             e => unimplemented!("unsupported type: {:x}", e),
         },
     ))
@@ -1119,19 +1718,71 @@ fn decode_data<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Data
 
 fn decode_element<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Element> {
     let (ctx, t) = ctx.read_leb128()?;
+    let decode_funcs = |ctx| decode_vec(ctx, |ctx| ctx.read_leb128());
+    let decode_exprs = |ctx| decode_vec(ctx, |ctx| decode_expr(ctx, ast::Instr::end));
+
+    // https://webassembly.github.io/spec/core/binary/modules.html#element-section
     Ok(match t {
         0 => {
-            let (ctx, expr) = decode_expr(ctx, ast::Instr::end)?;
-            let (ctx, elements) = decode_vec(ctx, |ctx| ctx.read_leb128())?;
+            let (ctx, offset) = decode_expr(ctx, ast::Instr::end)?;
+            let (ctx, funcs) = decode_funcs(ctx)?;
+            (ctx, ast::Element::FuncActive(offset, funcs))
+        }
+        1 => {
+            let (ctx, _) = decode_elemkind(ctx)?;
+            let (ctx, funcs) = decode_funcs(ctx)?;
+            (ctx, ast::Element::FuncPassive(funcs))
+        }
+        2 => {
+            let (ctx, tableidx) = ctx.read_leb128()?;
+            let (ctx, offset) = decode_expr(ctx, ast::Instr::end)?;
+            let (ctx, _) = decode_elemkind(ctx)?;
+            let (ctx, funcs) = decode_funcs(ctx)?;
+            (ctx, ast::Element::FuncActiveTable(tableidx, offset, funcs))
+        }
+        3 => {
+            let (ctx, _) = decode_elemkind(ctx)?;
+            let (ctx, funcs) = decode_funcs(ctx)?;
+            (ctx, ast::Element::FuncDeclarative(funcs))
+        }
+        4 => {
+            let (ctx, offset) = decode_expr(ctx, ast::Instr::end)?;
+            let (ctx, exprs) = decode_exprs(ctx)?;
+            (ctx, ast::Element::ExprActive(offset, exprs))
+        }
+        5 => {
+            let (ctx, reftype) = decode_reftype(ctx)?;
+            let (ctx, exprs) = decode_exprs(ctx)?;
+            (ctx, ast::Element::ExprPassive(reftype, exprs))
+        }
+        6 => {
+            let (ctx, tableidx) = ctx.read_leb128()?;
+            let (ctx, offset) = decode_expr(ctx, ast::Instr::end)?;
+            let (ctx, reftype) = decode_reftype(ctx)?;
+            let (ctx, exprs) = decode_exprs(ctx)?;
             (
                 ctx,
-                ast::Element::FuncActive(expr, Arc::new(Mutex::new(elements))),
+                ast::Element::ExprActiveTable(tableidx, offset, reftype, exprs),
             )
         }
+        7 => {
+            let (ctx, reftype) = decode_reftype(ctx)?;
+            let (ctx, exprs) = decode_exprs(ctx)?;
+            (ctx, ast::Element::ExprDeclarative(reftype, exprs))
+        }
         _ => unimplemented!("element segment of type: {}", t),
     })
 }
 
+/// Kind of the elements listed by function index, only `funcref` exists.
+fn decode_elemkind<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, u8> {
+    let (ctx, elemkind) = ctx.read_u8()?;
+    if elemkind != 0x00 {
+        unimplemented!("unsupported element kind: {}", elemkind);
+    }
+    Ok((ctx, elemkind))
+}
+
 fn decode_export<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Export> {
     let (ctx, name) = decode_name(ctx)?;
     let (ctx, descr) = decode_export_desc(ctx)?;
@@ -1144,24 +1795,37 @@ fn decode_export_desc<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, as
     Ok(match t {
         0x00 => {
             let (ctx, idx) = ctx.read_leb128()?;
-            (ctx, ast::ExportDescr::Func(Arc::new(Mutex::new(idx))))
+            (ctx, ast::ExportDescr::Func(idx))
         }
         0x01 => {
             let (ctx, idx) = ctx.read_leb128()?;
-            (ctx, ast::ExportDescr::Table(Arc::new(Mutex::new(idx))))
+            (ctx, ast::ExportDescr::Table(idx))
         }
         0x02 => {
             let (ctx, idx) = ctx.read_leb128()?;
-            (ctx, ast::ExportDescr::Mem(Arc::new(Mutex::new(idx))))
+            (ctx, ast::ExportDescr::Mem(idx))
         }
         0x03 => {
             let (ctx, idx) = ctx.read_leb128()?;
-            (ctx, ast::ExportDescr::Global(Arc::new(Mutex::new(idx))))
+            (ctx, ast::ExportDescr::Global(idx))
+        }
+        0x04 => {
+            let (ctx, idx) = ctx.read_leb128()?;
+            (ctx, ast::ExportDescr::Tag(idx))
         }
         _ => unimplemented!("unsupported export descr"),
     })
 }
 
+fn decode_tag<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Tag> {
+    let (ctx, attribute) = ctx.read_u8()?;
+    if attribute != 0 {
+        unimplemented!("tag attribute: {:x}", attribute);
+    }
+    let (ctx, typeidx) = ctx.read_leb128()?;
+    Ok((ctx, ast::Tag { typeidx }))
+}
+
 fn decode_global<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Global> {
     let (ctx, global_type) = decode_global_type(ctx)?;
     let (ctx, expr) = decode_expr(ctx, ast::Instr::end)?;
@@ -1194,14 +1858,22 @@ fn decode_memory<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::Me
     };
 
     let (ctx, mem) = match t {
-        0 => (ctx, ast::Memory { min, max: None }),
-        1 => {
+        0 => (
+            ctx,
+            ast::Memory {
+                min,
+                max: None,
+                shared: false,
+            },
+        ),
+        1 | 3 => {
             let (ctx, max) = ctx.read_leb128()?;
             (
                 ctx,
                 ast::Memory {
                     min,
                     max: Some(max),
+                    shared: t == 3,
                 },
             )
         }
@@ -1238,7 +1910,7 @@ fn decode_section<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::S
 
     let section = match id {
         0 => match decode_section_custom(section_bytes.clone()) {
-            Ok((_, res)) => ast::Section::Custom((section_size, Arc::new(Mutex::new(res)))),
+            Ok((_, res)) => ast::Section::Custom((section_size, res)),
             Err(err) => {
                 eprintln!("failed to parse custom section: {}. Ignoring.", err);
                 ast::Section::Unknown((id, size, section_bytes.input.to_vec()))
@@ -1246,19 +1918,19 @@ fn decode_section<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::S
         },
         1 => {
             let (_, res) = decode_section_type(section_bytes)?;
-            ast::Section::Type((section_size, Arc::new(Mutex::new(res))))
+            ast::Section::Type((section_size, res))
         }
         2 => {
             let (_, res) = decode_section_import(section_bytes)?;
-            ast::Section::Import((section_size, Arc::new(Mutex::new(res))))
+            ast::Section::Import((section_size, res))
         }
         3 => {
             let (_, res) = decode_section_func(section_bytes)?;
-            ast::Section::Func((section_size, Arc::new(Mutex::new(res))))
+            ast::Section::Func((section_size, res))
         }
         4 => {
             let (_, res) = decode_section_table(section_bytes)?;
-            ast::Section::Table((section_size, Arc::new(Mutex::new(res))))
+            ast::Section::Table((section_size, res))
         }
         5 => {
             let (_, res) = decode_section_memory(section_bytes)?;
@@ -1266,15 +1938,19 @@ fn decode_section<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::S
         }
         6 => {
             let (_, res) = decode_section_global(section_bytes)?;
-            ast::Section::Global((section_size, Arc::new(Mutex::new(res))))
+            ast::Section::Global((section_size, res))
         }
         7 => {
             let (_, res) = decode_section_export(section_bytes)?;
-            ast::Section::Export((section_size, Arc::new(Mutex::new(res))))
+            ast::Section::Export((section_size, res))
+        }
+        8 => {
+            let (_, funcidx) = section_bytes.read_leb128()?;
+            ast::Section::Start((section_size, funcidx))
         }
         9 => {
             let (_, res) = decode_section_element(section_bytes)?;
-            ast::Section::Element((section_size, Arc::new(Mutex::new(res))))
+            ast::Section::Element((section_size, res))
         }
         10 => {
             let (_, res) = decode_section_code(section_bytes)?;
@@ -1285,11 +1961,19 @@ fn decode_section<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast::S
                 value: res,
                 end_offset,
             };
-            ast::Section::Code((section_size, Arc::new(Mutex::new(value))))
+            ast::Section::Code((section_size, value))
         }
         11 => {
             let (_, res) = decode_section_data(section_bytes)?;
-            ast::Section::Data((section_size, Arc::new(Mutex::new(res))))
+            ast::Section::Data((section_size, res))
+        }
+        12 => {
+            let (_, count) = section_bytes.read_leb128()?;
+            ast::Section::DataCount((section_size, count))
+        }
+        13 => {
+            let (_, res) = decode_section_tag(section_bytes)?;
+            ast::Section::Tag((section_size, res))
         }
         id => {
             warn!("unknown section with id {}", id);
@@ -1325,7 +2009,10 @@ fn decode_blocktype<'a>(ctx: InputContext<'a>) -> IResult<InputContext<'a>, ast:
         let (ctx, _) = ctx.read_u8()?;
         Ok((ctx, ast::BlockType::Empty))
     } else {
-        if let Ok((ctx, valtype)) = decode_valtype(ctx.clone()) {
+        // Value types are encoded as negative numbers, type indices as
+        // positive ones.
+        if matches!(next, 0x7c..=0x7f | 0x7b | 0x70 | 0x6f | 0x69) {
+            let (ctx, valtype) = decode_valtype(ctx)?;
             Ok((ctx, ast::BlockType::ValueType(valtype)))
         } else {
             let (ctx, typeidx) = ctx.read_leb128()?;
@@ -1,5 +1,6 @@
 use crate::parse;
-use std::sync::Arc;
+use core_wasm_ast as ast;
+use core_wasm_ast::traverse::{self, VisitorContext};
 
 #[test]
 fn test_basic() {
@@ -33,7 +34,7 @@ fn test_basic() {
     // Try encoding and parsing
     let coredump_wasm = coredump_builder.serialize().unwrap();
     let coredump_wasm = parse(&coredump_wasm).unwrap();
-    let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(Arc::new(coredump_wasm));
+    let coredump_wasm = core_wasm_ast::traverse::WasmModule::new(coredump_wasm);
     let coredump = coredump_wasm.get_coredump().unwrap();
 
     let mut out = String::new();
@@ -51,3 +52,366 @@ fn test_basic() {
 )"#
     );
 }
+
+/// One instruction per line, for every opcode.
+const INSTRUCTIONS: &str = include_str!("../test/instructions.wat");
+
+#[test]
+fn test_instructions() {
+    colored::control::set_override(false);
+
+    let input = format!(
+        "(module (memory 1 1 shared) (memory 1) (func {}))",
+        INSTRUCTIONS.replace('\n', " ")
+    );
+    let input = wat::parse_str(input).unwrap();
+    let module = parse(&input).unwrap();
+    assert_eq!(wasm_printer::wasm::print(&module).unwrap(), input);
+
+    let body = module
+        .sections
+        .iter()
+        .find_map(|section| match &section.value {
+            ast::Section::Code((_, codes)) => Some(codes.value[0].body.value.clone()),
+            _ => None,
+        })
+        .unwrap();
+
+    let module = core_wasm_ast::traverse::WasmModule::new(module);
+    let mut out = String::new();
+    let mut printer = wasm_printer::WastPrinter {
+        module: &module,
+        out: &mut out,
+    };
+    for instr in &body {
+        if matches!(instr.value, ast::Instr::end) {
+            break;
+        }
+        printer.print_instr(&instr.value).unwrap();
+        printer.out.push('\n');
+    }
+    assert_eq!(out, INSTRUCTIONS);
+}
+
+#[test]
+fn test_start_and_data_count() {
+    let input = wat::parse_str(
+        r#"(module
+    (memory 1)
+    (func
+        (data.drop 0))
+    (start 0)
+    (data "passive"))"#,
+    )
+    .unwrap();
+    let module = parse(&input).unwrap();
+    assert_eq!(wasm_printer::wasm::print(&module).unwrap(), input);
+
+    let mut module = core_wasm_ast::traverse::WasmModule::new(module);
+    assert_eq!(module.start_func(), Some(0));
+    assert!(module.is_func_entry_point(0));
+
+    module.add_data(16, b"active");
+    let data_count = module
+        .inner()
+        .sections
+        .iter()
+        .find_map(|section| match &section.value {
+            ast::Section::DataCount((_, count)) => Some(*count),
+            _ => None,
+        });
+    assert_eq!(data_count, Some(2));
+}
+
+#[test]
+fn test_element_segments() {
+    let input = wat::parse_str(
+        r#"(module
+    (table 4 funcref)
+    (table 4 funcref)
+    (func)
+    (func
+        (drop (ref.func 1)))
+    (elem (i32.const 0) 0 1)
+    (elem func 0)
+    (elem (table 1) (i32.const 0) func 1)
+    (elem declare func 0)
+    (elem (i32.const 2) funcref (ref.func 1) (ref.null func))
+    (elem funcref (ref.func 0))
+    (elem (table 1) (i32.const 2) funcref (ref.func 1))
+    (elem declare funcref (ref.func 1)))"#,
+    )
+    .unwrap();
+    let module = parse(&input).unwrap();
+    assert_eq!(wasm_printer::wasm::print(&module).unwrap(), input);
+
+    let elements = |module: &core_wasm_ast::traverse::WasmModule| {
+        module
+            .inner()
+            .sections
+            .iter()
+            .find_map(|section| match &section.value {
+                ast::Section::Element((_, elements)) => Some(elements.clone()),
+                _ => None,
+            })
+            .unwrap()
+    };
+
+    let mut module = core_wasm_ast::traverse::WasmModule::new(module);
+    let forms = elements(&module)
+        .iter()
+        .map(|element| match element {
+            ast::Element::FuncActive(..) => 0,
+            ast::Element::FuncPassive(..) => 1,
+            ast::Element::FuncActiveTable(..) => 2,
+            ast::Element::FuncDeclarative(..) => 3,
+            ast::Element::ExprActive(..) => 4,
+            ast::Element::ExprPassive(..) => 5,
+            ast::Element::ExprActiveTable(..) => 6,
+            ast::Element::ExprDeclarative(..) => 7,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(forms, (0..8).collect::<Vec<_>>());
+
+    // Adding an import shifts the references to the defined functions
+    module
+        .add_import(&ast::Import {
+            module: "env".to_owned(),
+            name: "f".to_owned(),
+            import_type: ast::ImportType::Func(0),
+        })
+        .unwrap();
+    let funcs = elements(&module)
+        .iter()
+        .map(|element| element.funcs())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        funcs,
+        [
+            vec![1, 2],
+            vec![1],
+            vec![2],
+            vec![1],
+            vec![2],
+            vec![1],
+            vec![2],
+            vec![2]
+        ]
+    );
+    let body = module
+        .inner()
+        .sections
+        .iter()
+        .find_map(|section| match &section.value {
+            ast::Section::Code((_, codes)) => Some(codes.value[1].body.value.clone()),
+            _ => None,
+        })
+        .unwrap();
+    assert!(matches!(body[0].value, ast::Instr::ref_func(2)));
+}
+
+#[test]
+fn test_add_import() {
+    let input = wat::parse_str(r#"(module (func))"#).unwrap();
+    let mut module = core_wasm_ast::traverse::WasmModule::new(parse(&input).unwrap());
+
+    let global_type = ast::GlobalType {
+        valtype: ast::ValueType::NumType(ast::NumType::I32),
+        mutable: false,
+    };
+    let err = module
+        .add_import(&ast::Import {
+            module: "env".to_owned(),
+            name: "g".to_owned(),
+            import_type: ast::ImportType::Global(global_type),
+        })
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "only function imports can be added, got env.g"
+    );
+
+    let funcidx = module
+        .add_import(&ast::Import {
+            module: "env".to_owned(),
+            name: "f".to_owned(),
+            import_type: ast::ImportType::Func(0),
+        })
+        .unwrap();
+    assert_eq!(funcidx, 0);
+    assert_eq!(module.imports().len(), 1);
+}
+
+struct EditsVisitor;
+
+impl traverse::Visitor for EditsVisitor {
+    fn visit_import_section<'a>(&self, ctx: &'_ mut VisitorContext<'a, Vec<ast::Import>>) {
+        for name in ["g", "h"] {
+            ctx.insert_node_after(ast::Import {
+                module: "env".to_owned(),
+                name: name.to_owned(),
+                import_type: ast::ImportType::Func(0),
+            });
+        }
+    }
+
+    fn visit_func_section<'a>(&self, ctx: &'_ mut VisitorContext<'a, Vec<u32>>) {
+        ctx.insert_node_after(0);
+    }
+
+    fn visit_code_section<'a>(&self, ctx: &'_ mut VisitorContext<'a, Vec<ast::Code>>) {
+        ctx.insert_node_after(ast::Code {
+            locals: vec![],
+            size: ast::Value::new(0),
+            body: ast::Value::new(vec![
+                ast::Value::new(ast::Instr::nop),
+                ast::Value::new(ast::Instr::end),
+            ]),
+        });
+    }
+
+    fn visit_data_section<'a>(&self, ctx: &'_ mut VisitorContext<'a, Vec<ast::DataSegment>>) {
+        ctx.insert_node_after(ast::DataSegment {
+            offset: Some(ast::Value::new(vec![
+                ast::Value::new(ast::Instr::i32_const(8)),
+                ast::Value::new(ast::Instr::end),
+            ])),
+            bytes: b"b".to_vec(),
+            mode: ast::DataSegmentMode::Active,
+        });
+    }
+
+    fn visit_code<'a>(&self, ctx: &'_ mut VisitorContext<'a, ast::Code>, _funcidx: u32) {
+        ctx.prepend_body(vec![ast::Instr::i32_const(1), ast::Instr::drop]);
+        ctx.prepend_body(vec![ast::Instr::i32_const(2), ast::Instr::drop]);
+        ctx.append_body(vec![ast::Instr::i32_const(3), ast::Instr::drop]);
+    }
+
+    fn visit_instr<'a>(&self, ctx: &'_ mut VisitorContext<'a, ast::Value<ast::Instr>>) {
+        match ctx.node.value {
+            ast::Instr::nop => {
+                ctx.insert_node_after(ast::Instr::drop);
+                ctx.insert_node_before(ast::Instr::i32_const(10));
+                ctx.insert_node_before(ast::Instr::i32_const(11));
+                ctx.replace_node(ast::Instr::i32_add);
+            }
+            ast::Instr::call(_) => ctx.stop_traversal(),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_traverse() {
+    let input = wat::parse_str(
+        r#"(module
+    (import "env" "f" (func))
+    (memory 1)
+    (func
+        nop
+        (block
+            nop)
+        call 1
+        nop)
+    (data (i32.const 0) "a"))"#,
+    )
+    .unwrap();
+    let mut module = traverse::WasmModule::new(parse(&input).unwrap());
+    traverse::traverse(&mut module, &EditsVisitor);
+
+    // The appended imports shift the defined functions, the appended code is
+    // visited like the others. The last prepended instructions run first.
+    let expected = wat::parse_str(
+        r#"(module
+    (import "env" "f" (func))
+    (import "env" "g" (func))
+    (import "env" "h" (func))
+    (memory 1)
+    (func
+        i32.const 2
+        drop
+        i32.const 1
+        drop
+        i32.const 10
+        i32.const 11
+        i32.add
+        drop
+        (block
+            i32.const 10
+            i32.const 11
+            i32.add
+            drop)
+        call 3
+        nop
+        i32.const 3
+        drop)
+    (func
+        i32.const 2
+        drop
+        i32.const 1
+        drop
+        i32.const 10
+        i32.const 11
+        i32.add
+        drop
+        i32.const 3
+        drop)
+    (data (i32.const 0) "a")
+    (data (i32.const 8) "b"))"#,
+    )
+    .unwrap();
+    assert_eq!(wasm_printer::wasm::print(module.inner()).unwrap(), expected);
+}
+
+/// Records the locals of the visited functions.
+struct LocalsVisitor(std::sync::Mutex<Vec<Vec<ast::CodeLocal>>>);
+
+impl traverse::Visitor for LocalsVisitor {
+    fn visit_instr<'a>(&self, ctx: &'_ mut VisitorContext<'a, ast::Value<ast::Instr>>) {
+        if let ast::Instr::nop = ctx.node.value {
+            ctx.add_local(ast::ValueType::NumType(ast::NumType::I64));
+            self.0.lock().unwrap().push(ctx.func_locals().to_vec());
+        }
+    }
+}
+
+#[test]
+fn test_func_locals() {
+    let input = wat::parse_str(
+        r#"(module
+    (type (func))
+    (type (func (param i32)))
+    (import "env" "f" (func (type 1)))
+    (func (type 0) (local i32)
+        nop
+        nop))"#,
+    )
+    .unwrap();
+    let mut module = traverse::WasmModule::new(parse(&input).unwrap());
+    assert!(module.is_func_imported(0));
+    assert!(!module.is_func_imported(1));
+    assert_eq!(module.get_func_typeidx(0), 1);
+    assert_eq!(module.get_func_typeidx(1), 0);
+
+    let local = |count, num_type| ast::CodeLocal {
+        count,
+        value_type: ast::ValueType::NumType(num_type),
+    };
+    assert!(module.add_func_local(1, local(1, ast::NumType::F32)));
+    let visitor = LocalsVisitor(Default::default());
+    traverse::traverse(&mut module, &visitor);
+
+    // The locals added before and during the traversal aren't part of the
+    // original locals
+    let locals = visitor.0.into_inner().unwrap();
+    assert_eq!(locals.len(), 2);
+    for locals in locals {
+        assert_eq!(locals.len(), 1);
+        assert_eq!(locals[0].count, 1);
+        assert!(matches!(
+            locals[0].value_type,
+            ast::ValueType::NumType(ast::NumType::I32)
+        ));
+    }
+    assert_eq!(module.func_locals(1).len(), 4);
+}
@@ -10,19 +10,58 @@ pub fn print(module: &ast::Module) -> Result<Vec<u8>, BoxError> {
 
     write_header(&mut buffer)?;
 
-    for section in module.sections.lock().unwrap().iter() {
+    for section in module.sections.iter() {
         write_section(&mut buffer, &section.value)?;
     }
 
     Ok(buffer)
 }
 
+pub fn print_component(component: &ast::Component) -> Result<Vec<u8>, BoxError> {
+    let mut buffer = vec![];
+
+    buffer.write_all(b"\0asm")?;
+    buffer.write_all(&COMPONENT_VERSION.to_le_bytes())?;
+
+    for section in component.sections.iter() {
+        match section {
+            ast::ComponentSection::CoreModule(module) => {
+                write_component_section(&mut buffer, 1, &print(module)?);
+            }
+            ast::ComponentSection::Component(component) => {
+                write_component_section(&mut buffer, 4, &print_component(component)?);
+            }
+            ast::ComponentSection::Unknown((id, content)) => {
+                write_component_section(&mut buffer, *id, content);
+            }
+        }
+    }
+
+    Ok(buffer)
+}
+
+pub fn print_binary(binary: &ast::Binary) -> Result<Vec<u8>, BoxError> {
+    match binary {
+        ast::Binary::Module(module) => print(module),
+        ast::Binary::Component(component) => print_component(component),
+    }
+}
+
 pub fn write_header(buffer: &mut Vec<u8>) -> Result<(), BoxError> {
     buffer.write(b"\0asm")?;
     buffer.write(&1u32.to_le_bytes())?;
     Ok(())
 }
 
+// Version 0x0d of the component-model binary format, in layer 1.
+const COMPONENT_VERSION: u32 = 0x0001000d;
+
+fn write_component_section(buffer: &mut Vec<u8>, id: u8, content: &[u8]) {
+    buffer.push(id);
+    write_unsigned_leb128(buffer, content.len() as u64);
+    buffer.extend_from_slice(content);
+}
+
 macro_rules! write_section {
     ($b:expr, $o:expr, $id:expr, $write_fn:expr) => {
         $b.push($id); // section id
@@ -45,11 +84,11 @@ macro_rules! write_section {
 pub fn write_section(buffer: &mut Vec<u8>, section: &ast::Section) -> Result<(), BoxError> {
     match section {
         ast::Section::Import((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 2, write_section_import);
+            write_section!(buffer, content, 2, write_section_import);
             Ok(())
         }
         ast::Section::Table((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 4, write_section_table);
+            write_section!(buffer, content, 4, write_section_table);
             Ok(())
         }
         ast::Section::Memory((_size, content)) => {
@@ -57,19 +96,19 @@ pub fn write_section(buffer: &mut Vec<u8>, section: &ast::Section) -> Result<(),
             Ok(())
         }
         ast::Section::Code((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 10, write_section_code);
+            write_section!(buffer, content, 10, write_section_code);
             Ok(())
         }
         ast::Section::Type((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 1, write_section_type);
+            write_section!(buffer, content, 1, write_section_type);
             Ok(())
         }
         ast::Section::Element((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 9, write_section_element);
+            write_section!(buffer, content, 9, write_section_element);
             Ok(())
         }
         ast::Section::Custom((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 0, write_section_custom);
+            write_section!(buffer, content, 0, write_section_custom);
             Ok(())
         }
         ast::Section::Unknown((id, size, content)) => {
@@ -79,24 +118,42 @@ pub fn write_section(buffer: &mut Vec<u8>, section: &ast::Section) -> Result<(),
             Ok(())
         }
         ast::Section::Data((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 11, write_section_data);
+            write_section!(buffer, content, 11, write_section_data);
             Ok(())
         }
         ast::Section::Func((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 3, write_section_func);
+            write_section!(buffer, content, 3, write_section_func);
             Ok(())
         }
         ast::Section::Export((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 7, write_section_export);
+            write_section!(buffer, content, 7, write_section_export);
             Ok(())
         }
         ast::Section::Global((_size, content)) => {
-            write_section!(buffer, content.lock().unwrap(), 6, write_section_global);
+            write_section!(buffer, content, 6, write_section_global);
+            Ok(())
+        }
+        ast::Section::Tag((_size, content)) => {
+            write_section!(buffer, content, 13, write_section_tag);
+            Ok(())
+        }
+        ast::Section::Start((_size, funcidx)) => {
+            write_section!(buffer, funcidx, 8, write_section_u32);
+            Ok(())
+        }
+        ast::Section::DataCount((_size, count)) => {
+            write_section!(buffer, count, 12, write_section_u32);
             Ok(())
         }
     }
 }
 
+/// Sections holding a single index or count.
+fn write_section_u32(buffer: &mut Vec<u8>, content: &u32) -> Result<(), BoxError> {
+    write_unsigned_leb128(buffer, *content as u64);
+    Ok(())
+}
+
 fn write_section_import(buffer: &mut Vec<u8>, content: &Vec<ast::Import>) -> Result<(), BoxError> {
     write_vec_len(buffer, content); // vec length
 
@@ -129,6 +186,11 @@ fn write_section_import(buffer: &mut Vec<u8>, content: &Vec<ast::Import>) -> Res
                     buffer.push(0x00);
                 }
             }
+
+            ast::ImportType::Tag(tag) => {
+                buffer.push(0x4);
+                write_tag(buffer, tag);
+            }
         }
     }
 
@@ -166,10 +228,23 @@ fn write_reftype(buffer: &mut Vec<u8>, typeref: &ast::Reftype) {
     let b = match typeref {
         ast::Reftype::Func => 0x70,
         ast::Reftype::Extern => 0x6F,
+        ast::Reftype::Exn => 0x69,
     };
     buffer.push(b);
 }
 
+fn write_memarg(buffer: &mut Vec<u8>, memarg: &ast::MemArg) {
+    // The memidx is only encoded for memories other than the first one,
+    // indicated by bit 6 of the alignment.
+    if memarg.memidx == 0 {
+        write_unsigned_leb128(buffer, memarg.align as u64);
+    } else {
+        write_unsigned_leb128(buffer, (memarg.align | 0x40) as u64);
+        write_unsigned_leb128(buffer, memarg.memidx as u64);
+    }
+    write_unsigned_leb128(buffer, memarg.offset as u64);
+}
+
 pub(crate) fn write_utf8(buffer: &mut Vec<u8>, v: &str) {
     let bytes = v.as_bytes().to_vec();
     write_vec_len(buffer, &bytes);
@@ -188,7 +263,7 @@ fn write_section_memory(buffer: &mut Vec<u8>, content: &Vec<ast::Memory>) -> Res
 
 fn write_memory(buffer: &mut Vec<u8>, mem: &ast::Memory) -> Result<(), BoxError> {
     if let Some(max) = mem.max {
-        buffer.push(0x1);
+        buffer.push(if mem.shared { 0x3 } else { 0x1 });
         write_unsigned_leb128(buffer, mem.min.value as u64);
         write_unsigned_leb128(buffer, max as u64);
     } else {
@@ -216,6 +291,21 @@ fn write_section_global(buffer: &mut Vec<u8>, content: &Vec<ast::Global>) -> Res
     Ok(())
 }
 
+fn write_section_tag(buffer: &mut Vec<u8>, content: &Vec<ast::Tag>) -> Result<(), BoxError> {
+    write_vec_len(buffer, content); // vec length
+
+    for tag in content {
+        write_tag(buffer, tag);
+    }
+
+    Ok(())
+}
+
+fn write_tag(buffer: &mut Vec<u8>, tag: &ast::Tag) {
+    buffer.push(0x00); // exception attribute
+    write_unsigned_leb128(buffer, tag.typeidx as u64);
+}
+
 fn write_section_export(buffer: &mut Vec<u8>, content: &Vec<ast::Export>) -> Result<(), BoxError> {
     write_vec_len(buffer, &content); // vec length
 
@@ -224,19 +314,23 @@ fn write_section_export(buffer: &mut Vec<u8>, content: &Vec<ast::Export>) -> Res
         match &export.descr {
             ast::ExportDescr::Func(idx) => {
                 buffer.push(0x00);
-                write_unsigned_leb128(buffer, *idx.lock().unwrap() as u64);
+                write_unsigned_leb128(buffer, *idx as u64);
             }
             ast::ExportDescr::Table(idx) => {
                 buffer.push(0x01);
-                write_unsigned_leb128(buffer, *idx.lock().unwrap() as u64);
+                write_unsigned_leb128(buffer, *idx as u64);
             }
             ast::ExportDescr::Mem(idx) => {
                 buffer.push(0x02);
-                write_unsigned_leb128(buffer, *idx.lock().unwrap() as u64);
+                write_unsigned_leb128(buffer, *idx as u64);
             }
             ast::ExportDescr::Global(idx) => {
                 buffer.push(0x03);
-                write_unsigned_leb128(buffer, *idx.lock().unwrap() as u64);
+                write_unsigned_leb128(buffer, *idx as u64);
+            }
+            ast::ExportDescr::Tag(idx) => {
+                buffer.push(0x04);
+                write_unsigned_leb128(buffer, *idx as u64);
             }
         };
     }
@@ -324,11 +418,32 @@ fn write_section_custom(
             write_utf8(buffer, "corestack");
             wasm_coredump_encoder::encode_coredump_stack(buffer, content)?;
         }
+
+        ast::CustomSection::CodeOffsets(content) => {
+            write_utf8(buffer, "code_offsets");
+            write_section_custom_code_offsets(buffer, content)?
+        }
     }
 
     Ok(())
 }
 
+fn write_section_custom_code_offsets(
+    buffer: &mut Vec<u8>,
+    map: &ast::CodeOffsetMap,
+) -> Result<(), BoxError> {
+    write_unsigned_leb128(buffer, map.funcs.len() as u64);
+    for (funcidx, entries) in &map.funcs {
+        write_unsigned_leb128(buffer, *funcidx as u64);
+        write_vec_len(buffer, entries);
+        for (rewritten, original) in entries {
+            write_unsigned_leb128(buffer, *rewritten as u64);
+            write_unsigned_leb128(buffer, *original as u64);
+        }
+    }
+    Ok(())
+}
+
 fn write_section_custom_build_id(buffer: &mut Vec<u8>, id: &[u8]) -> Result<(), BoxError> {
     write_unsigned_leb128(buffer, id.len() as u64);
     buffer.extend_from_slice(id);
@@ -348,8 +463,6 @@ pub fn write_section_custom_name(
 
         let mut subsection = vec![];
         {
-            let func_names = func_names.lock().unwrap();
-
             write_unsigned_leb128(&mut subsection, func_names.len() as u64);
 
             for funcidx in 0..func_names.len() {
@@ -370,7 +483,7 @@ pub fn write_section_custom_name(
 
         let mut subsection = vec![];
         {
-            let global_names = content.global_names.lock().unwrap();
+            let global_names = &content.global_names;
 
             write_unsigned_leb128(&mut subsection, global_names.len() as u64);
 
@@ -395,15 +508,64 @@ fn write_section_element(
 ) -> Result<(), BoxError> {
     write_vec_len(buffer, &content); // vec length
 
+    let write_funcs = |buffer: &mut Vec<u8>, funcs: &Vec<u32>| {
+        write_vec_len(buffer, funcs);
+        for funcidx in funcs {
+            write_unsigned_leb128(buffer, *funcidx as u64);
+        }
+    };
+    let write_exprs = |buffer: &mut Vec<u8>, exprs: &Vec<ast::Expr>| {
+        write_vec_len(buffer, exprs);
+        for expr in exprs {
+            write_code_expr(buffer, &expr.value);
+        }
+    };
+
     for t in content {
         match t {
-            ast::Element::FuncActive(expr, vec) => {
+            ast::Element::FuncActive(offset, funcs) => {
                 buffer.push(0);
-                write_code_expr(buffer, &expr.value);
-                write_vec_len(buffer, &vec.lock().unwrap());
-                for funcidx in vec.lock().unwrap().iter() {
-                    write_unsigned_leb128(buffer, *funcidx as u64);
-                }
+                write_code_expr(buffer, &offset.value);
+                write_funcs(buffer, funcs);
+            }
+            ast::Element::FuncPassive(funcs) => {
+                buffer.push(1);
+                buffer.push(0x00); // elemkind
+                write_funcs(buffer, funcs);
+            }
+            ast::Element::FuncActiveTable(tableidx, offset, funcs) => {
+                buffer.push(2);
+                write_unsigned_leb128(buffer, *tableidx as u64);
+                write_code_expr(buffer, &offset.value);
+                buffer.push(0x00); // elemkind
+                write_funcs(buffer, funcs);
+            }
+            ast::Element::FuncDeclarative(funcs) => {
+                buffer.push(3);
+                buffer.push(0x00); // elemkind
+                write_funcs(buffer, funcs);
+            }
+            ast::Element::ExprActive(offset, exprs) => {
+                buffer.push(4);
+                write_code_expr(buffer, &offset.value);
+                write_exprs(buffer, exprs);
+            }
+            ast::Element::ExprPassive(reftype, exprs) => {
+                buffer.push(5);
+                write_reftype(buffer, reftype);
+                write_exprs(buffer, exprs);
+            }
+            ast::Element::ExprActiveTable(tableidx, offset, reftype, exprs) => {
+                buffer.push(6);
+                write_unsigned_leb128(buffer, *tableidx as u64);
+                write_code_expr(buffer, &offset.value);
+                write_reftype(buffer, reftype);
+                write_exprs(buffer, exprs);
+            }
+            ast::Element::ExprDeclarative(reftype, exprs) => {
+                buffer.push(7);
+                write_reftype(buffer, reftype);
+                write_exprs(buffer, exprs);
             }
         }
     }
@@ -421,7 +583,7 @@ fn write_section_code(
         buffer.push(0x0); // func size, going to be fixed.
 
         write_code_local(buffer, &func.locals);
-        write_code_expr(buffer, &func.body.lock().unwrap().value);
+        write_code_expr(buffer, &func.body.value);
 
         // func size fixup
         {
@@ -455,23 +617,132 @@ fn write_value_type(buffer: &mut Vec<u8>, value_type: &ast::ValueType) {
         NumType(I64) => 0x7E,
         NumType(F32) => 0x7D,
         NumType(F64) => 0x7C,
+        VectorType => 0x7B,
+        RefType(reftype) => return write_reftype(buffer, reftype),
     };
     buffer.push(b);
 }
 
+/// Offsets of the printed instructions that come from the parsed module, as
+/// (printed offset, parsed offset).
+type InstrOffsets = Vec<(usize, usize)>;
+
+/// Compute the offsets of the instructions in the printed module compared to
+/// the module it was parsed from. Functions that were added to the module
+/// aren't included.
+pub fn code_offset_map(module: &ast::Module) -> ast::CodeOffsetMap {
+    let mut map = ast::CodeOffsetMap::default();
+    let mut imported_funcs = 0;
+
+    for section in module.sections.iter() {
+        match &section.value {
+            ast::Section::Import((_size, content)) => {
+                imported_funcs = content
+                    .iter()
+                    .filter(|import| matches!(import.import_type, ast::ImportType::Func(_)))
+                    .count() as u32;
+            }
+            ast::Section::Code((_size, content)) => {
+                for (i, func) in content.value.iter().enumerate() {
+                    let body = &func.body;
+
+                    let mut buffer = vec![];
+                    let mut offsets = vec![];
+                    write_code_expr_with_offsets(&mut buffer, &body.value, Some(&mut offsets));
+
+                    if offsets.is_empty() {
+                        continue;
+                    }
+
+                    let entries = offsets
+                        .into_iter()
+                        .map(|(printed, parsed)| {
+                            (printed as u32, (parsed - body.start_offset) as u32)
+                        })
+                        .collect();
+                    map.funcs.insert(imported_funcs + i as u32, entries);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    map
+}
+
+/// Position of a function in the printed code section, relative to the
+/// start of the section's content.
+#[derive(Debug, Clone)]
+pub struct FuncPosition {
+    /// Start of the function's size
+    pub start: usize,
+    /// Start of the function's instructions, after its locals
+    pub instrs_start: usize,
+    pub end: usize,
+}
+
+/// Compute the position of each function in the printed code section.
+pub fn func_positions(module: &ast::Module) -> Vec<FuncPosition> {
+    let mut positions = vec![];
+
+    for section in module.sections.iter() {
+        if let ast::Section::Code((_size, content)) = &section.value {
+            let mut buffer = vec![];
+            write_vec_len(&mut buffer, &content.value);
+            let mut offset = buffer.len();
+
+            for func in &content.value {
+                let mut locals = vec![];
+                write_code_local(&mut locals, &func.locals);
+                let mut expr = vec![];
+                write_code_expr(&mut expr, &func.body.value);
+                let mut size = vec![];
+                write_unsigned_leb128(&mut size, (locals.len() + expr.len()) as u64);
+
+                let start = offset;
+                let instrs_start = start + size.len() + locals.len();
+                let end = instrs_start + expr.len();
+                positions.push(FuncPosition {
+                    start,
+                    instrs_start,
+                    end,
+                });
+                offset = end;
+            }
+        }
+    }
+
+    positions
+}
+
 fn write_code_expr(buffer: &mut Vec<u8>, expr: &Vec<ast::Value<ast::Instr>>) {
+    write_code_expr_with_offsets(buffer, expr, None)
+}
+
+fn write_code_expr_with_offsets(
+    buffer: &mut Vec<u8>,
+    expr: &Vec<ast::Value<ast::Instr>>,
+    mut offsets: Option<&mut InstrOffsets>,
+) {
     for instr in expr {
-        let id = instr.value.clone();
+        let id = &instr.value;
+
+        // Inserted instructions have no offsets
+        if let Some(offsets) = offsets.as_deref_mut() {
+            if instr.end_offset != 0 {
+                offsets.push((buffer.len(), instr.start_offset));
+            }
+        }
 
         macro_rules! write_instr {
             ($byte:expr, $instr:ident) => {
-                if matches!(id, ast::Instr::$instr) {
+                if matches!(*id, ast::Instr::$instr) {
                     buffer.push($byte);
                     continue;
                 }
             };
             ($byte:expr, $instr:ident(u8)) => {
-                if let ast::Instr::$instr(imm0) = id {
+                if let ast::Instr::$instr(imm0) = *id {
                     buffer.push($byte);
                     buffer.push(imm0);
                     continue;
@@ -479,7 +750,7 @@ fn write_code_expr(buffer: &mut Vec<u8>, expr: &Vec<ast::Value<ast::Instr>>) {
             };
 
             ($byte:expr, $instr:ident(f32)) => {
-                if let ast::Instr::$instr(imm0) = id {
+                if let ast::Instr::$instr(imm0) = *id {
                     buffer.push($byte);
                     write_float_f32(buffer, imm0);
                     continue;
@@ -487,7 +758,7 @@ fn write_code_expr(buffer: &mut Vec<u8>, expr: &Vec<ast::Value<ast::Instr>>) {
             };
 
             ($byte:expr, $instr:ident(f64)) => {
-                if let ast::Instr::$instr(imm0) = id {
+                if let ast::Instr::$instr(imm0) = *id {
                     buffer.push($byte);
                     write_float_f64(buffer, imm0);
                     continue;
@@ -495,7 +766,7 @@ fn write_code_expr(buffer: &mut Vec<u8>, expr: &Vec<ast::Value<ast::Instr>>) {
             };
 
             ($byte:expr, $instr:ident(i32)) => {
-                if let ast::Instr::$instr(imm0) = id {
+                if let ast::Instr::$instr(imm0) = *id {
                     buffer.push($byte);
                     write_signed_leb128(buffer, imm0 as i64);
                     continue;
@@ -503,7 +774,7 @@ fn write_code_expr(buffer: &mut Vec<u8>, expr: &Vec<ast::Value<ast::Instr>>) {
             };
 
             ($byte:expr, $instr:ident(i64)) => {
-                if let ast::Instr::$instr(imm0) = id {
+                if let ast::Instr::$instr(imm0) = *id {
                     buffer.push($byte);
                     write_signed_leb128(buffer, imm0);
                     continue;
@@ -511,85 +782,203 @@ fn write_code_expr(buffer: &mut Vec<u8>, expr: &Vec<ast::Value<ast::Instr>>) {
             };
 
             ($byte:expr, $instr:ident(u32)) => {
-                if let ast::Instr::$instr(imm0) = id {
+                if let ast::Instr::$instr(imm0) = *id {
                     buffer.push($byte);
                     write_unsigned_leb128(buffer, imm0 as u64);
                     continue;
                 }
             };
 
-            ($byte:expr, $instr:ident(MutableValue<u32>)) => {
+            ($byte:expr, $instr:ident(u32, u32)) => {
+                if let ast::Instr::$instr(imm0, imm1) = *id {
+                    buffer.push($byte);
+                    write_unsigned_leb128(buffer, imm0 as u64);
+                    write_unsigned_leb128(buffer, imm1 as u64);
+                    continue;
+                }
+            };
+
+            ($byte:expr, $instr:ident(memarg)) => {
                 if let ast::Instr::$instr(imm0) = id {
                     buffer.push($byte);
-                    write_unsigned_leb128(buffer, imm0.lock().unwrap().value as u64);
+                    write_memarg(buffer, imm0);
                     continue;
                 }
             };
 
-            ($byte:expr, $instr:ident(u32, u32)) => {
+            ($byte:expr, $instr:ident(Vec<u32>, u32)) => {
                 if let ast::Instr::$instr(imm0, imm1) = id {
                     buffer.push($byte);
+                    write_vec_len(buffer, imm0); // vec lengh
+                    for imm in imm0 {
+                        write_unsigned_leb128(buffer, *imm as u64);
+                    }
+                    write_unsigned_leb128(buffer, *imm1 as u64);
+                    continue;
+                }
+            };
+        }
+
+        macro_rules! write_prefixed {
+            ($prefix:expr, $op:expr, $instr:ident) => {
+                if matches!(*id, ast::Instr::$instr) {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    continue;
+                }
+            };
+
+            ($prefix:expr, $op:expr, $instr:ident(fence)) => {
+                if matches!(*id, ast::Instr::$instr) {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    buffer.push(0x00); // reserved byte
+                    continue;
+                }
+            };
+
+            ($prefix:expr, $op:expr, $instr:ident(u8)) => {
+                if let ast::Instr::$instr(imm0) = *id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    buffer.push(imm0);
+                    continue;
+                }
+            };
+
+            ($prefix:expr, $op:expr, $instr:ident(u8, u8)) => {
+                if let ast::Instr::$instr(imm0, imm1) = *id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    buffer.push(imm0);
+                    buffer.push(imm1);
+                    continue;
+                }
+            };
+
+            ($prefix:expr, $op:expr, $instr:ident(u32)) => {
+                if let ast::Instr::$instr(imm0) = *id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
                     write_unsigned_leb128(buffer, imm0 as u64);
-                    write_unsigned_leb128(buffer, imm1 as u64);
                     continue;
                 }
             };
 
-            ($byte:expr, $instr:ident(MutableValue<u32>, u32)) => {
-                if let ast::Instr::$instr(imm0, imm1) = id {
-                    buffer.push($byte);
-                    let imm0 = imm0.lock().unwrap().value;
+            ($prefix:expr, $op:expr, $instr:ident(u32, u8)) => {
+                if let ast::Instr::$instr(imm0, imm1) = *id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
                     write_unsigned_leb128(buffer, imm0 as u64);
-                    write_unsigned_leb128(buffer, imm1 as u64);
+                    buffer.push(imm1);
                     continue;
                 }
             };
 
-            ($byte:expr, $instr:ident(Vec<u32>, u32)) => {
-                if let ast::Instr::$instr(imm0, imm1) = id.clone() {
-                    buffer.push($byte);
-                    write_vec_len(buffer, &imm0); // vec lengh
-                    for imm in imm0 {
-                        write_unsigned_leb128(buffer, imm as u64);
-                    }
+            ($prefix:expr, $op:expr, $instr:ident(u32, u32)) => {
+                if let ast::Instr::$instr(imm0, imm1) = *id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    write_unsigned_leb128(buffer, imm0 as u64);
                     write_unsigned_leb128(buffer, imm1 as u64);
                     continue;
                 }
             };
+
+            ($prefix:expr, $op:expr, $instr:ident(memarg)) => {
+                if let ast::Instr::$instr(imm0) = id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    write_memarg(buffer, imm0);
+                    continue;
+                }
+            };
+
+            ($prefix:expr, $op:expr, $instr:ident(memarg, u8)) => {
+                if let ast::Instr::$instr(imm0, imm1) = id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    write_memarg(buffer, imm0);
+                    buffer.push(*imm1);
+                    continue;
+                }
+            };
+
+            ($prefix:expr, $op:expr, $instr:ident(u128)) => {
+                if let ast::Instr::$instr(imm0) = *id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    buffer.extend(imm0.to_le_bytes());
+                    continue;
+                }
+            };
+
+            ($prefix:expr, $op:expr, $instr:ident([u8; 16])) => {
+                if let ast::Instr::$instr(imm0) = *id {
+                    buffer.push($prefix);
+                    write_unsigned_leb128(buffer, $op);
+                    buffer.extend(imm0);
+                    continue;
+                }
+            };
         }
 
         write_instr!(0x00, unreachable);
         write_instr!(0x01, nop);
 
-        if let ast::Instr::Block(ref block_type, body) = id {
+        if let ast::Instr::Block(block_type, body) = id {
             buffer.push(0x02);
             write_blocktype(buffer, block_type);
-            write_code_expr(buffer, &body.lock().unwrap().value);
+            write_code_expr_with_offsets(buffer, &body.value, offsets.as_deref_mut());
             continue;
         }
 
-        if let ast::Instr::Loop(ref block_type, body) = id {
+        if let ast::Instr::Loop(block_type, body) = id {
             buffer.push(0x03);
             write_blocktype(buffer, block_type);
-            write_code_expr(buffer, &body.lock().unwrap().value);
+            write_code_expr_with_offsets(buffer, &body.value, offsets.as_deref_mut());
             continue;
         }
 
-        if let ast::Instr::If(ref block_type, body) = id {
+        if let ast::Instr::If(block_type, body) = id {
             // FIXME: support IfElse, If will contain both
             buffer.push(0x04);
             write_blocktype(buffer, block_type);
-            write_code_expr(buffer, &body.lock().unwrap().value);
+            write_code_expr_with_offsets(buffer, &body.value, offsets.as_deref_mut());
             continue;
         }
 
+        // https://github.com/WebAssembly/exception-handling/blob/main/proposals/exception-handling/Exceptions.md
+        if let ast::Instr::Try(block_type, body) = id {
+            buffer.push(0x06);
+            write_blocktype(buffer, block_type);
+            write_code_expr_with_offsets(buffer, &body.value, offsets.as_deref_mut());
+            continue;
+        }
+        if let ast::Instr::TryTable(block_type, catches, body) = id {
+            buffer.push(0x1f);
+            write_blocktype(buffer, block_type);
+            write_vec_len(buffer, catches);
+            for catch in catches {
+                write_catch(buffer, catch);
+            }
+            write_code_expr_with_offsets(buffer, &body.value, offsets.as_deref_mut());
+            continue;
+        }
+        write_instr!(0x07, catch(u32));
+        write_instr!(0x08, throw(u32));
+        write_instr!(0x09, rethrow(u32));
+        write_instr!(0x0a, throw_ref);
+        write_instr!(0x18, delegate(u32));
+        write_instr!(0x19, catch_all);
+
         write_instr!(0xc, br(u32));
         write_instr!(0xd, br_if(u32));
         write_instr!(0x0b, end);
         write_instr!(0x05, else_end);
         write_instr!(0x0e, br_table(Vec<u32>, u32));
         write_instr!(0x0f, Return);
-        write_instr!(0x10, call(MutableValue<u32>));
+        write_instr!(0x10, call(u32));
         write_instr!(0x11, call_indirect(u32, u32));
 
         write_instr!(0x1a, drop);
@@ -604,33 +993,33 @@ fn write_code_expr(buffer: &mut Vec<u8>, expr: &Vec<ast::Value<ast::Instr>>) {
         write_instr!(0x25, table_get(u32));
         write_instr!(0x26, table_set(u32));
 
-        write_instr!(0x28, i32_load(MutableValue<u32>, u32));
-        write_instr!(0x29, i64_load(MutableValue<u32>, u32));
-        write_instr!(0x2a, f32_load(MutableValue<u32>, u32));
-        write_instr!(0x2b, f64_load(MutableValue<u32>, u32));
-        write_instr!(0x2c, i32_load8_s(MutableValue<u32>, u32));
-        write_instr!(0x2d, i32_load8_u(MutableValue<u32>, u32));
-        write_instr!(0x2e, i32_load16_s(MutableValue<u32>, u32));
-        write_instr!(0x2f, i32_load16_u(MutableValue<u32>, u32));
-        write_instr!(0x30, i64_load8_s(MutableValue<u32>, u32));
-        write_instr!(0x31, i64_load8_u(MutableValue<u32>, u32));
-        write_instr!(0x32, i64_load16_s(MutableValue<u32>, u32));
-        write_instr!(0x33, i64_load16_u(MutableValue<u32>, u32));
-        write_instr!(0x34, i64_load32_s(MutableValue<u32>, u32));
-        write_instr!(0x35, i64_load32_u(MutableValue<u32>, u32));
-
-        write_instr!(0x36, i32_store(MutableValue<u32>, u32));
-        write_instr!(0x37, i64_store(MutableValue<u32>, u32));
-        write_instr!(0x38, f32_store(MutableValue<u32>, u32));
-        write_instr!(0x39, f64_store(MutableValue<u32>, u32));
-        write_instr!(0x3a, i32_store8(MutableValue<u32>, u32));
-        write_instr!(0x3b, i32_store16(MutableValue<u32>, u32));
-        write_instr!(0x3c, i64_store8(MutableValue<u32>, u32));
-        write_instr!(0x3d, i64_store16(MutableValue<u32>, u32));
-        write_instr!(0x3e, i64_store32(MutableValue<u32>, u32));
-
-        write_instr!(0x3f, memory_size(u8));
-        write_instr!(0x40, memory_grow(u8));
+        write_instr!(0x28, i32_load(memarg));
+        write_instr!(0x29, i64_load(memarg));
+        write_instr!(0x2a, f32_load(memarg));
+        write_instr!(0x2b, f64_load(memarg));
+        write_instr!(0x2c, i32_load8_s(memarg));
+        write_instr!(0x2d, i32_load8_u(memarg));
+        write_instr!(0x2e, i32_load16_s(memarg));
+        write_instr!(0x2f, i32_load16_u(memarg));
+        write_instr!(0x30, i64_load8_s(memarg));
+        write_instr!(0x31, i64_load8_u(memarg));
+        write_instr!(0x32, i64_load16_s(memarg));
+        write_instr!(0x33, i64_load16_u(memarg));
+        write_instr!(0x34, i64_load32_s(memarg));
+        write_instr!(0x35, i64_load32_u(memarg));
+
+        write_instr!(0x36, i32_store(memarg));
+        write_instr!(0x37, i64_store(memarg));
+        write_instr!(0x38, f32_store(memarg));
+        write_instr!(0x39, f64_store(memarg));
+        write_instr!(0x3a, i32_store8(memarg));
+        write_instr!(0x3b, i32_store16(memarg));
+        write_instr!(0x3c, i64_store8(memarg));
+        write_instr!(0x3d, i64_store16(memarg));
+        write_instr!(0x3e, i64_store32(memarg));
+
+        write_instr!(0x3f, memory_size(u32));
+        write_instr!(0x40, memory_grow(u32));
 
         write_instr!(0x41, i32_const(i32));
         write_instr!(0x42, i64_const(i64));
@@ -776,62 +1165,360 @@ fn write_code_expr(buffer: &mut Vec<u8>, expr: &Vec<ast::Value<ast::Instr>>) {
         write_instr!(0xc3, i64_extend16_s);
         write_instr!(0xc4, i64_extend32_s);
 
-        // https://webassembly.github.io/spec/core/binary/instructions.html#numeric-instructions
-        // saturating truncation instructions
-        if matches!(id, ast::Instr::i32_trunc_sat_f32_s) {
-            buffer.push(0xfc);
-            buffer.push(0);
-            continue;
-        }
-        if matches!(id, ast::Instr::i32_trunc_sat_f32_u) {
-            buffer.push(0xfc);
-            buffer.push(1);
-            continue;
-        }
-        if matches!(id, ast::Instr::i32_trunc_sat_f64_s) {
-            buffer.push(0xfc);
-            buffer.push(2);
-            continue;
-        }
-        if matches!(id, ast::Instr::i32_trunc_sat_f64_u) {
-            buffer.push(0xfc);
-            buffer.push(3);
-            continue;
-        }
-        if matches!(id, ast::Instr::i64_trunc_sat_f32_s) {
-            buffer.push(0xfc);
-            buffer.push(4);
-            continue;
-        }
-        if matches!(id, ast::Instr::i64_trunc_sat_f32_u) {
-            buffer.push(0xfc);
-            buffer.push(5);
-            continue;
-        }
-        if matches!(id, ast::Instr::i64_trunc_sat_f64_s) {
-            buffer.push(0xfc);
-            buffer.push(6);
-            continue;
-        }
-        if matches!(id, ast::Instr::i64_trunc_sat_f64_u) {
-            buffer.push(0xfc);
-            buffer.push(7);
-            continue;
-        }
-
-        if let ast::Instr::memory_copy(imm0, imm1) = id {
-            buffer.push(0xfc);
-            buffer.push(10);
-            buffer.push(imm0);
-            buffer.push(imm1);
-            continue;
-        }
-        if let ast::Instr::memory_fill(imm0) = id {
-            buffer.push(0xfc);
-            buffer.push(11);
-            buffer.push(imm0);
+        if let ast::Instr::ref_null(reftype) = id {
+            buffer.push(0xd0);
+            write_reftype(buffer, reftype);
             continue;
         }
+        write_instr!(0xd1, ref_is_null);
+        write_instr!(0xd2, ref_func(u32));
+
+        // 0xfc: saturating truncation, bulk memory and table instructions
+        // 0xfd: SIMD
+        // 0xfe: threads
+        write_prefixed!(0xfc, 0x00, i32_trunc_sat_f32_s);
+        write_prefixed!(0xfc, 0x01, i32_trunc_sat_f32_u);
+        write_prefixed!(0xfc, 0x02, i32_trunc_sat_f64_s);
+        write_prefixed!(0xfc, 0x03, i32_trunc_sat_f64_u);
+        write_prefixed!(0xfc, 0x04, i64_trunc_sat_f32_s);
+        write_prefixed!(0xfc, 0x05, i64_trunc_sat_f32_u);
+        write_prefixed!(0xfc, 0x06, i64_trunc_sat_f64_s);
+        write_prefixed!(0xfc, 0x07, i64_trunc_sat_f64_u);
+        write_prefixed!(0xfc, 0x08, memory_init(u32, u8));
+        write_prefixed!(0xfc, 0x09, data_drop(u32));
+        write_prefixed!(0xfc, 0x0a, memory_copy(u32, u32));
+        write_prefixed!(0xfc, 0x0b, memory_fill(u32));
+        write_prefixed!(0xfc, 0x0c, table_init(u32, u32));
+        write_prefixed!(0xfc, 0x0d, elem_drop(u32));
+        write_prefixed!(0xfc, 0x0e, table_copy(u32, u32));
+        write_prefixed!(0xfc, 0x0f, table_grow(u32));
+        write_prefixed!(0xfc, 0x10, table_size(u32));
+        write_prefixed!(0xfc, 0x11, table_fill(u32));
+
+        write_prefixed!(0xfd, 0x00, v128_load(memarg));
+        write_prefixed!(0xfd, 0x01, v128_load8x8_s(memarg));
+        write_prefixed!(0xfd, 0x02, v128_load8x8_u(memarg));
+        write_prefixed!(0xfd, 0x03, v128_load16x4_s(memarg));
+        write_prefixed!(0xfd, 0x04, v128_load16x4_u(memarg));
+        write_prefixed!(0xfd, 0x05, v128_load32x2_s(memarg));
+        write_prefixed!(0xfd, 0x06, v128_load32x2_u(memarg));
+        write_prefixed!(0xfd, 0x07, v128_load8_splat(memarg));
+        write_prefixed!(0xfd, 0x08, v128_load16_splat(memarg));
+        write_prefixed!(0xfd, 0x09, v128_load32_splat(memarg));
+        write_prefixed!(0xfd, 0x0a, v128_load64_splat(memarg));
+        write_prefixed!(0xfd, 0x0b, v128_store(memarg));
+        write_prefixed!(0xfd, 0x0c, v128_const(u128));
+        write_prefixed!(0xfd, 0x0d, i8x16_shuffle([u8; 16]));
+        write_prefixed!(0xfd, 0x0e, i8x16_swizzle);
+        write_prefixed!(0xfd, 0x0f, i8x16_splat);
+        write_prefixed!(0xfd, 0x10, i16x8_splat);
+        write_prefixed!(0xfd, 0x11, i32x4_splat);
+        write_prefixed!(0xfd, 0x12, i64x2_splat);
+        write_prefixed!(0xfd, 0x13, f32x4_splat);
+        write_prefixed!(0xfd, 0x14, f64x2_splat);
+        write_prefixed!(0xfd, 0x15, i8x16_extract_lane_s(u8));
+        write_prefixed!(0xfd, 0x16, i8x16_extract_lane_u(u8));
+        write_prefixed!(0xfd, 0x17, i8x16_replace_lane(u8));
+        write_prefixed!(0xfd, 0x18, i16x8_extract_lane_s(u8));
+        write_prefixed!(0xfd, 0x19, i16x8_extract_lane_u(u8));
+        write_prefixed!(0xfd, 0x1a, i16x8_replace_lane(u8));
+        write_prefixed!(0xfd, 0x1b, i32x4_extract_lane(u8));
+        write_prefixed!(0xfd, 0x1c, i32x4_replace_lane(u8));
+        write_prefixed!(0xfd, 0x1d, i64x2_extract_lane(u8));
+        write_prefixed!(0xfd, 0x1e, i64x2_replace_lane(u8));
+        write_prefixed!(0xfd, 0x1f, f32x4_extract_lane(u8));
+        write_prefixed!(0xfd, 0x20, f32x4_replace_lane(u8));
+        write_prefixed!(0xfd, 0x21, f64x2_extract_lane(u8));
+        write_prefixed!(0xfd, 0x22, f64x2_replace_lane(u8));
+        write_prefixed!(0xfd, 0x23, i8x16_eq);
+        write_prefixed!(0xfd, 0x24, i8x16_ne);
+        write_prefixed!(0xfd, 0x25, i8x16_lt_s);
+        write_prefixed!(0xfd, 0x26, i8x16_lt_u);
+        write_prefixed!(0xfd, 0x27, i8x16_gt_s);
+        write_prefixed!(0xfd, 0x28, i8x16_gt_u);
+        write_prefixed!(0xfd, 0x29, i8x16_le_s);
+        write_prefixed!(0xfd, 0x2a, i8x16_le_u);
+        write_prefixed!(0xfd, 0x2b, i8x16_ge_s);
+        write_prefixed!(0xfd, 0x2c, i8x16_ge_u);
+        write_prefixed!(0xfd, 0x2d, i16x8_eq);
+        write_prefixed!(0xfd, 0x2e, i16x8_ne);
+        write_prefixed!(0xfd, 0x2f, i16x8_lt_s);
+        write_prefixed!(0xfd, 0x30, i16x8_lt_u);
+        write_prefixed!(0xfd, 0x31, i16x8_gt_s);
+        write_prefixed!(0xfd, 0x32, i16x8_gt_u);
+        write_prefixed!(0xfd, 0x33, i16x8_le_s);
+        write_prefixed!(0xfd, 0x34, i16x8_le_u);
+        write_prefixed!(0xfd, 0x35, i16x8_ge_s);
+        write_prefixed!(0xfd, 0x36, i16x8_ge_u);
+        write_prefixed!(0xfd, 0x37, i32x4_eq);
+        write_prefixed!(0xfd, 0x38, i32x4_ne);
+        write_prefixed!(0xfd, 0x39, i32x4_lt_s);
+        write_prefixed!(0xfd, 0x3a, i32x4_lt_u);
+        write_prefixed!(0xfd, 0x3b, i32x4_gt_s);
+        write_prefixed!(0xfd, 0x3c, i32x4_gt_u);
+        write_prefixed!(0xfd, 0x3d, i32x4_le_s);
+        write_prefixed!(0xfd, 0x3e, i32x4_le_u);
+        write_prefixed!(0xfd, 0x3f, i32x4_ge_s);
+        write_prefixed!(0xfd, 0x40, i32x4_ge_u);
+        write_prefixed!(0xfd, 0x41, f32x4_eq);
+        write_prefixed!(0xfd, 0x42, f32x4_ne);
+        write_prefixed!(0xfd, 0x43, f32x4_lt);
+        write_prefixed!(0xfd, 0x44, f32x4_gt);
+        write_prefixed!(0xfd, 0x45, f32x4_le);
+        write_prefixed!(0xfd, 0x46, f32x4_ge);
+        write_prefixed!(0xfd, 0x47, f64x2_eq);
+        write_prefixed!(0xfd, 0x48, f64x2_ne);
+        write_prefixed!(0xfd, 0x49, f64x2_lt);
+        write_prefixed!(0xfd, 0x4a, f64x2_gt);
+        write_prefixed!(0xfd, 0x4b, f64x2_le);
+        write_prefixed!(0xfd, 0x4c, f64x2_ge);
+        write_prefixed!(0xfd, 0x4d, v128_not);
+        write_prefixed!(0xfd, 0x4e, v128_and);
+        write_prefixed!(0xfd, 0x4f, v128_andnot);
+        write_prefixed!(0xfd, 0x50, v128_or);
+        write_prefixed!(0xfd, 0x51, v128_xor);
+        write_prefixed!(0xfd, 0x52, v128_bitselect);
+        write_prefixed!(0xfd, 0x53, v128_any_true);
+        write_prefixed!(0xfd, 0x54, v128_load8_lane(memarg, u8));
+        write_prefixed!(0xfd, 0x55, v128_load16_lane(memarg, u8));
+        write_prefixed!(0xfd, 0x56, v128_load32_lane(memarg, u8));
+        write_prefixed!(0xfd, 0x57, v128_load64_lane(memarg, u8));
+        write_prefixed!(0xfd, 0x58, v128_store8_lane(memarg, u8));
+        write_prefixed!(0xfd, 0x59, v128_store16_lane(memarg, u8));
+        write_prefixed!(0xfd, 0x5a, v128_store32_lane(memarg, u8));
+        write_prefixed!(0xfd, 0x5b, v128_store64_lane(memarg, u8));
+        write_prefixed!(0xfd, 0x5c, v128_load32_zero(memarg));
+        write_prefixed!(0xfd, 0x5d, v128_load64_zero(memarg));
+        write_prefixed!(0xfd, 0x5e, f32x4_demote_f64x2_zero);
+        write_prefixed!(0xfd, 0x5f, f64x2_promote_low_f32x4);
+        write_prefixed!(0xfd, 0x60, i8x16_abs);
+        write_prefixed!(0xfd, 0x61, i8x16_neg);
+        write_prefixed!(0xfd, 0x62, i8x16_popcnt);
+        write_prefixed!(0xfd, 0x63, i8x16_all_true);
+        write_prefixed!(0xfd, 0x64, i8x16_bitmask);
+        write_prefixed!(0xfd, 0x65, i8x16_narrow_i16x8_s);
+        write_prefixed!(0xfd, 0x66, i8x16_narrow_i16x8_u);
+        write_prefixed!(0xfd, 0x67, f32x4_ceil);
+        write_prefixed!(0xfd, 0x68, f32x4_floor);
+        write_prefixed!(0xfd, 0x69, f32x4_trunc);
+        write_prefixed!(0xfd, 0x6a, f32x4_nearest);
+        write_prefixed!(0xfd, 0x6b, i8x16_shl);
+        write_prefixed!(0xfd, 0x6c, i8x16_shr_s);
+        write_prefixed!(0xfd, 0x6d, i8x16_shr_u);
+        write_prefixed!(0xfd, 0x6e, i8x16_add);
+        write_prefixed!(0xfd, 0x6f, i8x16_add_sat_s);
+        write_prefixed!(0xfd, 0x70, i8x16_add_sat_u);
+        write_prefixed!(0xfd, 0x71, i8x16_sub);
+        write_prefixed!(0xfd, 0x72, i8x16_sub_sat_s);
+        write_prefixed!(0xfd, 0x73, i8x16_sub_sat_u);
+        write_prefixed!(0xfd, 0x74, f64x2_ceil);
+        write_prefixed!(0xfd, 0x75, f64x2_floor);
+        write_prefixed!(0xfd, 0x76, i8x16_min_s);
+        write_prefixed!(0xfd, 0x77, i8x16_min_u);
+        write_prefixed!(0xfd, 0x78, i8x16_max_s);
+        write_prefixed!(0xfd, 0x79, i8x16_max_u);
+        write_prefixed!(0xfd, 0x7a, f64x2_trunc);
+        write_prefixed!(0xfd, 0x7b, i8x16_avgr_u);
+        write_prefixed!(0xfd, 0x7c, i16x8_extadd_pairwise_i8x16_s);
+        write_prefixed!(0xfd, 0x7d, i16x8_extadd_pairwise_i8x16_u);
+        write_prefixed!(0xfd, 0x7e, i32x4_extadd_pairwise_i16x8_s);
+        write_prefixed!(0xfd, 0x7f, i32x4_extadd_pairwise_i16x8_u);
+        write_prefixed!(0xfd, 0x80, i16x8_abs);
+        write_prefixed!(0xfd, 0x81, i16x8_neg);
+        write_prefixed!(0xfd, 0x82, i16x8_q15mulr_sat_s);
+        write_prefixed!(0xfd, 0x83, i16x8_all_true);
+        write_prefixed!(0xfd, 0x84, i16x8_bitmask);
+        write_prefixed!(0xfd, 0x85, i16x8_narrow_i32x4_s);
+        write_prefixed!(0xfd, 0x86, i16x8_narrow_i32x4_u);
+        write_prefixed!(0xfd, 0x87, i16x8_extend_low_i8x16_s);
+        write_prefixed!(0xfd, 0x88, i16x8_extend_high_i8x16_s);
+        write_prefixed!(0xfd, 0x89, i16x8_extend_low_i8x16_u);
+        write_prefixed!(0xfd, 0x8a, i16x8_extend_high_i8x16_u);
+        write_prefixed!(0xfd, 0x8b, i16x8_shl);
+        write_prefixed!(0xfd, 0x8c, i16x8_shr_s);
+        write_prefixed!(0xfd, 0x8d, i16x8_shr_u);
+        write_prefixed!(0xfd, 0x8e, i16x8_add);
+        write_prefixed!(0xfd, 0x8f, i16x8_add_sat_s);
+        write_prefixed!(0xfd, 0x90, i16x8_add_sat_u);
+        write_prefixed!(0xfd, 0x91, i16x8_sub);
+        write_prefixed!(0xfd, 0x92, i16x8_sub_sat_s);
+        write_prefixed!(0xfd, 0x93, i16x8_sub_sat_u);
+        write_prefixed!(0xfd, 0x94, f64x2_nearest);
+        write_prefixed!(0xfd, 0x95, i16x8_mul);
+        write_prefixed!(0xfd, 0x96, i16x8_min_s);
+        write_prefixed!(0xfd, 0x97, i16x8_min_u);
+        write_prefixed!(0xfd, 0x98, i16x8_max_s);
+        write_prefixed!(0xfd, 0x99, i16x8_max_u);
+        write_prefixed!(0xfd, 0x9b, i16x8_avgr_u);
+        write_prefixed!(0xfd, 0x9c, i16x8_extmul_low_i8x16_s);
+        write_prefixed!(0xfd, 0x9d, i16x8_extmul_high_i8x16_s);
+        write_prefixed!(0xfd, 0x9e, i16x8_extmul_low_i8x16_u);
+        write_prefixed!(0xfd, 0x9f, i16x8_extmul_high_i8x16_u);
+        write_prefixed!(0xfd, 0xa0, i32x4_abs);
+        write_prefixed!(0xfd, 0xa1, i32x4_neg);
+        write_prefixed!(0xfd, 0xa3, i32x4_all_true);
+        write_prefixed!(0xfd, 0xa4, i32x4_bitmask);
+        write_prefixed!(0xfd, 0xa7, i32x4_extend_low_i16x8_s);
+        write_prefixed!(0xfd, 0xa8, i32x4_extend_high_i16x8_s);
+        write_prefixed!(0xfd, 0xa9, i32x4_extend_low_i16x8_u);
+        write_prefixed!(0xfd, 0xaa, i32x4_extend_high_i16x8_u);
+        write_prefixed!(0xfd, 0xab, i32x4_shl);
+        write_prefixed!(0xfd, 0xac, i32x4_shr_s);
+        write_prefixed!(0xfd, 0xad, i32x4_shr_u);
+        write_prefixed!(0xfd, 0xae, i32x4_add);
+        write_prefixed!(0xfd, 0xb1, i32x4_sub);
+        write_prefixed!(0xfd, 0xb5, i32x4_mul);
+        write_prefixed!(0xfd, 0xb6, i32x4_min_s);
+        write_prefixed!(0xfd, 0xb7, i32x4_min_u);
+        write_prefixed!(0xfd, 0xb8, i32x4_max_s);
+        write_prefixed!(0xfd, 0xb9, i32x4_max_u);
+        write_prefixed!(0xfd, 0xba, i32x4_dot_i16x8_s);
+        write_prefixed!(0xfd, 0xbc, i32x4_extmul_low_i16x8_s);
+        write_prefixed!(0xfd, 0xbd, i32x4_extmul_high_i16x8_s);
+        write_prefixed!(0xfd, 0xbe, i32x4_extmul_low_i16x8_u);
+        write_prefixed!(0xfd, 0xbf, i32x4_extmul_high_i16x8_u);
+        write_prefixed!(0xfd, 0xc0, i64x2_abs);
+        write_prefixed!(0xfd, 0xc1, i64x2_neg);
+        write_prefixed!(0xfd, 0xc3, i64x2_all_true);
+        write_prefixed!(0xfd, 0xc4, i64x2_bitmask);
+        write_prefixed!(0xfd, 0xc7, i64x2_extend_low_i32x4_s);
+        write_prefixed!(0xfd, 0xc8, i64x2_extend_high_i32x4_s);
+        write_prefixed!(0xfd, 0xc9, i64x2_extend_low_i32x4_u);
+        write_prefixed!(0xfd, 0xca, i64x2_extend_high_i32x4_u);
+        write_prefixed!(0xfd, 0xcb, i64x2_shl);
+        write_prefixed!(0xfd, 0xcc, i64x2_shr_s);
+        write_prefixed!(0xfd, 0xcd, i64x2_shr_u);
+        write_prefixed!(0xfd, 0xce, i64x2_add);
+        write_prefixed!(0xfd, 0xd1, i64x2_sub);
+        write_prefixed!(0xfd, 0xd5, i64x2_mul);
+        write_prefixed!(0xfd, 0xd6, i64x2_eq);
+        write_prefixed!(0xfd, 0xd7, i64x2_ne);
+        write_prefixed!(0xfd, 0xd8, i64x2_lt_s);
+        write_prefixed!(0xfd, 0xd9, i64x2_gt_s);
+        write_prefixed!(0xfd, 0xda, i64x2_le_s);
+        write_prefixed!(0xfd, 0xdb, i64x2_ge_s);
+        write_prefixed!(0xfd, 0xdc, i64x2_extmul_low_i32x4_s);
+        write_prefixed!(0xfd, 0xdd, i64x2_extmul_high_i32x4_s);
+        write_prefixed!(0xfd, 0xde, i64x2_extmul_low_i32x4_u);
+        write_prefixed!(0xfd, 0xdf, i64x2_extmul_high_i32x4_u);
+        write_prefixed!(0xfd, 0xe0, f32x4_abs);
+        write_prefixed!(0xfd, 0xe1, f32x4_neg);
+        write_prefixed!(0xfd, 0xe3, f32x4_sqrt);
+        write_prefixed!(0xfd, 0xe4, f32x4_add);
+        write_prefixed!(0xfd, 0xe5, f32x4_sub);
+        write_prefixed!(0xfd, 0xe6, f32x4_mul);
+        write_prefixed!(0xfd, 0xe7, f32x4_div);
+        write_prefixed!(0xfd, 0xe8, f32x4_min);
+        write_prefixed!(0xfd, 0xe9, f32x4_max);
+        write_prefixed!(0xfd, 0xea, f32x4_pmin);
+        write_prefixed!(0xfd, 0xeb, f32x4_pmax);
+        write_prefixed!(0xfd, 0xec, f64x2_abs);
+        write_prefixed!(0xfd, 0xed, f64x2_neg);
+        write_prefixed!(0xfd, 0xef, f64x2_sqrt);
+        write_prefixed!(0xfd, 0xf0, f64x2_add);
+        write_prefixed!(0xfd, 0xf1, f64x2_sub);
+        write_prefixed!(0xfd, 0xf2, f64x2_mul);
+        write_prefixed!(0xfd, 0xf3, f64x2_div);
+        write_prefixed!(0xfd, 0xf4, f64x2_min);
+        write_prefixed!(0xfd, 0xf5, f64x2_max);
+        write_prefixed!(0xfd, 0xf6, f64x2_pmin);
+        write_prefixed!(0xfd, 0xf7, f64x2_pmax);
+        write_prefixed!(0xfd, 0xf8, i32x4_trunc_sat_f32x4_s);
+        write_prefixed!(0xfd, 0xf9, i32x4_trunc_sat_f32x4_u);
+        write_prefixed!(0xfd, 0xfa, f32x4_convert_i32x4_s);
+        write_prefixed!(0xfd, 0xfb, f32x4_convert_i32x4_u);
+        write_prefixed!(0xfd, 0xfc, i32x4_trunc_sat_f64x2_s_zero);
+        write_prefixed!(0xfd, 0xfd, i32x4_trunc_sat_f64x2_u_zero);
+        write_prefixed!(0xfd, 0xfe, f64x2_convert_low_i32x4_s);
+        write_prefixed!(0xfd, 0xff, f64x2_convert_low_i32x4_u);
+        write_prefixed!(0xfd, 0x100, i8x16_relaxed_swizzle);
+        write_prefixed!(0xfd, 0x101, i32x4_relaxed_trunc_f32x4_s);
+        write_prefixed!(0xfd, 0x102, i32x4_relaxed_trunc_f32x4_u);
+        write_prefixed!(0xfd, 0x103, i32x4_relaxed_trunc_f64x2_s_zero);
+        write_prefixed!(0xfd, 0x104, i32x4_relaxed_trunc_f64x2_u_zero);
+        write_prefixed!(0xfd, 0x105, f32x4_relaxed_madd);
+        write_prefixed!(0xfd, 0x106, f32x4_relaxed_nmadd);
+        write_prefixed!(0xfd, 0x107, f64x2_relaxed_madd);
+        write_prefixed!(0xfd, 0x108, f64x2_relaxed_nmadd);
+        write_prefixed!(0xfd, 0x109, i8x16_relaxed_laneselect);
+        write_prefixed!(0xfd, 0x10a, i16x8_relaxed_laneselect);
+        write_prefixed!(0xfd, 0x10b, i32x4_relaxed_laneselect);
+        write_prefixed!(0xfd, 0x10c, i64x2_relaxed_laneselect);
+        write_prefixed!(0xfd, 0x10d, f32x4_relaxed_min);
+        write_prefixed!(0xfd, 0x10e, f32x4_relaxed_max);
+        write_prefixed!(0xfd, 0x10f, f64x2_relaxed_min);
+        write_prefixed!(0xfd, 0x110, f64x2_relaxed_max);
+        write_prefixed!(0xfd, 0x111, i16x8_relaxed_q15mulr_s);
+        write_prefixed!(0xfd, 0x112, i16x8_relaxed_dot_i8x16_i7x16_s);
+        write_prefixed!(0xfd, 0x113, i32x4_relaxed_dot_i8x16_i7x16_add_s);
+
+        write_prefixed!(0xfe, 0x00, memory_atomic_notify(memarg));
+        write_prefixed!(0xfe, 0x01, memory_atomic_wait32(memarg));
+        write_prefixed!(0xfe, 0x02, memory_atomic_wait64(memarg));
+        write_prefixed!(0xfe, 0x03, atomic_fence(fence));
+        write_prefixed!(0xfe, 0x10, i32_atomic_load(memarg));
+        write_prefixed!(0xfe, 0x11, i64_atomic_load(memarg));
+        write_prefixed!(0xfe, 0x12, i32_atomic_load8_u(memarg));
+        write_prefixed!(0xfe, 0x13, i32_atomic_load16_u(memarg));
+        write_prefixed!(0xfe, 0x14, i64_atomic_load8_u(memarg));
+        write_prefixed!(0xfe, 0x15, i64_atomic_load16_u(memarg));
+        write_prefixed!(0xfe, 0x16, i64_atomic_load32_u(memarg));
+        write_prefixed!(0xfe, 0x17, i32_atomic_store(memarg));
+        write_prefixed!(0xfe, 0x18, i64_atomic_store(memarg));
+        write_prefixed!(0xfe, 0x19, i32_atomic_store8(memarg));
+        write_prefixed!(0xfe, 0x1a, i32_atomic_store16(memarg));
+        write_prefixed!(0xfe, 0x1b, i64_atomic_store8(memarg));
+        write_prefixed!(0xfe, 0x1c, i64_atomic_store16(memarg));
+        write_prefixed!(0xfe, 0x1d, i64_atomic_store32(memarg));
+        write_prefixed!(0xfe, 0x1e, i32_atomic_rmw_add(memarg));
+        write_prefixed!(0xfe, 0x1f, i64_atomic_rmw_add(memarg));
+        write_prefixed!(0xfe, 0x20, i32_atomic_rmw8_add_u(memarg));
+        write_prefixed!(0xfe, 0x21, i32_atomic_rmw16_add_u(memarg));
+        write_prefixed!(0xfe, 0x22, i64_atomic_rmw8_add_u(memarg));
+        write_prefixed!(0xfe, 0x23, i64_atomic_rmw16_add_u(memarg));
+        write_prefixed!(0xfe, 0x24, i64_atomic_rmw32_add_u(memarg));
+        write_prefixed!(0xfe, 0x25, i32_atomic_rmw_sub(memarg));
+        write_prefixed!(0xfe, 0x26, i64_atomic_rmw_sub(memarg));
+        write_prefixed!(0xfe, 0x27, i32_atomic_rmw8_sub_u(memarg));
+        write_prefixed!(0xfe, 0x28, i32_atomic_rmw16_sub_u(memarg));
+        write_prefixed!(0xfe, 0x29, i64_atomic_rmw8_sub_u(memarg));
+        write_prefixed!(0xfe, 0x2a, i64_atomic_rmw16_sub_u(memarg));
+        write_prefixed!(0xfe, 0x2b, i64_atomic_rmw32_sub_u(memarg));
+        write_prefixed!(0xfe, 0x2c, i32_atomic_rmw_and(memarg));
+        write_prefixed!(0xfe, 0x2d, i64_atomic_rmw_and(memarg));
+        write_prefixed!(0xfe, 0x2e, i32_atomic_rmw8_and_u(memarg));
+        write_prefixed!(0xfe, 0x2f, i32_atomic_rmw16_and_u(memarg));
+        write_prefixed!(0xfe, 0x30, i64_atomic_rmw8_and_u(memarg));
+        write_prefixed!(0xfe, 0x31, i64_atomic_rmw16_and_u(memarg));
+        write_prefixed!(0xfe, 0x32, i64_atomic_rmw32_and_u(memarg));
+        write_prefixed!(0xfe, 0x33, i32_atomic_rmw_or(memarg));
+        write_prefixed!(0xfe, 0x34, i64_atomic_rmw_or(memarg));
+        write_prefixed!(0xfe, 0x35, i32_atomic_rmw8_or_u(memarg));
+        write_prefixed!(0xfe, 0x36, i32_atomic_rmw16_or_u(memarg));
+        write_prefixed!(0xfe, 0x37, i64_atomic_rmw8_or_u(memarg));
+        write_prefixed!(0xfe, 0x38, i64_atomic_rmw16_or_u(memarg));
+        write_prefixed!(0xfe, 0x39, i64_atomic_rmw32_or_u(memarg));
+        write_prefixed!(0xfe, 0x3a, i32_atomic_rmw_xor(memarg));
+        write_prefixed!(0xfe, 0x3b, i64_atomic_rmw_xor(memarg));
+        write_prefixed!(0xfe, 0x3c, i32_atomic_rmw8_xor_u(memarg));
+        write_prefixed!(0xfe, 0x3d, i32_atomic_rmw16_xor_u(memarg));
+        write_prefixed!(0xfe, 0x3e, i64_atomic_rmw8_xor_u(memarg));
+        write_prefixed!(0xfe, 0x3f, i64_atomic_rmw16_xor_u(memarg));
+        write_prefixed!(0xfe, 0x40, i64_atomic_rmw32_xor_u(memarg));
+        write_prefixed!(0xfe, 0x41, i32_atomic_rmw_xchg(memarg));
+        write_prefixed!(0xfe, 0x42, i64_atomic_rmw_xchg(memarg));
+        write_prefixed!(0xfe, 0x43, i32_atomic_rmw8_xchg_u(memarg));
+        write_prefixed!(0xfe, 0x44, i32_atomic_rmw16_xchg_u(memarg));
+        write_prefixed!(0xfe, 0x45, i64_atomic_rmw8_xchg_u(memarg));
+        write_prefixed!(0xfe, 0x46, i64_atomic_rmw16_xchg_u(memarg));
+        write_prefixed!(0xfe, 0x47, i64_atomic_rmw32_xchg_u(memarg));
+        write_prefixed!(0xfe, 0x48, i32_atomic_rmw_cmpxchg(memarg));
+        write_prefixed!(0xfe, 0x49, i64_atomic_rmw_cmpxchg(memarg));
+        write_prefixed!(0xfe, 0x4a, i32_atomic_rmw8_cmpxchg_u(memarg));
+        write_prefixed!(0xfe, 0x4b, i32_atomic_rmw16_cmpxchg_u(memarg));
+        write_prefixed!(0xfe, 0x4c, i64_atomic_rmw8_cmpxchg_u(memarg));
+        write_prefixed!(0xfe, 0x4d, i64_atomic_rmw16_cmpxchg_u(memarg));
+        write_prefixed!(0xfe, 0x4e, i64_atomic_rmw32_cmpxchg_u(memarg));
 
         unimplemented!("unknown instruction: {:#?}", id);
     }
@@ -872,6 +1559,29 @@ fn write_float_f32(buffer: &mut Vec<u8>, n: f32) {
     buffer.extend(b.iter())
 }
 
+fn write_catch(buffer: &mut Vec<u8>, catch: &ast::Catch) {
+    match catch {
+        ast::Catch::Catch(tagidx, labelidx) => {
+            buffer.push(0x00);
+            write_unsigned_leb128(buffer, *tagidx as u64);
+            write_unsigned_leb128(buffer, *labelidx as u64);
+        }
+        ast::Catch::CatchRef(tagidx, labelidx) => {
+            buffer.push(0x01);
+            write_unsigned_leb128(buffer, *tagidx as u64);
+            write_unsigned_leb128(buffer, *labelidx as u64);
+        }
+        ast::Catch::CatchAll(labelidx) => {
+            buffer.push(0x02);
+            write_unsigned_leb128(buffer, *labelidx as u64);
+        }
+        ast::Catch::CatchAllRef(labelidx) => {
+            buffer.push(0x03);
+            write_unsigned_leb128(buffer, *labelidx as u64);
+        }
+    }
+}
+
 fn write_blocktype(buffer: &mut Vec<u8>, block_type: &ast::BlockType) {
     match block_type {
         ast::BlockType::Empty => {
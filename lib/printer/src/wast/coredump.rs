@@ -13,6 +13,9 @@ pub fn dump_coredump<W: Write>(
     for stack in &coredump.stacks {
         dump_stack(out, 1, &stack)?;
     }
+    for global in &coredump.globals {
+        dump_global(out, 1, global)?;
+    }
     if !coredump.data.is_empty() {
         dump_data(out, 1, &coredump.data)?;
     }
@@ -84,6 +87,24 @@ fn dump_frame<W: Write>(
     Ok(())
 }
 
+fn dump_global<W: Write>(
+    out: &mut W,
+    depth: usize,
+    global: &wasm_coredump_types::Global,
+) -> Result<(), BoxError> {
+    let tab = TAB.repeat(depth);
+    write!(out, "{}(global", tab)?;
+    if let Some(name) = &global.name {
+        write!(out, " (name \"{}\")", name)?;
+    }
+    write!(out, " ")?;
+    dump_value_type(out, 0, &global.value)?;
+    write!(out, " ")?;
+    dump_value(out, 0, &global.value)?;
+    writeln!(out, ")")?;
+    Ok(())
+}
+
 fn dump_value<W: Write>(
     out: &mut W,
     _depth: usize,
@@ -38,26 +38,225 @@ impl<'a, W: Write> WastPrinter<'a, W> {
             ast::ValueType::NumType(ast::NumType::F64) => "f64",
             ast::ValueType::NumType(ast::NumType::I32) => "i32",
             ast::ValueType::NumType(ast::NumType::I64) => "i64",
+            ast::ValueType::VectorType => "v128",
+            ast::ValueType::RefType(ast::Reftype::Func) => "funcref",
+            ast::ValueType::RefType(ast::Reftype::Extern) => "externref",
+            ast::ValueType::RefType(ast::Reftype::Exn) => "exnref",
         };
         write!(self.out, "{}", t.bright_red())?;
         Ok(())
     }
 
+    /// Print a constant expression, without its final `end`.
     pub fn print_expr(&mut self, node: &ast::Expr) -> Result<(), BoxError> {
+        let instrs = match node.value.split_last() {
+            Some((last, rest)) if matches!(last.value, ast::Instr::end) => rest,
+            _ => &node.value,
+        };
         write!(self.out, "(")?;
-        for instr in &node.value {
+        self.print_instrs(instrs)?;
+        write!(self.out, ")")?;
+        Ok(())
+    }
+
+    fn print_instrs(&mut self, instrs: &[ast::Value<ast::Instr>]) -> Result<(), BoxError> {
+        for (i, instr) in instrs.iter().enumerate() {
+            if i > 0 {
+                write!(self.out, " ")?;
+            }
             self.print_instr(&instr.value)?;
         }
-        write!(self.out, ")")?;
         Ok(())
     }
 
+    fn print_block(
+        &mut self,
+        name: &str,
+        block_type: &ast::BlockType,
+        body: &ast::Expr,
+    ) -> Result<(), BoxError> {
+        write!(self.out, "{}", name)?;
+        self.print_blocktype(block_type)?;
+        write!(self.out, " ")?;
+        self.print_instrs(&body.value)
+    }
+
+    fn print_blocktype(&mut self, node: &ast::BlockType) -> Result<(), BoxError> {
+        match node {
+            ast::BlockType::Empty => {}
+            ast::BlockType::ValueType(valtype) => {
+                write!(self.out, " ({} ", "result".bright_red())?;
+                self.print_valuetype(valtype)?;
+                write!(self.out, ")")?;
+            }
+            ast::BlockType::Typeidx(typeidx) => {
+                write!(self.out, " (type {})", typeidx.to_string().blue())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print an instruction in the flat format, the body of blocks ends with
+    /// `end`.
     pub fn print_instr(&mut self, node: &ast::Instr) -> Result<(), BoxError> {
         use ast::Instr::*;
 
+        if let Some(name) = instr_name(node) {
+            write!(self.out, "{}", name)?;
+            return Ok(());
+        }
+        if let Some((name, arg)) = memarg_instr(node) {
+            write!(self.out, "{}{}", name, memarg(arg))?;
+            return Ok(());
+        }
+        if let Some((name, lane)) = lane_instr(node) {
+            write!(self.out, "{} {}", name, lane.to_string().blue())?;
+            return Ok(());
+        }
+
         let expr = match node {
-            end => format!(""),
+            Block(block_type, body) => return self.print_block("block", block_type, body),
+            Loop(block_type, body) => return self.print_block("loop", block_type, body),
+            If(block_type, body) => return self.print_block("if", block_type, body),
+            Try(block_type, body) => return self.print_block("try", block_type, body),
+            TryTable(block_type, catches, body) => {
+                write!(self.out, "try_table")?;
+                self.print_blocktype(block_type)?;
+                for clause in catches {
+                    let clause = match clause {
+                        ast::Catch::Catch(tag, label) => format!("(catch {} {})", tag, label),
+                        ast::Catch::CatchRef(tag, label) => {
+                            format!("(catch_ref {} {})", tag, label)
+                        }
+                        ast::Catch::CatchAll(label) => format!("(catch_all {})", label),
+                        ast::Catch::CatchAllRef(label) => format!("(catch_all_ref {})", label),
+                    };
+                    write!(self.out, " {}", clause)?;
+                }
+                write!(self.out, " ")?;
+                return self.print_instrs(&body.value);
+            }
+
+            call(funcidx) => format!("call {}", funcidx.to_string().blue()),
+            call_indirect(typeidx, tableidx) => format!(
+                "call_indirect {} (type {})",
+                tableidx.to_string().blue(),
+                typeidx.to_string().blue()
+            ),
+            br(labelidx) => format!("br {}", labelidx.to_string().blue()),
+            br_if(labelidx) => format!("br_if {}", labelidx.to_string().blue()),
+            br_table(labels, default) => {
+                let mut expr = "br_table".to_owned();
+                for labelidx in labels.iter().chain([default]) {
+                    write!(expr, " {}", labelidx.to_string().blue())?;
+                }
+                expr
+            }
+            catch(tagidx) => format!("catch {}", tagidx.to_string().blue()),
+            delegate(labelidx) => format!("delegate {}", labelidx.to_string().blue()),
+            rethrow(labelidx) => format!("rethrow {}", labelidx.to_string().blue()),
+            throw(tagidx) => format!("throw {}", tagidx.to_string().blue()),
+
+            local_get(idx) => format!("local.get {}", idx.to_string().blue()),
+            local_set(idx) => format!("local.set {}", idx.to_string().blue()),
+            local_tee(idx) => format!("local.tee {}", idx.to_string().blue()),
+            global_get(idx) => format!("global.get {}", idx.to_string().blue()),
+            global_set(idx) => format!("global.set {}", idx.to_string().blue()),
+            table_get(idx) => format!("table.get {}", idx.to_string().blue()),
+            table_set(idx) => format!("table.set {}", idx.to_string().blue()),
+
+            ref_null(ast::Reftype::Func) => "ref.null func".to_owned(),
+            ref_null(ast::Reftype::Extern) => "ref.null extern".to_owned(),
+            ref_null(ast::Reftype::Exn) => "ref.null exn".to_owned(),
+            ref_func(funcidx) => format!("ref.func {}", funcidx.to_string().blue()),
+
+            memory_size(memidx) => format!("memory.size {}", memidx.to_string().blue()),
+            memory_grow(memidx) => format!("memory.grow {}", memidx.to_string().blue()),
+            memory_copy(dst, src) => format!(
+                "memory.copy {} {}",
+                dst.to_string().blue(),
+                src.to_string().blue()
+            ),
+            memory_fill(memidx) => format!("memory.fill {}", memidx.to_string().blue()),
+            memory_init(dataidx, memidx) => format!(
+                "memory.init {} {}",
+                memidx.to_string().blue(),
+                dataidx.to_string().blue()
+            ),
+            data_drop(dataidx) => format!("data.drop {}", dataidx.to_string().blue()),
+            table_init(elemidx, tableidx) => format!(
+                "table.init {} {}",
+                tableidx.to_string().blue(),
+                elemidx.to_string().blue()
+            ),
+            elem_drop(elemidx) => format!("elem.drop {}", elemidx.to_string().blue()),
+            table_copy(dst, src) => format!(
+                "table.copy {} {}",
+                dst.to_string().blue(),
+                src.to_string().blue()
+            ),
+            table_grow(idx) => format!("table.grow {}", idx.to_string().blue()),
+            table_size(idx) => format!("table.size {}", idx.to_string().blue()),
+            table_fill(idx) => format!("table.fill {}", idx.to_string().blue()),
+
             i32_const(v) => format!("i32.const {}", v.to_string().blue()),
+            i64_const(v) => format!("i64.const {}", v.to_string().blue()),
+            f32_const(v) => format!("f32.const {}", v.to_string().blue()),
+            f64_const(v) => format!("f64.const {}", v.to_string().blue()),
+            v128_const(v) => {
+                let mut expr = "v128.const i32x4".to_owned();
+                for lane in v.to_le_bytes().chunks(4) {
+                    let lane = u32::from_le_bytes(lane.try_into().unwrap());
+                    write!(expr, " {}", format!("{:#010x}", lane).blue())?;
+                }
+                expr
+            }
+            i8x16_shuffle(lanes) => {
+                let mut expr = "i8x16.shuffle".to_owned();
+                for lane in lanes {
+                    write!(expr, " {}", lane.to_string().blue())?;
+                }
+                expr
+            }
+            v128_load8_lane(arg, lane) => {
+                format!("v128.load8_lane{} {}", memarg(arg), lane.to_string().blue())
+            }
+            v128_load16_lane(arg, lane) => format!(
+                "v128.load16_lane{} {}",
+                memarg(arg),
+                lane.to_string().blue()
+            ),
+            v128_load32_lane(arg, lane) => format!(
+                "v128.load32_lane{} {}",
+                memarg(arg),
+                lane.to_string().blue()
+            ),
+            v128_load64_lane(arg, lane) => format!(
+                "v128.load64_lane{} {}",
+                memarg(arg),
+                lane.to_string().blue()
+            ),
+            v128_store8_lane(arg, lane) => format!(
+                "v128.store8_lane{} {}",
+                memarg(arg),
+                lane.to_string().blue()
+            ),
+            v128_store16_lane(arg, lane) => format!(
+                "v128.store16_lane{} {}",
+                memarg(arg),
+                lane.to_string().blue()
+            ),
+            v128_store32_lane(arg, lane) => format!(
+                "v128.store32_lane{} {}",
+                memarg(arg),
+                lane.to_string().blue()
+            ),
+            v128_store64_lane(arg, lane) => format!(
+                "v128.store64_lane{} {}",
+                memarg(arg),
+                lane.to_string().blue()
+            ),
+
             e => {
                 warn!("unsupported expr: {:?}", e);
                 "unknown".to_owned()
@@ -94,6 +293,10 @@ impl<'a, W: Write> WastPrinter<'a, W> {
             ast::ImportType::Global(_globaltype) => {
                 write!(self.out, "(global)")?;
             }
+
+            ast::ImportType::Tag(tag) => {
+                write!(self.out, "(tag (type {}))", tag.typeidx)?;
+            }
         }
 
         write!(self.out, ")")?;
@@ -134,3 +337,529 @@ impl<'a, W: Write> WastPrinter<'a, W> {
         Ok(())
     }
 }
+
+/// Memory argument, the alignment is printed in bytes. The memidx is only
+/// printed for memories other than the first one.
+fn memarg(arg: &ast::MemArg) -> String {
+    let mut out = String::new();
+    if arg.memidx != 0 {
+        out += &format!(" {}", arg.memidx.to_string().blue());
+    }
+    if arg.offset != 0 {
+        out += &format!(" offset={}", arg.offset.to_string().blue());
+    }
+    out += &format!(" align={}", (1u64 << arg.align).to_string().blue());
+    out
+}
+
+/// Name of the instructions without immediates.
+fn instr_name(node: &ast::Instr) -> Option<&'static str> {
+    use ast::Instr::*;
+
+    let name = match node {
+        unreachable => "unreachable",
+        nop => "nop",
+        drop => "drop",
+        select => "select",
+        ref_is_null => "ref.is_null",
+        else_end => "else",
+        end => "end",
+        Return => "return",
+        catch_all => "catch_all",
+        throw_ref => "throw_ref",
+        i32_eqz => "i32.eqz",
+        i32_eq => "i32.eq",
+        i32_ne => "i32.ne",
+        i32_lt_s => "i32.lt_s",
+        i32_lt_u => "i32.lt_u",
+        i32_gt_s => "i32.gt_s",
+        i32_gt_u => "i32.gt_u",
+        i32_le_s => "i32.le_s",
+        i32_le_u => "i32.le_u",
+        i32_ge_s => "i32.ge_s",
+        i32_ge_u => "i32.ge_u",
+        i64_eqz => "i64.eqz",
+        i64_eq => "i64.eq",
+        i64_ne => "i64.ne",
+        i64_lt_s => "i64.lt_s",
+        i64_lt_u => "i64.lt_u",
+        i64_gt_s => "i64.gt_s",
+        i64_gt_u => "i64.gt_u",
+        i64_le_s => "i64.le_s",
+        i64_le_u => "i64.le_u",
+        i64_ge_s => "i64.ge_s",
+        i64_ge_u => "i64.ge_u",
+        f32_eq => "f32.eq",
+        f32_ne => "f32.ne",
+        f32_lt => "f32.lt",
+        f32_gt => "f32.gt",
+        f32_le => "f32.le",
+        f32_ge => "f32.ge",
+        f64_eq => "f64.eq",
+        f64_ne => "f64.ne",
+        f64_lt => "f64.lt",
+        f64_gt => "f64.gt",
+        f64_le => "f64.le",
+        f64_ge => "f64.ge",
+        i32_clz => "i32.clz",
+        i32_ctz => "i32.ctz",
+        i32_popcnt => "i32.popcnt",
+        i32_add => "i32.add",
+        i32_sub => "i32.sub",
+        i32_mul => "i32.mul",
+        i32_div_s => "i32.div_s",
+        i32_div_u => "i32.div_u",
+        i32_rem_s => "i32.rem_s",
+        i32_rem_u => "i32.rem_u",
+        i32_and => "i32.and",
+        i32_or => "i32.or",
+        i32_xor => "i32.xor",
+        i32_shl => "i32.shl",
+        i32_shr_s => "i32.shr_s",
+        i32_shr_u => "i32.shr_u",
+        i32_rotl => "i32.rotl",
+        i32_rotr => "i32.rotr",
+        i64_clz => "i64.clz",
+        i64_ctz => "i64.ctz",
+        i64_popcnt => "i64.popcnt",
+        i64_add => "i64.add",
+        i64_sub => "i64.sub",
+        i64_mul => "i64.mul",
+        i64_div_s => "i64.div_s",
+        i64_div_u => "i64.div_u",
+        i64_rem_s => "i64.rem_s",
+        i64_rem_u => "i64.rem_u",
+        i64_and => "i64.and",
+        i64_or => "i64.or",
+        i64_xor => "i64.xor",
+        i64_shl => "i64.shl",
+        i64_shr_s => "i64.shr_s",
+        i64_shr_u => "i64.shr_u",
+        i64_rotl => "i64.rotl",
+        i64_rotr => "i64.rotr",
+        f32_abs => "f32.abs",
+        f32_neg => "f32.neg",
+        f32_ceil => "f32.ceil",
+        f32_floor => "f32.floor",
+        f32_trunc => "f32.trunc",
+        f32_nearest => "f32.nearest",
+        f32_sqrt => "f32.sqrt",
+        f32_add => "f32.add",
+        f32_sub => "f32.sub",
+        f32_mul => "f32.mul",
+        f32_div => "f32.div",
+        f32_min => "f32.min",
+        f32_max => "f32.max",
+        f32_copysign => "f32.copysign",
+        f64_abs => "f64.abs",
+        f64_neg => "f64.neg",
+        f64_ceil => "f64.ceil",
+        f64_floor => "f64.floor",
+        f64_trunc => "f64.trunc",
+        f64_nearest => "f64.nearest",
+        f64_sqrt => "f64.sqrt",
+        f64_add => "f64.add",
+        f64_sub => "f64.sub",
+        f64_mul => "f64.mul",
+        f64_div => "f64.div",
+        f64_min => "f64.min",
+        f64_max => "f64.max",
+        f64_copysign => "f64.copysign",
+        i32_wrap_i64 => "i32.wrap_i64",
+        i32_trunc_f32_s => "i32.trunc_f32_s",
+        i32_trunc_f32_u => "i32.trunc_f32_u",
+        i32_trunc_f64_s => "i32.trunc_f64_s",
+        i32_trunc_f64_u => "i32.trunc_f64_u",
+        i64_extend_i32_s => "i64.extend_i32_s",
+        i64_extend_i32_u => "i64.extend_i32_u",
+        i64_trunc_f32_s => "i64.trunc_f32_s",
+        i64_trunc_f32_u => "i64.trunc_f32_u",
+        i64_trunc_f64_s => "i64.trunc_f64_s",
+        i64_trunc_f64_u => "i64.trunc_f64_u",
+        f32_convert_i32_s => "f32.convert_i32_s",
+        f32_convert_i32_u => "f32.convert_i32_u",
+        f32_convert_i64_s => "f32.convert_i64_s",
+        f32_convert_i64_u => "f32.convert_i64_u",
+        f32_demote_f64 => "f32.demote_f64",
+        f64_convert_i32_s => "f64.convert_i32_s",
+        f64_convert_i32_u => "f64.convert_i32_u",
+        f64_convert_i64_s => "f64.convert_i64_s",
+        f64_convert_i64_u => "f64.convert_i64_u",
+        f64_promote_f32 => "f64.promote_f32",
+        i32_reinterpret_f32 => "i32.reinterpret_f32",
+        i64_reinterpret_f64 => "i64.reinterpret_f64",
+        f32_reinterpret_i32 => "f32.reinterpret_i32",
+        f64_reinterpret_i64 => "f64.reinterpret_i64",
+        i32_extend8_s => "i32.extend8_s",
+        i32_extend16_s => "i32.extend16_s",
+        i64_extend8_s => "i64.extend8_s",
+        i64_extend16_s => "i64.extend16_s",
+        i64_extend32_s => "i64.extend32_s",
+        i32_trunc_sat_f32_s => "i32.trunc_sat_f32_s",
+        i32_trunc_sat_f32_u => "i32.trunc_sat_f32_u",
+        i32_trunc_sat_f64_s => "i32.trunc_sat_f64_s",
+        i32_trunc_sat_f64_u => "i32.trunc_sat_f64_u",
+        i64_trunc_sat_f32_s => "i64.trunc_sat_f32_s",
+        i64_trunc_sat_f32_u => "i64.trunc_sat_f32_u",
+        i64_trunc_sat_f64_s => "i64.trunc_sat_f64_s",
+        i64_trunc_sat_f64_u => "i64.trunc_sat_f64_u",
+        i8x16_swizzle => "i8x16.swizzle",
+        i8x16_splat => "i8x16.splat",
+        i16x8_splat => "i16x8.splat",
+        i32x4_splat => "i32x4.splat",
+        i64x2_splat => "i64x2.splat",
+        f32x4_splat => "f32x4.splat",
+        f64x2_splat => "f64x2.splat",
+        i8x16_eq => "i8x16.eq",
+        i8x16_ne => "i8x16.ne",
+        i8x16_lt_s => "i8x16.lt_s",
+        i8x16_lt_u => "i8x16.lt_u",
+        i8x16_gt_s => "i8x16.gt_s",
+        i8x16_gt_u => "i8x16.gt_u",
+        i8x16_le_s => "i8x16.le_s",
+        i8x16_le_u => "i8x16.le_u",
+        i8x16_ge_s => "i8x16.ge_s",
+        i8x16_ge_u => "i8x16.ge_u",
+        i16x8_eq => "i16x8.eq",
+        i16x8_ne => "i16x8.ne",
+        i16x8_lt_s => "i16x8.lt_s",
+        i16x8_lt_u => "i16x8.lt_u",
+        i16x8_gt_s => "i16x8.gt_s",
+        i16x8_gt_u => "i16x8.gt_u",
+        i16x8_le_s => "i16x8.le_s",
+        i16x8_le_u => "i16x8.le_u",
+        i16x8_ge_s => "i16x8.ge_s",
+        i16x8_ge_u => "i16x8.ge_u",
+        i32x4_eq => "i32x4.eq",
+        i32x4_ne => "i32x4.ne",
+        i32x4_lt_s => "i32x4.lt_s",
+        i32x4_lt_u => "i32x4.lt_u",
+        i32x4_gt_s => "i32x4.gt_s",
+        i32x4_gt_u => "i32x4.gt_u",
+        i32x4_le_s => "i32x4.le_s",
+        i32x4_le_u => "i32x4.le_u",
+        i32x4_ge_s => "i32x4.ge_s",
+        i32x4_ge_u => "i32x4.ge_u",
+        f32x4_eq => "f32x4.eq",
+        f32x4_ne => "f32x4.ne",
+        f32x4_lt => "f32x4.lt",
+        f32x4_gt => "f32x4.gt",
+        f32x4_le => "f32x4.le",
+        f32x4_ge => "f32x4.ge",
+        f64x2_eq => "f64x2.eq",
+        f64x2_ne => "f64x2.ne",
+        f64x2_lt => "f64x2.lt",
+        f64x2_gt => "f64x2.gt",
+        f64x2_le => "f64x2.le",
+        f64x2_ge => "f64x2.ge",
+        v128_not => "v128.not",
+        v128_and => "v128.and",
+        v128_andnot => "v128.andnot",
+        v128_or => "v128.or",
+        v128_xor => "v128.xor",
+        v128_bitselect => "v128.bitselect",
+        v128_any_true => "v128.any_true",
+        f32x4_demote_f64x2_zero => "f32x4.demote_f64x2_zero",
+        f64x2_promote_low_f32x4 => "f64x2.promote_low_f32x4",
+        i8x16_abs => "i8x16.abs",
+        i8x16_neg => "i8x16.neg",
+        i8x16_popcnt => "i8x16.popcnt",
+        i8x16_all_true => "i8x16.all_true",
+        i8x16_bitmask => "i8x16.bitmask",
+        i8x16_narrow_i16x8_s => "i8x16.narrow_i16x8_s",
+        i8x16_narrow_i16x8_u => "i8x16.narrow_i16x8_u",
+        f32x4_ceil => "f32x4.ceil",
+        f32x4_floor => "f32x4.floor",
+        f32x4_trunc => "f32x4.trunc",
+        f32x4_nearest => "f32x4.nearest",
+        i8x16_shl => "i8x16.shl",
+        i8x16_shr_s => "i8x16.shr_s",
+        i8x16_shr_u => "i8x16.shr_u",
+        i8x16_add => "i8x16.add",
+        i8x16_add_sat_s => "i8x16.add_sat_s",
+        i8x16_add_sat_u => "i8x16.add_sat_u",
+        i8x16_sub => "i8x16.sub",
+        i8x16_sub_sat_s => "i8x16.sub_sat_s",
+        i8x16_sub_sat_u => "i8x16.sub_sat_u",
+        f64x2_ceil => "f64x2.ceil",
+        f64x2_floor => "f64x2.floor",
+        i8x16_min_s => "i8x16.min_s",
+        i8x16_min_u => "i8x16.min_u",
+        i8x16_max_s => "i8x16.max_s",
+        i8x16_max_u => "i8x16.max_u",
+        f64x2_trunc => "f64x2.trunc",
+        i8x16_avgr_u => "i8x16.avgr_u",
+        i16x8_extadd_pairwise_i8x16_s => "i16x8.extadd_pairwise_i8x16_s",
+        i16x8_extadd_pairwise_i8x16_u => "i16x8.extadd_pairwise_i8x16_u",
+        i32x4_extadd_pairwise_i16x8_s => "i32x4.extadd_pairwise_i16x8_s",
+        i32x4_extadd_pairwise_i16x8_u => "i32x4.extadd_pairwise_i16x8_u",
+        i16x8_abs => "i16x8.abs",
+        i16x8_neg => "i16x8.neg",
+        i16x8_q15mulr_sat_s => "i16x8.q15mulr_sat_s",
+        i16x8_all_true => "i16x8.all_true",
+        i16x8_bitmask => "i16x8.bitmask",
+        i16x8_narrow_i32x4_s => "i16x8.narrow_i32x4_s",
+        i16x8_narrow_i32x4_u => "i16x8.narrow_i32x4_u",
+        i16x8_extend_low_i8x16_s => "i16x8.extend_low_i8x16_s",
+        i16x8_extend_high_i8x16_s => "i16x8.extend_high_i8x16_s",
+        i16x8_extend_low_i8x16_u => "i16x8.extend_low_i8x16_u",
+        i16x8_extend_high_i8x16_u => "i16x8.extend_high_i8x16_u",
+        i16x8_shl => "i16x8.shl",
+        i16x8_shr_s => "i16x8.shr_s",
+        i16x8_shr_u => "i16x8.shr_u",
+        i16x8_add => "i16x8.add",
+        i16x8_add_sat_s => "i16x8.add_sat_s",
+        i16x8_add_sat_u => "i16x8.add_sat_u",
+        i16x8_sub => "i16x8.sub",
+        i16x8_sub_sat_s => "i16x8.sub_sat_s",
+        i16x8_sub_sat_u => "i16x8.sub_sat_u",
+        f64x2_nearest => "f64x2.nearest",
+        i16x8_mul => "i16x8.mul",
+        i16x8_min_s => "i16x8.min_s",
+        i16x8_min_u => "i16x8.min_u",
+        i16x8_max_s => "i16x8.max_s",
+        i16x8_max_u => "i16x8.max_u",
+        i16x8_avgr_u => "i16x8.avgr_u",
+        i16x8_extmul_low_i8x16_s => "i16x8.extmul_low_i8x16_s",
+        i16x8_extmul_high_i8x16_s => "i16x8.extmul_high_i8x16_s",
+        i16x8_extmul_low_i8x16_u => "i16x8.extmul_low_i8x16_u",
+        i16x8_extmul_high_i8x16_u => "i16x8.extmul_high_i8x16_u",
+        i32x4_abs => "i32x4.abs",
+        i32x4_neg => "i32x4.neg",
+        i32x4_all_true => "i32x4.all_true",
+        i32x4_bitmask => "i32x4.bitmask",
+        i32x4_extend_low_i16x8_s => "i32x4.extend_low_i16x8_s",
+        i32x4_extend_high_i16x8_s => "i32x4.extend_high_i16x8_s",
+        i32x4_extend_low_i16x8_u => "i32x4.extend_low_i16x8_u",
+        i32x4_extend_high_i16x8_u => "i32x4.extend_high_i16x8_u",
+        i32x4_shl => "i32x4.shl",
+        i32x4_shr_s => "i32x4.shr_s",
+        i32x4_shr_u => "i32x4.shr_u",
+        i32x4_add => "i32x4.add",
+        i32x4_sub => "i32x4.sub",
+        i32x4_mul => "i32x4.mul",
+        i32x4_min_s => "i32x4.min_s",
+        i32x4_min_u => "i32x4.min_u",
+        i32x4_max_s => "i32x4.max_s",
+        i32x4_max_u => "i32x4.max_u",
+        i32x4_dot_i16x8_s => "i32x4.dot_i16x8_s",
+        i32x4_extmul_low_i16x8_s => "i32x4.extmul_low_i16x8_s",
+        i32x4_extmul_high_i16x8_s => "i32x4.extmul_high_i16x8_s",
+        i32x4_extmul_low_i16x8_u => "i32x4.extmul_low_i16x8_u",
+        i32x4_extmul_high_i16x8_u => "i32x4.extmul_high_i16x8_u",
+        i64x2_abs => "i64x2.abs",
+        i64x2_neg => "i64x2.neg",
+        i64x2_all_true => "i64x2.all_true",
+        i64x2_bitmask => "i64x2.bitmask",
+        i64x2_extend_low_i32x4_s => "i64x2.extend_low_i32x4_s",
+        i64x2_extend_high_i32x4_s => "i64x2.extend_high_i32x4_s",
+        i64x2_extend_low_i32x4_u => "i64x2.extend_low_i32x4_u",
+        i64x2_extend_high_i32x4_u => "i64x2.extend_high_i32x4_u",
+        i64x2_shl => "i64x2.shl",
+        i64x2_shr_s => "i64x2.shr_s",
+        i64x2_shr_u => "i64x2.shr_u",
+        i64x2_add => "i64x2.add",
+        i64x2_sub => "i64x2.sub",
+        i64x2_mul => "i64x2.mul",
+        i64x2_eq => "i64x2.eq",
+        i64x2_ne => "i64x2.ne",
+        i64x2_lt_s => "i64x2.lt_s",
+        i64x2_gt_s => "i64x2.gt_s",
+        i64x2_le_s => "i64x2.le_s",
+        i64x2_ge_s => "i64x2.ge_s",
+        i64x2_extmul_low_i32x4_s => "i64x2.extmul_low_i32x4_s",
+        i64x2_extmul_high_i32x4_s => "i64x2.extmul_high_i32x4_s",
+        i64x2_extmul_low_i32x4_u => "i64x2.extmul_low_i32x4_u",
+        i64x2_extmul_high_i32x4_u => "i64x2.extmul_high_i32x4_u",
+        f32x4_abs => "f32x4.abs",
+        f32x4_neg => "f32x4.neg",
+        f32x4_sqrt => "f32x4.sqrt",
+        f32x4_add => "f32x4.add",
+        f32x4_sub => "f32x4.sub",
+        f32x4_mul => "f32x4.mul",
+        f32x4_div => "f32x4.div",
+        f32x4_min => "f32x4.min",
+        f32x4_max => "f32x4.max",
+        f32x4_pmin => "f32x4.pmin",
+        f32x4_pmax => "f32x4.pmax",
+        f64x2_abs => "f64x2.abs",
+        f64x2_neg => "f64x2.neg",
+        f64x2_sqrt => "f64x2.sqrt",
+        f64x2_add => "f64x2.add",
+        f64x2_sub => "f64x2.sub",
+        f64x2_mul => "f64x2.mul",
+        f64x2_div => "f64x2.div",
+        f64x2_min => "f64x2.min",
+        f64x2_max => "f64x2.max",
+        f64x2_pmin => "f64x2.pmin",
+        f64x2_pmax => "f64x2.pmax",
+        i32x4_trunc_sat_f32x4_s => "i32x4.trunc_sat_f32x4_s",
+        i32x4_trunc_sat_f32x4_u => "i32x4.trunc_sat_f32x4_u",
+        f32x4_convert_i32x4_s => "f32x4.convert_i32x4_s",
+        f32x4_convert_i32x4_u => "f32x4.convert_i32x4_u",
+        i32x4_trunc_sat_f64x2_s_zero => "i32x4.trunc_sat_f64x2_s_zero",
+        i32x4_trunc_sat_f64x2_u_zero => "i32x4.trunc_sat_f64x2_u_zero",
+        f64x2_convert_low_i32x4_s => "f64x2.convert_low_i32x4_s",
+        f64x2_convert_low_i32x4_u => "f64x2.convert_low_i32x4_u",
+        i8x16_relaxed_swizzle => "i8x16.relaxed_swizzle",
+        i32x4_relaxed_trunc_f32x4_s => "i32x4.relaxed_trunc_f32x4_s",
+        i32x4_relaxed_trunc_f32x4_u => "i32x4.relaxed_trunc_f32x4_u",
+        i32x4_relaxed_trunc_f64x2_s_zero => "i32x4.relaxed_trunc_f64x2_s_zero",
+        i32x4_relaxed_trunc_f64x2_u_zero => "i32x4.relaxed_trunc_f64x2_u_zero",
+        f32x4_relaxed_madd => "f32x4.relaxed_madd",
+        f32x4_relaxed_nmadd => "f32x4.relaxed_nmadd",
+        f64x2_relaxed_madd => "f64x2.relaxed_madd",
+        f64x2_relaxed_nmadd => "f64x2.relaxed_nmadd",
+        i8x16_relaxed_laneselect => "i8x16.relaxed_laneselect",
+        i16x8_relaxed_laneselect => "i16x8.relaxed_laneselect",
+        i32x4_relaxed_laneselect => "i32x4.relaxed_laneselect",
+        i64x2_relaxed_laneselect => "i64x2.relaxed_laneselect",
+        f32x4_relaxed_min => "f32x4.relaxed_min",
+        f32x4_relaxed_max => "f32x4.relaxed_max",
+        f64x2_relaxed_min => "f64x2.relaxed_min",
+        f64x2_relaxed_max => "f64x2.relaxed_max",
+        i16x8_relaxed_q15mulr_s => "i16x8.relaxed_q15mulr_s",
+        i16x8_relaxed_dot_i8x16_i7x16_s => "i16x8.relaxed_dot_i8x16_i7x16_s",
+        i32x4_relaxed_dot_i8x16_i7x16_add_s => "i32x4.relaxed_dot_i8x16_i7x16_add_s",
+        atomic_fence => "atomic.fence",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Name and memory argument of the loads, stores and atomic instructions.
+fn memarg_instr(node: &ast::Instr) -> Option<(&'static str, &ast::MemArg)> {
+    use ast::Instr::*;
+
+    let instr = match node {
+        i32_load(arg) => ("i32.load", arg),
+        i64_load(arg) => ("i64.load", arg),
+        f32_load(arg) => ("f32.load", arg),
+        f64_load(arg) => ("f64.load", arg),
+        i32_load8_s(arg) => ("i32.load8_s", arg),
+        i32_load8_u(arg) => ("i32.load8_u", arg),
+        i32_load16_s(arg) => ("i32.load16_s", arg),
+        i32_load16_u(arg) => ("i32.load16_u", arg),
+        i64_load8_s(arg) => ("i64.load8_s", arg),
+        i64_load8_u(arg) => ("i64.load8_u", arg),
+        i64_load16_s(arg) => ("i64.load16_s", arg),
+        i64_load16_u(arg) => ("i64.load16_u", arg),
+        i64_load32_s(arg) => ("i64.load32_s", arg),
+        i64_load32_u(arg) => ("i64.load32_u", arg),
+        i32_store(arg) => ("i32.store", arg),
+        i64_store(arg) => ("i64.store", arg),
+        f32_store(arg) => ("f32.store", arg),
+        f64_store(arg) => ("f64.store", arg),
+        i32_store8(arg) => ("i32.store8", arg),
+        i32_store16(arg) => ("i32.store16", arg),
+        i64_store8(arg) => ("i64.store8", arg),
+        i64_store16(arg) => ("i64.store16", arg),
+        i64_store32(arg) => ("i64.store32", arg),
+        v128_load(arg) => ("v128.load", arg),
+        v128_load8x8_s(arg) => ("v128.load8x8_s", arg),
+        v128_load8x8_u(arg) => ("v128.load8x8_u", arg),
+        v128_load16x4_s(arg) => ("v128.load16x4_s", arg),
+        v128_load16x4_u(arg) => ("v128.load16x4_u", arg),
+        v128_load32x2_s(arg) => ("v128.load32x2_s", arg),
+        v128_load32x2_u(arg) => ("v128.load32x2_u", arg),
+        v128_load8_splat(arg) => ("v128.load8_splat", arg),
+        v128_load16_splat(arg) => ("v128.load16_splat", arg),
+        v128_load32_splat(arg) => ("v128.load32_splat", arg),
+        v128_load64_splat(arg) => ("v128.load64_splat", arg),
+        v128_store(arg) => ("v128.store", arg),
+        v128_load32_zero(arg) => ("v128.load32_zero", arg),
+        v128_load64_zero(arg) => ("v128.load64_zero", arg),
+        memory_atomic_notify(arg) => ("memory.atomic.notify", arg),
+        memory_atomic_wait32(arg) => ("memory.atomic.wait32", arg),
+        memory_atomic_wait64(arg) => ("memory.atomic.wait64", arg),
+        i32_atomic_load(arg) => ("i32.atomic.load", arg),
+        i64_atomic_load(arg) => ("i64.atomic.load", arg),
+        i32_atomic_load8_u(arg) => ("i32.atomic.load8_u", arg),
+        i32_atomic_load16_u(arg) => ("i32.atomic.load16_u", arg),
+        i64_atomic_load8_u(arg) => ("i64.atomic.load8_u", arg),
+        i64_atomic_load16_u(arg) => ("i64.atomic.load16_u", arg),
+        i64_atomic_load32_u(arg) => ("i64.atomic.load32_u", arg),
+        i32_atomic_store(arg) => ("i32.atomic.store", arg),
+        i64_atomic_store(arg) => ("i64.atomic.store", arg),
+        i32_atomic_store8(arg) => ("i32.atomic.store8", arg),
+        i32_atomic_store16(arg) => ("i32.atomic.store16", arg),
+        i64_atomic_store8(arg) => ("i64.atomic.store8", arg),
+        i64_atomic_store16(arg) => ("i64.atomic.store16", arg),
+        i64_atomic_store32(arg) => ("i64.atomic.store32", arg),
+        i32_atomic_rmw_add(arg) => ("i32.atomic.rmw.add", arg),
+        i64_atomic_rmw_add(arg) => ("i64.atomic.rmw.add", arg),
+        i32_atomic_rmw8_add_u(arg) => ("i32.atomic.rmw8.add_u", arg),
+        i32_atomic_rmw16_add_u(arg) => ("i32.atomic.rmw16.add_u", arg),
+        i64_atomic_rmw8_add_u(arg) => ("i64.atomic.rmw8.add_u", arg),
+        i64_atomic_rmw16_add_u(arg) => ("i64.atomic.rmw16.add_u", arg),
+        i64_atomic_rmw32_add_u(arg) => ("i64.atomic.rmw32.add_u", arg),
+        i32_atomic_rmw_sub(arg) => ("i32.atomic.rmw.sub", arg),
+        i64_atomic_rmw_sub(arg) => ("i64.atomic.rmw.sub", arg),
+        i32_atomic_rmw8_sub_u(arg) => ("i32.atomic.rmw8.sub_u", arg),
+        i32_atomic_rmw16_sub_u(arg) => ("i32.atomic.rmw16.sub_u", arg),
+        i64_atomic_rmw8_sub_u(arg) => ("i64.atomic.rmw8.sub_u", arg),
+        i64_atomic_rmw16_sub_u(arg) => ("i64.atomic.rmw16.sub_u", arg),
+        i64_atomic_rmw32_sub_u(arg) => ("i64.atomic.rmw32.sub_u", arg),
+        i32_atomic_rmw_and(arg) => ("i32.atomic.rmw.and", arg),
+        i64_atomic_rmw_and(arg) => ("i64.atomic.rmw.and", arg),
+        i32_atomic_rmw8_and_u(arg) => ("i32.atomic.rmw8.and_u", arg),
+        i32_atomic_rmw16_and_u(arg) => ("i32.atomic.rmw16.and_u", arg),
+        i64_atomic_rmw8_and_u(arg) => ("i64.atomic.rmw8.and_u", arg),
+        i64_atomic_rmw16_and_u(arg) => ("i64.atomic.rmw16.and_u", arg),
+        i64_atomic_rmw32_and_u(arg) => ("i64.atomic.rmw32.and_u", arg),
+        i32_atomic_rmw_or(arg) => ("i32.atomic.rmw.or", arg),
+        i64_atomic_rmw_or(arg) => ("i64.atomic.rmw.or", arg),
+        i32_atomic_rmw8_or_u(arg) => ("i32.atomic.rmw8.or_u", arg),
+        i32_atomic_rmw16_or_u(arg) => ("i32.atomic.rmw16.or_u", arg),
+        i64_atomic_rmw8_or_u(arg) => ("i64.atomic.rmw8.or_u", arg),
+        i64_atomic_rmw16_or_u(arg) => ("i64.atomic.rmw16.or_u", arg),
+        i64_atomic_rmw32_or_u(arg) => ("i64.atomic.rmw32.or_u", arg),
+        i32_atomic_rmw_xor(arg) => ("i32.atomic.rmw.xor", arg),
+        i64_atomic_rmw_xor(arg) => ("i64.atomic.rmw.xor", arg),
+        i32_atomic_rmw8_xor_u(arg) => ("i32.atomic.rmw8.xor_u", arg),
+        i32_atomic_rmw16_xor_u(arg) => ("i32.atomic.rmw16.xor_u", arg),
+        i64_atomic_rmw8_xor_u(arg) => ("i64.atomic.rmw8.xor_u", arg),
+        i64_atomic_rmw16_xor_u(arg) => ("i64.atomic.rmw16.xor_u", arg),
+        i64_atomic_rmw32_xor_u(arg) => ("i64.atomic.rmw32.xor_u", arg),
+        i32_atomic_rmw_xchg(arg) => ("i32.atomic.rmw.xchg", arg),
+        i64_atomic_rmw_xchg(arg) => ("i64.atomic.rmw.xchg", arg),
+        i32_atomic_rmw8_xchg_u(arg) => ("i32.atomic.rmw8.xchg_u", arg),
+        i32_atomic_rmw16_xchg_u(arg) => ("i32.atomic.rmw16.xchg_u", arg),
+        i64_atomic_rmw8_xchg_u(arg) => ("i64.atomic.rmw8.xchg_u", arg),
+        i64_atomic_rmw16_xchg_u(arg) => ("i64.atomic.rmw16.xchg_u", arg),
+        i64_atomic_rmw32_xchg_u(arg) => ("i64.atomic.rmw32.xchg_u", arg),
+        i32_atomic_rmw_cmpxchg(arg) => ("i32.atomic.rmw.cmpxchg", arg),
+        i64_atomic_rmw_cmpxchg(arg) => ("i64.atomic.rmw.cmpxchg", arg),
+        i32_atomic_rmw8_cmpxchg_u(arg) => ("i32.atomic.rmw8.cmpxchg_u", arg),
+        i32_atomic_rmw16_cmpxchg_u(arg) => ("i32.atomic.rmw16.cmpxchg_u", arg),
+        i64_atomic_rmw8_cmpxchg_u(arg) => ("i64.atomic.rmw8.cmpxchg_u", arg),
+        i64_atomic_rmw16_cmpxchg_u(arg) => ("i64.atomic.rmw16.cmpxchg_u", arg),
+        i64_atomic_rmw32_cmpxchg_u(arg) => ("i64.atomic.rmw32.cmpxchg_u", arg),
+        _ => return None,
+    };
+    Some(instr)
+}
+
+/// Name and lane index of the SIMD lane instructions.
+fn lane_instr(node: &ast::Instr) -> Option<(&'static str, u8)> {
+    use ast::Instr::*;
+
+    let instr = match node {
+        i8x16_extract_lane_s(lane) => ("i8x16.extract_lane_s", *lane),
+        i8x16_extract_lane_u(lane) => ("i8x16.extract_lane_u", *lane),
+        i8x16_replace_lane(lane) => ("i8x16.replace_lane", *lane),
+        i16x8_extract_lane_s(lane) => ("i16x8.extract_lane_s", *lane),
+        i16x8_extract_lane_u(lane) => ("i16x8.extract_lane_u", *lane),
+        i16x8_replace_lane(lane) => ("i16x8.replace_lane", *lane),
+        i32x4_extract_lane(lane) => ("i32x4.extract_lane", *lane),
+        i32x4_replace_lane(lane) => ("i32x4.replace_lane", *lane),
+        i64x2_extract_lane(lane) => ("i64x2.extract_lane", *lane),
+        i64x2_replace_lane(lane) => ("i64x2.replace_lane", *lane),
+        f32x4_extract_lane(lane) => ("f32x4.extract_lane", *lane),
+        f32x4_replace_lane(lane) => ("f32x4.replace_lane", *lane),
+        f64x2_extract_lane(lane) => ("f64x2.extract_lane", *lane),
+        f64x2_replace_lane(lane) => ("f64x2.replace_lane", *lane),
+        _ => return None,
+    };
+    Some(instr)
+}